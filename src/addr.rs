@@ -1,17 +1,29 @@
 use core::{
-    convert::{Into, TryInto},
+    convert::TryInto,
     fmt,
     ops::{Add, AddAssign, Sub, SubAssign},
 };
 
-use bit_field::BitField;
+use crate::paging::{Granule4KiB, PageTableLevel, TranslationGranule};
 use ux::*;
 
 pub const ALIGN_4KIB: u64 = 0x0000_1000;
 pub const ALIGN_2MIB: u64 = 0x0020_0000;
 pub const ALIGN_1GIB: u64 = 0x4000_0000;
 
-#[derive(Clone, Copy, Debug)]
+/// The number of virtual address bits of each VA range.
+///
+/// The 64KiB granule translates 52-bit addresses with the `lva` feature, see
+/// [`TranslationGranule::VA_BITS`] and [`VirtAddr::try_new_for`].
+pub const VA_BITS: u32 = 48;
+
+/// The two virtual address ranges of the EL1&0 translation regime, with [`VA_BITS`]-bit
+/// addresses.
+///
+/// The bits above [`VA_BITS`] of an address select the range, and with it the translation table
+/// base register: all zeros for TTBR0_EL1, all ones for TTBR1_EL1 (usually the kernel's higher
+/// half).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum VirtAddrRange {
     /// 0x0000000000000000 to 0x0000FFFFFFFFFFFF
@@ -21,13 +33,35 @@ pub enum VirtAddrRange {
 }
 
 impl VirtAddrRange {
+    /// The range translated through TTBR0_EL1.
+    pub const TTBR0: Self = VirtAddrRange::BottomRange;
+    /// The range translated through TTBR1_EL1.
+    pub const TTBR1: Self = VirtAddrRange::TopRange;
+
     /// Returns the address offset
     pub fn as_offset(&self) -> u64 {
         match self {
             VirtAddrRange::BottomRange => 0,
-            VirtAddrRange::TopRange => 0xFFFF_0000_0000_0000,
+            VirtAddrRange::TopRange => !((1 << VA_BITS) - 1),
         }
     }
+
+    /// Returns the number of the translation table base register of this range, as used by
+    /// `translation::ttbr_el1_write`.
+    pub fn ttbr(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Returns whether the given address lies in this range.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr.as_u64() >> VA_BITS == self.as_offset() >> VA_BITS
+    }
+
+    /// Returns the address at `offset` (less than 2^VA_BITS) from the start of this range.
+    pub fn addr(&self, offset: u64) -> VirtAddr {
+        debug_assert!(offset < 1 << VA_BITS, "offset out of range");
+        VirtAddr(self.as_offset() | offset)
+    }
 }
 
 /// A canonical 64-bit virtual memory address.
@@ -50,32 +84,71 @@ pub struct PhysAddr(u64);
 
 /// A passed `u64` was not a valid virtual address.
 ///
-/// This means that bits 48 to 64 are not
+/// This means that bits [`VA_BITS`] to 64 are not
 /// a valid sign extension and are not null either. So automatic sign extension would have
 /// overwritten possibly meaningful bits. This likely indicates a bug, for example an invalid
 /// address calculation.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VirtAddrNotValid(u64);
 
 impl VirtAddr {
     /// Creates a new canonical virtual address.
+    ///
+    /// Panics if the address is not canonical, see [`try_new`](VirtAddr::try_new). Usable in
+    /// constants, where the panic is a compile error.
     #[inline]
-    pub fn new(addr: u64) -> VirtAddr {
-        // Self::try_new(addr).expect("invalid virtual address")
-        VirtAddr(addr)
+    pub const fn new(addr: u64) -> VirtAddr {
+        match Self::try_new(addr) {
+            Ok(addr) => addr,
+            Err(_) => panic!("invalid virtual address"),
+        }
     }
 
     /// Tries to create a new canonical virtual address.
     /// in aarch64, valid virtual address starts with 0x0000 or 0xffff.
-    pub fn try_new(addr: u64) -> Result<VirtAddr, VirtAddrNotValid> {
-        match addr.get_bits(48..64) {
-            0 | 0xffff => Ok(VirtAddr(addr)), // address is canonical
+    pub const fn try_new(addr: u64) -> Result<VirtAddr, VirtAddrNotValid> {
+        Self::try_new_bits(addr, VA_BITS)
+    }
+
+    /// Tries to create a new canonical virtual address of the translation tables of the granule
+    /// `G`, whose VA ranges have [`TranslationGranule::VA_BITS`] bits.
+    ///
+    /// This is [`try_new`](VirtAddr::try_new), except for the 52-bit addresses of the 64KiB
+    /// granule with the `lva` feature, starting with 0x000 or 0xfff.
+    pub const fn try_new_for<G: TranslationGranule>(
+        addr: u64,
+    ) -> Result<VirtAddr, VirtAddrNotValid> {
+        Self::try_new_bits(addr, G::VA_BITS)
+    }
+
+    const fn try_new_bits(addr: u64, bits: u32) -> Result<VirtAddr, VirtAddrNotValid> {
+        match addr >> bits {
+            0 => Ok(VirtAddr(addr)), // address is canonical
+            other if other == u64::MAX >> bits => Ok(VirtAddr(addr)),
             other => Err(VirtAddrNotValid(other)),
         }
     }
 
+    /// Tries to create a new canonical virtual address in the given range.
+    pub fn try_new_in(addr: u64, range: VirtAddrRange) -> Result<VirtAddr, VirtAddrNotValid> {
+        match VirtAddr::try_new(addr) {
+            Ok(addr) if range.contains(addr) => Ok(addr),
+            _ => Err(VirtAddrNotValid(addr >> VA_BITS)),
+        }
+    }
+
+    /// Creates a new canonical virtual address, replacing bits [`VA_BITS`] to 64 by the sign
+    /// extension of the highest address bit.
+    ///
+    /// Unlike [`try_new`](VirtAddr::try_new), this never fails, but may silently change the
+    /// address.
+    pub const fn new_truncate(addr: u64) -> VirtAddr {
+        // shift left then arithmetic shift right to copy the highest bit to the upper bits
+        VirtAddr(((addr << (64 - VA_BITS)) as i64 >> (64 - VA_BITS)) as u64)
+    }
+
     /// Creates a new canonical virtual address without checks.
-    pub fn new_unchecked(addr: u64) -> VirtAddr {
+    pub const fn new_unchecked(addr: u64) -> VirtAddr {
         VirtAddr(addr)
     }
 
@@ -86,13 +159,16 @@ impl VirtAddr {
 
     /// Converts the address to an `u64`.
     #[inline]
-    pub fn as_u64(self) -> u64 {
+    pub const fn as_u64(self) -> u64 {
         self.0
     }
 
-    /// Creates a virtual address from the given pointer
+    /// Creates a virtual address from the given pointer.
+    ///
+    /// The address is not checked, so that a tagged pointer keeps its tag and converts back to
+    /// the same pointer with [`as_ptr`](VirtAddr::as_ptr).
     pub fn from_ptr<T>(ptr: *const T) -> Self {
-        Self::new(cast::u64(ptr as usize))
+        Self::new_unchecked(cast::u64(ptr as usize))
     }
 
     /// Converts the address to a raw pointer.
@@ -110,29 +186,20 @@ impl VirtAddr {
     /// Aligns the virtual address upwards to the given alignment.
     ///
     /// See the `align_up` function for more information.
-    pub fn align_up<U>(self, align: U) -> Self
-    where
-        U: Into<u64>,
-    {
-        VirtAddr(align_up(self.0, align.into()))
+    pub const fn align_up(self, align: u64) -> Self {
+        VirtAddr(align_up(self.0, align))
     }
 
     /// Aligns the virtual address downwards to the given alignment.
     ///
     /// See the `align_down` function for more information.
-    pub fn align_down<U>(self, align: U) -> Self
-    where
-        U: Into<u64>,
-    {
-        VirtAddr(align_down(self.0, align.into()))
+    pub const fn align_down(self, align: u64) -> Self {
+        VirtAddr(align_down(self.0, align))
     }
 
     /// Checks whether the virtual address has the demanded alignment.
-    pub fn is_aligned<U>(self, align: U) -> bool
-    where
-        U: Into<u64>,
-    {
-        self.align_down(align) == self
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
     }
 
     /// Returns the 12-bit page offset of this virtual address.
@@ -142,9 +209,9 @@ impl VirtAddr {
 
     /// Returns the VA range
     pub fn va_range(&self) -> Result<VirtAddrRange, VirtAddrNotValid> {
-        match self.va_range_bits() {
-            0x0000 => Ok(VirtAddrRange::BottomRange),
-            0xffff => Ok(VirtAddrRange::TopRange),
+        match self.0 >> VA_BITS {
+            0 => Ok(VirtAddrRange::BottomRange),
+            bits if bits == u64::MAX >> VA_BITS => Ok(VirtAddrRange::TopRange),
             _ => Err(VirtAddrNotValid(self.0)),
         }
     }
@@ -154,25 +221,34 @@ impl VirtAddr {
         ((self.0 >> 48) & 0xffff) as u16
     }
 
-    /// Returns the 9-bit level 1 page table index.
+    /// Returns the index of the entry translating this address in a table of the given lookup
+    /// level, with the 4KiB granule.
+    ///
+    /// See [`PageTableLevel::table_index`] for the other granules.
+    #[inline]
+    pub fn page_table_index(&self, level: PageTableLevel) -> usize {
+        level.table_index::<Granule4KiB>(*self)
+    }
+
+    /// Returns the 9-bit level 1 page table index, the index at [`PageTableLevel::L3`].
     #[inline]
     pub fn p1_index(&self) -> u9 {
         u9::new(((self.0 >> 12) & 0o777).try_into().unwrap())
     }
 
-    /// Returns the 9-bit level 2 page table index.
+    /// Returns the 9-bit level 2 page table index, the index at [`PageTableLevel::L2`].
     #[inline]
     pub fn p2_index(&self) -> u9 {
         u9::new(((self.0 >> 12 >> 9) & 0o777).try_into().unwrap())
     }
 
-    /// Returns the 9-bit level 3 page table index.
+    /// Returns the 9-bit level 3 page table index, the index at [`PageTableLevel::L1`].
     #[inline]
     pub fn p3_index(&self) -> u9 {
         u9::new(((self.0 >> 12 >> 9 >> 9) & 0o777).try_into().unwrap())
     }
 
-    /// Returns the 9-bit level 4 page table index.
+    /// Returns the 9-bit level 4 page table index, the index at [`PageTableLevel::L0`].
     #[inline]
     pub fn p4_index(&self) -> u9 {
         u9::new(((self.0 >> 12 >> 9 >> 9 >> 9) & 0o777).try_into().unwrap())
@@ -246,68 +322,89 @@ impl Sub<VirtAddr> for VirtAddr {
 
 /// A passed `u64` was not a valid physical address.
 ///
-/// This means that bits 52 to 64 are not were not all null.
-#[derive(Debug)]
+/// This means that bits 52 to 64, or the bits beyond the physical address size for
+/// [`PhysAddr::try_new_in`], were not all null.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PhysAddrNotValid(u64);
 
 impl PhysAddr {
     /// Creates a new physical address.
     ///
-    /// Panics if a bit in the range 52 to 64 is set.
+    /// Panics if a bit in the range 52 to 64 is set. Usable in constants, where the panic is a
+    /// compile error.
     #[inline]
-    pub fn new(addr: u64) -> PhysAddr {
-        // Self::try_new(addr).expect("physical addresses must not have any bits in the range 52 to
-        // 64 set")
-        PhysAddr(addr)
+    pub const fn new(addr: u64) -> PhysAddr {
+        match Self::try_new(addr) {
+            Ok(addr) => addr,
+            Err(_) => panic!("physical addresses must not have any bits in the range 52 to 64 set"),
+        }
     }
 
     /// Tries to create a new physical address.
     ///
     /// Fails if any bits in the range 52 to 64 are set.
-    pub fn try_new(addr: u64) -> Result<PhysAddr, PhysAddrNotValid> {
-        match addr.get_bits(52..64) {
+    pub const fn try_new(addr: u64) -> Result<PhysAddr, PhysAddrNotValid> {
+        match addr >> 52 {
             0 => Ok(PhysAddr(addr)), // address is valid
             other => Err(PhysAddrNotValid(other)),
         }
     }
 
+    /// Tries to create a new physical address within the physical address size of `pa_bits`
+    /// bits.
+    ///
+    /// Fails if any bits in the range `pa_bits` to 64 are set.
+    pub fn try_new_in(addr: u64, pa_bits: u8) -> Result<PhysAddr, PhysAddrNotValid> {
+        debug_assert!(pa_bits <= 52, "`pa_bits` must not exceed 52");
+        match addr >> pa_bits {
+            0 => Ok(PhysAddr(addr)),
+            other => Err(PhysAddrNotValid(other)),
+        }
+    }
+
+    /// Tries to create a new physical address within the physical address size supported by
+    /// the PE, see [`pa_bits`].
+    #[inline]
+    pub fn try_new_supported(addr: u64) -> Result<PhysAddr, PhysAddrNotValid> {
+        Self::try_new_in(addr, pa_bits())
+    }
+
+    /// Creates a new physical address, clearing bits 52 to 64.
+    ///
+    /// Unlike [`try_new`](PhysAddr::try_new), this never fails, but may silently change the
+    /// address.
+    pub const fn new_truncate(addr: u64) -> PhysAddr {
+        PhysAddr(addr & ((1 << 52) - 1))
+    }
+
     /// Converts the address to an `u64`.
     #[inline]
-    pub fn as_u64(self) -> u64 {
+    pub const fn as_u64(self) -> u64 {
         self.0
     }
 
     /// Convenience method for checking if a physical address is null.
-    pub fn is_null(&self) -> bool {
+    pub const fn is_null(&self) -> bool {
         self.0 == 0
     }
 
     /// Aligns the physical address upwards to the given alignment.
     ///
     /// See the `align_up` function for more information.
-    pub fn align_up<U>(self, align: U) -> Self
-    where
-        U: Into<u64>,
-    {
-        PhysAddr(align_up(self.0, align.into()))
+    pub const fn align_up(self, align: u64) -> Self {
+        PhysAddr(align_up(self.0, align))
     }
 
     /// Aligns the physical address downwards to the given alignment.
     ///
     /// See the `align_down` function for more information.
-    pub fn align_down<U>(self, align: U) -> Self
-    where
-        U: Into<u64>,
-    {
-        PhysAddr(align_down(self.0, align.into()))
+    pub const fn align_down(self, align: u64) -> Self {
+        PhysAddr(align_down(self.0, align))
     }
 
     /// Checks whether the physical address has the demanded alignment.
-    pub fn is_aligned<U>(self, align: U) -> bool
-    where
-        U: Into<u64>,
-    {
-        self.align_down(align) == self
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
     }
 }
 
@@ -400,12 +497,36 @@ impl Sub<PhysAddr> for PhysAddr {
     }
 }
 
+/// Returns the physical address size in bits encoded by the PARange field of
+/// ID_AA64MMFR0_EL1, or `None` for reserved values.
+pub fn pa_range_bits(pa_range: u8) -> Option<u8> {
+    match pa_range {
+        0b0000 => Some(32),
+        0b0001 => Some(36),
+        0b0010 => Some(40),
+        0b0011 => Some(42),
+        0b0100 => Some(44),
+        0b0101 => Some(48),
+        0b0110 => Some(52),
+        _ => None,
+    }
+}
+
+/// Returns the physical address size in bits supported by the PE (ID_AA64MMFR0_EL1.PARange).
+#[inline]
+pub fn pa_bits() -> u8 {
+    use crate::registers::*;
+
+    let pa_range = ID_AA64MMFR0_EL1.read(ID_AA64MMFR0_EL1::PARange);
+    pa_range_bits(pa_range as u8).unwrap_or(52)
+}
+
 /// Align address downwards.
 ///
 /// Returns the greatest x with alignment `align` so that x <= addr. The alignment must be
 ///  a power of 2.
 #[inline]
-pub fn align_down(addr: u64, align: u64) -> u64 {
+pub const fn align_down(addr: u64, align: u64) -> u64 {
     debug_assert!(align.is_power_of_two(), "`align` must be a power of two");
     addr & !(align - 1)
 }
@@ -415,7 +536,7 @@ pub fn align_down(addr: u64, align: u64) -> u64 {
 /// Returns the smallest x with alignment `align` so that x >= addr. The alignment must be
 /// a power of 2.
 #[inline]
-pub fn align_up(addr: u64, align: u64) -> u64 {
+pub const fn align_up(addr: u64, align: u64) -> u64 {
     debug_assert!(align.is_power_of_two(), "`align` must be a power of two");
     let align_mask = align - 1;
     if addr & align_mask == 0 {
@@ -445,4 +566,61 @@ mod tests {
         assert_eq!(align_up(0, 2), 0);
         assert_eq!(align_up(0, 0x8000000000000000), 0);
     }
+
+    #[test]
+    pub fn test_va_range() {
+        let low = VirtAddr::new(0x0000_1234_5678_9000);
+        let high = VirtAddr::new(0xffff_1234_5678_9000);
+        assert!(VirtAddrRange::TTBR0.contains(low));
+        assert!(!VirtAddrRange::TTBR0.contains(high));
+        assert!(VirtAddrRange::TTBR1.contains(high));
+        assert_eq!(VirtAddrRange::TTBR1.addr(0x1234_5678_9000), high);
+        assert_eq!(VirtAddrRange::TTBR1.ttbr(), 1);
+        assert!(VirtAddr::try_new_in(high.as_u64(), VirtAddrRange::TTBR1).is_ok());
+        assert!(VirtAddr::try_new_in(high.as_u64(), VirtAddrRange::TTBR0).is_err());
+        assert!(VirtAddr::try_new_in(0x8000_0000_0000_0000, VirtAddrRange::TTBR1).is_err());
+    }
+
+    #[test]
+    pub fn test_checked_constructors() {
+        assert_eq!(
+            VirtAddr::new_truncate(0x1234_8000_0000_0000),
+            VirtAddr::new(0xffff_8000_0000_0000)
+        );
+        assert_eq!(
+            VirtAddr::new_truncate(0xff00_1234_5678_9000),
+            VirtAddr::new(0x0000_1234_5678_9000)
+        );
+        assert!(VirtAddr::try_new(0x0001_0000_0000_0000).is_err());
+        assert!(VirtAddr::try_new_for::<Granule4KiB>(0xfff8_0000_0000_0000).is_err());
+        assert_eq!(
+            VirtAddr::try_new_for::<crate::paging::Granule64KiB>(0xfff8_0000_0000_0000).is_ok(),
+            cfg!(feature = "lva")
+        );
+        // a tagged pointer is kept as is
+        let tagged = 0x0a00_0000_0000_1000 as *const u8;
+        assert_eq!(VirtAddr::from_ptr(tagged).as_ptr::<u8>(), tagged);
+
+        assert_eq!(
+            PhysAddr::new_truncate(0xfff0_0000_8000_0000),
+            PhysAddr::new(0x8000_0000)
+        );
+        assert!(PhysAddr::try_new(0x0010_0000_0000_0000).is_err());
+        assert!(PhysAddr::try_new_in(0xffff_ffff, pa_range_bits(0).unwrap()).is_ok());
+        assert!(PhysAddr::try_new_in(0x1_0000_0000, pa_range_bits(0).unwrap()).is_err());
+        assert_eq!(pa_range_bits(0b0101), Some(48));
+        assert_eq!(pa_range_bits(0b0111), None);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn test_virt_addr_not_canonical() {
+        VirtAddr::new(0x0001_0000_0000_0000);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn test_phys_addr_too_wide() {
+        PhysAddr::new(0x0010_0000_0000_0000);
+    }
 }
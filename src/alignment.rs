@@ -0,0 +1,60 @@
+//! Control of the alignment checks of EL1 and EL0.
+//!
+//! By default, unaligned accesses to Normal memory are permitted, and only the exclusive,
+//! load-acquire/store-release and Device memory accesses must be aligned. SCTLR_EL1.A makes all
+//! unaligned data accesses fault, and SCTLR_EL1.SA and SA0 check the alignment of the stack
+//! pointer when it is used as the base of an access. The faults are decoded with
+//! [`AlignmentFault`](crate::fault::AlignmentFault).
+
+use crate::{barrier, registers::*};
+
+/// Returns whether all unaligned data accesses at EL1 and EL0 fault (SCTLR_EL1.A).
+#[inline]
+pub fn is_check_enabled() -> bool {
+    SCTLR_EL1.is_set(SCTLR_EL1::A)
+}
+
+/// Enables or disables the alignment check of the data accesses at EL1 and EL0 (SCTLR_EL1.A).
+///
+/// # Safety
+///
+/// When enabling, the code running at EL1 must not make unaligned accesses: the compiler may
+/// emit them, e.g. for copies or packed structures, unless told otherwise (`+strict-align`).
+#[inline]
+pub unsafe fn set_check(enabled: bool) {
+    SCTLR_EL1.modify(if enabled {
+        SCTLR_EL1::A::Enable
+    } else {
+        SCTLR_EL1::A::Disable
+    });
+    barrier::isb();
+}
+
+/// Returns whether the stack alignment checks of EL1 (SCTLR_EL1.SA) and EL0 (SCTLR_EL1.SA0) are
+/// enabled.
+#[inline]
+pub fn stack_check_enabled() -> (bool, bool) {
+    let sctlr = SCTLR_EL1.extract();
+    (sctlr.is_set(SCTLR_EL1::SA), sctlr.is_set(SCTLR_EL1::SA0))
+}
+
+/// Enables or disables the stack alignment checks of EL1 (SCTLR_EL1.SA) and EL0
+/// (SCTLR_EL1.SA0).
+///
+/// With a check enabled, an access using a stack pointer not aligned to 16 bytes as its base
+/// causes an SP alignment fault.
+#[inline]
+pub fn set_stack_check(el1: bool, el0: bool) {
+    let sa = if el1 {
+        SCTLR_EL1::SA::Enable
+    } else {
+        SCTLR_EL1::SA::Disable
+    };
+    let sa0 = if el0 {
+        SCTLR_EL1::SA0::Enable
+    } else {
+        SCTLR_EL1::SA0::Disable
+    };
+    SCTLR_EL1.modify(sa + sa0);
+    unsafe { barrier::isb() };
+}
@@ -0,0 +1,214 @@
+//! Wrappers around single instructions.
+//!
+//! Re-exports the wrappers of `cortex_a::asm`, under their names, and adds the ones it lacks.
+
+pub use cortex_a::asm::{barrier, eret, nop, ret, sev, sevl, wfe, wfi};
+
+/// Returns the current stack pointer.
+#[inline(always)]
+pub fn sp() -> usize {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let sp;
+        unsafe { core::arch::asm!("mov {}, sp", out(reg) sp, options(nomem, nostack)) };
+        sp
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
+
+/// Returns the address of the current instruction.
+#[inline(always)]
+pub fn get_pc() -> usize {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let pc;
+        unsafe { core::arch::asm!("adr {}, .", out(reg) pc, options(nomem, nostack)) };
+        pc
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
+
+/// Yield: hints that the current thread is spinning, e.g. in a spin-wait loop, so that a
+/// multithreaded core can favor its other threads. `yield` is a keyword, hence the underscore.
+#[inline(always)]
+pub fn yield_() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("yield", options(nomem, nostack))
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
+
+/// Data Gathering Hint: hints that the preceding stores to Normal Non-cacheable or Device-GRE
+/// memory should not be merged with the following ones (FEAT_DGH). A no-op on cores without it.
+#[inline(always)]
+pub fn dgh() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        // DGH is HINT #6
+        core::arch::asm!("hint #6", options(nomem, nostack))
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
+
+/// Wait For Event with Timeout: like [`wfe`], but wakes up at the latest when the virtual count
+/// (CNTVCT_EL0) reaches `deadline` (FEAT_WFxT).
+///
+/// # Safety
+///
+/// The PE must implement FEAT_WFxT, see
+/// [`CpuFeatures::has_wfxt`](crate::cpufeature::CpuFeatures::has_wfxt): the instruction is
+/// UNDEFINED otherwise.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline(always)]
+pub unsafe fn wfet(deadline: u64) {
+    #[cfg(target_arch = "aarch64")]
+    {
+        // WFET x0, by its encoding so that no architecture extension is needed to assemble it
+        core::arch::asm!(".inst 0xd5031000", in("x0") deadline, options(nomem, nostack))
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
+
+/// The accesses a prefetch prepares for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefetchKind {
+    /// Loads (PLD).
+    Load,
+    /// Stores (PST).
+    Store,
+}
+
+/// The cache level a prefetch targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheLevel {
+    /// The level 1 cache.
+    L1,
+    /// The level 2 cache.
+    L2,
+    /// The level 3 cache.
+    L3,
+}
+
+/// The retention policy of the prefetched data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefetchPolicy {
+    /// The data is expected to be accessed more than once (KEEP).
+    Keep,
+    /// The data is expected to be accessed only once, e.g. by a copy loop (STRM).
+    Stream,
+}
+
+/// Issues `prfm` with the operation selected by `$kind`, `$level` and `$policy`, which must
+/// fold to constants for the match to compile to a single instruction.
+#[cfg(target_arch = "aarch64")]
+macro_rules! prfm {
+    ($addr:expr, $kind:expr, $level:expr, $policy:expr, [$(($k:ident, $l:ident, $p:ident, $op:literal)),*]) => {
+        match ($kind, $level, $policy) {
+            $((PrefetchKind::$k, CacheLevel::$l, PrefetchPolicy::$p) => core::arch::asm!(
+                concat!("prfm ", $op, ", [{}]"),
+                in(reg) $addr,
+                options(readonly, nostack, preserves_flags)
+            ),)*
+        }
+    };
+}
+
+/// Prefetch Memory: hints that the cache line containing `addr` will soon be accessed, so that
+/// the PE can fetch it into the cache level `level`.
+///
+/// A prefetch never faults, so `addr` does not have to be mapped. Called with constant
+/// arguments, this is a single `prfm` instruction.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline(always)]
+pub fn prefetch<T>(addr: *const T, kind: PrefetchKind, level: CacheLevel, policy: PrefetchPolicy) {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        prfm!(
+            addr,
+            kind,
+            level,
+            policy,
+            [
+                (Load, L1, Keep, "pldl1keep"),
+                (Load, L1, Stream, "pldl1strm"),
+                (Load, L2, Keep, "pldl2keep"),
+                (Load, L2, Stream, "pldl2strm"),
+                (Load, L3, Keep, "pldl3keep"),
+                (Load, L3, Stream, "pldl3strm"),
+                (Store, L1, Keep, "pstl1keep"),
+                (Store, L1, Stream, "pstl1strm"),
+                (Store, L2, Keep, "pstl2keep"),
+                (Store, L2, Stream, "pstl2strm"),
+                (Store, L3, Keep, "pstl3keep"),
+                (Store, L3, Stream, "pstl3strm")
+            ]
+        )
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
+
+/// Prefetches the cache line containing `addr` into the level 1 cache for loads (PLDL1KEEP).
+#[inline(always)]
+pub fn prefetch_load<T>(addr: *const T) {
+    prefetch(
+        addr,
+        PrefetchKind::Load,
+        CacheLevel::L1,
+        PrefetchPolicy::Keep,
+    )
+}
+
+/// Prefetches the cache line containing `addr` into the level 1 cache for stores (PSTL1KEEP).
+#[inline(always)]
+pub fn prefetch_store<T>(addr: *mut T) {
+    prefetch(
+        addr,
+        PrefetchKind::Store,
+        CacheLevel::L1,
+        PrefetchPolicy::Keep,
+    )
+}
+
+/// Consumption of Speculative Data Barrier: no instruction after it, other than a branch, can
+/// speculatively use the result of a conditional select or move mispredicted before it.
+///
+/// Placed after a bounds check clamping an index with `csel`, it mitigates Spectre variant 1.
+#[inline(always)]
+pub fn csdb() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        // CSDB is HINT #20
+        core::arch::asm!("hint #20", options(nomem, nostack))
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
+
+/// Speculative Store Bypass Barrier: a load after it can't speculatively read the value of a
+/// memory location from before a store to it that precedes the barrier, mitigating Spectre
+/// variant 4.
+#[inline(always)]
+pub fn ssbb() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        // SSBB is DSB #0
+        core::arch::asm!("dsb #0", options(nostack))
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    unimplemented!()
+}
@@ -0,0 +1,174 @@
+//! Atomic operations selecting the LSE instructions at run time.
+//!
+//! The Large System Extensions (FEAT_LSE, ARMv8.1) add single-instruction atomics (CAS, SWP,
+//! LDADD...) that scale much better under contention than load-exclusive/store-exclusive loops.
+//! The compiler only emits them when the target enables the `lse` feature, so a kernel built for
+//! ARMv8.0 never uses them. The operations of this module check for FEAT_LSE on first use and
+//! use the LSE instructions if present, an LL/SC loop otherwise.
+//!
+//! All the operations have acquire and release semantics, like `Ordering::AcqRel`.
+
+use crate::registers::*;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+const LSE_UNKNOWN: u8 = 0;
+const LSE_ABSENT: u8 = 1;
+const LSE_PRESENT: u8 = 2;
+
+/// Whether FEAT_LSE is implemented, detected on first use.
+static LSE: AtomicU8 = AtomicU8::new(LSE_UNKNOWN);
+
+/// Returns whether the current PE implements the LSE atomics (ID_AA64ISAR0_EL1.Atomic).
+///
+/// The result of the first call is cached: all the PEs of the system are expected to agree.
+#[inline]
+pub fn has_lse() -> bool {
+    match LSE.load(Ordering::Relaxed) {
+        LSE_UNKNOWN => {
+            let present = ID_AA64ISAR0_EL1.read(ID_AA64ISAR0_EL1::Atomic) >= 2;
+            LSE.store(
+                if present { LSE_PRESENT } else { LSE_ABSENT },
+                Ordering::Relaxed,
+            );
+            present
+        }
+        lse => lse == LSE_PRESENT,
+    }
+}
+
+/// Stores `new` into `atomic` if its value is `current`, and returns the previous value.
+///
+/// The store happened if the returned value is `current`.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub fn cas64(atomic: &AtomicU64, current: u64, new: u64) -> u64 {
+    match () {
+        #[cfg(target_arch = "aarch64")]
+        () => {
+            let ptr = atomic.as_ptr();
+            let mut old = current;
+            if has_lse() {
+                unsafe {
+                    core::arch::asm!(
+                        ".arch_extension lse",
+                        "casal {old}, {new}, [{ptr}]",
+                        old = inout(reg) old,
+                        new = in(reg) new,
+                        ptr = in(reg) ptr,
+                        options(nostack, preserves_flags)
+                    )
+                };
+            } else {
+                unsafe {
+                    core::arch::asm!(
+                        "2:",
+                        "ldaxr {old}, [{ptr}]",
+                        "cmp {old}, {current}",
+                        "b.ne 3f",
+                        "stlxr {status:w}, {new}, [{ptr}]",
+                        "cbnz {status:w}, 2b",
+                        "3:",
+                        old = out(reg) old,
+                        status = out(reg) _,
+                        current = in(reg) current,
+                        new = in(reg) new,
+                        ptr = in(reg) ptr,
+                        options(nostack)
+                    )
+                };
+            }
+            old
+        }
+
+        #[cfg(not(target_arch = "aarch64"))]
+        () => unimplemented!(),
+    }
+}
+
+/// Stores `value` into `atomic`, and returns the previous value.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub fn swap64(atomic: &AtomicU64, value: u64) -> u64 {
+    match () {
+        #[cfg(target_arch = "aarch64")]
+        () => {
+            let ptr = atomic.as_ptr();
+            let old;
+            if has_lse() {
+                unsafe {
+                    core::arch::asm!(
+                        ".arch_extension lse",
+                        "swpal {value}, {old}, [{ptr}]",
+                        value = in(reg) value,
+                        old = out(reg) old,
+                        ptr = in(reg) ptr,
+                        options(nostack, preserves_flags)
+                    )
+                };
+            } else {
+                unsafe {
+                    core::arch::asm!(
+                        "2:",
+                        "ldaxr {old}, [{ptr}]",
+                        "stlxr {status:w}, {value}, [{ptr}]",
+                        "cbnz {status:w}, 2b",
+                        old = out(reg) old,
+                        status = out(reg) _,
+                        value = in(reg) value,
+                        ptr = in(reg) ptr,
+                        options(nostack, preserves_flags)
+                    )
+                };
+            }
+            old
+        }
+
+        #[cfg(not(target_arch = "aarch64"))]
+        () => unimplemented!(),
+    }
+}
+
+/// Adds `value` to `atomic`, wrapping around on overflow, and returns the previous value.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub fn fetch_add(atomic: &AtomicU64, value: u64) -> u64 {
+    match () {
+        #[cfg(target_arch = "aarch64")]
+        () => {
+            let ptr = atomic.as_ptr();
+            let old;
+            if has_lse() {
+                unsafe {
+                    core::arch::asm!(
+                        ".arch_extension lse",
+                        "ldaddal {value}, {old}, [{ptr}]",
+                        value = in(reg) value,
+                        old = out(reg) old,
+                        ptr = in(reg) ptr,
+                        options(nostack, preserves_flags)
+                    )
+                };
+            } else {
+                unsafe {
+                    core::arch::asm!(
+                        "2:",
+                        "ldaxr {old}, [{ptr}]",
+                        "add {sum}, {old}, {value}",
+                        "stlxr {status:w}, {sum}, [{ptr}]",
+                        "cbnz {status:w}, 2b",
+                        old = out(reg) old,
+                        sum = out(reg) _,
+                        status = out(reg) _,
+                        value = in(reg) value,
+                        ptr = in(reg) ptr,
+                        options(nostack, preserves_flags)
+                    )
+                };
+            }
+            old
+        }
+
+        #[cfg(not(target_arch = "aarch64"))]
+        () => unimplemented!(),
+    }
+}
@@ -6,6 +6,8 @@
 //   - Andre Richter <andre.o.richter@gmail.com>
 
 //! Barrier functions.
+//!
+//! Simulated by the `sim` module on other architectures.
 
 pub mod sealed {
     pub trait Dmb {
@@ -33,7 +35,7 @@ macro_rules! dmb_dsb {
                     }
 
                     #[cfg(not(target_arch = "aarch64"))]
-                    () => unimplemented!(),
+                    () => crate::sim::record(crate::sim::Event::Dmb),
                 }
             }
         }
@@ -47,7 +49,7 @@ macro_rules! dmb_dsb {
                     }
 
                     #[cfg(not(target_arch = "aarch64"))]
-                    () => unimplemented!(),
+                    () => crate::sim::record(crate::sim::Event::Dsb),
                 }
             }
         }
@@ -91,7 +93,7 @@ impl sealed::Isb for SY {
             }
 
             #[cfg(not(target_arch = "aarch64"))]
-            () => unimplemented!(),
+            () => crate::sim::record(crate::sim::Event::Isb),
         }
     }
 }
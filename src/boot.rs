@@ -0,0 +1,335 @@
+//! Initial translation tables, built before the MMU is on.
+//!
+//! Early boot code runs with flat addresses and without a frame allocator. [`BootPageTables`]
+//! builds identity and offset mappings in a statically provided pool of [`PageTable`]s, and
+//! returns the [`MmuConfig`] to pass to [`enable_mmu`](crate::translation::enable_mmu), or the
+//! raw register values for assembly boot code.
+//!
+//! Virtual addresses with bit 63 set are mapped by the table of the upper VA range
+//! (TTBR1_EL1), the others by the table of the lower VA range (TTBR0_EL1). Both cover the
+//! [`VA_BITS`](TranslationGranule::VA_BITS) bits of the granule.
+
+use crate::{
+    paging::{
+        granule::{TranslationGranule, PAGE_LEVEL},
+        memory_attribute::MairConfig,
+        page_table::{PageTableAttribute, PageTableFlags},
+        Granule4KiB, PageSize, PageTable, PhysFrame,
+    },
+    registers::*,
+    translation::{MmuConfig, MmuError, TcrBuilder, TtbrBuilder},
+    PhysAddr, VirtAddr,
+};
+use core::{mem::size_of, ops::Range};
+
+/// An error indicating that a range could not be mapped by [`BootPageTables`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMapError {
+    /// The addresses or the size are not aligned to the block size.
+    Unaligned,
+    /// Part of the range is already mapped.
+    AlreadyMapped,
+    /// The pool has no free table left.
+    OutOfTables,
+}
+
+/// The register values of the translation regime built by [`BootPageTables`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootRegisters {
+    /// The value of MAIR_EL1.
+    pub mair: u64,
+    /// The value of TCR_EL1.
+    pub tcr: u64,
+    /// The value of TTBR0_EL1, 0 if the lower VA range is unused.
+    pub ttbr0: u64,
+    /// The value of TTBR1_EL1, 0 if the upper VA range is unused.
+    pub ttbr1: u64,
+}
+
+/// A builder of translation tables allocated from a fixed pool, for use with the MMU off.
+///
+/// The physical address of a table is the address of its element in the pool, so the pool must
+/// be identity mapped, which it is while the MMU is off. Tables are zeroed when they are taken
+/// from the pool.
+///
+/// With the data cache off, the tables are written straight to memory. Lines of the pool that
+/// may be held by the caches from an earlier stage must be invalidated before building.
+pub struct BootPageTables<'a, G: TranslationGranule = Granule4KiB> {
+    pool: &'a mut [PageTable<G>],
+    used: usize,
+    roots: [Option<usize>; 2],
+}
+
+impl<'a, G: TranslationGranule> BootPageTables<'a, G> {
+    /// Creates a builder allocating its tables from `pool`.
+    pub fn new(pool: &'a mut [PageTable<G>]) -> Self {
+        Self {
+            pool,
+            used: 0,
+            roots: [None, None],
+        }
+    }
+
+    /// Returns the number of tables taken from the pool.
+    pub fn tables_used(&self) -> usize {
+        self.used
+    }
+
+    /// Maps `phys` at the same virtual addresses, with blocks or pages of size `S`.
+    ///
+    /// `flags` are added to the default block or page flags, e.g. the access permissions and
+    /// execute-never bits.
+    pub fn map_identity<S: PageSize<Granule = G>>(
+        &mut self,
+        phys: Range<PhysAddr>,
+        flags: PageTableFlags,
+        attr: impl Into<PageTableAttribute>,
+    ) -> Result<(), BootMapError> {
+        let virt = VirtAddr::new(phys.start.as_u64());
+        self.map::<S>(virt, phys, flags, attr)
+    }
+
+    /// Maps `phys` at the virtual addresses `offset` above, with blocks or pages of size `S`,
+    /// e.g. for a higher-half linear map.
+    ///
+    /// `flags` are added to the default block or page flags, e.g. the access permissions and
+    /// execute-never bits.
+    pub fn map_offset<S: PageSize<Granule = G>>(
+        &mut self,
+        offset: u64,
+        phys: Range<PhysAddr>,
+        flags: PageTableFlags,
+        attr: impl Into<PageTableAttribute>,
+    ) -> Result<(), BootMapError> {
+        let virt = VirtAddr::new(phys.start.as_u64().wrapping_add(offset));
+        self.map::<S>(virt, phys, flags, attr)
+    }
+
+    /// Maps `phys` at the virtual addresses starting at `virt`, with blocks or pages of size
+    /// `S`.
+    ///
+    /// `flags` are added to the default block or page flags, e.g. the access permissions and
+    /// execute-never bits.
+    pub fn map<S: PageSize<Granule = G>>(
+        &mut self,
+        virt: VirtAddr,
+        phys: Range<PhysAddr>,
+        flags: PageTableFlags,
+        attr: impl Into<PageTableAttribute>,
+    ) -> Result<(), BootMapError> {
+        let size = phys.end.as_u64().saturating_sub(phys.start.as_u64());
+        if !virt.is_aligned(S::SIZE)
+            || !phys.start.is_aligned(S::SIZE)
+            || !phys.end.is_aligned(S::SIZE)
+        {
+            return Err(BootMapError::Unaligned);
+        }
+        let (flags, attr) = if S::LEVEL == PAGE_LEVEL {
+            (flags | PageTableFlags::default_page(), attr.into())
+        } else {
+            (
+                (flags | PageTableFlags::default_block()) - PageTableFlags::TABLE_OR_PAGE,
+                attr.into(),
+            )
+        };
+        for offset in (0..size).step_by(S::SIZE as usize) {
+            let virt = VirtAddr::try_new_for::<G>(virt.as_u64() + offset)
+                .expect("invalid virtual address");
+            let table = self.leaf_table(virt, S::LEVEL)?;
+            let entry = &mut self.pool[table][G::table_index(virt, S::LEVEL)];
+            if !entry.is_unused() {
+                return Err(BootMapError::AlreadyMapped);
+            }
+            entry.set_addr_in::<G>(phys.start + offset, flags, attr);
+        }
+        Ok(())
+    }
+
+    /// Returns the physical address of the table of the lower VA range, if it is used.
+    pub fn ttbr0(&self) -> Option<PhysAddr> {
+        self.roots[0].map(|root| self.table_addr(root))
+    }
+
+    /// Returns the physical address of the table of the upper VA range, if it is used.
+    pub fn ttbr1(&self) -> Option<PhysAddr> {
+        self.roots[1].map(|root| self.table_addr(root))
+    }
+
+    /// Returns the configuration of the translation regime using the built tables, with the
+    /// memory attributes `mair` and the table walk settings of `tcr`.
+    ///
+    /// The VA ranges of the used tables are enabled in `tcr`, with the granule `G`.
+    pub fn mmu_config(&self, mair: MairConfig, tcr: TcrBuilder) -> MmuConfig {
+        let mut tcr = tcr;
+        if self.roots[0].is_some() {
+            tcr = tcr.ttbr0::<G>(G::VA_BITS as u8);
+        }
+        if self.roots[1].is_some() {
+            tcr = tcr.ttbr1::<G>(G::VA_BITS as u8);
+        }
+        let ttbr = |addr: PhysAddr| TtbrBuilder::new(PhysFrame::containing_address(addr));
+        let mut config = MmuConfig::new(mair, tcr);
+        if let Some(addr) = self.ttbr0() {
+            config = config.ttbr0(ttbr(addr));
+        }
+        if let Some(addr) = self.ttbr1() {
+            config = config.ttbr1(ttbr(addr));
+        }
+        config
+    }
+
+    /// Returns the register values of [`mmu_config`](Self::mmu_config), validated against the
+    /// features of the PE described by the value of ID_AA64MMFR0_EL1.
+    pub fn registers(
+        &self,
+        mair: MairConfig,
+        tcr: TcrBuilder,
+        mmfr0: u64,
+    ) -> Result<BootRegisters, MmuError> {
+        let (tcr, ttbr0, ttbr1) = self.mmu_config(mair, tcr).values(mmfr0)?;
+        Ok(BootRegisters {
+            mair: mair.value(),
+            tcr,
+            ttbr0,
+            ttbr1,
+        })
+    }
+
+    /// Like [`registers`](Self::registers), validated against the features of the current PE.
+    #[inline]
+    pub fn current_registers(
+        &self,
+        mair: MairConfig,
+        tcr: TcrBuilder,
+    ) -> Result<BootRegisters, MmuError> {
+        self.registers(mair, tcr, ID_AA64MMFR0_EL1.get())
+    }
+
+    fn table_addr(&self, table: usize) -> PhysAddr {
+        PhysAddr::new(&self.pool[table] as *const _ as u64)
+    }
+
+    fn alloc_table(&mut self) -> Result<usize, BootMapError> {
+        let table = self.used;
+        self.pool
+            .get_mut(table)
+            .ok_or(BootMapError::OutOfTables)?
+            .zero();
+        self.used += 1;
+        Ok(table)
+    }
+
+    /// Returns the table of lookup level `level` translating `virt`, creating the missing
+    /// tables on the way.
+    fn leaf_table(&mut self, virt: VirtAddr, level: usize) -> Result<usize, BootMapError> {
+        let range = (virt.as_u64() >> 63) as usize;
+        let mut table = match self.roots[range] {
+            Some(root) => root,
+            None => {
+                let root = self.alloc_table()?;
+                self.roots[range] = Some(root);
+                root
+            }
+        };
+        for level in G::START_LEVEL..level {
+            let index = G::table_index(virt, level);
+            let entry = self.pool[table][index];
+            table = if entry.is_unused() {
+                let next = self.alloc_table()?;
+                let addr = self.table_addr(next);
+                self.pool[table][index].set_addr_in::<G>(
+                    addr,
+                    PageTableFlags::default_table(),
+                    PageTableAttribute::new(0, 0, 0),
+                );
+                next
+            } else if entry.is_block() {
+                return Err(BootMapError::AlreadyMapped);
+            } else {
+                let base = self.table_addr(0).as_u64();
+                ((entry.addr_in::<G>().as_u64() - base) as usize) / size_of::<PageTable<G>>()
+            };
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        paging::{Size2MiB, Size4KiB},
+        translation::Shareability,
+    };
+
+    #[test]
+    pub fn test_boot_page_tables() {
+        let mut pool: [PageTable; 7] = [
+            PageTable::new(),
+            PageTable::new(),
+            PageTable::new(),
+            PageTable::new(),
+            PageTable::new(),
+            PageTable::new(),
+            PageTable::new(),
+        ];
+        let mut mair = MairConfig::new();
+        let normal = mair.add(0xff, Shareability::InnerShareable).unwrap();
+        let attr = normal.attr();
+        let mut tables = BootPageTables::new(&mut pool);
+
+        // identity map of 4MiB with 2MiB blocks: root, level 1 and level 2 tables
+        let ram = PhysAddr::new(0x4000_0000)..PhysAddr::new(0x4040_0000);
+        tables
+            .map_identity::<Size2MiB>(ram.clone(), PageTableFlags::empty(), attr)
+            .unwrap();
+        assert_eq!(tables.tables_used(), 3);
+        assert_eq!(
+            tables.map_identity::<Size2MiB>(ram.clone(), PageTableFlags::empty(), attr),
+            Err(BootMapError::AlreadyMapped)
+        );
+        assert_eq!(
+            tables.map_identity::<Size2MiB>(
+                PhysAddr::new(0x1000)..PhysAddr::new(0x20_1000),
+                PageTableFlags::empty(),
+                attr
+            ),
+            Err(BootMapError::Unaligned)
+        );
+
+        // higher-half map with pages: four more tables
+        let offset = 0xffff_0000_0000_0000;
+        let first = PhysAddr::new(0x4000_0000)..PhysAddr::new(0x4000_2000);
+        tables
+            .map_offset::<Size4KiB>(offset, first, PageTableFlags::PXN, attr)
+            .unwrap();
+        assert_eq!(tables.tables_used(), 7);
+        let second = PhysAddr::new(0x4020_0000)..PhysAddr::new(0x4020_1000);
+        assert_eq!(
+            tables.map_offset::<Size4KiB>(offset, second, PageTableFlags::PXN, attr),
+            Err(BootMapError::OutOfTables)
+        );
+
+        let ttbr0 = tables.ttbr0().unwrap();
+        let ttbr1 = tables.ttbr1().unwrap();
+        let mmfr0 = (0b1111 << 24) | 0b0101;
+        let registers = tables.registers(mair, TcrBuilder::new(), mmfr0).unwrap();
+        assert_eq!(registers.mair, 0xff);
+        assert_eq!(
+            (registers.ttbr0, registers.ttbr1),
+            (ttbr0.as_u64(), ttbr1.as_u64())
+        );
+        assert_eq!(registers.tcr & 0x3f, 64 - 48);
+
+        // the last level entries
+        let l2 = &pool[2];
+        assert!(l2[0].is_block() && l2[1].is_block());
+        assert_eq!(l2[1].addr(), PhysAddr::new(0x4020_0000));
+        assert!(l2[0].flags().contains(PageTableFlags::AF));
+        let l3 = &pool[6];
+        assert!(!l3[1].is_block());
+        assert_eq!(l3[1].addr(), PhysAddr::new(0x4000_1000));
+        assert!(l3[1].flags().contains(PageTableFlags::PXN));
+        assert!(l3[2].is_unused());
+    }
+}
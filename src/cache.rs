@@ -1,8 +1,10 @@
 use crate::{
     barrier::{dsb, isb, sealed},
+    paging::{Page, PageSize},
     registers::*,
 };
 use core::marker::PhantomData;
+use tock_registers::LocalRegisterCopy;
 
 pub use crate::barrier::{ISH, NSH, SY};
 
@@ -39,18 +41,92 @@ pub trait Cache {
     /// Cache line size in bytes
     fn cache_line_size() -> u64;
 
-    /// Flush cache for the VA interval [start, end) in the shareability domain.
-    fn flush_range<A: sealed::Dsb>(start: usize, end: usize, domain: A) {
+    /// Whether the operation is not required for coherence between the
+    /// instruction and data caches, according to CTR_EL0.IDC or CTR_EL0.DIC.
+    ///
+    /// Callers maintaining the caches for another reason, e.g. cleaning page
+    /// tables for a non-coherent table walker, should use the `force_`
+    /// variants.
+    fn is_redundant() -> bool {
+        false
+    }
+
+    /// Issue the flush operations for the cache lines of the VA interval
+    /// [start, end), without waiting for their completion.
+    ///
+    /// The operations are skipped if they are redundant, see
+    /// [`Cache::is_redundant`]. They are only guaranteed to be complete after
+    /// a DSB, e.g. the one of [`CacheFlushBatch::commit`].
+    fn flush_lines(start: usize, end: usize) {
+        if !Self::is_redundant() {
+            Self::force_flush_lines(start, end);
+        }
+    }
+
+    /// Like [`Cache::flush_lines`], but issues the operations even if they are
+    /// redundant.
+    fn force_flush_lines(start: usize, end: usize) {
         let line_size = 4 << Self::cache_line_size();
         let mut addr = start & !(line_size - 1);
         while addr < end {
             Self::flush_line_op(addr);
             addr += line_size;
         }
+    }
+
+    /// Flush cache for the VA interval [start, end) in the shareability domain.
+    ///
+    /// Redundant operations are skipped, but the barriers are still issued.
+    fn flush_range<A: sealed::Dsb>(start: usize, end: usize, domain: A) {
+        Self::flush_lines(start, end);
+        unsafe { dsb(domain) };
+        unsafe { isb() };
+    }
+
+    /// Like [`Cache::flush_range`], but issues the operations even if they are
+    /// redundant.
+    fn force_flush_range<A: sealed::Dsb>(start: usize, end: usize, domain: A) {
+        Self::force_flush_lines(start, end);
         unsafe { dsb(domain) };
         unsafe { isb() };
     }
 
+    /// Flush cache for the VA interval [start, end) like [`Cache::flush_range`],
+    /// but at most `lines_per_chunk` lines at a time, calling `yield_now`
+    /// between chunks.
+    ///
+    /// Each chunk is completed with a DSB before `yield_now` is called, so the
+    /// caller can take pending interrupts or reschedule there to keep the
+    /// latency of flushing large ranges bounded.
+    fn flush_range_chunked<A: sealed::Dsb, F: FnMut()>(
+        start: usize,
+        end: usize,
+        domain: A,
+        lines_per_chunk: usize,
+        mut yield_now: F,
+    ) {
+        assert!(lines_per_chunk != 0, "chunk size must not be zero");
+        if Self::is_redundant() {
+            Self::flush_range(start, end, domain);
+            return;
+        }
+        let line_size = 4 << Self::cache_line_size();
+        let mut addr = start & !(line_size - 1);
+        while addr < end {
+            let mut lines = 0;
+            while addr < end && lines < lines_per_chunk {
+                Self::flush_line_op(addr);
+                addr += line_size;
+                lines += 1;
+            }
+            unsafe { sealed::Dsb::__dsb(&domain) };
+            if addr < end {
+                yield_now();
+            }
+        }
+        unsafe { isb() };
+    }
+
     /// Flush cache for the VA interval [start, start + sizeend) in the
     /// shareability domain.
     fn flush_area<A: sealed::Dsb>(start: usize, size: usize, domain: A) {
@@ -58,6 +134,41 @@ pub trait Cache {
     }
 }
 
+/// A batch of cache flushes completed by a single DSB and ISB.
+///
+/// Flushing many small, disjoint buffers with [`Cache::flush_range`] pays for
+/// the barriers once per buffer. A batch issues the per-line operations of
+/// each range as it is added, for any combination of caches, and only waits
+/// for all of them in [`commit`](CacheFlushBatch::commit).
+#[must_use = "the flushes are only complete after `commit`"]
+pub struct CacheFlushBatch<A: sealed::Dsb> {
+    domain: A,
+}
+
+impl<A: sealed::Dsb> CacheFlushBatch<A> {
+    /// Creates an empty batch completed in the shareability domain.
+    pub fn new(domain: A) -> Self {
+        Self { domain }
+    }
+
+    /// Adds the VA interval [start, end) flushed in the cache `C`.
+    pub fn add_range<C: Cache>(&mut self, start: usize, end: usize) -> &mut Self {
+        C::flush_lines(start, end);
+        self
+    }
+
+    /// Adds the VA interval [start, start + size) flushed in the cache `C`.
+    pub fn add_area<C: Cache>(&mut self, start: usize, size: usize) -> &mut Self {
+        self.add_range::<C>(start, start + size)
+    }
+
+    /// Waits for the completion of all flushes of the batch.
+    pub fn commit(self) {
+        unsafe { dsb(self.domain) };
+        unsafe { isb() };
+    }
+}
+
 pub struct ICache<F: Flush = Invalidate, P: CoherencyPoint = PoU> {
     _f: PhantomData<F>,
     _p: PhantomData<P>,
@@ -126,6 +237,13 @@ macro_rules! cache_line_size {
 
 macro_rules! define_cache_op {
     ($cache:ident, $flush:ident, $point:ident) => {
+        define_cache_op!(@impl $cache, $flush, $point, false);
+    };
+    // the operation is redundant if the CTR_EL0 bit is set
+    ($cache:ident, $flush:ident, $point:ident, $redundant_if:ident) => {
+        define_cache_op!(@impl $cache, $flush, $point, CTR_EL0.is_set(CTR_EL0::$redundant_if));
+    };
+    (@impl $cache:ident, $flush:ident, $point:ident, $redundant:expr) => {
         impl Cache for $cache<$flush, $point> {
             #[inline]
             fn flush_line_op(vaddr: usize) {
@@ -148,16 +266,127 @@ macro_rules! define_cache_op {
             fn cache_line_size() -> u64 {
                 CTR_EL0.read(cache_line_size!($cache))
             }
+            #[inline]
+            fn is_redundant() -> bool {
+                $redundant
+            }
+        }
+    };
+}
+
+/// Calls `op` with the set/way operand of every line of the data or unified
+/// cache at `level` (0 for level 1), whose geometry is given by `geometry`.
+fn for_each_set_way<F: FnMut(u64)>(level: u64, geometry: CacheGeometry, mut op: F) {
+    let line_shift = geometry.line_size.trailing_zeros() as u64;
+    let (ways, sets) = (geometry.ways as u64, geometry.sets as u64);
+    // the way is stored in the top log2(ways) bits of the lower 32 bits
+    let way_shift = ((ways - 1) as u32).leading_zeros();
+    for way in 0..ways {
+        for set in 0..sets {
+            op(way << way_shift | set << line_shift | level << 1);
+        }
+    }
+}
+
+/// Calls `op` with the set/way operand of every line of the data and unified
+/// caches of the levels below `levels`, then waits for their completion.
+///
+/// After every `lines_per_chunk` lines but the last, waits for their
+/// completion and calls `yield_now`.
+fn flush_sets_ways<F: FnMut(u64), Y: FnMut()>(
+    levels: u64,
+    lines_per_chunk: usize,
+    mut op: F,
+    mut yield_now: Y,
+) {
+    assert!(lines_per_chunk != 0, "chunk size must not be zero");
+    let clidr = CLIDR_EL1.get();
+    let ccidx = ID_AA64MMFR2_EL1.read(ID_AA64MMFR2_EL1::CCIDX) != 0;
+    let mut lines = 0;
+    unsafe { dsb(SY) };
+    for level in 0..levels {
+        // no cache or instruction cache only
+        if (clidr >> (3 * level)) & 0b111 < 0b010 {
+            continue;
+        }
+        CSSELR_EL1.write(CSSELR_EL1::Level.val(level) + CSSELR_EL1::InD::Data);
+        unsafe { isb() };
+        let geometry = CacheGeometry::from_ccsidr(CCSIDR_EL1.get(), ccidx);
+        for_each_set_way(level, geometry, |set_way| {
+            if lines == lines_per_chunk {
+                unsafe { dsb(SY) };
+                yield_now();
+                lines = 0;
+            }
+            op(set_way);
+            lines += 1;
+        });
+    }
+    unsafe { dsb(SY) };
+    unsafe { isb() };
+}
+
+macro_rules! define_set_way_op {
+    ($flush:ident, $point:ident, $levels:ident, $local_levels:ident) => {
+        impl DCache<$flush, $point> {
+            /// Flush the whole data and unified caches by set/way, up to the
+            /// coherency point, e.g. before powering down the PE or turning on
+            /// the MMU.
+            ///
+            /// Set/way operations only affect the caches of the current PE and
+            /// are not broadcast. Other PEs can allocate lines again at any
+            /// time.
+            #[inline]
+            pub fn flush_all_sets_ways() {
+                Self::flush_all_sets_ways_chunked(usize::MAX, || {});
+            }
+
+            /// Like [`flush_all_sets_ways`](Self::flush_all_sets_ways), but at
+            /// most `lines_per_chunk` lines at a time, calling `yield_now`
+            /// between chunks.
+            ///
+            /// Each chunk is completed with a DSB before `yield_now` is called,
+            /// so the caller can take pending interrupts there. Lines allocated
+            /// meanwhile in the sets already flushed are not flushed again.
+            #[inline]
+            pub fn flush_all_sets_ways_chunked<F: FnMut()>(lines_per_chunk: usize, yield_now: F) {
+                let levels = CLIDR_EL1.read(CLIDR_EL1::$levels);
+                flush_sets_ways(levels, lines_per_chunk, Self::set_way_op, yield_now);
+            }
+
+            /// Like [`flush_all_sets_ways`](Self::flush_all_sets_ways), but only
+            /// for the levels that are private to the current PE.
+            #[inline]
+            pub fn local_flush_all_sets_ways() {
+                let levels = CLIDR_EL1.read(CLIDR_EL1::$local_levels);
+                flush_sets_ways(levels, usize::MAX, Self::set_way_op, || {});
+            }
+
+            #[inline]
+            fn set_way_op(set_way: u64) {
+                unsafe {
+                    core::arch::asm!(
+                        concat!("dc ", cache_op!($flush), "sw, {set_way}"),
+                        set_way = in(reg) set_way,
+                        options(nostack)
+                    )
+                }
+            }
         }
     };
 }
 
-define_cache_op!(ICache, Invalidate, PoU);
-define_cache_op!(DCache, Clean, PoU);
+define_cache_op!(ICache, Invalidate, PoU, DIC);
+define_cache_op!(DCache, Clean, PoU, IDC);
 define_cache_op!(DCache, Clean, PoC);
 define_cache_op!(DCache, Invalidate, PoC);
 define_cache_op!(DCache, CleanAndInvalidate, PoC);
 
+define_set_way_op!(Clean, PoU, LoUU, LoUIS);
+define_set_way_op!(Clean, PoC, LoC, LoUIS);
+define_set_way_op!(Invalidate, PoC, LoC, LoUIS);
+define_set_way_op!(CleanAndInvalidate, PoC, LoC, LoUIS);
+
 /// Level 1 instruction cache policy
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum L1ICachePolicy {
@@ -177,3 +406,330 @@ pub fn get_l1_icache_policy() -> L1ICachePolicy {
         _ => Unsupport,
     }
 }
+
+/// The instruction cache maintenance needed to make new code visible to instruction fetches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ICacheSync {
+    /// None, the instruction cache is coherent with the data cache (CTR_EL0.DIC).
+    None,
+    /// Invalidation of the lines of the range, for PIPT and VIPT instruction caches.
+    Lines,
+    /// Invalidation of the whole instruction cache, for the other policies.
+    All,
+}
+
+/// Returns whether the data cache must be cleaned to the PoU, and how the instruction cache
+/// must be invalidated, to synchronize them according to the value of CTR_EL0.
+fn icache_sync_ops(ctr: u64) -> (bool, ICacheSync) {
+    let ctr = LocalRegisterCopy::<u64, CTR_EL0::Register>::new(ctr);
+    let clean = !ctr.is_set(CTR_EL0::IDC);
+    let invalidate = if ctr.is_set(CTR_EL0::DIC) {
+        ICacheSync::None
+    } else {
+        match ctr.read_as_enum(CTR_EL0::L1Ip) {
+            Some(CTR_EL0::L1Ip::Value::PIPT) | Some(CTR_EL0::L1Ip::Value::VIPT) => {
+                ICacheSync::Lines
+            }
+            _ => ICacheSync::All,
+        }
+    };
+    (clean, invalidate)
+}
+
+/// Makes the instructions written to the VA interval [start, start + len) visible to the
+/// instruction fetches of all PEs in the inner shareable domain, e.g. after loading a module or
+/// emitting JIT code.
+///
+/// Cleans the data cache to the PoU and invalidates the instruction cache over the range, with
+/// the barriers in between, skipping the steps that CTR_EL0.IDC and CTR_EL0.DIC report as
+/// unnecessary. Other PEs must still execute an ISB, or take an exception, before running the
+/// new code.
+#[inline]
+pub fn sync_icache_range(start: usize, len: usize) {
+    let end = start + len;
+    let (clean, invalidate) = icache_sync_ops(CTR_EL0.get());
+    if clean {
+        DCache::<Clean, PoU>::force_flush_lines(start, end);
+    }
+    unsafe { dsb(ISH) };
+    match invalidate {
+        ICacheSync::None => unsafe { isb() },
+        ICacheSync::Lines => ICache::force_flush_range(start, end, ISH),
+        ICacheSync::All => ICache::flush_all(),
+    }
+}
+
+/// Returns the size in bytes of the blocks zeroed by DC ZVA, or `None` if the instruction is
+/// prohibited (DCZID_EL0).
+#[inline]
+pub fn dc_zva_block_size() -> Option<usize> {
+    let dczid = DCZID_EL0.extract();
+    if dczid.is_set(DCZID_EL0::DZP) {
+        None
+    } else {
+        Some(4 << dczid.read(DCZID_EL0::BS))
+    }
+}
+
+/// Splits the interval [start, end) at the first and last `block`-aligned addresses in it, or
+/// returns `(end, end)` if it contains no whole block.
+fn split_blocks(start: usize, end: usize, block: usize) -> (usize, usize) {
+    let first = (start + block - 1) & !(block - 1);
+    let last = end & !(block - 1);
+    if first < last {
+        (first, last)
+    } else {
+        (end, end)
+    }
+}
+
+/// Zeroes the `len` bytes at `start`, with DC ZVA for the whole blocks in the range, and with
+/// stores for the rest or if DC ZVA is prohibited.
+///
+/// # Safety
+///
+/// The range must be mapped as writable Normal memory: DC ZVA raises an alignment fault on
+/// Device memory.
+#[inline]
+pub unsafe fn zero_range_dczva(start: usize, len: usize) {
+    let end = start + len;
+    let block = dc_zva_block_size();
+    let (first, last) = match block {
+        Some(block) => split_blocks(start, end, block),
+        None => (end, end),
+    };
+    core::ptr::write_bytes(start as *mut u8, 0, first - start);
+    if let Some(block) = block {
+        for addr in (first..last).step_by(block) {
+            core::arch::asm!("dc zva, {addr}", addr = in(reg) addr, options(nostack));
+        }
+    }
+    core::ptr::write_bytes(last as *mut u8, 0, end - last);
+}
+
+/// Zeroes a page with DC ZVA, e.g. before a frame allocator hands it out.
+///
+/// # Safety
+///
+/// The page must be mapped as writable Normal memory.
+#[inline]
+pub unsafe fn zero_page_dczva<S: PageSize>(page: Page<S>) {
+    zero_range_dczva(page.start_address().as_u64() as usize, S::SIZE as usize);
+}
+
+/// The type of a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    Instruction,
+    Data,
+    Unified,
+}
+
+/// The geometry of a cache, as reported by CCSIDR_EL1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheGeometry {
+    /// Number of sets.
+    pub sets: u32,
+    /// Associativity (number of ways).
+    pub ways: u32,
+    /// Line size in bytes.
+    pub line_size: u32,
+}
+
+impl CacheGeometry {
+    /// Decodes the value of CCSIDR_EL1, in the 64-bit format of FEAT_CCIDX if `ccidx` is set.
+    pub const fn from_ccsidr(ccsidr: u64, ccidx: bool) -> Self {
+        let (sets, ways) = if ccidx {
+            ((ccsidr >> 32) & 0xff_ffff, (ccsidr >> 3) & 0x1f_ffff)
+        } else {
+            ((ccsidr >> 13) & 0x7fff, (ccsidr >> 3) & 0x3ff)
+        };
+        CacheGeometry {
+            sets: sets as u32 + 1,
+            ways: ways as u32 + 1,
+            line_size: 16 << (ccsidr & 0b111),
+        }
+    }
+
+    /// Returns the size of the cache in bytes.
+    pub const fn size(&self) -> u64 {
+        self.sets as u64 * self.ways as u64 * self.line_size as u64
+    }
+}
+
+/// A cache of the hierarchy, returned by [`topology`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheLevelInfo {
+    /// The cache level, from 1.
+    pub level: u8,
+    /// The type of the cache.
+    pub kind: CacheType,
+    /// The geometry of the cache.
+    pub geometry: CacheGeometry,
+}
+
+/// An iterator over the caches of the current PE, returned by [`topology`].
+#[derive(Debug, Clone)]
+pub struct CacheTopology {
+    clidr: u64,
+    ccidx: bool,
+    level: u8,
+    data_pending: bool,
+}
+
+/// Returns the caches of the current PE, from level 1 outwards.
+///
+/// The levels with separate instruction and data caches yield the instruction cache first.
+/// Each geometry is read by selecting the cache in CSSELR_EL1, so the iterator must not be
+/// interleaved with other users of CSSELR_EL1 on the same PE.
+pub fn topology() -> CacheTopology {
+    CacheTopology {
+        clidr: CLIDR_EL1.get(),
+        ccidx: ID_AA64MMFR2_EL1.read(ID_AA64MMFR2_EL1::CCIDX) != 0,
+        level: 0,
+        data_pending: false,
+    }
+}
+
+/// Returns the caches implemented at `level` (0 for level 1) according to CLIDR_EL1, as an
+/// instruction and a data or unified cache.
+fn cache_types(clidr: u64, level: u8) -> (Option<CacheType>, Option<CacheType>) {
+    match (clidr >> (3 * level)) & 0b111 {
+        0b001 => (Some(CacheType::Instruction), None),
+        0b010 => (None, Some(CacheType::Data)),
+        0b011 => (Some(CacheType::Instruction), Some(CacheType::Data)),
+        0b100 => (None, Some(CacheType::Unified)),
+        _ => (None, None),
+    }
+}
+
+impl CacheTopology {
+    fn info(&self, kind: CacheType) -> CacheLevelInfo {
+        let ind = match kind {
+            CacheType::Instruction => CSSELR_EL1::InD::Instruction,
+            _ => CSSELR_EL1::InD::Data,
+        };
+        CSSELR_EL1.write(CSSELR_EL1::Level.val(self.level as u64) + ind);
+        unsafe { isb() };
+        CacheLevelInfo {
+            level: self.level + 1,
+            kind,
+            geometry: CacheGeometry::from_ccsidr(CCSIDR_EL1.get(), self.ccidx),
+        }
+    }
+}
+
+impl Iterator for CacheTopology {
+    type Item = CacheLevelInfo;
+
+    fn next(&mut self) -> Option<CacheLevelInfo> {
+        while self.level < 7 {
+            let (instruction, data) = cache_types(self.clidr, self.level);
+            if !self.data_pending {
+                if let Some(kind) = instruction {
+                    self.data_pending = true;
+                    return Some(self.info(kind));
+                }
+            }
+            self.data_pending = false;
+            let info = data.map(|kind| self.info(kind));
+            if instruction.is_none() && data.is_none() {
+                // no cache at this level and above
+                self.level = 7;
+                return None;
+            }
+            self.level += 1;
+            if info.is_some() {
+                return info;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_for_each_set_way() {
+        // 32KiB, 4 ways, 128 sets of 64 bytes
+        let geometry = CacheGeometry::from_ccsidr(127 << 13 | 3 << 3 | 2, false);
+        let (mut count, mut last) = (0, 0);
+        for_each_set_way(1, geometry, |set_way| {
+            count += 1;
+            last = set_way;
+        });
+        assert_eq!(count, 512);
+        assert_eq!(last, 3 << 30 | 127 << 6 | 1 << 1);
+
+        let mut count = 0;
+        for_each_set_way(0, CacheGeometry::from_ccsidr(0, false), |set_way| {
+            assert_eq!(set_way, 0);
+            count += 1;
+        });
+        assert_eq!(count, 1);
+
+        // 16 ways of 32768 sets, beyond the 32-bit format
+        let geometry = CacheGeometry::from_ccsidr(32767 << 32 | 15 << 3 | 2, true);
+        let (mut count, mut last) = (0, 0);
+        for_each_set_way(2, geometry, |set_way| {
+            count += 1;
+            last = set_way;
+        });
+        assert_eq!(count, 16 * 32768);
+        assert_eq!(last, 15 << 28 | 32767 << 6 | 2 << 1);
+    }
+
+    #[test]
+    pub fn test_cache_geometry() {
+        // 32KiB, 4 ways, 128 sets of 64 bytes
+        let geometry = CacheGeometry::from_ccsidr(127 << 13 | 3 << 3 | 2, false);
+        assert_eq!(
+            geometry,
+            CacheGeometry {
+                sets: 128,
+                ways: 4,
+                line_size: 64
+            }
+        );
+        assert_eq!(geometry.size(), 32 * 1024);
+
+        // 1MiB, 16 ways, 1024 sets of 64 bytes, FEAT_CCIDX format
+        let geometry = CacheGeometry::from_ccsidr(1023 << 32 | 15 << 3 | 2, true);
+        assert_eq!((geometry.sets, geometry.ways), (1024, 16));
+        assert_eq!(geometry.size(), 1024 * 1024);
+
+        // L1 I+D, L2 unified
+        let clidr = 0b100 << 3 | 0b011;
+        assert_eq!(
+            cache_types(clidr, 0),
+            (Some(CacheType::Instruction), Some(CacheType::Data))
+        );
+        assert_eq!(cache_types(clidr, 1), (None, Some(CacheType::Unified)));
+        assert_eq!(cache_types(clidr, 2), (None, None));
+    }
+
+    #[test]
+    pub fn test_split_blocks() {
+        assert_eq!(split_blocks(0x1000, 0x2000, 64), (0x1000, 0x2000));
+        assert_eq!(split_blocks(0x1010, 0x1130, 64), (0x1040, 0x1100));
+        assert_eq!(split_blocks(0x1010, 0x1070, 64), (0x1070, 0x1070));
+        assert_eq!(split_blocks(0x1000, 0x1000, 64), (0x1000, 0x1000));
+    }
+
+    #[test]
+    pub fn test_icache_sync_ops() {
+        // VIPT, no IDC or DIC
+        assert_eq!(icache_sync_ops(0b10 << 14), (true, ICacheSync::Lines));
+        // PIPT with IDC
+        assert_eq!(
+            icache_sync_ops(1 << 28 | 0b11 << 14),
+            (false, ICacheSync::Lines)
+        );
+        // VPIPT
+        assert_eq!(icache_sync_ops(0), (true, ICacheSync::All));
+        // IDC and DIC
+        assert_eq!(icache_sync_ops(3 << 28), (false, ICacheSync::None));
+    }
+}
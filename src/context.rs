@@ -0,0 +1,279 @@
+//! The register frame saved on exception entry, and exception return.
+//!
+//! [`ExceptionContext`] is the usual 34-register trap frame: the general-purpose registers, the
+//! stack pointer of the interrupted context, ELR_EL1 and SPSR_EL1. The exception vectors save it
+//! on entry and restore it before ERET, so a handler can inspect or redirect the interrupted
+//! context by modifying it. [`ExceptionContext::prepare_eret_to`] builds the frame of a context
+//! that has never run, e.g. the first entry of a thread into userspace.
+
+use crate::{interrupts::DaifMask, registers::*, VirtAddr};
+use core::fmt;
+
+/// The exception level and stack pointer selected by SPSR.M[3:0] for AArch64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExceptionMode {
+    /// EL0, with SP_EL0.
+    El0t = 0b0000,
+    /// EL1, with SP_EL0.
+    El1t = 0b0100,
+    /// EL1, with SP_EL1.
+    El1h = 0b0101,
+    /// EL2, with SP_EL0.
+    El2t = 0b1000,
+    /// EL2, with SP_EL2.
+    El2h = 0b1001,
+}
+
+impl ExceptionMode {
+    /// Decodes SPSR.M[3:0], or returns `None` for EL3 and the reserved values.
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        match bits & 0xf {
+            0b0000 => Some(ExceptionMode::El0t),
+            0b0100 => Some(ExceptionMode::El1t),
+            0b0101 => Some(ExceptionMode::El1h),
+            0b1000 => Some(ExceptionMode::El2t),
+            0b1001 => Some(ExceptionMode::El2h),
+            _ => None,
+        }
+    }
+
+    /// Returns the exception level.
+    pub const fn el(self) -> u8 {
+        self as u8 >> 2
+    }
+}
+
+/// A saved program status, as in SPSR_EL1 for an exception taken from AArch64.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Spsr(u64);
+
+impl Spsr {
+    const M_MASK: u64 = 0b1111;
+    /// M[4], set for an exception taken from AArch32.
+    const AARCH32: u64 = 1 << 4;
+    const SS: u64 = 1 << 21;
+
+    /// Creates a value for a return to `mode`, with all the exceptions unmasked.
+    pub const fn new(mode: ExceptionMode) -> Self {
+        Spsr(mode as u64)
+    }
+
+    /// Creates a value from the raw bits.
+    pub const fn from_bits(value: u64) -> Self {
+        Spsr(value)
+    }
+
+    /// Returns the raw value.
+    pub const fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the mode, or `None` for AArch32 and the reserved modes.
+    pub const fn mode(&self) -> Option<ExceptionMode> {
+        if self.0 & Self::AARCH32 != 0 {
+            return None;
+        }
+        ExceptionMode::from_bits(self.0 as u8)
+    }
+
+    /// Returns the value with the mode replaced by `mode`.
+    pub const fn with_mode(self, mode: ExceptionMode) -> Self {
+        Spsr(self.0 & !(Self::M_MASK | Self::AARCH32) | mode as u64)
+    }
+
+    /// Returns the exceptions masked on return.
+    pub const fn daif(&self) -> DaifMask {
+        DaifMask::from_bits_truncate(self.0)
+    }
+
+    /// Returns the value with the masked exceptions replaced by `daif`.
+    pub const fn with_daif(self, daif: DaifMask) -> Self {
+        Spsr(self.0 & !DaifMask::all().bits() | daif.bits())
+    }
+
+    /// Returns whether software step is active on return (SS).
+    pub const fn single_step(&self) -> bool {
+        self.0 & Self::SS != 0
+    }
+
+    /// Returns the value with SS set to `enabled`.
+    pub const fn with_single_step(self, enabled: bool) -> Self {
+        if enabled {
+            Spsr(self.0 | Self::SS)
+        } else {
+            Spsr(self.0 & !Self::SS)
+        }
+    }
+}
+
+impl fmt::Debug for Spsr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Spsr")
+            .field("value", &format_args!("{:#x}", self.0))
+            .field("mode", &self.mode())
+            .field("daif", &self.daif())
+            .field("single_step", &self.single_step())
+            .finish()
+    }
+}
+
+/// The registers of an interrupted context, as saved by the exception vectors.
+///
+/// The layout is fixed: X0 to X30 at offsets 0 to 240, then SP, ELR_EL1 and SPSR_EL1.
+#[derive(Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct ExceptionContext {
+    /// The general-purpose registers X0 to X30.
+    pub x: [u64; 31],
+    /// The stack pointer of the interrupted context: SP_EL0 for an exception from EL0.
+    pub sp: u64,
+    /// The return address, ELR_EL1.
+    pub elr: u64,
+    /// The saved program status, SPSR_EL1.
+    pub spsr: u64,
+}
+
+impl ExceptionContext {
+    /// Creates a frame with all the registers zero.
+    pub const fn zeroed() -> Self {
+        ExceptionContext {
+            x: [0; 31],
+            sp: 0,
+            elr: 0,
+            spsr: 0,
+        }
+    }
+
+    /// Creates the frame of a context that starts running at `el0_entry` in EL0, with the stack
+    /// pointer `sp` and the program status `pstate`.
+    ///
+    /// The general-purpose registers are zero, so that no kernel value leaks into the new
+    /// context; arguments can be passed by setting them afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mode of `pstate` is not EL0t: returning to EL1 with a frame built for
+    /// userspace would run user-controlled code with kernel privileges.
+    pub fn prepare_eret_to(el0_entry: VirtAddr, sp: VirtAddr, pstate: Spsr) -> Self {
+        assert_eq!(
+            pstate.mode(),
+            Some(ExceptionMode::El0t),
+            "prepare_eret_to must return to EL0"
+        );
+        ExceptionContext {
+            sp: sp.as_u64(),
+            elr: el0_entry.as_u64(),
+            spsr: pstate.value(),
+            ..Self::zeroed()
+        }
+    }
+
+    /// Returns the return address.
+    pub fn elr(&self) -> VirtAddr {
+        VirtAddr::new_unchecked(self.elr)
+    }
+
+    /// Sets the return address, e.g. to skip the faulting instruction.
+    pub fn set_elr(&mut self, elr: VirtAddr) {
+        self.elr = elr.as_u64();
+    }
+
+    /// Returns the saved program status.
+    pub const fn spsr(&self) -> Spsr {
+        Spsr(self.spsr)
+    }
+
+    /// Sets the saved program status.
+    pub fn set_spsr(&mut self, spsr: Spsr) {
+        self.spsr = spsr.value();
+    }
+
+    /// Returns whether the interrupted context runs at EL0.
+    pub const fn is_user(&self) -> bool {
+        matches!(self.spsr().mode(), Some(ExceptionMode::El0t))
+    }
+
+    /// Copies ELR_EL1 and SPSR_EL1 into the frame.
+    ///
+    /// For handlers entered with the registers still holding the values of this exception.
+    #[inline]
+    pub fn save_elr_spsr(&mut self) {
+        self.elr = ELR_EL1.get();
+        self.spsr = SPSR_EL1.get();
+    }
+
+    /// Loads ELR_EL1 and SPSR_EL1 from the frame, for the next ERET.
+    ///
+    /// # Safety
+    ///
+    /// The ERET goes to the loaded address, at the loaded exception level: the frame must
+    /// describe a valid context, and no exception may be taken before the ERET.
+    #[inline]
+    pub unsafe fn restore_elr_spsr(&self) {
+        ELR_EL1.set(self.elr);
+        SPSR_EL1.set(self.spsr);
+    }
+}
+
+impl Default for ExceptionContext {
+    fn default() -> Self {
+        Self::zeroed()
+    }
+}
+
+impl fmt::Debug for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExceptionContext")
+            .field("elr", &format_args!("{:#x}", self.elr))
+            .field("sp", &format_args!("{:#x}", self.sp))
+            .field("spsr", &self.spsr())
+            .field("x", &self.x)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_exception_context() {
+        assert_eq!(core::mem::size_of::<ExceptionContext>(), 34 * 8);
+
+        let pstate = Spsr::new(ExceptionMode::El0t).with_daif(DaifMask::DEBUG);
+        let mut ctx = ExceptionContext::prepare_eret_to(
+            VirtAddr::new(0x40_0000),
+            VirtAddr::new(0x7fff_f000),
+            pstate,
+        );
+        assert!(ctx.is_user());
+        assert_eq!(ctx.elr(), VirtAddr::new(0x40_0000));
+        assert_eq!(ctx.sp, 0x7fff_f000);
+        assert_eq!(ctx.spsr, 0x200);
+        assert!(ctx.x.iter().all(|&x| x == 0));
+
+        ctx.set_spsr(ctx.spsr().with_single_step(true));
+        assert_eq!(ctx.spsr, 0x20_0200);
+        assert!(ctx.spsr().single_step());
+        assert_eq!(ctx.spsr().daif(), DaifMask::DEBUG);
+
+        // EL1h with DAIF masked, as on exception entry
+        let el1 = Spsr::from_bits(0x3c5);
+        assert_eq!(el1.mode(), Some(ExceptionMode::El1h));
+        assert_eq!(el1.mode().map(ExceptionMode::el), Some(1));
+        assert_eq!(el1.daif(), DaifMask::all());
+        assert_eq!(el1.with_mode(ExceptionMode::El0t).value(), 0x3c0);
+        assert_eq!(Spsr::from_bits(0x10).mode(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn test_prepare_eret_to_el1() {
+        ExceptionContext::prepare_eret_to(
+            VirtAddr::new(0),
+            VirtAddr::new(0),
+            Spsr::new(ExceptionMode::El1h),
+        );
+    }
+}
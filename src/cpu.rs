@@ -0,0 +1,185 @@
+//! Identification of the cores of a multiprocessor system.
+//!
+//! The MPIDR_EL1 register identifies a core by up to four affinity levels. Their meaning depends
+//! on the implementation, e.g. Aff0 is the core within a cluster and Aff1 the cluster, or, on
+//! multi-threaded cores ([`Affinity::mt`]), Aff0 is the thread, Aff1 the core and Aff2 the cluster.
+//! [`Topology`] turns an affinity into a dense index, e.g. for per-CPU arrays.
+
+use crate::registers::*;
+use core::fmt;
+
+/// The mask of the affinity fields of MPIDR_EL1.
+const AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// The affinity of a core, as read from MPIDR_EL1.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Affinity(u64);
+
+impl Affinity {
+    /// Creates an affinity from the value of MPIDR_EL1.
+    ///
+    /// The fields other than Aff0..Aff3 and MT are ignored.
+    pub const fn from_mpidr(mpidr: u64) -> Self {
+        Affinity(mpidr & (AFFINITY_MASK | 1 << 24))
+    }
+
+    /// Creates an affinity from its four levels.
+    pub const fn new(aff3: u8, aff2: u8, aff1: u8, aff0: u8) -> Self {
+        Affinity((aff3 as u64) << 32 | (aff2 as u64) << 16 | (aff1 as u64) << 8 | aff0 as u64)
+    }
+
+    /// Returns the affinity of the current core.
+    #[inline]
+    pub fn current() -> Self {
+        Self::from_mpidr(MPIDR_EL1.get())
+    }
+
+    /// Returns the affinity level 0, the lowest level.
+    pub const fn aff0(&self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Returns the affinity level 1.
+    pub const fn aff1(&self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    /// Returns the affinity level 2.
+    pub const fn aff2(&self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    /// Returns the affinity level 3, the highest level.
+    pub const fn aff3(&self) -> u8 {
+        (self.0 >> 32) as u8
+    }
+
+    /// Returns the affinity level `level` (0 to 3).
+    pub fn level(&self, level: usize) -> u8 {
+        match level {
+            0 => self.aff0(),
+            1 => self.aff1(),
+            2 => self.aff2(),
+            3 => self.aff3(),
+            _ => panic!("invalid affinity level {}", level),
+        }
+    }
+
+    /// Returns whether the lowest affinity level consists of logical PEs implemented with a
+    /// multi-threading approach (the MT bit).
+    pub const fn mt(&self) -> bool {
+        self.0 & 1 << 24 != 0
+    }
+
+    /// Returns the affinity fields in the MPIDR_EL1 layout, as expected by the `target_cpu`
+    /// arguments of [PSCI](crate::psci) and by interrupt controllers.
+    pub const fn value(&self) -> u64 {
+        self.0 & AFFINITY_MASK
+    }
+}
+
+impl fmt::Debug for Affinity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Affinity({}.{}.{}.{}",
+            self.aff3(),
+            self.aff2(),
+            self.aff1(),
+            self.aff0()
+        )?;
+        if self.mt() {
+            write!(f, ", MT")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Returns the affinity of the current core.
+///
+/// Unlike the usual `MPIDR_EL1 & 3`, all affinity levels are kept, so that the cores of
+/// different clusters are told apart.
+#[inline]
+pub fn cpuid() -> Affinity {
+    Affinity::current()
+}
+
+/// The number of nodes at each affinity level, used to linearize affinities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Topology {
+    counts: [u16; 4],
+}
+
+impl Topology {
+    /// Creates a topology with `counts[n]` nodes at affinity level `n` in each node of level
+    /// `n + 1`, e.g. `[4, 2, 1, 1]` for two clusters of four cores.
+    ///
+    /// Panics if a count is zero or larger than 256.
+    pub fn new(counts: [u16; 4]) -> Self {
+        assert!(
+            counts.iter().all(|&count| count != 0 && count <= 256),
+            "invalid affinity level count"
+        );
+        Topology { counts }
+    }
+
+    /// Returns the total number of cores.
+    pub fn num_cpus(&self) -> usize {
+        self.counts.iter().map(|&count| count as usize).product()
+    }
+
+    /// Returns the dense index of the core with the given affinity, from 0 to
+    /// [`num_cpus`](Topology::num_cpus) - 1.
+    ///
+    /// Returns `None` if the affinity is outside of the topology.
+    pub fn index(&self, affinity: Affinity) -> Option<usize> {
+        (0..4).rev().try_fold(0, |index, level| {
+            let count = self.counts[level] as usize;
+            let aff = affinity.level(level) as usize;
+            if aff < count {
+                Some(index * count + aff)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the affinity of the core with the given dense index, the inverse of
+    /// [`index`](Topology::index).
+    ///
+    /// Returns `None` if the index is outside of the topology.
+    pub fn affinity(&self, mut index: usize) -> Option<Affinity> {
+        if index >= self.num_cpus() {
+            return None;
+        }
+        let mut affs = [0; 4];
+        for (aff, &count) in affs.iter_mut().zip(self.counts.iter()) {
+            *aff = (index % count as usize) as u8;
+            index /= count as usize;
+        }
+        Some(Affinity::new(affs[3], affs[2], affs[1], affs[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_affinity() {
+        let affinity = Affinity::from_mpidr(0x8000_0000 | 1 << 24 | 0x01_0203);
+        assert_eq!(
+            (affinity.aff2(), affinity.aff1(), affinity.aff0()),
+            (1, 2, 3)
+        );
+        assert!(affinity.mt());
+        assert_eq!(affinity.value(), 0x01_0203);
+
+        let topology = Topology::new([4, 2, 1, 1]);
+        assert_eq!(topology.num_cpus(), 8);
+        assert_eq!(topology.index(Affinity::new(0, 0, 1, 2)), Some(6));
+        assert_eq!(topology.index(Affinity::new(0, 0, 2, 0)), None);
+        assert_eq!(topology.affinity(6), Some(Affinity::new(0, 0, 1, 2)));
+        assert_eq!(topology.affinity(8), None);
+    }
+}
@@ -0,0 +1,310 @@
+//! Detection of the optional features of the PE, from its ID registers.
+//!
+//! [`CpuFeatures::current`] reads the ID registers once; the queries then decode the copies, so
+//! that the features of a secondary PE can be compared with those of the boot PE, or a value
+//! captured elsewhere decoded with [`CpuFeatures::from_raw`].
+
+use crate::{addr::pa_range_bits, mte::MteSupport, pac::PacSupport, pan::PanSupport, registers::*};
+use bitflags::bitflags;
+
+bitflags! {
+    /// The translation granules supported by stage 1, from ID_AA64MMFR0_EL1.TGran*.
+    pub struct Granules: u8 {
+        /// The 4 KiB granule.
+        const SIZE_4KIB = 1 << 0;
+        /// The 16 KiB granule.
+        const SIZE_16KIB = 1 << 1;
+        /// The 64 KiB granule.
+        const SIZE_64KIB = 1 << 2;
+    }
+}
+
+/// The raw values of the ID registers of a PE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuFeatures {
+    /// ID_AA64ISAR0_EL1.
+    pub isar0: u64,
+    /// ID_AA64ISAR1_EL1.
+    pub isar1: u64,
+    /// ID_AA64ISAR2_EL1.
+    pub isar2: u64,
+    /// ID_AA64MMFR0_EL1.
+    pub mmfr0: u64,
+    /// ID_AA64MMFR1_EL1.
+    pub mmfr1: u64,
+    /// ID_AA64MMFR2_EL1.
+    pub mmfr2: u64,
+    /// ID_AA64PFR0_EL1.
+    pub pfr0: u64,
+    /// ID_AA64PFR1_EL1.
+    pub pfr1: u64,
+}
+
+/// Returns the 4-bit field of `reg` at `shift`.
+const fn field(reg: u64, shift: u32) -> u8 {
+    (reg >> shift) as u8 & 0xf
+}
+
+impl CpuFeatures {
+    /// Creates the features from raw register values, in the order of the fields.
+    #[allow(clippy::too_many_arguments)]
+    pub const fn from_raw(
+        isar0: u64,
+        isar1: u64,
+        isar2: u64,
+        mmfr0: u64,
+        mmfr1: u64,
+        mmfr2: u64,
+        pfr0: u64,
+        pfr1: u64,
+    ) -> Self {
+        Self {
+            isar0,
+            isar1,
+            isar2,
+            mmfr0,
+            mmfr1,
+            mmfr2,
+            pfr0,
+            pfr1,
+        }
+    }
+
+    /// Reads the ID registers of the current PE.
+    #[inline]
+    pub fn current() -> Self {
+        Self {
+            isar0: ID_AA64ISAR0_EL1.get(),
+            isar1: ID_AA64ISAR1_EL1.get(),
+            isar2: ID_AA64ISAR2_EL1.get(),
+            mmfr0: ID_AA64MMFR0_EL1.get(),
+            mmfr1: ID_AA64MMFR1_EL1.get(),
+            mmfr2: ID_AA64MMFR2_EL1.get(),
+            pfr0: ID_AA64PFR0_EL1.get(),
+            pfr1: ID_AA64PFR1_EL1.get(),
+        }
+    }
+
+    /// Returns whether the LSE atomic instructions are implemented (FEAT_LSE).
+    pub const fn has_lse(&self) -> bool {
+        field(self.isar0, 20) >= 2
+    }
+
+    /// Returns whether the TLBI range instructions are implemented (FEAT_TLBIRANGE).
+    pub const fn has_tlb_range(&self) -> bool {
+        field(self.isar0, 56) >= 2
+    }
+
+    /// Returns whether the CRC32 instructions are implemented.
+    pub const fn has_crc32(&self) -> bool {
+        field(self.isar0, 16) != 0
+    }
+
+    /// Returns whether the AES instructions are implemented (FEAT_AES).
+    pub const fn has_aes(&self) -> bool {
+        field(self.isar0, 4) != 0
+    }
+
+    /// Returns whether the SHA-256 instructions are implemented (FEAT_SHA256).
+    pub const fn has_sha2(&self) -> bool {
+        field(self.isar0, 12) != 0
+    }
+
+    /// Returns whether the RNDR and RNDRRS registers are implemented (FEAT_RNG).
+    pub const fn has_rng(&self) -> bool {
+        field(self.isar0, 60) != 0
+    }
+
+    /// Returns whether the WFET and WFIT instructions are implemented (FEAT_WFxT).
+    pub const fn has_wfxt(&self) -> bool {
+        field(self.isar2, 0) >= 2
+    }
+
+    /// Returns whether the floating-point instructions are implemented.
+    pub const fn has_fp(&self) -> bool {
+        field(self.pfr0, 16) != 0xf
+    }
+
+    /// Returns whether the Advanced SIMD instructions are implemented.
+    pub const fn has_asimd(&self) -> bool {
+        field(self.pfr0, 20) != 0xf
+    }
+
+    /// Returns whether the Scalable Vector Extension is implemented (FEAT_SVE).
+    pub const fn has_sve(&self) -> bool {
+        field(self.pfr0, 32) != 0
+    }
+
+    /// Returns whether EL2 is implemented.
+    pub const fn has_el2(&self) -> bool {
+        field(self.pfr0, 8) != 0
+    }
+
+    /// Returns whether EL3 is implemented.
+    pub const fn has_el3(&self) -> bool {
+        field(self.pfr0, 12) != 0
+    }
+
+    /// Returns whether the RAS Extension is implemented (FEAT_RAS).
+    pub const fn has_ras(&self) -> bool {
+        field(self.pfr0, 28) != 0
+    }
+
+    /// Returns whether Privileged Access Never is implemented (FEAT_PAN).
+    pub fn has_pan(&self) -> bool {
+        self.pan() != PanSupport::None
+    }
+
+    /// Returns the level of support for PAN.
+    pub fn pan(&self) -> PanSupport {
+        PanSupport::from_mmfr1(self.mmfr1)
+    }
+
+    /// Returns whether User Access Override is implemented (FEAT_UAO).
+    pub const fn has_uao(&self) -> bool {
+        field(self.mmfr2, 4) != 0
+    }
+
+    /// Returns whether the Virtualization Host Extensions are implemented (FEAT_VHE).
+    pub const fn has_vhe(&self) -> bool {
+        field(self.mmfr1, 8) != 0
+    }
+
+    /// Returns whether the MMU can update the access flag (FEAT_HAFDBS).
+    pub const fn has_hw_access_flag(&self) -> bool {
+        field(self.mmfr1, 0) != 0
+    }
+
+    /// Returns whether the MMU can update the access flag and the dirty state (FEAT_HAFDBS).
+    pub const fn has_hw_dirty(&self) -> bool {
+        field(self.mmfr1, 0) >= 2
+    }
+
+    /// Returns whether address authentication is implemented (FEAT_PAuth).
+    pub fn has_pauth(&self) -> bool {
+        self.pac().address
+    }
+
+    /// Returns the pointer authentication features.
+    pub fn pac(&self) -> PacSupport {
+        PacSupport::from_isar1(self.isar1)
+    }
+
+    /// Returns whether full MTE, with tag checks, is implemented (FEAT_MTE2).
+    pub fn has_mte(&self) -> bool {
+        self.mte() >= MteSupport::Full
+    }
+
+    /// Returns the level of support for MTE.
+    pub fn mte(&self) -> MteSupport {
+        MteSupport::from_pfr1(self.pfr1)
+    }
+
+    /// Returns whether Branch Target Identification is implemented (FEAT_BTI).
+    pub const fn has_bti(&self) -> bool {
+        field(self.pfr1, 0) != 0
+    }
+
+    /// Returns the physical address size in bits (ID_AA64MMFR0_EL1.PARange), or 52 for the
+    /// values reserved at the time of writing.
+    pub fn pa_range_bits(&self) -> u8 {
+        pa_range_bits(field(self.mmfr0, 0)).unwrap_or(52)
+    }
+
+    /// Returns the virtual address size in bits of the 64 KiB granule (FEAT_LVA), 48 or 52.
+    pub const fn va_bits(&self) -> u8 {
+        if field(self.mmfr2, 16) == 1 {
+            52
+        } else {
+            48
+        }
+    }
+
+    /// Returns the size in bits of the ASIDs, 8 or 16.
+    pub const fn asid_bits(&self) -> u8 {
+        if field(self.mmfr0, 4) == 2 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Returns the size in bits of the VMIDs, 8 or 16.
+    pub const fn vmid_bits(&self) -> u8 {
+        if field(self.mmfr1, 4) == 2 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Returns the translation granules supported by stage 1.
+    pub fn supported_granules(&self) -> Granules {
+        let mut granules = Granules::empty();
+        // TGran4 and TGran64 are 0b1111 if not supported, TGran16 0b0000
+        if field(self.mmfr0, 28) != 0xf {
+            granules |= Granules::SIZE_4KIB;
+        }
+        if field(self.mmfr0, 20) != 0 {
+            granules |= Granules::SIZE_16KIB;
+        }
+        if field(self.mmfr0, 24) != 0xf {
+            granules |= Granules::SIZE_64KIB;
+        }
+        granules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_cpufeature() {
+        // Cortex-A72: ARMv8.0
+        let a72 = CpuFeatures::from_raw(0x0001_1120, 0, 0, 0x0000_1124, 0, 0, 0x0000_2222, 0);
+        assert!(!a72.has_lse());
+        assert!(a72.has_crc32());
+        assert!(a72.has_aes());
+        assert!(!a72.has_pan());
+        assert!(!a72.has_hw_dirty());
+        assert!(a72.has_fp() && a72.has_asimd());
+        assert!(a72.has_el2() && a72.has_el3());
+        assert_eq!(a72.pa_range_bits(), 44);
+        assert_eq!(a72.asid_bits(), 16);
+        assert_eq!(a72.vmid_bits(), 8);
+        assert_eq!(
+            a72.supported_granules(),
+            Granules::SIZE_4KIB | Granules::SIZE_64KIB
+        );
+
+        // Neoverse-N1: ARMv8.2 with the 16 KiB granule
+        let n1 = CpuFeatures::from_raw(
+            0x0010_0000_1021_1120,
+            0x0010_0001,
+            0,
+            0x0010_1125,
+            0x1022_2122,
+            0x0000_1011,
+            0x1100_0000_1011_1112,
+            0x0000_0020,
+        );
+        assert!(n1.has_lse());
+        assert!(!n1.has_tlb_range());
+        assert!(n1.has_pan());
+        assert_eq!(n1.pan(), PanSupport::Pan2);
+        assert!(n1.has_uao());
+        assert!(n1.has_vhe());
+        assert!(n1.has_hw_dirty());
+        assert!(!n1.has_pauth());
+        assert!(!n1.has_mte());
+        assert_eq!(n1.pa_range_bits(), 48);
+        assert_eq!(n1.vmid_bits(), 16);
+        assert_eq!(n1.supported_granules(), Granules::all());
+
+        let none = CpuFeatures::from_raw(0, 0, 0, 0xff00_0000, 0, 0, 0xff_0000, 0);
+        assert!(none.supported_granules().is_empty());
+        assert!(!none.has_fp() && !none.has_asimd());
+        assert!(!none.has_wfxt());
+    }
+}
@@ -0,0 +1,316 @@
+//! Hardware breakpoints and watchpoints.
+//!
+//! A core has 2 to 16 breakpoints and watchpoints, as reported by ID_AA64DFR0_EL1
+//! ([`num_breakpoints`], [`num_watchpoints`]). Each is programmed through a value register
+//! (DBGBVR<n>_EL1, DBGWVR<n>_EL1) and a control register (DBGBCR<n>_EL1, DBGWCR<n>_EL1).
+//! [`HwBreakpoint::set`] and [`HwWatchpoint::set`] encode and write them, and [`enable_debug`]
+//! turns on the resulting debug exceptions through MDSCR_EL1.
+//!
+//! Breakpoints and watchpoints are per core, and match at both EL0 and EL1.
+
+use crate::{barrier::isb, registers::*, VirtAddr};
+
+/// The maximum number of breakpoints or watchpoints defined by the architecture.
+pub const MAX_DEBUG_REGISTERS: usize = 16;
+
+/// DBGBCR_EL1 and DBGWCR_EL1 fields.
+const CR_E: u64 = 1 << 0;
+const CR_PMC_EL0_EL1: u64 = 0b11 << 1;
+const BCR_BAS_A64: u64 = 0b1111 << 5;
+const BCR_BT_SHIFT: u64 = 20;
+const WCR_LSC_SHIFT: u64 = 3;
+const WCR_BAS_SHIFT: u64 = 5;
+const WCR_MASK_SHIFT: u64 = 24;
+
+/// The largest region a single watchpoint can cover (2GiB).
+const MAX_MASK_BITS: u32 = 31;
+
+/// Returns the number of breakpoints of the current core.
+#[inline]
+pub fn num_breakpoints() -> usize {
+    ID_AA64DFR0_EL1.read(ID_AA64DFR0_EL1::BRPs) as usize + 1
+}
+
+/// Returns the number of watchpoints of the current core.
+#[inline]
+pub fn num_watchpoints() -> usize {
+    ID_AA64DFR0_EL1.read(ID_AA64DFR0_EL1::WRPs) as usize + 1
+}
+
+/// Returns the number of breakpoints that support context matching. They are the
+/// highest-numbered breakpoints.
+#[inline]
+pub fn num_context_breakpoints() -> usize {
+    ID_AA64DFR0_EL1.read(ID_AA64DFR0_EL1::CTX_CMPs) as usize + 1
+}
+
+/// An error indicating that a breakpoint or watchpoint could not be set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugError {
+    /// The breakpoint or watchpoint number is not implemented by the core.
+    InvalidIndex,
+    /// The breakpoint does not support context matching.
+    NotContextAware,
+    /// The instruction address is not 4-byte aligned.
+    UnalignedAddress,
+    /// The watched region can't be matched by a single watchpoint.
+    InvalidRegion,
+}
+
+/// What a breakpoint matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointKind {
+    /// The execution of the instruction at the given address.
+    Address,
+    /// Any instruction executed while CONTEXTIDR_EL1 holds the given value.
+    ContextId,
+}
+
+/// The data accesses a watchpoint matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointAccess {
+    /// Loads.
+    Load = 0b01,
+    /// Stores.
+    Store = 0b10,
+    /// Loads and stores.
+    LoadStore = 0b11,
+}
+
+/// A breakpoint programmed by [`HwBreakpoint::set`].
+#[derive(Debug)]
+pub struct HwBreakpoint {
+    index: usize,
+}
+
+impl HwBreakpoint {
+    /// Sets breakpoint `index` of the current core to match `addr`, which is an instruction
+    /// address or, for [`BreakpointKind::ContextId`], a CONTEXTIDR_EL1 value.
+    ///
+    /// Any previous setting of the breakpoint is replaced. The breakpoint only raises exceptions
+    /// once [`enable_debug`] has been called.
+    pub fn set(index: usize, addr: u64, kind: BreakpointKind) -> Result<Self, DebugError> {
+        let count = num_breakpoints();
+        if index >= count {
+            return Err(DebugError::InvalidIndex);
+        }
+        if kind == BreakpointKind::ContextId && index < count - num_context_breakpoints() {
+            return Err(DebugError::NotContextAware);
+        }
+        let (value, ctrl) = breakpoint_registers(addr, kind)?;
+        unsafe {
+            write_dbgbcr(index, 0);
+            write_dbgbvr(index, value);
+            write_dbgbcr(index, ctrl);
+            isb();
+        }
+        Ok(HwBreakpoint { index })
+    }
+
+    /// Returns the breakpoint number.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Disables the breakpoint.
+    pub fn clear(self) {
+        unsafe {
+            write_dbgbcr(self.index, 0);
+            isb();
+        }
+    }
+}
+
+/// A watchpoint programmed by [`HwWatchpoint::set`].
+#[derive(Debug)]
+pub struct HwWatchpoint {
+    index: usize,
+}
+
+impl HwWatchpoint {
+    /// Sets watchpoint `index` of the current core to match the `access`es to the `len` bytes at
+    /// `addr`.
+    ///
+    /// The region must either lie within one doubleword, or be a naturally aligned power of two
+    /// of at most 2GiB. Any previous setting of the watchpoint is replaced. The watchpoint only
+    /// raises exceptions once [`enable_debug`] has been called.
+    pub fn set(
+        index: usize,
+        addr: VirtAddr,
+        len: u64,
+        access: WatchpointAccess,
+    ) -> Result<Self, DebugError> {
+        if index >= num_watchpoints() {
+            return Err(DebugError::InvalidIndex);
+        }
+        let (value, ctrl) = watchpoint_registers(addr.as_u64(), len, access)?;
+        unsafe {
+            write_dbgwcr(index, 0);
+            write_dbgwvr(index, value);
+            write_dbgwcr(index, ctrl);
+            isb();
+        }
+        Ok(HwWatchpoint { index })
+    }
+
+    /// Returns the watchpoint number.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Disables the watchpoint.
+    pub fn clear(self) {
+        unsafe {
+            write_dbgwcr(self.index, 0);
+            isb();
+        }
+    }
+}
+
+/// Enables breakpoint, watchpoint and vector catch exceptions at EL1 and EL0.
+///
+/// This unlocks the OS lock, sets MDSCR_EL1.{MDE, KDE} and unmasks debug exceptions in PSTATE.
+///
+/// # Safety
+///
+/// A debug exception handler must be installed.
+#[inline]
+pub unsafe fn enable_debug() {
+    OSLAR_EL1.write(OSLAR_EL1::OSLK::Unlocked);
+    MDSCR_EL1.modify(MDSCR_EL1::MDE::Enable + MDSCR_EL1::KDE::Enable);
+    isb();
+    #[cfg(target_arch = "aarch64")]
+    core::arch::asm!("msr daifclr, #8", options(nomem, nostack));
+}
+
+/// Disables breakpoint, watchpoint and vector catch exceptions (MDSCR_EL1.MDE).
+#[inline]
+pub fn disable_debug() {
+    MDSCR_EL1.modify(MDSCR_EL1::MDE::Disable);
+    unsafe { isb() };
+}
+
+/// Computes the DBGBVR_EL1 and DBGBCR_EL1 values of a breakpoint.
+fn breakpoint_registers(addr: u64, kind: BreakpointKind) -> Result<(u64, u64), DebugError> {
+    let ctrl = CR_E | CR_PMC_EL0_EL1 | BCR_BAS_A64;
+    match kind {
+        BreakpointKind::Address if addr & 3 != 0 => Err(DebugError::UnalignedAddress),
+        BreakpointKind::Address => Ok((addr, ctrl)),
+        BreakpointKind::ContextId => Ok((addr & 0xffff_ffff, ctrl | 0b0010 << BCR_BT_SHIFT)),
+    }
+}
+
+/// Computes the DBGWVR_EL1 and DBGWCR_EL1 values of a watchpoint.
+fn watchpoint_registers(
+    addr: u64,
+    len: u64,
+    access: WatchpointAccess,
+) -> Result<(u64, u64), DebugError> {
+    let ctrl = CR_E | CR_PMC_EL0_EL1 | (access as u64) << WCR_LSC_SHIFT;
+    if len == 0 {
+        return Err(DebugError::InvalidRegion);
+    }
+    if len <= 8 && (addr & 7) + len <= 8 {
+        // Within one doubleword: select the bytes with BAS.
+        let bas = ((1u64 << len) - 1) << (addr & 7);
+        return Ok((addr & !7, ctrl | bas << WCR_BAS_SHIFT));
+    }
+    let bits = len.trailing_zeros();
+    if !len.is_power_of_two() || bits > MAX_MASK_BITS || addr & (len - 1) != 0 {
+        return Err(DebugError::InvalidRegion);
+    }
+    Ok((
+        addr,
+        ctrl | 0xff << WCR_BAS_SHIFT | u64::from(bits) << WCR_MASK_SHIFT,
+    ))
+}
+
+macro_rules! indexed_sysreg_write {
+    ($name:ident, $reg:literal, [$($n:literal),*]) => {
+        #[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+        #[inline]
+        pub(crate) unsafe fn $name(n: usize, value: u64) {
+            match () {
+                #[cfg(target_arch = "aarch64")]
+                () => match n {
+                    $($n => core::arch::asm!(
+                        concat!("msr ", $reg, stringify!($n), "_el1, {}"),
+                        in(reg) value,
+                        options(nomem, nostack)
+                    ),)*
+                    _ => unreachable!(),
+                },
+
+                #[cfg(not(target_arch = "aarch64"))]
+                () => unimplemented!(),
+            }
+        }
+    };
+}
+
+indexed_sysreg_write!(
+    write_dbgbvr,
+    "dbgbvr",
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+);
+indexed_sysreg_write!(
+    write_dbgbcr,
+    "dbgbcr",
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+);
+indexed_sysreg_write!(
+    write_dbgwvr,
+    "dbgwvr",
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+);
+indexed_sysreg_write!(
+    write_dbgwcr,
+    "dbgwcr",
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_debug_registers() {
+        assert_eq!(
+            breakpoint_registers(0x8_0000, BreakpointKind::Address),
+            Ok((0x8_0000, 0x1e7))
+        );
+        assert_eq!(
+            breakpoint_registers(0x8_0002, BreakpointKind::Address),
+            Err(DebugError::UnalignedAddress)
+        );
+        assert_eq!(
+            breakpoint_registers(0x1_0000_0042, BreakpointKind::ContextId),
+            Ok((0x42, 0x2001e7))
+        );
+
+        // 2 bytes inside a doubleword
+        let (value, ctrl) = watchpoint_registers(0x1004, 2, WatchpointAccess::Store).unwrap();
+        assert_eq!(value, 0x1000);
+        assert_eq!((ctrl >> WCR_BAS_SHIFT) & 0xff, 0b0011_0000);
+        assert_eq!((ctrl >> WCR_LSC_SHIFT) & 0b11, 0b10);
+        assert_eq!(ctrl >> WCR_MASK_SHIFT, 0);
+
+        // a page
+        let (value, ctrl) = watchpoint_registers(0x8_0000, 0x1000, WatchpointAccess::Load).unwrap();
+        assert_eq!(value, 0x8_0000);
+        assert_eq!(ctrl >> WCR_MASK_SHIFT, 12);
+
+        assert_eq!(
+            watchpoint_registers(0x1006, 4, WatchpointAccess::Load),
+            Err(DebugError::InvalidRegion)
+        );
+        assert_eq!(
+            watchpoint_registers(0x8_0800, 0x1000, WatchpointAccess::Load),
+            Err(DebugError::InvalidRegion)
+        );
+        assert_eq!(
+            watchpoint_registers(0, 1 << 32, WatchpointAccess::LoadStore),
+            Err(DebugError::InvalidRegion)
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! The Exception levels, and checks that privileged code runs at the level it needs.
+//!
+//! Accessing a register of a higher Exception level than the current one is UNDEFINED, and shows
+//! up as an unknown exception at the access. The helpers of this crate that need a privileged
+//! level check it with [`require_el`] in debug builds, so that running them at the wrong level,
+//! e.g. an EL2 helper in a kernel started at EL1 by QEMU, panics with the levels involved
+//! instead.
+
+use crate::registers::*;
+use core::fmt;
+
+/// An Exception level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExceptionLevel {
+    /// EL0, applications.
+    EL0 = 0,
+    /// EL1, the kernel.
+    EL1 = 1,
+    /// EL2, the hypervisor.
+    EL2 = 2,
+    /// EL3, the secure monitor.
+    EL3 = 3,
+}
+
+impl ExceptionLevel {
+    /// Returns the level of the given number, ignoring the bits above the 2 low bits.
+    pub const fn from_bits(el: u8) -> Self {
+        match el & 0b11 {
+            0 => ExceptionLevel::EL0,
+            1 => ExceptionLevel::EL1,
+            2 => ExceptionLevel::EL2,
+            _ => ExceptionLevel::EL3,
+        }
+    }
+
+    /// Returns the number of the level, from 0 to 3.
+    pub const fn number(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Returns the current Exception level, from CurrentEL.
+///
+/// CurrentEL can't be read at EL0: there, the read itself is UNDEFINED.
+#[inline]
+pub fn current_el() -> ExceptionLevel {
+    ExceptionLevel::from_bits(CurrentEL.read(CurrentEL::EL) as u8)
+}
+
+/// An error indicating that the current Exception level is below the one an operation needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientEl {
+    /// The lowest Exception level the operation can run at.
+    pub required: ExceptionLevel,
+    /// The current Exception level.
+    pub current: ExceptionLevel,
+}
+
+impl InsufficientEl {
+    /// Checks `current` against `required`.
+    pub fn check(required: ExceptionLevel, current: ExceptionLevel) -> Result<(), Self> {
+        if current >= required {
+            Ok(())
+        } else {
+            Err(Self { required, current })
+        }
+    }
+}
+
+impl fmt::Display for InsufficientEl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "requires EL{} or higher, running at EL{}",
+            self.required.number(),
+            self.current.number()
+        )
+    }
+}
+
+/// Returns an error if the current Exception level is below `required`.
+///
+/// See [`current_el`] for EL0.
+#[inline]
+pub fn require_el(required: ExceptionLevel) -> Result<(), InsufficientEl> {
+    InsufficientEl::check(required, current_el())
+}
+
+/// Panics if the current Exception level is below `required`, in debug builds.
+#[cfg_attr(
+    not(all(debug_assertions, target_arch = "aarch64")),
+    allow(unused_variables)
+)]
+#[inline]
+#[track_caller]
+pub(crate) fn debug_require_el(required: ExceptionLevel) {
+    #[cfg(all(debug_assertions, target_arch = "aarch64"))]
+    if let Err(err) = require_el(required) {
+        panic!("{}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_exception_level() {
+        assert_eq!(ExceptionLevel::from_bits(2), ExceptionLevel::EL2);
+        assert_eq!(ExceptionLevel::from_bits(0b101), ExceptionLevel::EL1);
+        assert!(ExceptionLevel::EL1 < ExceptionLevel::EL2);
+
+        assert_eq!(
+            InsufficientEl::check(ExceptionLevel::EL1, ExceptionLevel::EL2),
+            Ok(())
+        );
+        let err = InsufficientEl::check(ExceptionLevel::EL2, ExceptionLevel::EL1).unwrap_err();
+        assert_eq!(err.required, ExceptionLevel::EL2);
+        assert_eq!(err.current, ExceptionLevel::EL1);
+    }
+}
@@ -0,0 +1,367 @@
+//! Reporting of faults and diagnostics detected by the crate.
+//!
+//! The debug facilities of this crate (e.g. the [break-before-make checker](crate::paging::bbm),
+//! [tripwires](crate::tripwire) and the [snapshot validation](crate::paging::snapshot)) report what
+//! they find as a [`FaultRecord`] through a single kernel-chosen [`FaultSink`], installed once with
+//! [`set_sink`]. This way all diagnostics end up in the same channel, whether that is a UART, a
+//! ring buffer or semihosting.
+//!
+//! Without a sink, fatal records panic and all other records are dropped.
+//!
+//! [`DataAbortInfo`] collects the syndrome of the aborts taken by the MMU, the first step of a
+//! page fault handler, and [`AlignmentFault`] that of the alignment faults, whether they are
+//! reported as aborts or with their own exception class.
+
+use crate::{
+    registers::{
+        esr::{EsrEl1, ExceptionClass, FaultStatus},
+        Readable, ELR_EL1, FAR_EL1,
+    },
+    VirtAddr,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The subsystem that detected a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FaultSource {
+    /// The [break-before-make checker](crate::paging::bbm).
+    BreakBeforeMake,
+    /// A [tripwire](crate::tripwire) hit.
+    Tripwire,
+    /// An exception not handled by the kernel, with its [decoded syndrome](crate::registers::esr),
+    /// see [`report_unhandled_exception`].
+    Exception,
+    /// A page table hierarchy rejected by
+    /// [`snapshot::validate`](crate::paging::snapshot::validate).
+    Snapshot,
+}
+
+/// The severity of a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Diagnostic information.
+    Info,
+    /// Something went wrong, but execution can continue.
+    Error,
+    /// Execution must not continue. [`FaultSink::halt`] is called after the record is written.
+    Fatal,
+}
+
+/// A fault reported by the crate.
+#[derive(Debug, Clone, Copy)]
+pub struct FaultRecord<'a> {
+    /// The subsystem that detected the fault.
+    pub source: FaultSource,
+    /// The severity of the fault.
+    pub severity: Severity,
+    /// A human-readable description of the fault.
+    pub message: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for FaultRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:?}] {:?}: {}",
+            self.severity, self.source, self.message
+        )
+    }
+}
+
+/// A channel for the faults reported by the crate.
+pub trait FaultSink: Sync {
+    /// Writes a fault record.
+    fn write(&self, record: &FaultRecord);
+
+    /// Stops execution after a fatal fault has been written.
+    ///
+    /// Panics by default.
+    fn halt(&self, record: &FaultRecord) -> ! {
+        panic!("{}", record)
+    }
+}
+
+/// The sink used before [`set_sink`] is called.
+struct DefaultSink;
+
+impl FaultSink for DefaultSink {
+    fn write(&self, _record: &FaultRecord) {}
+}
+
+/// The error returned by [`set_sink`] if a sink has already been installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetSinkError;
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const INITIALIZED: usize = 2;
+
+static STATE: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
+static mut SINK: &dyn FaultSink = &DefaultSink;
+
+/// Installs the sink that receives all faults reported by the crate.
+///
+/// The sink can only be installed once.
+pub fn set_sink(sink: &'static dyn FaultSink) -> Result<(), SetSinkError> {
+    STATE
+        .compare_exchange(
+            UNINITIALIZED,
+            INITIALIZING,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .map_err(|_| SetSinkError)?;
+    unsafe { SINK = sink };
+    STATE.store(INITIALIZED, Ordering::Release);
+    Ok(())
+}
+
+/// Returns the installed sink.
+pub fn sink() -> &'static dyn FaultSink {
+    if STATE.load(Ordering::Acquire) == INITIALIZED {
+        unsafe { SINK }
+    } else {
+        &DefaultSink
+    }
+}
+
+/// Reports a fault to the installed sink, and halts if it is fatal.
+pub fn report(record: &FaultRecord) {
+    let sink = sink();
+    sink.write(record);
+    if record.severity == Severity::Fatal {
+        sink.halt(record);
+    }
+}
+
+/// Reports an exception that the kernel does not handle, e.g. from the fallback of its exception
+/// vector, as a [`Fatal`](Severity::Fatal) fault of [`FaultSource::Exception`].
+///
+/// The record contains the decoded syndrome, and the fault status of an abort.
+pub fn report_unhandled_exception(esr: EsrEl1, far: u64, elr: u64) -> ! {
+    match DataAbortInfo::new(esr, far, elr) {
+        Some(info) => report(&FaultRecord {
+            source: FaultSource::Exception,
+            severity: Severity::Fatal,
+            message: format_args!(
+                "unhandled {:?} at {:#x}, FAR {:#x}: {:?}",
+                esr,
+                elr,
+                far,
+                info.fault_status()
+            ),
+        }),
+        None => report(&FaultRecord {
+            source: FaultSource::Exception,
+            severity: Severity::Fatal,
+            message: format_args!("unhandled {:?} at {:#x}, FAR {:#x}", esr, elr, far),
+        }),
+    }
+    unreachable!("fatal faults halt")
+}
+
+/// The syndrome of a Data or Instruction Abort, captured on exception entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataAbortInfo {
+    /// The exception syndrome.
+    pub esr: EsrEl1,
+    /// The faulting virtual address, if FAR_EL1 is valid, as reported: it may be tagged or not
+    /// canonical.
+    pub far: Option<VirtAddr>,
+    /// The address of the faulting instruction.
+    pub elr: u64,
+}
+
+impl DataAbortInfo {
+    /// Reads ESR_EL1, FAR_EL1 and ELR_EL1.
+    ///
+    /// Must be called before anything else can overwrite them, e.g. a nested exception. Returns
+    /// `None` if the exception is not an abort.
+    #[inline]
+    pub fn read() -> Option<Self> {
+        Self::new(EsrEl1::read(), FAR_EL1.get(), ELR_EL1.get())
+    }
+
+    /// Decodes the given register values.
+    ///
+    /// Returns `None` if `esr` is not the syndrome of a Data or Instruction Abort.
+    pub fn new(esr: EsrEl1, far: u64, elr: u64) -> Option<Self> {
+        let far_not_valid = match (esr.data_abort(), esr.instruction_abort()) {
+            (Some(iss), _) => iss.far_not_valid(),
+            (_, Some(iss)) => iss.far_not_valid(),
+            _ => return None,
+        };
+        Some(DataAbortInfo {
+            esr,
+            far: if far_not_valid {
+                None
+            } else {
+                Some(VirtAddr::new_unchecked(far))
+            },
+            elr,
+        })
+    }
+
+    /// Returns the fault status code, i.e. the kind of fault and its lookup level.
+    pub fn fault_status(&self) -> FaultStatus {
+        match (self.esr.data_abort(), self.esr.instruction_abort()) {
+            (Some(iss), _) => iss.fault_status(),
+            (_, Some(iss)) => iss.fault_status(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the lookup level of a translation table related fault.
+    pub fn level(&self) -> Option<u8> {
+        self.fault_status().level()
+    }
+
+    /// Returns whether the abort was caused by an instruction fetch.
+    pub fn is_instruction_fetch(&self) -> bool {
+        self.esr.instruction_abort().is_some()
+    }
+
+    /// Returns whether the abort was caused by a write.
+    ///
+    /// Cache maintenance and address translation instructions are reported as reads.
+    pub fn is_write(&self) -> bool {
+        matches!(self.esr.data_abort(), Some(iss) if iss.write())
+    }
+
+    /// Returns whether the abort was taken from a lower Exception level, i.e. from EL0.
+    pub fn from_lower_el(&self) -> bool {
+        matches!(
+            self.esr.class(),
+            ExceptionClass::DataAbortLowerEl | ExceptionClass::InstrAbortLowerEl
+        )
+    }
+
+    /// Returns whether the abort is an alignment fault, see [`AlignmentFault`].
+    pub fn is_alignment_fault(&self) -> bool {
+        self.fault_status() == FaultStatus::Alignment
+    }
+}
+
+/// The kind of an [`AlignmentFault`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentFaultKind {
+    /// An unaligned data access, with SCTLR_EL1.A set or by an instruction that requires
+    /// alignment, reported as a Data Abort.
+    Data {
+        /// Whether the access was a write.
+        write: bool,
+    },
+    /// An access based on a stack pointer not aligned to 16 bytes, with SCTLR_EL1.SA or SA0 set.
+    Stack,
+    /// A branch to an address not aligned to 4 bytes, reported when the target is fetched.
+    Pc,
+}
+
+/// The syndrome of an alignment fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlignmentFault {
+    /// The kind of fault.
+    pub kind: AlignmentFaultKind,
+    /// The faulting address if FAR_EL1 is valid: the data address for a Data Abort, the target
+    /// of the branch for a PC alignment fault. Not reported for an SP alignment fault. Like
+    /// [`DataAbortInfo::far`], it may be tagged or not canonical.
+    pub far: Option<VirtAddr>,
+    /// The address of the faulting instruction.
+    ///
+    /// The Exception level the fault was taken from is not part of the syndrome of SP and PC
+    /// alignment faults: it is the mode of SPSR_EL1, see [`Spsr`](crate::context::Spsr).
+    pub elr: u64,
+}
+
+impl AlignmentFault {
+    /// Reads ESR_EL1, FAR_EL1 and ELR_EL1.
+    ///
+    /// Returns `None` if the exception is not an alignment fault.
+    #[inline]
+    pub fn read() -> Option<Self> {
+        Self::new(EsrEl1::read(), FAR_EL1.get(), ELR_EL1.get())
+    }
+
+    /// Decodes the given register values.
+    ///
+    /// Returns `None` if `esr` is not the syndrome of an alignment fault.
+    pub fn new(esr: EsrEl1, far: u64, elr: u64) -> Option<Self> {
+        let (kind, far) = match esr.class() {
+            ExceptionClass::SpAlignment => (AlignmentFaultKind::Stack, None),
+            ExceptionClass::PcAlignment => {
+                (AlignmentFaultKind::Pc, Some(VirtAddr::new_unchecked(far)))
+            }
+            _ => {
+                let info = DataAbortInfo::new(esr, far, elr)?;
+                if !info.is_alignment_fault() || info.is_instruction_fetch() {
+                    return None;
+                }
+                let kind = AlignmentFaultKind::Data {
+                    write: info.is_write(),
+                };
+                (kind, info.far)
+            }
+        };
+        Some(AlignmentFault { kind, far, elr })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_data_abort_info() {
+        // write at EL1, level 2 translation fault
+        let info = DataAbortInfo::new(EsrEl1::new(0x9600_0046), 0x1234, 0x8_0000).unwrap();
+        assert_eq!(info.fault_status(), FaultStatus::Translation(2));
+        assert_eq!(info.level(), Some(2));
+        assert_eq!(info.far, Some(VirtAddr::new(0x1234)));
+        assert!(info.is_write() && !info.is_instruction_fetch() && !info.from_lower_el());
+
+        // instruction fetch at EL0, access flag fault, FAR not valid
+        let info = DataAbortInfo::new(EsrEl1::new(0x8200_040b), 0x1234, 0).unwrap();
+        assert_eq!(info.fault_status(), FaultStatus::AccessFlag(3));
+        assert_eq!(info.far, None);
+        assert!(info.is_instruction_fetch() && info.from_lower_el());
+
+        assert!(DataAbortInfo::new(EsrEl1::new(0x5600_0000), 0, 0).is_none());
+
+        // a tagged pointer, and a translation fault on an address out of the VA ranges
+        for far in [0x0a00_0000_0000_1234, 0x0001_0000_0000_0000] {
+            let info = DataAbortInfo::new(EsrEl1::new(0x9600_0046), far, 0).unwrap();
+            assert_eq!(info.far.map(VirtAddr::as_u64), Some(far));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Translation(2)")]
+    pub fn test_report_unhandled_exception() {
+        report_unhandled_exception(EsrEl1::new(0x9600_0046), 0x1234, 0x8_0000);
+    }
+
+    #[test]
+    pub fn test_alignment_fault() {
+        // unaligned write at EL0
+        let fault = AlignmentFault::new(EsrEl1::new(0x9200_0061), 0x1001, 0x40_0000).unwrap();
+        assert_eq!(fault.kind, AlignmentFaultKind::Data { write: true });
+        assert_eq!(fault.far, Some(VirtAddr::new(0x1001)));
+        assert!(DataAbortInfo::new(EsrEl1::new(0x9200_0061), 0x1001, 0)
+            .unwrap()
+            .is_alignment_fault());
+
+        let fault = AlignmentFault::new(EsrEl1::new(0x9a00_0000), 0, 0x8_0000).unwrap();
+        assert_eq!(fault.kind, AlignmentFaultKind::Stack);
+        assert_eq!(fault.far, None);
+        let fault = AlignmentFault::new(EsrEl1::new(0x8a00_0000), 0x8_0002, 0x8_0000).unwrap();
+        assert_eq!(fault.kind, AlignmentFaultKind::Pc);
+        assert_eq!(fault.far, Some(VirtAddr::new(0x8_0002)));
+
+        // a translation fault is not an alignment fault
+        assert!(AlignmentFault::new(EsrEl1::new(0x9600_0046), 0x1234, 0).is_none());
+    }
+}
@@ -0,0 +1,216 @@
+//! The GICv3 CPU interface, through its system registers.
+//!
+//! A kernel running in Non-secure EL1 takes its interrupts as Group 1 interrupts: once
+//! [`init_cpu_interface`] has enabled the interface on a core, an IRQ handler gets the INTID with
+//! [`acknowledge`] and completes it with [`end_of_interrupt`], and cores signal each other with
+//! [`send_sgi`]. The Distributor and Redistributors, which enable and route the interrupts, are
+//! memory-mapped and out of the scope of this module.
+
+use crate::{barrier, cpu::Affinity, registers::*};
+
+/// The INTIDs of the Software Generated Interrupts.
+pub const SGI_RANGE: core::ops::Range<u32> = 0..16;
+
+/// The INTIDs of the Private Peripheral Interrupts.
+pub const PPI_RANGE: core::ops::Range<u32> = 16..32;
+
+/// The INTID returned by an acknowledge when no interrupt is pending.
+pub const SPURIOUS: u32 = 1023;
+
+/// The lowest priority, which masks no interrupt when used as the priority mask.
+pub const LOWEST_PRIORITY: u8 = 0xff;
+
+/// Enables the system register interface of the CPU interface (ICC_SRE_EL1.SRE), and returns
+/// whether it is enabled.
+///
+/// The interface may be enabled already, or forced off by a higher Exception level (ICC_SRE_EL2
+/// or ICC_SRE_EL3 with SRE or Enable cleared), in which case this returns `false`.
+///
+/// # Safety
+///
+/// The memory-mapped CPU interface must not be in use.
+#[inline]
+pub unsafe fn enable_system_registers() -> bool {
+    ICC_SRE_EL1.modify(ICC_SRE_EL1::SRE::SET);
+    barrier::isb();
+    ICC_SRE_EL1.is_set(ICC_SRE_EL1::SRE)
+}
+
+/// Enables the CPU interface of the current core for Group 1 interrupts with a priority higher
+/// than `priority_mask`, e.g. [`LOWEST_PRIORITY`] to take all of them.
+///
+/// Panics if the system register interface can't be enabled.
+///
+/// # Safety
+///
+/// See [`enable_system_registers`]. IRQs are taken as soon as they are unmasked in PSTATE, so the
+/// exception vectors must be installed.
+pub unsafe fn init_cpu_interface(priority_mask: u8) {
+    assert!(
+        enable_system_registers(),
+        "GIC system register interface disabled"
+    );
+    ICC_PMR_EL1.write(ICC_PMR_EL1::Priority.val(priority_mask as u64));
+    ICC_BPR1_EL1.write(ICC_BPR1_EL1::BinaryPoint.val(0));
+    // EOI both drops the priority and deactivates the interrupt
+    ICC_CTLR_EL1.modify(ICC_CTLR_EL1::EOImode::CLEAR);
+    ICC_IGRPEN1_EL1.write(ICC_IGRPEN1_EL1::Enable::SET);
+    barrier::isb();
+}
+
+/// Disables the Group 1 interrupts on the current core.
+#[inline]
+pub fn disable_cpu_interface() {
+    ICC_IGRPEN1_EL1.write(ICC_IGRPEN1_EL1::Enable::CLEAR);
+    unsafe { barrier::isb() };
+}
+
+/// Returns the priority mask of the current core.
+#[inline]
+pub fn priority_mask() -> u8 {
+    ICC_PMR_EL1.read(ICC_PMR_EL1::Priority) as u8
+}
+
+/// Sets the priority mask of the current core: only the interrupts with a priority higher than
+/// `mask`, i.e. a lower value, are signaled.
+#[inline]
+pub fn set_priority_mask(mask: u8) {
+    ICC_PMR_EL1.write(ICC_PMR_EL1::Priority.val(mask as u64));
+}
+
+/// Returns the priority of the highest priority active interrupt, or `None` if no interrupt is
+/// active on the current core.
+#[inline]
+pub fn running_priority() -> Option<u8> {
+    match ICC_RPR_EL1.read(ICC_RPR_EL1::Priority) as u8 {
+        // the idle priority
+        0xff => None,
+        priority => Some(priority),
+    }
+}
+
+/// Returns the INTID of the highest priority pending Group 1 interrupt, without acknowledging it.
+#[inline]
+pub fn highest_pending() -> Option<u32> {
+    valid_intid(ICC_HPPIR1_EL1.read(ICC_HPPIR1_EL1::INTID) as u32)
+}
+
+/// Acknowledges the highest priority pending Group 1 interrupt, and returns its INTID.
+///
+/// Returns `None` if no interrupt is pending, e.g. because it was taken by another core. An
+/// acknowledged interrupt must be completed with [`end_of_interrupt`].
+#[inline]
+pub fn acknowledge() -> Option<u32> {
+    valid_intid(ICC_IAR1_EL1.read(ICC_IAR1_EL1::INTID) as u32)
+}
+
+/// Completes the interrupt `intid`, returned by [`acknowledge`].
+///
+/// Interrupts must be completed in the reverse order of their acknowledgment.
+#[inline]
+pub fn end_of_interrupt(intid: u32) {
+    ICC_EOIR1_EL1.write(ICC_EOIR1_EL1::INTID.val(intid as u64));
+}
+
+/// Deactivates the interrupt `intid`, for a CPU interface with ICC_CTLR_EL1.EOImode set.
+#[inline]
+pub fn deactivate(intid: u32) {
+    ICC_DIR_EL1.write(ICC_DIR_EL1::INTID.val(intid as u64));
+}
+
+/// Returns `intid` unless it is one of the special INTIDs 1020 to 1023.
+fn valid_intid(intid: u32) -> Option<u32> {
+    if (1020..=SPURIOUS).contains(&intid) {
+        None
+    } else {
+        Some(intid)
+    }
+}
+
+/// The cores targeted by an SGI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgiTarget {
+    /// All the cores but the current one.
+    AllButSelf,
+    /// The cores with the given Aff3.Aff2.Aff1 affinity, and an Aff0 of `range * 16 + n` for each
+    /// bit `n` set in `list`.
+    List {
+        /// The affinity, of which Aff0 is ignored.
+        affinity: Affinity,
+        /// The range selector of Aff0, above 0 only if ICC_CTLR_EL1.RSS is set.
+        range: u8,
+        /// The bitmap of the targets in the range.
+        list: u16,
+    },
+}
+
+impl SgiTarget {
+    /// Returns the target of the single core with the given affinity.
+    pub const fn cpu(affinity: Affinity) -> Self {
+        SgiTarget::List {
+            affinity,
+            range: affinity.aff0() / 16,
+            list: 1 << (affinity.aff0() % 16),
+        }
+    }
+
+    /// Returns the value of ICC_SGI1R_EL1 sending the SGI `intid` to the target.
+    ///
+    /// Panics if `intid` is not an SGI.
+    pub fn sgi1r_value(&self, intid: u32) -> u64 {
+        assert!(SGI_RANGE.contains(&intid), "not an SGI: {}", intid);
+        let intid = (intid as u64) << 24;
+        match *self {
+            SgiTarget::AllButSelf => 1 << 40 | intid,
+            SgiTarget::List {
+                affinity,
+                range,
+                list,
+            } => {
+                (affinity.aff3() as u64) << 48
+                    | (range as u64 & 0xf) << 44
+                    | (affinity.aff2() as u64) << 32
+                    | intid
+                    | (affinity.aff1() as u64) << 16
+                    | list as u64
+            }
+        }
+    }
+}
+
+/// Sends the SGI `intid` (0 to 15) to `target` as a Group 1 interrupt.
+///
+/// The SGI is only delivered to the cores where it is enabled in the Redistributor. Writes to
+/// memory before the call are observed by the targets before they take the SGI.
+///
+/// Panics if `intid` is not an SGI.
+#[inline]
+pub fn send_sgi(target: SgiTarget, intid: u32) {
+    let value = target.sgi1r_value(intid);
+    unsafe { barrier::dsb(barrier::ISHST) };
+    ICC_SGI1R_EL1.set(value);
+    unsafe { barrier::isb() };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_sgi1r_value() {
+        assert_eq!(SgiTarget::AllButSelf.sgi1r_value(3), 0x100_0300_0000);
+        let target = SgiTarget::cpu(Affinity::new(1, 2, 3, 4));
+        assert_eq!(target.sgi1r_value(15), 0x1_0002_0f03_0010);
+        let target = SgiTarget::cpu(Affinity::new(0, 0, 0, 0x21));
+        assert_eq!(target.sgi1r_value(0), 0x2000_0000_0002);
+        let target = SgiTarget::List {
+            affinity: Affinity::new(0, 0, 1, 0),
+            range: 0,
+            list: 0b1011,
+        };
+        assert_eq!(target.sgi1r_value(1), 0x0101_000b);
+
+        assert_eq!(valid_intid(27), Some(27));
+        assert_eq!(valid_intid(SPURIOUS), None);
+    }
+}
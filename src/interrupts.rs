@@ -0,0 +1,109 @@
+//! Masking of interrupts and other asynchronous exceptions through PSTATE.DAIF.
+
+use crate::registers::*;
+use bitflags::bitflags;
+
+bitflags! {
+    /// The exception mask bits of DAIF, set when the exception is masked.
+    pub struct DaifMask: u64 {
+        /// Watchpoint, Breakpoint and Software Step exceptions.
+        const DEBUG = 1 << 9;
+        /// SError interrupts.
+        const SERROR = 1 << 8;
+        /// IRQ interrupts.
+        const IRQ = 1 << 7;
+        /// FIQ interrupts.
+        const FIQ = 1 << 6;
+    }
+}
+
+/// Returns the exceptions currently masked.
+#[inline]
+pub fn masked() -> DaifMask {
+    DaifMask::from_bits_truncate(DAIF.get())
+}
+
+/// Returns whether IRQs are enabled.
+#[inline]
+pub fn are_enabled() -> bool {
+    !masked().contains(DaifMask::IRQ)
+}
+
+/// Enables IRQs.
+#[inline]
+pub fn enable() {
+    unsafe { core::arch::asm!("msr daifclr, #2", options(nostack)) };
+}
+
+/// Disables IRQs.
+#[inline]
+pub fn disable() {
+    unsafe { core::arch::asm!("msr daifset, #2", options(nostack)) };
+}
+
+/// Masks the given exceptions, in addition to those already masked.
+#[inline]
+pub fn mask(mask: DaifMask) {
+    DAIF.set(DAIF.get() | mask.bits());
+}
+
+/// Unmasks the given exceptions.
+#[inline]
+pub fn unmask(mask: DaifMask) {
+    DAIF.set(DAIF.get() & !mask.bits());
+}
+
+/// Masks exceptions while it is alive, and restores the previous DAIF value when dropped.
+///
+/// Guards must be dropped in the reverse order of their creation.
+#[derive(Debug)]
+#[must_use = "the exceptions are unmasked again when the guard is dropped"]
+pub struct DaifGuard {
+    saved: u64,
+}
+
+impl DaifGuard {
+    /// Saves DAIF and masks the given exceptions.
+    #[inline]
+    pub fn new(mask: DaifMask) -> Self {
+        let saved = DAIF.get();
+        DAIF.set(saved | mask.bits());
+        Self { saved }
+    }
+
+    /// Returns the exceptions that were masked when the guard was created.
+    pub fn saved(&self) -> DaifMask {
+        DaifMask::from_bits_truncate(self.saved)
+    }
+}
+
+impl Drop for DaifGuard {
+    #[inline]
+    fn drop(&mut self) {
+        DAIF.set(self.saved);
+    }
+}
+
+/// Runs `f` with IRQs and FIQs masked, and restores the previous masks afterwards.
+///
+/// This can be nested: IRQs stay masked after the inner call returns if they were masked before.
+#[inline]
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = DaifGuard::new(DaifMask::IRQ | DaifMask::FIQ);
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_daif_mask() {
+        assert_eq!((DaifMask::IRQ | DaifMask::FIQ).bits(), 0xc0);
+        assert_eq!(DaifMask::all().bits(), 0x3c0);
+        assert_eq!(DaifMask::from_bits_truncate(0x3c5), DaifMask::all());
+    }
+}
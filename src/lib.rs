@@ -2,9 +2,40 @@
 
 pub use addr::{align_down, align_up, PhysAddr, VirtAddr, ALIGN_1GIB, ALIGN_2MIB, ALIGN_4KIB};
 pub mod addr;
+pub mod alignment;
+pub mod asm;
+pub mod atomic;
 pub mod barrier;
+pub mod boot;
 pub mod cache;
+pub mod context;
+pub mod cpu;
+pub mod cpufeature;
+pub mod debug;
+pub mod el;
+pub mod fault;
+pub mod gic;
+pub mod interrupts;
+pub mod mte;
+pub mod pac;
 pub mod paging;
+pub mod pan;
+pub mod percpu;
+pub mod pmu;
+pub mod power;
+pub mod psci;
+pub mod rand;
 pub mod registers;
+pub mod serror;
+#[cfg(not(target_arch = "aarch64"))]
+pub mod sim;
+pub mod smccc;
+pub mod smp;
+pub mod snapshot;
+pub mod spin;
+pub mod stack;
+pub mod timer;
 pub mod translation;
-pub use cortex_a::asm;
+pub mod tripwire;
+pub mod usercopy;
+pub mod vectors;
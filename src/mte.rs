@@ -0,0 +1,274 @@
+//! Memory Tagging Extension (MTE).
+//!
+//! MTE associates a 4-bit allocation tag with every 16-byte granule of tagged memory, and a
+//! 4-bit logical tag with every pointer, held in bits 59:56. An access through a pointer whose
+//! logical tag differs from the allocation tag of the memory is a tag check fault, reported
+//! synchronously as a data abort or asynchronously in TFSR_EL1, see [`TagCheckMode`].
+//!
+//! Tagging requires the top byte of the addresses to be ignored
+//! ([`TcrBuilder::top_byte_ignore`](crate::translation::TcrBuilder::top_byte_ignore)) and the
+//! memory to be mapped with the Tagged Normal memory type
+//! ([`MairNormalTagged`](crate::paging::memory_attribute::MairNormalTagged)).
+
+use crate::{
+    barrier::{dsb, isb, NSH},
+    registers::*,
+};
+
+/// The size of the memory granule sharing an allocation tag.
+pub const TAG_GRANULE: usize = 16;
+
+/// The shift of the logical tag in a pointer.
+const TAG_SHIFT: u32 = 56;
+
+/// SCTLR_EL1 fields.
+const SCTLR_ATA: u64 = 1 << 43;
+const SCTLR_ATA0: u64 = 1 << 42;
+const SCTLR_TCF_SHIFT: u32 = 40;
+const SCTLR_TCF0_SHIFT: u32 = 38;
+const SCTLR_ITFSB: u64 = 1 << 37;
+const SCTLR_MTE_MASK: u64 = SCTLR_ATA | SCTLR_ATA0 | 0b1111 << SCTLR_TCF0_SHIFT | SCTLR_ITFSB;
+
+/// The level of support for MTE, from ID_AA64PFR1_EL1.MTE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MteSupport {
+    /// MTE is not implemented.
+    None,
+    /// Only the MTE instructions are implemented, without tag storage or checks (FEAT_MTE).
+    InstructionsOnly,
+    /// Full MTE is implemented (FEAT_MTE2).
+    Full,
+    /// Full MTE is implemented, with asymmetric tag check faults (FEAT_MTE3).
+    Asymmetric,
+}
+
+impl MteSupport {
+    /// Decodes the value of ID_AA64PFR1_EL1.
+    pub fn from_pfr1(pfr1: u64) -> Self {
+        match (pfr1 >> 8) & 0xf {
+            0 => Self::None,
+            1 => Self::InstructionsOnly,
+            2 => Self::Full,
+            _ => Self::Asymmetric,
+        }
+    }
+
+    /// Returns the MTE support of the current PE.
+    #[inline]
+    pub fn current() -> Self {
+        Self::from_pfr1(ID_AA64PFR1_EL1.get())
+    }
+}
+
+/// Returns whether the current PE implements full MTE, with tag checks.
+#[inline]
+pub fn is_supported() -> bool {
+    MteSupport::current() >= MteSupport::Full
+}
+
+/// How tag check faults are reported (SCTLR_EL1.TCF and SCTLR_EL1.TCF0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagCheckMode {
+    /// Tag check faults have no effect.
+    Ignore = 0b00,
+    /// Tag check faults cause a synchronous data abort.
+    Sync = 0b01,
+    /// Tag check faults are accumulated in TFSR_EL1 or TFSRE0_EL1.
+    Async = 0b10,
+    /// Reads fault synchronously and writes asynchronously (FEAT_MTE3).
+    Asymmetric = 0b11,
+}
+
+/// Returns the SCTLR_EL1 bits allowing tag accesses at EL1 and EL0, with the given tag check
+/// modes.
+fn sctlr_bits(el1: TagCheckMode, el0: TagCheckMode) -> u64 {
+    SCTLR_ATA
+        | SCTLR_ATA0
+        | SCTLR_ITFSB
+        | (el1 as u64) << SCTLR_TCF_SHIFT
+        | (el0 as u64) << SCTLR_TCF0_SHIFT
+}
+
+/// Enables tag accesses at EL1 and EL0 (SCTLR_EL1.ATA and ATA0), with the given tag check modes.
+///
+/// Asynchronous faults are also synchronized into TFSR_EL1 on taking an exception to EL1
+/// (SCTLR_EL1.ITFSB).
+///
+/// # Safety
+///
+/// With checks enabled, tagged memory must only be accessed through pointers with the right
+/// logical tag.
+#[inline]
+pub unsafe fn enable(el1: TagCheckMode, el0: TagCheckMode) {
+    SCTLR_EL1.set(SCTLR_EL1.get() & !SCTLR_MTE_MASK | sctlr_bits(el1, el0));
+    isb();
+}
+
+/// Sets the tags that [`random_tag`] must not generate (GCR_EL1.Exclude), one bit per tag, and
+/// selects the generator seeded by [`seed`].
+#[inline]
+pub fn set_exclude(exclude: u16) {
+    GCR_EL1.write(GCR_EL1::Exclude.val(exclude as u64));
+    unsafe { isb() };
+}
+
+/// Seeds the pseudo-random tag generator of [`random_tag`] (RGSR_EL1.SEED).
+///
+/// The seed must not be zero, or the generator only generates the tag 0.
+#[inline]
+pub fn seed(seed: u16) {
+    RGSR_EL1.write(RGSR_EL1::SEED.val(seed as u64));
+    unsafe { isb() };
+}
+
+/// The asynchronous tag check faults accumulated since they were last taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AsyncTagFaults {
+    /// A fault was detected on an address of the lower VA range.
+    pub ttbr0: bool,
+    /// A fault was detected on an address of the upper VA range.
+    pub ttbr1: bool,
+}
+
+impl AsyncTagFaults {
+    fn from_tfsr(tfsr: u64) -> Self {
+        Self {
+            ttbr0: tfsr & 1 != 0,
+            ttbr1: tfsr & 2 != 0,
+        }
+    }
+
+    /// Returns whether a fault was detected.
+    pub fn any(&self) -> bool {
+        self.ttbr0 || self.ttbr1
+    }
+}
+
+/// Returns and clears the asynchronous tag check faults of EL1 (TFSR_EL1).
+#[inline]
+pub fn take_async_faults() -> AsyncTagFaults {
+    // make sure the faults of the preceding accesses are recorded
+    unsafe {
+        dsb(NSH);
+        isb();
+    }
+    let faults = AsyncTagFaults::from_tfsr(TFSR_EL1.get());
+    TFSR_EL1.set(0);
+    faults
+}
+
+/// Returns and clears the asynchronous tag check faults of EL0 (TFSRE0_EL1), e.g. on a context
+/// switch.
+#[inline]
+pub fn take_el0_async_faults() -> AsyncTagFaults {
+    let faults = AsyncTagFaults::from_tfsr(TFSRE0_EL1.get());
+    TFSRE0_EL1.set(0);
+    faults
+}
+
+/// Returns the logical tag of a pointer.
+pub const fn logical_tag(ptr: u64) -> u8 {
+    (ptr >> TAG_SHIFT) as u8 & 0xf
+}
+
+/// Returns `ptr` with its logical tag replaced by `tag`.
+pub const fn with_tag(ptr: u64, tag: u8) -> u64 {
+    ptr & !(0xf << TAG_SHIFT) | ((tag & 0xf) as u64) << TAG_SHIFT
+}
+
+/// Returns `ptr` with a random logical tag that is neither excluded by GCR_EL1 nor in
+/// `exclude` (IRG).
+#[inline]
+pub fn random_tag(ptr: u64, exclude: u16) -> u64 {
+    let tagged;
+    unsafe {
+        core::arch::asm!(
+            ".arch_extension memtag",
+            "irg {tagged}, {ptr}, {exclude}",
+            tagged = out(reg) tagged,
+            ptr = in(reg) ptr,
+            exclude = in(reg) exclude as u64,
+            options(nomem, nostack)
+        )
+    };
+    tagged
+}
+
+/// Returns `ptr` with its logical tag replaced by the allocation tag of the granule it points
+/// to (LDG).
+///
+/// # Safety
+///
+/// `ptr` must point to mapped memory.
+#[inline]
+pub unsafe fn load_tag(ptr: u64) -> u64 {
+    let mut tagged = ptr;
+    core::arch::asm!(
+        ".arch_extension memtag",
+        "ldg {tagged}, [{ptr}]",
+        tagged = inout(reg) tagged,
+        ptr = in(reg) ptr,
+        options(readonly, nostack)
+    );
+    tagged
+}
+
+/// Sets the allocation tags of the `len` bytes at `ptr` to the logical tag of `ptr` (STG and
+/// ST2G).
+///
+/// # Safety
+///
+/// `ptr` and `len` must be aligned to [`TAG_GRANULE`], and the memory must be mapped as Tagged
+/// Normal memory. Pointers with the old tag of the memory no longer pass the tag checks.
+#[inline]
+pub unsafe fn tag_range(ptr: u64, len: usize) {
+    debug_assert!((ptr as usize | len) & (TAG_GRANULE - 1) == 0);
+    let end = ptr + len as u64;
+    let mut addr = ptr;
+    while addr + 2 * TAG_GRANULE as u64 <= end {
+        core::arch::asm!(
+            ".arch_extension memtag",
+            "st2g {addr}, [{addr}]",
+            addr = in(reg) addr,
+            options(nostack)
+        );
+        addr += 2 * TAG_GRANULE as u64;
+    }
+    if addr < end {
+        core::arch::asm!(
+            ".arch_extension memtag",
+            "stg {addr}, [{addr}]",
+            addr = in(reg) addr,
+            options(nostack)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_mte() {
+        assert_eq!(MteSupport::from_pfr1(0x0000), MteSupport::None);
+        assert_eq!(MteSupport::from_pfr1(0x0221), MteSupport::Full);
+        assert_eq!(MteSupport::from_pfr1(0x0300), MteSupport::Asymmetric);
+
+        assert_eq!(
+            sctlr_bits(TagCheckMode::Sync, TagCheckMode::Async),
+            0xc00_0000_0000 | 1 << 40 | 2 << 38 | 1 << 37
+        );
+        assert_eq!(
+            sctlr_bits(TagCheckMode::Asymmetric, TagCheckMode::Asymmetric) & !SCTLR_MTE_MASK,
+            0
+        );
+
+        let ptr = 0xffff_0000_1234_5670;
+        assert_eq!(logical_tag(ptr), 0xf);
+        assert_eq!(with_tag(ptr, 0x3), 0xf3ff_0000_1234_5670);
+        assert_eq!(logical_tag(with_tag(ptr, 0x13)), 0x3);
+
+        assert!(!AsyncTagFaults::from_tfsr(0).any());
+        assert!(AsyncTagFaults::from_tfsr(2).ttbr1);
+    }
+}
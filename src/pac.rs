@@ -0,0 +1,343 @@
+//! Pointer Authentication (PAuth) key management.
+//!
+//! Pointer authentication signs pointers with a Pointer Authentication Code (PAC) stored in their
+//! unused upper bits, computed from the pointer, a 64-bit modifier and one of five 128-bit keys:
+//! two for instruction addresses (IA, IB), two for data addresses (DA, DB) and one for generic
+//! authentication (GA). Instructions using a key do nothing until the key is enabled in
+//! SCTLR_EL1 ([`enable`]), except for the generic key which is always enabled.
+//!
+//! The keys are not banked by Exception level: kernels that use PAC for themselves and their
+//! processes switch keys on entry and exit with [`PacKeys`].
+
+use crate::{barrier::isb, registers::*};
+use bitflags::bitflags;
+
+/// A pointer authentication key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacKey {
+    /// Instruction address key A (APIAKey_EL1).
+    IA = 0,
+    /// Instruction address key B (APIBKey_EL1).
+    IB = 1,
+    /// Data address key A (APDAKey_EL1).
+    DA = 2,
+    /// Data address key B (APDBKey_EL1).
+    DB = 3,
+    /// Generic authentication key (APGAKey_EL1).
+    GA = 4,
+}
+
+impl PacKey {
+    /// All keys, in the order of [`PacKeys::keys`].
+    pub const ALL: [PacKey; 5] = [Self::IA, Self::IB, Self::DA, Self::DB, Self::GA];
+}
+
+bitflags! {
+    /// The SCTLR_EL1 bits enabling the address authentication keys at EL0 and EL1.
+    pub struct PacEnable: u64 {
+        /// Instruction key A (EnIA).
+        const IA = 1 << 31;
+        /// Instruction key B (EnIB).
+        const IB = 1 << 30;
+        /// Data key A (EnDA).
+        const DA = 1 << 27;
+        /// Data key B (EnDB).
+        const DB = 1 << 13;
+    }
+}
+
+/// The pointer authentication features of a PE, from ID_AA64ISAR1_EL1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacSupport {
+    /// Address authentication is implemented, with the QARMA5 or an IMPLEMENTATION DEFINED
+    /// algorithm.
+    pub address: bool,
+    /// Generic authentication (PACGA) is implemented.
+    pub generic: bool,
+}
+
+impl PacSupport {
+    /// Decodes the value of ID_AA64ISAR1_EL1.
+    pub fn from_isar1(isar1: u64) -> Self {
+        let field = |shift: u64| (isar1 >> shift) & 0xf != 0;
+        Self {
+            address: field(4) || field(8),
+            generic: field(24) || field(28),
+        }
+    }
+
+    /// Returns the pointer authentication features of the current PE.
+    #[inline]
+    pub fn current() -> Self {
+        Self::from_isar1(ID_AA64ISAR1_EL1.get())
+    }
+}
+
+/// Returns whether the current PE implements address authentication.
+#[inline]
+pub fn is_supported() -> bool {
+    PacSupport::current().address
+}
+
+macro_rules! key_access {
+    ($($key:ident => $lo:literal, $hi:literal),* $(,)?) => {
+        /// Returns the value of a key.
+        #[inline]
+        pub fn get_key(key: PacKey) -> u128 {
+            match () {
+                #[cfg(target_arch = "aarch64")]
+                () => {
+                    let (lo, hi): (u64, u64);
+                    match key {
+                        $(PacKey::$key => unsafe {
+                            core::arch::asm!(
+                                concat!("mrs {lo}, ", $lo),
+                                concat!("mrs {hi}, ", $hi),
+                                lo = out(reg) lo,
+                                hi = out(reg) hi,
+                                options(nomem, nostack)
+                            )
+                        },)*
+                    }
+                    (hi as u128) << 64 | lo as u128
+                }
+
+                #[cfg(not(target_arch = "aarch64"))]
+                () => unimplemented!("{:?}", key),
+            }
+        }
+
+        /// Sets the value of a key.
+        ///
+        /// The new key takes effect after a context synchronization event, e.g. an ISB or an
+        /// exception return.
+        ///
+        /// # Safety
+        ///
+        /// Pointers signed with the old key, e.g. the return addresses on the stack, fail to
+        /// authenticate with the new one.
+        #[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+        #[inline]
+        pub unsafe fn set_key(key: PacKey, value: u128) {
+            match () {
+                #[cfg(target_arch = "aarch64")]
+                () => match key {
+                    $(PacKey::$key => core::arch::asm!(
+                        concat!("msr ", $lo, ", {lo}"),
+                        concat!("msr ", $hi, ", {hi}"),
+                        lo = in(reg) value as u64,
+                        hi = in(reg) (value >> 64) as u64,
+                        options(nomem, nostack)
+                    ),)*
+                },
+
+                #[cfg(not(target_arch = "aarch64"))]
+                () => unimplemented!(),
+            }
+        }
+    };
+}
+
+// APxxKeyLo_EL1 and APxxKeyHi_EL1, by their encodings
+key_access!(
+    IA => "S3_0_C2_C1_0", "S3_0_C2_C1_1",
+    IB => "S3_0_C2_C1_2", "S3_0_C2_C1_3",
+    DA => "S3_0_C2_C2_0", "S3_0_C2_C2_1",
+    DB => "S3_0_C2_C2_2", "S3_0_C2_C2_3",
+    GA => "S3_0_C2_C3_0", "S3_0_C2_C3_1",
+);
+
+/// The values of all keys, e.g. those of a process saved on a context switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PacKeys {
+    /// The keys, indexed by [`PacKey`].
+    pub keys: [u128; 5],
+}
+
+impl PacKeys {
+    /// Reads the keys of the current PE.
+    #[inline]
+    pub fn save() -> Self {
+        let mut keys = [0; 5];
+        for (value, &key) in keys.iter_mut().zip(PacKey::ALL.iter()) {
+            *value = get_key(key);
+        }
+        Self { keys }
+    }
+
+    /// Writes the keys to the current PE, followed by an ISB.
+    ///
+    /// # Safety
+    ///
+    /// See [`set_key`]. The caller must not return through a frame whose return address was
+    /// signed with the old keys.
+    #[inline]
+    pub unsafe fn restore(&self) {
+        for (&value, &key) in self.keys.iter().zip(PacKey::ALL.iter()) {
+            set_key(key, value);
+        }
+        isb();
+    }
+
+    /// Returns the value of a key.
+    pub fn get(&self, key: PacKey) -> u128 {
+        self.keys[key as usize]
+    }
+
+    /// Sets the value of a key.
+    pub fn set(&mut self, key: PacKey, value: u128) {
+        self.keys[key as usize] = value;
+    }
+}
+
+/// Returns the address authentication keys enabled in SCTLR_EL1.
+#[inline]
+pub fn enabled() -> PacEnable {
+    PacEnable::from_bits_truncate(SCTLR_EL1.get())
+}
+
+/// Enables the given address authentication keys, in addition to those already enabled.
+///
+/// # Safety
+///
+/// The caller must not return through a frame whose return address was left unsigned while the
+/// key was disabled, e.g. when enabling IA from a function compiled with
+/// `-Z branch-protection=pac-ret`.
+#[inline]
+pub unsafe fn enable(keys: PacEnable) {
+    SCTLR_EL1.set(SCTLR_EL1.get() | keys.bits());
+    isb();
+}
+
+/// Disables the given address authentication keys.
+///
+/// # Safety
+///
+/// The caller must not return through a frame whose return address was signed with a key that
+/// is now disabled.
+#[inline]
+pub unsafe fn disable(keys: PacEnable) {
+    SCTLR_EL1.set(SCTLR_EL1.get() & !keys.bits());
+    isb();
+}
+
+/// Removes the PAC from a pointer in software, like the XPAC instructions.
+///
+/// `va_bits` is the size of the VA range of the pointer (64 - TCR_EL1.TxSZ), and `tbi` whether
+/// top byte ignore is enabled for it, in which case bits 63:56 are kept. The PAC bits are
+/// replaced with bit 55, which selects the VA range.
+pub const fn strip(ptr: u64, va_bits: u32, tbi: bool) -> u64 {
+    let top = if tbi { 56 } else { 64 };
+    let pac_mask = ((1u128 << top) - (1u128 << va_bits)) as u64;
+    if ptr & 1 << 55 != 0 {
+        ptr | pac_mask
+    } else {
+        ptr & !pac_mask
+    }
+}
+
+macro_rules! pac_op {
+    ($op:literal, $ptr:ident, $modifier:ident) => {
+        core::arch::asm!(
+            ".arch_extension pauth",
+            concat!($op, " {ptr}, {modifier}"),
+            ptr = inout(reg) $ptr,
+            modifier = in(reg) $modifier,
+            options(nomem, nostack)
+        )
+    };
+}
+
+/// Signs `ptr` with an address key and `modifier` (PACIA, PACIB, PACDA or PACDB).
+///
+/// Returns `ptr` unchanged if the key is not [enabled](enable). Panics if `key` is the generic
+/// key, use [`generic_mac`] instead.
+#[inline]
+pub fn sign(key: PacKey, mut ptr: u64, modifier: u64) -> u64 {
+    unsafe {
+        match key {
+            PacKey::IA => pac_op!("pacia", ptr, modifier),
+            PacKey::IB => pac_op!("pacib", ptr, modifier),
+            PacKey::DA => pac_op!("pacda", ptr, modifier),
+            PacKey::DB => pac_op!("pacdb", ptr, modifier),
+            PacKey::GA => panic!("the generic key does not sign pointers"),
+        }
+    }
+    ptr
+}
+
+/// Authenticates `ptr`, signed with an address key and `modifier`, and removes its PAC
+/// (AUTIA, AUTIB, AUTDA or AUTDB).
+///
+/// If the authentication fails, the result is a non-canonical pointer that faults when used,
+/// or, with FEAT_FPAC, the authentication itself faults. Panics if `key` is the generic key.
+#[inline]
+pub fn auth(key: PacKey, mut ptr: u64, modifier: u64) -> u64 {
+    unsafe {
+        match key {
+            PacKey::IA => pac_op!("autia", ptr, modifier),
+            PacKey::IB => pac_op!("autib", ptr, modifier),
+            PacKey::DA => pac_op!("autda", ptr, modifier),
+            PacKey::DB => pac_op!("autdb", ptr, modifier),
+            PacKey::GA => panic!("the generic key does not sign pointers"),
+        }
+    }
+    ptr
+}
+
+/// Computes the 32-bit generic authentication code of `value` with the generic key and
+/// `modifier` (PACGA).
+#[inline]
+pub fn generic_mac(value: u64, modifier: u64) -> u32 {
+    let mut mac = value;
+    unsafe {
+        core::arch::asm!(
+            ".arch_extension pauth",
+            "pacga {mac}, {mac}, {modifier}",
+            mac = inout(reg) mac,
+            modifier = in(reg) modifier,
+            options(nomem, nostack, pure)
+        )
+    };
+    (mac >> 32) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_pac() {
+        assert_eq!(
+            PacSupport::from_isar1(0x0100_0010),
+            PacSupport {
+                address: true,
+                generic: true
+            }
+        );
+        assert_eq!(
+            PacSupport::from_isar1(0x0011_1001),
+            PacSupport {
+                address: false,
+                generic: false
+            }
+        );
+        assert_eq!(PacEnable::all().bits(), 0xc800_2000);
+
+        // lower range, 48-bit VA
+        assert_eq!(strip(0x002a_0000_1234_5678, 48, false), 0x1234_5678);
+        assert_eq!(
+            strip(0x5a2a_0000_1234_5678, 48, true),
+            0x5a00_0000_1234_5678
+        );
+        // upper range
+        assert_eq!(
+            strip(0xffaa_0000_1234_5678, 48, false),
+            0xffff_0000_1234_5678
+        );
+        assert_eq!(
+            strip(0xff80_1234_5678_0000, 39, false),
+            0xffff_ffb4_5678_0000
+        );
+    }
+}
@@ -0,0 +1,484 @@
+//! Address spaces: a page table hierarchy tagged with an ASID, e.g. the virtual memory of a
+//! process.
+//!
+//! An [`AddressSpace`] owns the frame of its root table and reaches its page tables through a
+//! [`PageTableFrameMapping`]. It maps and unmaps pages through a [`MappedPageTable`], installs
+//! itself in TTBR0_EL1 with [`activate`](AddressSpace::activate), and invalidates the TLB entries
+//! tagged with its ASID when dropped, so that the ASID can be reused. The ASID is either fixed at
+//! creation, or managed by an [`AsidAllocator`] with
+//! [`activate_with`](AddressSpace::activate_with).
+//!
+//! A mapping is either global, shared by all the address spaces (e.g. the kernel), or private to
+//! the address space, with the nG bit set so that its TLB entries are tagged with the ASID. The
+//! TLB entries of a page must be invalidated for the ASID it was mapped with: mapping a page with
+//! the wrong nG bit, or invalidating it for the wrong ASID, leaves stale entries behind.
+//! [`map_range_to`](AddressSpace::map_range_to) and [`unmap_range`](AddressSpace::unmap_range)
+//! take a [`MappingKind`] that sets the nG bit and selects the TLB invalidation accordingly.
+
+use crate::{
+    paging::{
+        asid::{AsidAllocator, AsidContext},
+        frame::PhysFrameRange,
+        frame_alloc::{FrameAllocator, FrameDeallocator},
+        granule::{Granule4KiB, TranslationGranule},
+        mapper::{
+            MapRangeError, MapToError, MappedPageTable, Mapper, MapperFlush, MapperFlushRange,
+            PageTableFrameMapping, Translate, TranslateError, TranslatePage, UnmapError,
+            UnmapRangeError,
+        },
+        page::{Page, PageRange, PageSize},
+        page_table::{PageTableAttribute, PageTableFlags},
+        PhysFrame, UnusedPhysFrame,
+    },
+    percpu::cpu_index,
+    registers::*,
+    translation::{AsidSize, TtbrBuilder, TtbrError},
+    PhysAddr, VirtAddr,
+};
+
+/// Whether a mapping is shared by all address spaces, or private to one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingKind {
+    /// A mapping shared by all address spaces, with the nG bit clear. Its TLB entries match any
+    /// ASID, and are invalidated for all ASIDs.
+    Global,
+    /// A mapping of the address space with the given ASID, with the nG bit set. Its TLB entries
+    /// are invalidated for the ASID only.
+    PerProcess(u16),
+}
+
+impl MappingKind {
+    /// Returns `flags` with the nG bit set or cleared for the kind.
+    pub fn flags(self, flags: PageTableFlags) -> PageTableFlags {
+        match self {
+            MappingKind::Global => flags - PageTableFlags::nG,
+            MappingKind::PerProcess(_) => flags | PageTableFlags::nG,
+        }
+    }
+
+    /// Returns the kind of a mapping with `flags` in the address space with the ASID `asid`.
+    pub fn of(flags: PageTableFlags, asid: u16) -> Self {
+        if flags.contains(PageTableFlags::nG) {
+            MappingKind::PerProcess(asid)
+        } else {
+            MappingKind::Global
+        }
+    }
+
+    /// Flushes the page of `flush` from the TLB, for all ASIDs or only for the ASID of the kind.
+    pub fn flush<S: PageSize>(self, flush: MapperFlush<S>) {
+        match self {
+            MappingKind::Global => flush.flush(),
+            MappingKind::PerProcess(asid) => flush.flush_asid(asid),
+        }
+    }
+
+    /// Flushes the pages of `flush` from the TLB, for all ASIDs or only for the ASID of the kind.
+    pub fn flush_range<S: PageSize>(self, flush: MapperFlushRange<S>) {
+        match self {
+            MappingKind::Global => flush.flush(),
+            MappingKind::PerProcess(asid) => flush.flush_asid(asid),
+        }
+    }
+}
+
+/// A page table hierarchy for the lower VA range (TTBR0_EL1), tagged with an ASID.
+#[derive(Debug)]
+pub struct AddressSpace<P, G = Granule4KiB>
+where
+    P: PageTableFrameMapping<G> + Clone,
+    G: TranslationGranule,
+{
+    root: PhysFrame<G::Page>,
+    asid: u16,
+    context: AsidContext,
+    phys_to_virt: P,
+}
+
+impl<P, G> AddressSpace<P, G>
+where
+    P: PageTableFrameMapping<G> + Clone,
+    G: TranslationGranule,
+{
+    /// Creates an empty address space with the root table in `root`, which is zeroed, and the
+    /// ASID `asid`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that `phys_to_virt` is correct, and that no other address space
+    /// in use has the ASID `asid`. `asid` is ignored if the address space is activated with
+    /// [`activate_with`](AddressSpace::activate_with).
+    pub unsafe fn new(root: UnusedPhysFrame<G::Page>, asid: u16, phys_to_virt: P) -> Self {
+        let root = root.frame();
+        (*phys_to_virt.frame_to_pointer(root)).zero();
+        Self {
+            root,
+            asid,
+            context: AsidContext::default(),
+            phys_to_virt,
+        }
+    }
+
+    /// Returns the frame of the root table.
+    pub fn root(&self) -> PhysFrame<G::Page> {
+        self.root
+    }
+
+    /// Returns the ASID, as given at creation or by the last
+    /// [`activate_with`](AddressSpace::activate_with).
+    pub fn asid(&self) -> u16 {
+        self.asid
+    }
+
+    /// Returns a mapper for the page tables of the address space.
+    pub fn mapper(&mut self) -> MappedPageTable<'_, P, G> {
+        unsafe { MappedPageTable::from_frame(self.root, self.phys_to_virt.clone()) }
+    }
+
+    /// Maps `page` to `frame`, see [`Mapper::map_to`].
+    ///
+    /// The returned flush can be restricted to the address space with
+    /// [`flush_asid`](MapperFlush::flush_asid).
+    pub fn map_to<S, A>(
+        &mut self,
+        page: Page<S>,
+        frame: UnusedPhysFrame<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<S>, MapToError>
+    where
+        S: PageSize<Granule = G>,
+        A: FrameAllocator<G::Page>,
+    {
+        self.mapper()
+            .map_to(page, frame, flags, attr, frame_allocator)
+    }
+
+    /// Removes the mapping of `page`, and returns the frame it was mapped to, see
+    /// [`Mapper::unmap`].
+    pub fn unmap<S>(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError>
+    where
+        S: PageSize<Granule = G>,
+    {
+        self.mapper().unmap(page)
+    }
+
+    /// Returns the kind of the mappings private to the address space, tagged with its ASID.
+    pub fn per_process(&self) -> MappingKind {
+        MappingKind::PerProcess(self.asid)
+    }
+
+    /// Maps each page of `pages` to the frame at the same position in `frames`, with the nG bit
+    /// of `flags` set or cleared for `kind`, see [`Mapper::map_range_to`].
+    ///
+    /// Panics if `kind` is [`PerProcess`](MappingKind::PerProcess) with another ASID than the
+    /// address space.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed `frames` are unused, i.e. not used for any other
+    /// mappings.
+    pub unsafe fn map_range_to<S, A>(
+        &mut self,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        kind: MappingKind,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>>
+    where
+        S: PageSize<Granule = G>,
+        A: FrameAllocator<G::Page>,
+    {
+        self.check_kind(kind);
+        self.mapper()
+            .map_range_to(pages, frames, kind.flags(flags), attr, frame_allocator)
+    }
+
+    /// Removes the mappings of all pages in `pages`, see [`Mapper::unmap_range`], and flushes
+    /// them from the TLB for `kind`: for all ASIDs if [`Global`](MappingKind::Global), for the
+    /// ASID of the address space otherwise.
+    ///
+    /// If unmapping a page fails, the error holds the flush of the pages before it, to be passed
+    /// to [`MappingKind::flush_range`].
+    ///
+    /// Panics if `kind` is [`PerProcess`](MappingKind::PerProcess) with another ASID than the
+    /// address space, or if it is not the [kind](MappingKind::of) of a mapped page of `pages`,
+    /// which would leave its TLB entries for other ASIDs. Nothing is unmapped in this case.
+    pub fn unmap_range<S>(
+        &mut self,
+        pages: PageRange<S>,
+        kind: MappingKind,
+    ) -> Result<(), UnmapRangeError<S>>
+    where
+        S: PageSize<Granule = G>,
+    {
+        self.check_kind(kind);
+        let asid = self.asid;
+        let mapper = self.mapper();
+        for page in pages {
+            if let Ok(entry) = mapper.get_entry(page) {
+                if entry.flags().contains(PageTableFlags::VALID) {
+                    assert_eq!(
+                        MappingKind::of(entry.flags(), asid),
+                        kind,
+                        "mapping kind of another page"
+                    );
+                }
+            }
+        }
+        kind.flush_range(self.mapper().unmap_range(pages)?);
+        Ok(())
+    }
+
+    fn check_kind(&self, kind: MappingKind) {
+        if let MappingKind::PerProcess(asid) = kind {
+            assert_eq!(asid, self.asid, "mapping kind of another address space");
+        }
+    }
+
+    /// Returns the frame `page` is mapped to, see [`TranslatePage::translate_page`].
+    pub fn translate_page<S>(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError>
+    where
+        S: PageSize<Granule = G>,
+    {
+        // references to the page tables only live as long as a `&mut self` borrow
+        let mapper = unsafe { MappedPageTable::from_frame(self.root, self.phys_to_virt.clone()) };
+        mapper.translate_page(page)
+    }
+
+    /// Returns the TTBR0_EL1 value installing the address space, with CnP cleared.
+    pub fn ttbr(&self) -> TtbrBuilder {
+        let asid_size = if self.asid > AsidSize::Bits8.max_asid() {
+            AsidSize::Bits16
+        } else {
+            AsidSize::Bits8
+        };
+        TtbrBuilder::new(PhysFrame::containing_address(self.root.start_address()))
+            .asid(self.asid)
+            .asid_size(asid_size)
+    }
+
+    /// Installs the address space in TTBR0_EL1, with CnP set if the PE implements it
+    /// (ID_AA64MMFR2_EL1.CnP), so that the PEs running the address space share its TLB entries.
+    ///
+    /// Fails if the ASID is 16 bits wide and the PE only supports 8-bit ASIDs.
+    ///
+    /// # Safety
+    ///
+    /// The address space must map the code and data in use at the time of the switch, and
+    /// TCR_EL1 must select TTBR0_EL1 for the ASIDs (TCR_EL1.A1 clear).
+    pub unsafe fn activate(&self) -> Result<(), TtbrError> {
+        self.ttbr()
+            .cnp(ID_AA64MMFR2_EL1.read(ID_AA64MMFR2_EL1::CnP) != 0)
+            .apply(0)
+    }
+
+    /// Installs the address space in TTBR0_EL1 like [`activate`](AddressSpace::activate), with
+    /// an ASID from `allocator` for the current core ([`cpu_index`]).
+    ///
+    /// The address space keeps its ASID as long as the generation of `allocator` doesn't change.
+    /// The TLB of the current core is only flushed when the generation changed since the last
+    /// activation on the core, as required by [`AsidAllocator::activate`].
+    ///
+    /// # Safety
+    ///
+    /// See [`activate`](AddressSpace::activate). All the address spaces must get their ASID from
+    /// `allocator`.
+    pub unsafe fn activate_with<const CPUS: usize>(
+        &mut self,
+        allocator: &mut AsidAllocator<CPUS>,
+    ) -> Result<(), TtbrError> {
+        let (asid, flush) = allocator.activate(cpu_index(), &mut self.context);
+        self.asid = asid;
+        if flush {
+            crate::translation::local_invalidate_tlb_all();
+        }
+        self.activate()
+    }
+
+    /// Frees the empty page tables of the address space, see [`MappedPageTable::clean_up`].
+    ///
+    /// # Safety
+    ///
+    /// See [`MappedPageTable::clean_up`].
+    pub unsafe fn clean_up<D>(&mut self, deallocator: &mut D)
+    where
+        D: FrameDeallocator<G::Page>,
+    {
+        self.mapper().clean_up(deallocator)
+    }
+}
+
+impl<P> AddressSpace<P, Granule4KiB>
+where
+    P: PageTableFrameMapping + Clone,
+{
+    /// Returns the physical address `addr` translates to, see
+    /// [`Translate::translate_addr`].
+    pub fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        // references to the page tables only live as long as a `&mut self` borrow
+        let mapper = unsafe { MappedPageTable::from_frame(self.root, self.phys_to_virt.clone()) };
+        mapper.translate_addr(addr)
+    }
+}
+
+impl<P, G> Drop for AddressSpace<P, G>
+where
+    P: PageTableFrameMapping<G> + Clone,
+    G: TranslationGranule,
+{
+    /// Invalidates the TLB entries tagged with the ASID in all PEs.
+    ///
+    /// The address space must not be active on any PE anymore. The page tables and the root
+    /// frame are not freed.
+    fn drop(&mut self) {
+        crate::translation::invalidate_tlb_asid(self.asid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::{
+        bbm,
+        mapper::IdentityMapping,
+        test_util::{tables, TableAllocator},
+        Size2MiB, Size4KiB,
+    };
+
+    #[test]
+    pub fn test_address_space() {
+        let mut tables = tables::<Granule4KiB, 4>();
+        let mut allocator = TableAllocator::new(&mut tables);
+        let root = allocator.allocate_frame().unwrap();
+        let mut space = unsafe { AddressSpace::new(root, 0x1234, IdentityMapping) };
+        assert_eq!(space.asid(), 0x1234);
+        let attr = PageTableAttribute::new(0, 0, 0);
+
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0x40_1000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_5000));
+        space
+            .map_to(
+                page,
+                unsafe { UnusedPhysFrame::new(frame) },
+                PageTableFlags::default_page(),
+                attr,
+                &mut allocator,
+            )
+            .unwrap()
+            .ignore();
+        assert_eq!(space.translate_page(page).unwrap(), frame);
+        assert_eq!(
+            space.translate_addr(VirtAddr::new(0x40_1234)),
+            Some(PhysAddr::new(0x8000_5234))
+        );
+        assert!(space
+            .translate_page(Page::<Size2MiB>::containing_address(VirtAddr::new(0)))
+            .is_err());
+
+        let (unmapped, flush) = space.unmap(page).unwrap();
+        flush.ignore();
+        bbm::notify_tlb_invalidated();
+        assert_eq!(unmapped, frame);
+        assert_eq!(space.translate_addr(VirtAddr::new(0x40_1234)), None);
+
+        let ttbr = space.ttbr().value(AsidSize::Bits16).unwrap();
+        assert_eq!(ttbr >> 48, 0x1234);
+        assert_eq!(
+            ttbr & 0xffff_ffff_fffe,
+            space.root().start_address().as_u64()
+        );
+        assert_eq!(
+            space.ttbr().value(AsidSize::Bits8),
+            Err(TtbrError::AsidSizeNotSupported)
+        );
+    }
+
+    #[test]
+    pub fn test_mapping_kind() {
+        let mut tables = tables::<Granule4KiB, 4>();
+        let mut allocator = TableAllocator::new(&mut tables);
+        let root = allocator.allocate_frame().unwrap();
+        let mut space = unsafe { AddressSpace::new(root, 7, IdentityMapping) };
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let page = |addr| Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+        let frame = |addr| PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(addr));
+
+        let global = Page::range(page(0x40_0000), page(0x40_2000));
+        let private = Page::range(page(0x40_2000), page(0x40_5000));
+        for (pages, start, kind) in [
+            (global, 0x8000_0000, MappingKind::Global),
+            (private, 0x8000_2000, space.per_process()),
+        ] {
+            unsafe {
+                space.map_range_to(
+                    pages,
+                    PhysFrame::range(frame(start), frame(start + pages.len() * 0x1000)),
+                    PageTableFlags::default_page() | PageTableFlags::nG,
+                    attr,
+                    kind,
+                    &mut allocator,
+                )
+            }
+            .unwrap()
+            .ignore();
+        }
+        bbm::notify_tlb_invalidated();
+        let flags = |space: &mut AddressSpace<_>, addr| {
+            space.mapper().get_entry(page(addr)).unwrap().flags()
+        };
+        assert!(!flags(&mut space, 0x40_1000).contains(PageTableFlags::nG));
+        assert!(flags(&mut space, 0x40_3000).contains(PageTableFlags::nG));
+        assert_eq!(
+            MappingKind::of(flags(&mut space, 0x40_3000), space.asid()),
+            MappingKind::PerProcess(7)
+        );
+        assert_eq!(
+            MappingKind::of(flags(&mut space, 0x40_0000), space.asid()),
+            MappingKind::Global
+        );
+
+        space.unmap_range(private, space.per_process()).unwrap();
+        space.unmap_range(global, MappingKind::Global).unwrap();
+        assert_eq!(space.translate_addr(VirtAddr::new(0x40_3000)), None);
+        assert_eq!(space.translate_addr(VirtAddr::new(0x40_1000)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "mapping kind of another page")]
+    pub fn test_mapping_kind_mismatch() {
+        let mut tables = tables::<Granule4KiB, 4>();
+        let mut allocator = TableAllocator::new(&mut tables);
+        let root = allocator.allocate_frame().unwrap();
+        let mut space = unsafe { AddressSpace::new(root, 7, IdentityMapping) };
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0x40_0000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        space
+            .map_to(
+                page,
+                unsafe { UnusedPhysFrame::new(frame) },
+                PageTableFlags::default_page(),
+                PageTableAttribute::new(0, 0, 0),
+                &mut allocator,
+            )
+            .unwrap()
+            .ignore();
+        bbm::notify_tlb_invalidated();
+        // a global page only flushed for the ASID of the address space
+        let _ = space.unmap_range(Page::range(page, page + 1), space.per_process());
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn test_mapping_kind_other_asid() {
+        let mut tables = tables::<Granule4KiB, 1>();
+        let mut allocator = TableAllocator::new(&mut tables);
+        let root = allocator.allocate_frame().unwrap();
+        let mut space = unsafe { AddressSpace::new(root, 7, IdentityMapping) };
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0x40_0000));
+        let _ = space.unmap_range(Page::range(page, page + 1), MappingKind::PerProcess(8));
+    }
+}
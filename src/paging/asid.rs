@@ -0,0 +1,196 @@
+//! Allocation of ASIDs with generations, flushing the TLB only when ASIDs are reused.
+//!
+//! Each address space carries an [`AsidContext`]: the ASID it was given, tagged with the
+//! generation of the allocator at the time. ASIDs are allocated from a bitmap and are not freed
+//! individually. When they run out, the generation is bumped and the bitmap cleared, except for
+//! the ASIDs running on a core, which keep theirs: the contexts of the old generation get a new
+//! ASID when next activated, and every core flushes its TLB once before using an ASID of the new
+//! generation. As long as the ASIDs last, activating an address space never flushes the TLB.
+//!
+//! The allocator is not synchronized: a kernel keeps it behind a lock, taken on each
+//! [`activate`](AsidAllocator::activate).
+
+use crate::translation::AsidSize;
+
+/// The number of bits of an ASID in a tag.
+const ASID_BITS: u32 = 16;
+/// The number of 64-bit words of the bitmap of 16-bit ASIDs.
+const BITMAP_WORDS: usize = (1 << ASID_BITS) / 64;
+
+/// The ASID of an address space, tagged with the generation it was allocated in.
+///
+/// The default context has no ASID yet, and gets one on its first activation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AsidContext(u64);
+
+impl AsidContext {
+    /// Returns the generation the ASID was allocated in, 0 if none was allocated.
+    pub const fn generation(&self) -> u64 {
+        self.0 >> ASID_BITS
+    }
+
+    /// Returns the ASID, which is only valid for the current generation of the allocator.
+    pub const fn asid(&self) -> u16 {
+        self.0 as u16
+    }
+
+    const fn new(generation: u64, asid: u16) -> Self {
+        AsidContext(generation << ASID_BITS | asid as u64)
+    }
+}
+
+/// An allocator of ASIDs for up to `CPUS` cores.
+///
+/// ASID 0 is never allocated, so that it can be used by the kernel, e.g. while TTBR0_EL1 holds no
+/// user address space.
+#[derive(Debug)]
+pub struct AsidAllocator<const CPUS: usize> {
+    max_asid: u16,
+    generation: u64,
+    used: [u64; BITMAP_WORDS],
+    next: u16,
+    active: [AsidContext; CPUS],
+    reserved: [AsidContext; CPUS],
+    flush_pending: [bool; CPUS],
+}
+
+impl<const CPUS: usize> AsidAllocator<CPUS> {
+    /// Creates an allocator of ASIDs of the given size.
+    ///
+    /// Panics if there are not more ASIDs than cores.
+    pub fn new(asid_size: AsidSize) -> Self {
+        let max_asid = asid_size.max_asid();
+        assert!(CPUS < max_asid as usize, "not enough ASIDs for the cores");
+        let mut allocator = Self {
+            max_asid,
+            generation: 1,
+            used: [0; BITMAP_WORDS],
+            next: 1,
+            active: [AsidContext::default(); CPUS],
+            reserved: [AsidContext::default(); CPUS],
+            flush_pending: [false; CPUS],
+        };
+        allocator.set_used(0);
+        allocator
+    }
+
+    /// Returns the current generation.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the ASID for `context` on the core `cpu`, allocating a new one if `context` is
+    /// from an older generation, and records it as running on `cpu`.
+    ///
+    /// Also returns whether the TLB of `cpu` must be flushed, e.g. with
+    /// [`local_invalidate_tlb_all`](crate::translation::local_invalidate_tlb_all), before the
+    /// ASID is used: once after each generation change.
+    ///
+    /// Panics if `cpu` is not below `CPUS`.
+    pub fn activate(&mut self, cpu: usize, context: &mut AsidContext) -> (u16, bool) {
+        if context.generation() != self.generation {
+            *context = self.new_context(*context);
+        }
+        self.active[cpu] = *context;
+        let flush = core::mem::replace(&mut self.flush_pending[cpu], false);
+        (context.asid(), flush)
+    }
+
+    /// Returns a context of the current generation for `old`, keeping its ASID if possible.
+    fn new_context(&mut self, old: AsidContext) -> AsidContext {
+        if old.generation() != 0 {
+            let new = AsidContext::new(self.generation, old.asid());
+            // still running on a core at the last rollover: the ASID was kept for it
+            let mut reserved = false;
+            for context in self.reserved.iter_mut().filter(|context| **context == old) {
+                *context = new;
+                reserved = true;
+            }
+            if reserved || !self.is_used(old.asid()) {
+                self.set_used(old.asid());
+                return new;
+            }
+        }
+
+        let asid = match self.find_free() {
+            Some(asid) => asid,
+            None => {
+                self.rollover();
+                self.find_free().expect("no ASID left after a rollover")
+            }
+        };
+        self.set_used(asid);
+        self.next = asid.wrapping_add(1);
+        AsidContext::new(self.generation, asid)
+    }
+
+    /// Starts a new generation: frees all the ASIDs but the ones running on a core, and
+    /// schedules a TLB flush on all the cores.
+    fn rollover(&mut self) {
+        self.generation += 1;
+        self.used = [0; BITMAP_WORDS];
+        self.set_used(0);
+        for cpu in 0..CPUS {
+            // a core that didn't activate anything since the last rollover still runs its
+            // reserved ASID
+            let context = core::mem::take(&mut self.active[cpu]);
+            if context.generation() != 0 {
+                self.reserved[cpu] = context;
+            }
+            let asid = self.reserved[cpu].asid();
+            self.set_used(asid);
+        }
+        self.flush_pending = [true; CPUS];
+        self.next = 1;
+    }
+
+    /// Returns the first free ASID from `next`, wrapping around.
+    fn find_free(&self) -> Option<u16> {
+        let count = self.max_asid as usize + 1;
+        (0..count)
+            .map(|offset| ((self.next as usize + offset) % count) as u16)
+            .find(|&asid| !self.is_used(asid))
+    }
+
+    fn is_used(&self, asid: u16) -> bool {
+        self.used[asid as usize / 64] & 1 << (asid % 64) != 0
+    }
+
+    fn set_used(&mut self, asid: u16) {
+        self.used[asid as usize / 64] |= 1 << (asid % 64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_asid_allocator() {
+        let mut allocator = AsidAllocator::<2>::new(AsidSize::Bits8);
+        let mut first = AsidContext::default();
+        assert_eq!(allocator.activate(0, &mut first), (1, false));
+        assert_eq!(first.generation(), 1);
+        // same generation: same ASID, no flush
+        assert_eq!(allocator.activate(1, &mut first), (1, false));
+
+        let mut running = AsidContext::default();
+        assert_eq!(allocator.activate(1, &mut running), (2, false));
+        for asid in 3..=255 {
+            let mut context = AsidContext::default();
+            assert_eq!(allocator.activate(0, &mut context), (asid, false));
+        }
+
+        // the ASIDs are exhausted: rollover, keeping the ASIDs running on the cores (255 on
+        // core 0, 2 on core 1)
+        let mut context = AsidContext::default();
+        assert_eq!(allocator.activate(0, &mut context), (1, true));
+        assert_eq!(allocator.generation(), 2);
+        assert_eq!(allocator.activate(1, &mut running), (2, true));
+        assert_eq!(running.generation(), 2);
+        assert_eq!(allocator.activate(1, &mut running), (2, false));
+
+        // `first` lost its ASID to `context`
+        assert_eq!(allocator.activate(1, &mut first), (3, false));
+    }
+}
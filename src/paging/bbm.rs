@@ -0,0 +1,217 @@
+//! Break-before-make checker for debug builds.
+//!
+//! Changing the output address, memory attributes, block size or contiguous hint of a valid
+//! descriptor requires the break-before-make sequence: write an invalid descriptor, invalidate
+//! the TLB, then write the new descriptor. Skipping it can leave conflicting TLB entries that show
+//! up as rare memory corruption.
+//!
+//! With `debug_assertions` enabled, every descriptor write performed through [`PageTableEntry`]
+//! methods is checked against that sequence, and a violation is reported as a fatal fault through
+//! the [fault sink](crate::fault) at the faulty write. The checker tracks invalidated descriptors
+//! by address, and considers any TLB invalidation issued by this crate (or reported with
+//! [`notify_tlb_invalidated`]) as completing the sequence. Only descriptors published through
+//! these methods are tracked: new tables are cleared with [`PageTable::zero`], which bypasses the
+//! checker, and the history of a table is dropped when it is reset or freed, see
+//! [`forget_table`]. In release builds all of this
+//! compiles to nothing, unless the `bbm-check` feature is enabled, e.g. to chase a TLB conflict
+//! abort that only shows up in an optimized kernel.
+//!
+//! When the checker is enabled, [`Mapper::update_flags`] also refuses the flag changes that
+//! require the sequence, see [`requires_break_before_make`], instead of writing them.
+//!
+//! [`Mapper::update_flags`]: super::Mapper::update_flags
+//!
+//! [`PageTableEntry`]: super::PageTableEntry
+//! [`PageTable::zero`]: super::PageTable::zero
+
+use super::{
+    granule::TranslationGranule,
+    page_table::{PageTable, PageTableFlags, ADDR_MASK, MEMORY_ATTR_MASK},
+};
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+use crate::fault::{FaultRecord, FaultSource, Severity};
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Descriptor bits that can only be changed with break-before-make.
+const BBM_MASK: u64 = ADDR_MASK
+    | MEMORY_ATTR_MASK
+    | PageTableFlags::TABLE_OR_PAGE.bits()
+    | PageTableFlags::Contiguous.bits();
+
+/// The number of invalidated descriptors tracked at the same time. When full, the oldest one is
+/// forgotten, which can only hide violations.
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+const TRACKED: usize = 64;
+
+/// Incremented by every TLB invalidation.
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+static TLBI_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Addresses of descriptors that were invalidated, and the TLBI epoch at that time.
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+static PENDING_ENTRY: [AtomicUsize; TRACKED] = [ZERO_USIZE; TRACKED];
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+static PENDING_EPOCH: [AtomicU64; TRACKED] = [ZERO_U64; TRACKED];
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_USIZE: AtomicUsize = AtomicUsize::new(0);
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_U64: AtomicU64 = AtomicU64::new(0);
+
+/// Records a TLB invalidation that covers all descriptors invalidated so far.
+///
+/// The TLB maintenance functions of this crate call this themselves. Kernels that issue their own
+/// TLBI instructions should call it after the completing DSB.
+#[inline]
+pub fn notify_tlb_invalidated() {
+    #[cfg(any(debug_assertions, feature = "bbm-check"))]
+    TLBI_EPOCH.fetch_add(1, Ordering::Release);
+}
+
+/// Forgets the invalidated descriptors tracked in `table`, which is being reset or freed.
+///
+/// The checker tracks descriptors by address, so the next table reusing the memory would inherit
+/// their history. [`PageTable::zero`] and the mappers call this themselves. Kernels that free
+/// page tables on their own should call it before returning the frame to their allocator.
+///
+/// [`PageTable::zero`]: super::PageTable::zero
+#[cfg_attr(
+    not(any(debug_assertions, feature = "bbm-check")),
+    allow(unused_variables)
+)]
+#[inline]
+pub fn forget_table<G: TranslationGranule>(table: &PageTable<G>) {
+    #[cfg(any(debug_assertions, feature = "bbm-check"))]
+    {
+        let raw = table.as_raw();
+        let start = raw.as_ptr() as usize;
+        let range = start..start + core::mem::size_of_val(raw);
+        for pending in PENDING_ENTRY.iter() {
+            let entry = pending.load(Ordering::Acquire);
+            if range.contains(&entry) {
+                let _ = pending.compare_exchange(entry, 0, Ordering::AcqRel, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Returns whether changing a valid descriptor from `old` to `new` requires break-before-make,
+/// i.e. whether both are valid and they differ in their output address, memory attributes,
+/// descriptor type or contiguous hint.
+#[inline]
+pub fn requires_break_before_make(old: u64, new: u64) -> bool {
+    let valid = PageTableFlags::VALID.bits();
+    old & new & valid != 0 && (old ^ new) & BBM_MASK != 0
+}
+
+/// Checks the change of the descriptor at `entry` from `old` to `new`.
+#[cfg_attr(
+    not(any(debug_assertions, feature = "bbm-check")),
+    allow(unused_variables)
+)]
+#[inline]
+pub(crate) fn check_transition(entry: *const u64, old: u64, new: u64) {
+    #[cfg(any(debug_assertions, feature = "bbm-check"))]
+    {
+        let valid = PageTableFlags::VALID.bits();
+        match (old & valid != 0, new & valid != 0) {
+            (true, true) => {
+                if requires_break_before_make(old, new) {
+                    report(format_args!(
+                        "descriptor at {:p} changed from {:#x} to {:#x}",
+                        entry, old, new
+                    ));
+                }
+            }
+            (true, false) => record_invalidated(entry as usize),
+            (false, true) => {
+                if take_invalidated(entry as usize) == Some(TLBI_EPOCH.load(Ordering::Acquire)) {
+                    report(format_args!(
+                        "descriptor at {:p} set to {:#x} without TLB invalidation",
+                        entry, new
+                    ));
+                }
+            }
+            (false, false) => {}
+        }
+    }
+}
+
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+fn report(message: core::fmt::Arguments) {
+    crate::fault::report(&FaultRecord {
+        source: FaultSource::BreakBeforeMake,
+        severity: Severity::Fatal,
+        message,
+    });
+}
+
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+fn record_invalidated(entry: usize) {
+    let epoch = TLBI_EPOCH.load(Ordering::Acquire);
+    let slot = (0..TRACKED)
+        .find(|&i| PENDING_ENTRY[i].load(Ordering::Relaxed) == entry)
+        .or_else(|| {
+            (0..TRACKED).find(|&i| {
+                PENDING_ENTRY[i]
+                    .compare_exchange(0, entry, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            })
+        })
+        .unwrap_or_else(|| NEXT_SLOT.fetch_add(1, Ordering::Relaxed) % TRACKED);
+    PENDING_EPOCH[slot].store(epoch, Ordering::Release);
+    PENDING_ENTRY[slot].store(entry, Ordering::Release);
+}
+
+#[cfg(any(debug_assertions, feature = "bbm-check"))]
+fn take_invalidated(entry: usize) -> Option<u64> {
+    (0..TRACKED).find_map(|i| {
+        let epoch = PENDING_EPOCH[i].load(Ordering::Acquire);
+        PENDING_ENTRY[i]
+            .compare_exchange(entry, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .ok()
+            .map(|_| epoch)
+    })
+}
+
+#[cfg(all(test, any(debug_assertions, feature = "bbm-check")))]
+mod tests {
+    use crate::{
+        paging::{PageTableAttribute, PageTableEntry, PageTableFlags},
+        PhysAddr,
+    };
+
+    #[test]
+    pub fn test_break_before_make() {
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let mut entry = PageTableEntry::new();
+        entry.set_addr(PhysAddr::new(0x1000), PageTableFlags::default_page(), attr);
+        let raw = entry.raw();
+        // permission changes don't need break-before-make
+        assert!(!super::requires_break_before_make(
+            raw,
+            raw | PageTableFlags::AP_RO.bits()
+        ));
+        assert!(super::requires_break_before_make(
+            raw,
+            raw | PageTableFlags::Contiguous.bits()
+        ));
+        entry.set_flags(PageTableFlags::default_page() | PageTableFlags::AP_RO);
+        entry.set_unused();
+        super::notify_tlb_invalidated();
+        entry.set_addr(PhysAddr::new(0x2000), PageTableFlags::default_page(), attr);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn test_break_before_make_violation() {
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let mut entry = PageTableEntry::new();
+        entry.set_addr(PhysAddr::new(0x1000), PageTableFlags::default_page(), attr);
+        entry.set_addr(PhysAddr::new(0x2000), PageTableFlags::default_page(), attr);
+    }
+}
@@ -0,0 +1,250 @@
+//! Copy-on-write sharing of address spaces, the building block of `fork()`.
+//!
+//! [`clone_page_table`] copies a page table hierarchy into another root table, and
+//! [`clone_page_table_chunked`] does so in bounded chunks for real-time kernels. Pages and blocks
+//! are shared between both hierarchies instead of copied: writable mappings become read-only in
+//! both and are marked with the `WRITABLE_SHARED` software flag, read-only mappings are marked
+//! with `READONLY_SHARED`. A write to a shared page then raises a permission fault, and the
+//! kernel copies the page (or takes it back if it is no longer shared) and restores write access
+//! with [`unshared_flags`].
+
+use super::{
+    granule::{TranslationGranule, PAGE_LEVEL},
+    mapper::{MapToError, MapperFlushAll, PageTableFrameMapping},
+    page_table::{AccessPermission, PageTable, PageTableFlags},
+    FrameAllocator, PhysFrame,
+};
+
+/// Clones the hierarchy starting at `src` into the empty root table `dst`, sharing all pages and
+/// blocks copy-on-write.
+///
+/// The translation tables of `dst` are allocated from `allocator`. The mappings of `src` become
+/// read-only, so the returned flush must be performed before `src` is used again. On error, the
+/// mappings shared so far stay read-only, and the TLB must be flushed as well.
+///
+/// Returns `MapToError::FrameAllocationFailed` if a table could not be allocated and
+/// `MapToError::PageAlreadyMapped` if an entry of `dst` is in use where `src` has a mapping.
+///
+/// # Safety
+///
+/// The caller must guarantee that `phys_to_virt` is correct, that `src` and `dst` are the roots
+/// of valid page table hierarchies and that no table is shared between them.
+pub unsafe fn clone_page_table<G, P, A>(
+    src: &mut PageTable<G>,
+    dst: &mut PageTable<G>,
+    phys_to_virt: &P,
+    allocator: &mut A,
+) -> Result<MapperFlushAll, MapToError>
+where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+    A: FrameAllocator<G::Page>,
+{
+    clone_page_table_chunked(src, dst, phys_to_virt, allocator, usize::MAX, || {})
+}
+
+/// Like [`clone_page_table`], but copies at most `entries_per_chunk` valid entries at a time,
+/// calling `yield_now` between chunks, to keep the latency of cloning large hierarchies bounded.
+///
+/// # Safety
+///
+/// See [`clone_page_table`]. In addition, code run by `yield_now` must not write through the
+/// mappings of `src`: their TLB entries are only flushed by the returned flush, so a write could
+/// reach a page already shared with `dst`.
+pub unsafe fn clone_page_table_chunked<G, P, A, F>(
+    src: &mut PageTable<G>,
+    dst: &mut PageTable<G>,
+    phys_to_virt: &P,
+    allocator: &mut A,
+    entries_per_chunk: usize,
+    mut yield_now: F,
+) -> Result<MapperFlushAll, MapToError>
+where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+    A: FrameAllocator<G::Page>,
+    F: FnMut(),
+{
+    assert!(entries_per_chunk != 0, "chunk size must not be zero");
+    let mut chunk = Chunk {
+        left: entries_per_chunk,
+        size: entries_per_chunk,
+        yield_now: &mut yield_now,
+    };
+    let level = G::START_LEVEL;
+    clone_table(src, dst, level, phys_to_virt, allocator, &mut chunk)?;
+    Ok(MapperFlushAll::new())
+}
+
+/// The entries left to copy in the current chunk of [`clone_page_table_chunked`].
+struct Chunk<'a> {
+    left: usize,
+    size: usize,
+    yield_now: &'a mut dyn FnMut(),
+}
+
+unsafe fn clone_table<G, P, A>(
+    src: &mut PageTable<G>,
+    dst: &mut PageTable<G>,
+    level: usize,
+    phys_to_virt: &P,
+    allocator: &mut A,
+    chunk: &mut Chunk,
+) -> Result<(), MapToError>
+where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+    A: FrameAllocator<G::Page>,
+{
+    for (src_entry, dst_entry) in src.iter_mut().zip(dst.iter_mut()) {
+        let flags = src_entry.flags();
+        if !flags.contains(PageTableFlags::VALID) {
+            continue;
+        }
+        if chunk.left == 0 {
+            (chunk.yield_now)();
+            chunk.left = chunk.size;
+        }
+        chunk.left -= 1;
+        if !dst_entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped);
+        }
+
+        if level != PAGE_LEVEL && flags.contains(PageTableFlags::TABLE_OR_PAGE) {
+            let frame = allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?
+                .frame();
+            let dst_table = &mut *phys_to_virt.frame_to_pointer(frame);
+            dst_table.zero();
+            crate::barrier::dsb(crate::barrier::ISHST);
+            dst_entry.set_addr_in::<G>(frame.start_address(), flags, src_entry.attr());
+
+            let src_frame = PhysFrame::containing_address(src_entry.addr_in::<G>());
+            let src_table = &mut *phys_to_virt.frame_to_pointer(src_frame);
+            clone_table(
+                src_table,
+                dst_table,
+                level + 1,
+                phys_to_virt,
+                allocator,
+                chunk,
+            )?;
+        } else if level == PAGE_LEVEL && !flags.contains(PageTableFlags::TABLE_OR_PAGE) {
+            // reserved encoding, treated as invalid by the MMU
+            continue;
+        } else {
+            let flags = shared_flags(flags);
+            src_entry.set_flags(flags);
+            dst_entry.set_addr_in::<G>(src_entry.addr_in::<G>(), flags, src_entry.attr());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the flags of a page or block shared copy-on-write.
+///
+/// Writable mappings become read-only with `WRITABLE_SHARED`, read-only mappings get
+/// `READONLY_SHARED`. The hardware dirty state (`DBM`) is cleared, so that the MMU can't make the
+/// mapping writable again.
+pub fn shared_flags(flags: PageTableFlags) -> PageTableFlags {
+    let ap = AccessPermission::from(flags);
+    if ap.writable() {
+        let ap = if ap.el0_accessible() {
+            AccessPermission::ReadOnly
+        } else {
+            AccessPermission::PrivilegedReadOnly
+        };
+        (flags - PageTableFlags::DBM).with_ap(ap) | PageTableFlags::WRITABLE_SHARED
+    } else if flags.contains(PageTableFlags::WRITABLE_SHARED) {
+        flags
+    } else {
+        flags | PageTableFlags::READONLY_SHARED
+    }
+}
+
+/// Returns the flags of a page or block that is no longer shared, restoring write access if it
+/// was writable before [`shared_flags`].
+pub fn unshared_flags(flags: PageTableFlags) -> PageTableFlags {
+    if flags.contains(PageTableFlags::WRITABLE_SHARED) {
+        let ap = if AccessPermission::from(flags).el0_accessible() {
+            AccessPermission::ReadWrite
+        } else {
+            AccessPermission::PrivilegedReadWrite
+        };
+        (flags - PageTableFlags::WRITABLE_SHARED).with_ap(ap)
+    } else {
+        flags - PageTableFlags::READONLY_SHARED
+    }
+}
+
+/// Returns whether a write fault on a mapping with these flags is a copy-on-write fault.
+pub fn is_cow_fault(flags: PageTableFlags) -> bool {
+    flags.contains(PageTableFlags::VALID | PageTableFlags::WRITABLE_SHARED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        paging::{
+            test_util::{split_tables, tables},
+            Granule4KiB, PageTableAttribute,
+        },
+        PhysAddr,
+    };
+
+    #[test]
+    pub fn test_clone_page_table() {
+        // 5 valid entries: the root, level 1 and level 2 entries, and 2 pages
+        for (chunk, yields) in [(usize::MAX, 0), (2, 2)] {
+            let mut tables = tables::<Granule4KiB, 8>();
+            let (src, rest) = tables.split_first_mut().unwrap();
+            let (src_tables, rest) = rest.split_at_mut(3);
+            let (dst, mut allocator) = split_tables(rest);
+            let attr = PageTableAttribute::new(0, 0, 0);
+            let table_addr = |table: &PageTable| PhysAddr::new(table as *const _ as u64);
+
+            let rw = PageTableFlags::default_page() | PageTableFlags::AP_EL0 | PageTableFlags::DBM;
+            let ro = PageTableFlags::default_page() | PageTableFlags::AP_RO;
+            src_tables[2][0].set_addr(PhysAddr::new(0x1000), rw, attr);
+            src_tables[2][1].set_addr(PhysAddr::new(0x2000), ro, attr);
+            let p1 = table_addr(&src_tables[2]);
+            src_tables[1][0].set_addr(p1, PageTableFlags::default_table(), attr);
+            let p2 = table_addr(&src_tables[1]);
+            src_tables[0][0].set_addr(p2, PageTableFlags::default_table(), attr);
+            src[0].set_addr(
+                table_addr(&src_tables[0]),
+                PageTableFlags::default_table(),
+                attr,
+            );
+
+            let phys_to_virt = |frame: PhysFrame| frame.start_address().as_u64() as *mut PageTable;
+            unsafe {
+                let mut count = 0;
+                clone_page_table_chunked(src, dst, &phys_to_virt, &mut allocator, chunk, || {
+                    count += 1
+                })
+                .unwrap()
+                .ignore();
+                assert_eq!(count, yields);
+            }
+            assert_eq!(allocator.tables.len(), 0);
+
+            let rw_shared = PageTableFlags::default_page()
+                | PageTableFlags::AP_EL0
+                | PageTableFlags::AP_RO
+                | PageTableFlags::WRITABLE_SHARED;
+            let ro_shared = ro | PageTableFlags::READONLY_SHARED;
+            assert_eq!(src_tables[2][0].flags(), rw_shared);
+            assert_eq!(src_tables[2][1].flags(), ro_shared);
+            // the copy of the table of the pages
+            assert_eq!(rest[3][0].flags(), rw_shared);
+            assert_eq!(rest[3][1].addr(), PhysAddr::new(0x2000));
+            assert!(is_cow_fault(rw_shared));
+            assert!(!is_cow_fault(ro_shared));
+            assert_eq!(unshared_flags(rw_shared), rw - PageTableFlags::DBM);
+            assert_eq!(unshared_flags(ro_shared), ro);
+        }
+    }
+}
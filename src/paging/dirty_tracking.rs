@@ -0,0 +1,158 @@
+//! Tracking of written pages, with or without hardware dirty state management.
+//!
+//! With FEAT_HAFDBS and TCR_EL1.HD set, the MMU makes a writable-clean mapping (`DBM` set,
+//! `AP_RO` set) writable on the first write to it, so the page becomes dirty without a fault.
+//! Older cores take a permission fault instead, and the kernel records the write in the
+//! software `DIRTY` flag. `DBM` and the software `WRITE` flag are the same bit, so both schemes
+//! share one encoding:
+//!
+//! - [`tracked_flags`] returns the flags of a writable-clean mapping,
+//! - the permission fault handler calls [`handle_write_fault`], which makes the mapping writable
+//!   and dirty if the MMU didn't,
+//! - [`PageTableEntry::is_dirty`] and [`PageTableEntry::clear_dirty`] read and reset the state of
+//!   both schemes.
+
+use super::page_table::{AccessPermission, PageTableEntry, PageTableFlags};
+use crate::{barrier::isb, registers::*};
+use core::sync::atomic::Ordering;
+
+/// TCR_EL1.HA: hardware management of the access flag.
+const TCR_HA: u64 = 1 << 39;
+/// TCR_EL1.HD: hardware management of the dirty state.
+const TCR_HD: u64 = 1 << 40;
+
+/// How the dirty state of writable-clean mappings is updated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirtyTracking {
+    /// The MMU makes the mapping writable on the first write (FEAT_HAFDBS, TCR_EL1.HD set).
+    Hardware,
+    /// The first write raises a permission fault, handled by [`handle_write_fault`].
+    Software,
+}
+
+impl DirtyTracking {
+    /// Returns the dirty state management of the current PE, from ID_AA64MMFR1_EL1.HAFDBS and
+    /// TCR_EL1.HD.
+    #[inline]
+    pub fn current() -> Self {
+        if is_hardware_supported() && TCR_EL1.get() & TCR_HD != 0 {
+            Self::Hardware
+        } else {
+            Self::Software
+        }
+    }
+}
+
+/// Returns whether the current PE can manage the dirty state in hardware.
+#[inline]
+pub fn is_hardware_supported() -> bool {
+    ID_AA64MMFR1_EL1.matches_all(ID_AA64MMFR1_EL1::HAFDBS::AccessFlagDirtyState)
+}
+
+/// Enables the hardware management of the access flag and the dirty state (TCR_EL1.HA and HD),
+/// if the PE supports it.
+///
+/// Returns whether it is enabled. Without it, [`handle_write_fault`] keeps tracking writes in
+/// software.
+///
+/// # Safety
+///
+/// The kernel must not rely on an access fault being taken for mappings without the access
+/// flag.
+#[inline]
+pub unsafe fn enable_hardware() -> bool {
+    if !is_hardware_supported() {
+        return false;
+    }
+    TCR_EL1.set(TCR_EL1.get() | TCR_HA | TCR_HD);
+    isb();
+    true
+}
+
+/// Returns the flags of a writable-clean mapping with the permissions of `flags`.
+///
+/// Writable mappings become read-only with `DBM` (`WRITE`) set, so that the first write makes
+/// them dirty; read-only mappings are left unchanged.
+pub fn tracked_flags(flags: PageTableFlags) -> PageTableFlags {
+    let ap = AccessPermission::from(flags);
+    if !ap.writable() {
+        return flags;
+    }
+    let ap = if ap.el0_accessible() {
+        AccessPermission::ReadOnly
+    } else {
+        AccessPermission::PrivilegedReadOnly
+    };
+    (flags - PageTableFlags::DIRTY).with_ap(ap) | PageTableFlags::DBM
+}
+
+/// The outcome of [`handle_write_fault`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteFault {
+    /// The mapping was writable-clean, and is now writable and dirty. The TLB entries of the
+    /// page should be invalidated on the current PE.
+    Dirtied,
+    /// The mapping is already writable, e.g. made dirty by another PE or by the MMU, and the
+    /// fault came from a stale TLB entry. The write can be retried.
+    Spurious,
+    /// The mapping is not writable: the fault is a real permission fault, e.g. a copy-on-write
+    /// one.
+    NotWritable,
+}
+
+/// Handles a permission fault of a write to the page or block mapped by `entry`.
+///
+/// A writable-clean mapping is atomically made writable and marked `DIRTY`, so that a
+/// concurrent hardware update of the entry isn't lost.
+pub fn handle_write_fault(entry: &mut PageTableEntry) -> WriteFault {
+    let atomic = entry.atomic();
+    let mut old = atomic.load(Ordering::Acquire);
+    loop {
+        let flags = PageTableFlags::from_bits_truncate(old);
+        if !flags.contains(PageTableFlags::VALID) || !flags.contains(PageTableFlags::DBM) {
+            return WriteFault::NotWritable;
+        }
+        if !flags.contains(PageTableFlags::AP_RO) {
+            return WriteFault::Spurious;
+        }
+        let new = (old & !PageTableFlags::AP_RO.bits()) | PageTableFlags::DIRTY.bits();
+        match atomic.compare_exchange_weak(old, new, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return WriteFault::Dirtied,
+            Err(current) => old = current,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{paging::PageTableAttribute, PhysAddr};
+
+    #[test]
+    pub fn test_dirty_tracking() {
+        let rw = PageTableFlags::default_page() | PageTableFlags::AP_EL0;
+        let ro = PageTableFlags::default_page() | PageTableFlags::AP_RO;
+        assert_eq!(
+            tracked_flags(rw),
+            rw | PageTableFlags::AP_RO | PageTableFlags::DBM
+        );
+        assert_eq!(tracked_flags(ro), ro);
+
+        let mut entry = PageTableEntry::new();
+        let attr = PageTableAttribute::new(0, 0, 0);
+        entry.set_addr(PhysAddr::new(0x1000), tracked_flags(rw), attr);
+        assert!(!entry.is_dirty());
+        assert_eq!(handle_write_fault(&mut entry), WriteFault::Dirtied);
+        assert!(entry.is_dirty());
+        assert!(entry.flags().contains(PageTableFlags::DIRTY));
+        assert_eq!(entry.ap(), AccessPermission::ReadWrite);
+        assert_eq!(handle_write_fault(&mut entry), WriteFault::Spurious);
+
+        assert!(entry.clear_dirty());
+        assert_eq!(entry.flags(), tracked_flags(rw));
+
+        let mut entry = PageTableEntry::new();
+        entry.set_addr(PhysAddr::new(0x2000), ro, attr);
+        assert_eq!(handle_write_fault(&mut entry), WriteFault::NotWritable);
+    }
+}
@@ -7,7 +7,7 @@ use crate::{
 use core::{
     fmt,
     marker::PhantomData,
-    ops::{Add, AddAssign, Sub, SubAssign},
+    ops::{Add, AddAssign, Deref, Sub, SubAssign},
 };
 
 /// A physical memory frame.
@@ -22,7 +22,7 @@ impl<S: PageSize> PhysFrame<S> {
     /// Returns the frame that starts at the given virtual address.
     ///
     /// Returns an error if the address is not correctly aligned (i.e. is not a valid frame start).
-    pub fn from_start_address(address: PhysAddr) -> Result<Self, ()> {
+    pub const fn from_start_address(address: PhysAddr) -> Result<Self, ()> {
         if !address.is_aligned(S::SIZE) {
             return Err(());
         }
@@ -30,7 +30,7 @@ impl<S: PageSize> PhysFrame<S> {
     }
 
     /// Returns the frame that contains the given physical address.
-    pub fn containing_address(address: PhysAddr) -> Self {
+    pub const fn containing_address(address: PhysAddr) -> Self {
         PhysFrame {
             start_address: address.align_down(S::SIZE),
             size: PhantomData,
@@ -38,12 +38,12 @@ impl<S: PageSize> PhysFrame<S> {
     }
 
     /// Returns the start address of the frame.
-    pub fn start_address(&self) -> PhysAddr {
+    pub const fn start_address(&self) -> PhysAddr {
         self.start_address
     }
 
-    /// Returns the size the frame (4KB, 2MB or 1GB).
-    pub fn size(&self) -> u64 {
+    /// Returns the size the frame (e.g. 4KB, 2MB or 1GB).
+    pub const fn size(&self) -> u64 {
         S::SIZE
     }
 
@@ -57,7 +57,7 @@ impl<S: PageSize> PhysFrame<S> {
         PhysFrameRangeInclusive { start, end }
     }
 
-    pub fn of_addr(address: u64) -> Self {
+    pub const fn of_addr(address: u64) -> Self {
         Self::containing_address(PhysAddr::new(address))
     }
 
@@ -109,6 +109,40 @@ impl<S: PageSize> Sub<PhysFrame<S>> for PhysFrame<S> {
     }
 }
 
+/// A physical frame that is not used for any mapping or other purpose.
+///
+/// Returned by [`FrameAllocator::allocate_frame`](super::FrameAllocator::allocate_frame) and
+/// consumed by [`Mapper::map_to`](super::Mapper::map_to), so that the guarantee that a mapped
+/// frame is unused is given once, when the witness is created, instead of at every mapping.
+/// It is deliberately neither `Clone` nor `Copy`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnusedPhysFrame<S: PageSize = Size4KiB>(PhysFrame<S>);
+
+impl<S: PageSize> UnusedPhysFrame<S> {
+    /// Creates the witness that `frame` is unused.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that `frame` is unused, i.e. not used for any mapping or other
+    /// purpose, and that no other `UnusedPhysFrame` for it exists.
+    pub unsafe fn new(frame: PhysFrame<S>) -> Self {
+        UnusedPhysFrame(frame)
+    }
+
+    /// Returns the frame, giving up the witness.
+    pub fn frame(self) -> PhysFrame<S> {
+        self.0
+    }
+}
+
+impl<S: PageSize> Deref for UnusedPhysFrame<S> {
+    type Target = PhysFrame<S>;
+
+    fn deref(&self) -> &PhysFrame<S> {
+        &self.0
+    }
+}
+
 /// An range of physical memory frames, exclusive the upper bound.
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
@@ -124,6 +158,25 @@ impl<S: PageSize> PhysFrameRange<S> {
     pub fn is_empty(&self) -> bool {
         !(self.start < self.end)
     }
+
+    /// Returns the number of frames in the range.
+    pub fn len(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.end - self.start
+        }
+    }
+
+    /// Returns the size of the range in bytes.
+    pub fn size(&self) -> u64 {
+        self.len() * S::SIZE
+    }
+
+    /// Returns whether the range contains `frame`.
+    pub fn contains(&self, frame: PhysFrame<S>) -> bool {
+        self.start <= frame && frame < self.end
+    }
 }
 
 impl<S: PageSize> Iterator for PhysFrameRange<S> {
@@ -138,6 +191,33 @@ impl<S: PageSize> Iterator for PhysFrameRange<S> {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len() as usize;
+        (len, Some(len))
+    }
+}
+
+impl<S: PageSize> DoubleEndedIterator for PhysFrameRange<S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.start < self.end {
+            self.end -= 1;
+            Some(self.end)
+        } else {
+            None
+        }
+    }
+}
+
+impl<S: PageSize> ExactSizeIterator for PhysFrameRange<S> {}
+
+impl<S: PageSize> From<PhysFrameRangeInclusive<S>> for PhysFrameRange<S> {
+    fn from(range: PhysFrameRangeInclusive<S>) -> Self {
+        PhysFrameRange {
+            start: range.start,
+            end: range.end + 1,
+        }
+    }
 }
 
 impl<S: PageSize> fmt::Debug for PhysFrameRange<S> {
@@ -155,7 +235,7 @@ impl<S: PageSize> fmt::Debug for PhysFrameRange<S> {
 pub struct PhysFrameRangeInclusive<S: PageSize = Size4KiB> {
     /// The start of the range, inclusive.
     pub start: PhysFrame<S>,
-    /// The start of the range, exclusive.
+    /// The end of the range, inclusive.
     pub end: PhysFrame<S>,
 }
 
@@ -164,6 +244,25 @@ impl<S: PageSize> PhysFrameRangeInclusive<S> {
     pub fn is_empty(&self) -> bool {
         !(self.start <= self.end)
     }
+
+    /// Returns the number of frames in the range.
+    pub fn len(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.end - self.start + 1
+        }
+    }
+
+    /// Returns the size of the range in bytes.
+    pub fn size(&self) -> u64 {
+        self.len() * S::SIZE
+    }
+
+    /// Returns whether the range contains `frame`.
+    pub fn contains(&self, frame: PhysFrame<S>) -> bool {
+        self.start <= frame && frame <= self.end
+    }
 }
 
 impl<S: PageSize> Iterator for PhysFrameRangeInclusive<S> {
@@ -172,7 +271,12 @@ impl<S: PageSize> Iterator for PhysFrameRangeInclusive<S> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.start <= self.end {
             let frame = self.start.clone();
-            self.start += 1;
+            // the frame after the last one of the physical address space is not valid: the range
+            // is then emptied by moving its end below its start instead
+            match PhysAddr::try_new(frame.start_address().as_u64() + S::SIZE) {
+                Ok(next) => self.start = PhysFrame::containing_address(next),
+                Err(_) => self.end = frame - 1,
+            }
             Some(frame)
         } else {
             None
@@ -188,3 +292,28 @@ impl<S: PageSize> fmt::Debug for PhysFrameRangeInclusive<S> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_frame_ranges() {
+        let start = PhysFrame::<Size4KiB>::of_addr(0x4000_0000);
+        let range = PhysFrame::range(start, start + 4);
+        assert_eq!(range.len(), 4);
+        assert_eq!(range.size(), 0x4000);
+        assert!(range.contains(start + 3) && !range.contains(start + 4));
+        assert_eq!(range.clone().next_back(), Some(start + 3));
+        assert_eq!(range.step_by(2).nth(1), Some(start + 2));
+
+        let inclusive = PhysFrame::range_inclusive(start, start + 3);
+        assert_eq!(inclusive.len(), 4);
+        assert!(inclusive.contains(start + 3));
+        assert_eq!(PhysFrameRange::from(inclusive), range);
+        assert_eq!(PhysFrame::range(start + 1, start).len(), 0);
+
+        let last = PhysFrame::<Size4KiB>::of_addr(0x000f_ffff_ffff_f000);
+        assert_eq!(PhysFrame::range_inclusive(last - 1, last).count(), 2);
+    }
+}
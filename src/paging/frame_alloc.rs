@@ -1,18 +1,69 @@
 //! Traits for abstracting away frame allocation and deallocation.
 
-use crate::paging::{PageSize, PhysFrame};
+use crate::paging::{
+    frame::{PhysFrameRange, UnusedPhysFrame},
+    PageSize, PhysFrame,
+};
 
 /// A trait for types that can allocate a frame of memory.
 ///
-/// This trait is unsafe to implement because the implementer must guarantee that
-/// the `allocate_frame` method returns only unique unused frames.
+/// # Safety
+///
+/// The implementer must guarantee that the `allocate_frame` method returns only unique unused
+/// frames, as it creates their [`UnusedPhysFrame`] witnesses.
 pub unsafe trait FrameAllocator<S: PageSize> {
     /// Allocate a frame of the appropriate size and return it if possible.
-    fn allocate_frame(&mut self) -> Option<PhysFrame<S>>;
+    fn allocate_frame(&mut self) -> Option<UnusedPhysFrame<S>>;
+
+    /// Allocate `count` physically contiguous frames, starting at an address aligned to `align`
+    /// bytes, and return them if possible.
+    ///
+    /// `align` must be a power of two. Alignments below the frame size have no effect. This
+    /// allows backing huge pages or DMA buffers with frames of the same allocator.
+    ///
+    /// The default implementation doesn't support contiguous allocations and returns `None`.
+    fn allocate_contiguous(&mut self, count: usize, align: u64) -> Option<PhysFrameRange<S>> {
+        let _ = (count, align);
+        None
+    }
+}
+
+unsafe impl<S: PageSize, A: FrameAllocator<S> + ?Sized> FrameAllocator<S> for &mut A {
+    #[inline]
+    fn allocate_frame(&mut self) -> Option<UnusedPhysFrame<S>> {
+        (**self).allocate_frame()
+    }
+
+    #[inline]
+    fn allocate_contiguous(&mut self, count: usize, align: u64) -> Option<PhysFrameRange<S>> {
+        (**self).allocate_contiguous(count, align)
+    }
 }
 
 /// A trait for types that can deallocate a frame of memory.
 pub trait FrameDeallocator<S: PageSize> {
     /// Deallocate the given frame of memory.
     fn deallocate_frame(&mut self, frame: PhysFrame<S>);
+
+    /// Deallocate the given contiguous frames, e.g. returned by
+    /// [`FrameAllocator::allocate_contiguous`].
+    ///
+    /// The default implementation deallocates the frames one by one.
+    fn deallocate_contiguous(&mut self, frames: PhysFrameRange<S>) {
+        for frame in frames {
+            self.deallocate_frame(frame);
+        }
+    }
+}
+
+impl<S: PageSize, D: FrameDeallocator<S> + ?Sized> FrameDeallocator<S> for &mut D {
+    #[inline]
+    fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        (**self).deallocate_frame(frame)
+    }
+
+    #[inline]
+    fn deallocate_contiguous(&mut self, frames: PhysFrameRange<S>) {
+        (**self).deallocate_contiguous(frames)
+    }
 }
@@ -0,0 +1,328 @@
+//! Translation granules: the 4KiB, 16KiB and 64KiB translation table formats.
+//!
+//! The granule determines the page size, the size of a translation table (one page), the number
+//! of bits resolved at each lookup level and the available block sizes:
+//!
+//! | Granule | Entries per table | Level 1 block | Level 2 block | Page (level 3) |
+//! |---------|-------------------|---------------|---------------|----------------|
+//! | 4KiB    | 512               | 1GiB          | 2MiB          | 4KiB           |
+//! | 16KiB   | 2048              | -             | 32MiB         | 16KiB          |
+//! | 64KiB   | 8192              | -             | 512MiB        | 64KiB          |
+//!
+//! Lookup levels are numbered as in the ARM ARM, from 0 (the level 4 table of this crate, `p4`)
+//! to 3 (the level 1 table, `p1`). A 48-bit virtual address is assumed, or a 52-bit one with the
+//! 64KiB granule and the `lva` feature: see [`TranslationGranule::VA_BITS`].
+
+use super::{
+    page::{PageSize, Size16KiB, Size4KiB, Size64KiB},
+    page_table::PageTableEntry,
+};
+use crate::{registers::TCR_EL1, VirtAddr};
+use core::fmt;
+use tock_registers::fields::FieldValue;
+
+/// The lookup level of the translation tables that map pages (not blocks).
+pub const PAGE_LEVEL: usize = 3;
+
+/// A lookup level of the translation tables, numbered as in the ARM ARM.
+///
+/// [`PageTableLevel::L0`] is the level 4 table of this crate (`p4`), [`PageTableLevel::L3`] the
+/// level 1 table (`p1`), which maps pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum PageTableLevel {
+    /// Level 0, the root with the 4KiB and 16KiB granules.
+    L0 = 0,
+    /// Level 1, with 1GiB blocks with the 4KiB granule.
+    L1 = 1,
+    /// Level 2, with 2MiB, 32MiB or 512MiB blocks.
+    L2 = 2,
+    /// Level 3, which maps pages.
+    L3 = 3,
+}
+
+impl PageTableLevel {
+    /// Returns the level of the given number, or `None` if it is above 3.
+    pub const fn new(level: usize) -> Option<Self> {
+        match level {
+            0 => Some(PageTableLevel::L0),
+            1 => Some(PageTableLevel::L1),
+            2 => Some(PageTableLevel::L2),
+            3 => Some(PageTableLevel::L3),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of the level, from 0 to 3.
+    pub const fn number(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the level of the tables pointed to by the entries of this level, or `None` for
+    /// level 3.
+    pub const fn next_lower(self) -> Option<Self> {
+        Self::new(self as usize + 1)
+    }
+
+    /// Returns the level of the tables pointing to the tables of this level, or `None` for
+    /// level 0.
+    pub const fn next_higher(self) -> Option<Self> {
+        match self {
+            PageTableLevel::L0 => None,
+            _ => Self::new(self as usize - 1),
+        }
+    }
+
+    /// Returns the size of the virtual memory translated by one entry of this level with the
+    /// granule `G`, to which the addresses it translates are aligned.
+    pub fn entry_address_space_alignment<G: TranslationGranule>(self) -> u64 {
+        G::Page::SIZE << ((PAGE_LEVEL - self.number()) as u32 * G::INDEX_BITS)
+    }
+
+    /// Returns the size of the virtual memory translated by a whole table of this level with the
+    /// granule `G`.
+    pub fn table_address_space_alignment<G: TranslationGranule>(self) -> u64 {
+        self.entry_address_space_alignment::<G>() << G::INDEX_BITS
+    }
+
+    /// Returns the index of the entry translating `addr` in a table of this level with the
+    /// granule `G`.
+    #[inline]
+    pub fn table_index<G: TranslationGranule>(self, addr: VirtAddr) -> usize {
+        G::table_index(addr, self.number())
+    }
+}
+
+/// Returns the mask of the virtual address bits translated by the translation tables of the
+/// granule `G`.
+#[inline]
+pub(crate) const fn va_mask<G: TranslationGranule>() -> u64 {
+    (1 << G::VA_BITS) - 1
+}
+
+/// Trait for abstracting over the three translation granules of aarch64, 4KiB, 16KiB and 64KiB.
+pub trait TranslationGranule: Copy + Eq + Ord + fmt::Debug {
+    /// The page size, which is also the size of a translation table.
+    type Page: PageSize;
+
+    /// The entries of a translation table, aligned to the table size.
+    type Entries: TableEntries;
+
+    /// The number of virtual address bits resolved by one lookup level.
+    const INDEX_BITS: u32;
+
+    /// The initial lookup level for a 48-bit virtual address.
+    const START_LEVEL: usize;
+
+    /// The size of the virtual addresses translated by the tables, in bits: 52 for the 64KiB
+    /// granule with the `lva` feature, 48 otherwise.
+    const VA_BITS: u32 = if cfg!(feature = "lva") && Self::Page::SIZE == 0x10000 {
+        52
+    } else {
+        48
+    };
+
+    /// The size of the output addresses of the descriptors, in bits: 52 for the 64KiB granule
+    /// with the `lpa` feature, 48 otherwise.
+    const OA_BITS: u32 = if cfg!(feature = "lpa") && Self::Page::SIZE == 0x10000 {
+        52
+    } else {
+        48
+    };
+
+    /// The TCR_EL1.TG0 value selecting this granule for TTBR0_EL1.
+    const TCR_TG0: FieldValue<u64, TCR_EL1::Register>;
+
+    /// The TCR_EL1.TG1 value selecting this granule for TTBR1_EL1.
+    const TCR_TG1: FieldValue<u64, TCR_EL1::Register>;
+
+    /// A translation table with all entries unused.
+    const EMPTY_TABLE: Self::Entries;
+
+    /// Returns the number of entries of a contiguous run at the given lookup level: the entries
+    /// that must map contiguous memory with identical attributes to carry the contiguous hint.
+    #[inline]
+    fn contiguous_entries(level: usize) -> usize {
+        match (Self::Page::SIZE, level) {
+            (0x1000, _) => 16,
+            (0x4000, PAGE_LEVEL) => 128,
+            _ => 32,
+        }
+    }
+
+    /// Returns the index of the entry translating `addr` in a table of the given lookup level.
+    #[inline]
+    fn table_index(addr: VirtAddr, level: usize) -> usize {
+        debug_assert!((Self::START_LEVEL..=PAGE_LEVEL).contains(&level));
+        let shift =
+            Self::Page::SIZE.trailing_zeros() + (PAGE_LEVEL - level) as u32 * Self::INDEX_BITS;
+        ((addr.as_u64() & va_mask::<Self>()) >> shift) as usize & ((1 << Self::INDEX_BITS) - 1)
+    }
+}
+
+/// The storage of the entries of a translation table.
+///
+/// Implemented for an array of entries per granule, with the alignment required by the MMU.
+pub trait TableEntries: Copy + AsRef<[PageTableEntry]> + AsMut<[PageTableEntry]> {}
+
+/// The 4KiB translation granule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Granule4KiB {}
+
+/// The 16KiB translation granule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Granule16KiB {}
+
+/// The 64KiB translation granule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Granule64KiB {}
+
+macro_rules! granule {
+    (
+        $granule:ident, $page:ident, $entries:ident,
+        align = $align:literal, index_bits = $bits:literal, start_level = $start:literal,
+        tg0 = $tg0:ident, tg1 = $tg1:ident
+    ) => {
+        /// The entries of a translation table of the granule.
+        #[derive(Clone, Copy)]
+        #[repr(C, align($align))]
+        pub struct $entries([PageTableEntry; 1 << $bits]);
+
+        impl AsRef<[PageTableEntry]> for $entries {
+            #[inline]
+            fn as_ref(&self) -> &[PageTableEntry] {
+                &self.0
+            }
+        }
+
+        impl AsMut<[PageTableEntry]> for $entries {
+            #[inline]
+            fn as_mut(&mut self) -> &mut [PageTableEntry] {
+                &mut self.0
+            }
+        }
+
+        impl TableEntries for $entries {}
+
+        // no padding: the size of the entries is the alignment
+        #[cfg(feature = "bytemuck")]
+        unsafe impl bytemuck::Zeroable for $entries {}
+
+        #[cfg(feature = "bytemuck")]
+        unsafe impl bytemuck::Pod for $entries {}
+
+        impl TranslationGranule for $granule {
+            type Page = $page;
+            type Entries = $entries;
+            const INDEX_BITS: u32 = $bits;
+            const START_LEVEL: usize = $start;
+            const TCR_TG0: FieldValue<u64, TCR_EL1::Register> = TCR_EL1::TG0::$tg0;
+            const TCR_TG1: FieldValue<u64, TCR_EL1::Register> = TCR_EL1::TG1::$tg1;
+            const EMPTY_TABLE: Self::Entries = $entries([PageTableEntry::new(); 1 << $bits]);
+        }
+    };
+}
+
+granule!(
+    Granule4KiB,
+    Size4KiB,
+    Entries4KiB,
+    align = 4096,
+    index_bits = 9,
+    start_level = 0,
+    tg0 = KiB_4,
+    tg1 = KiB_4
+);
+granule!(
+    Granule16KiB,
+    Size16KiB,
+    Entries16KiB,
+    align = 16384,
+    index_bits = 11,
+    start_level = 0,
+    tg0 = KiB_16,
+    tg1 = KiB_16
+);
+granule!(
+    Granule64KiB,
+    Size64KiB,
+    Entries64KiB,
+    align = 65536,
+    index_bits = 13,
+    start_level = 1,
+    tg0 = KiB_64,
+    tg1 = KiB_64
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_table_index() {
+        let addr = VirtAddr::new(0xffff_8123_4567_89ab);
+        assert_eq!(
+            Granule4KiB::table_index(addr, 0),
+            u16::from(addr.p4_index()) as usize
+        );
+        assert_eq!(
+            Granule4KiB::table_index(addr, 3),
+            u16::from(addr.p1_index()) as usize
+        );
+
+        assert_eq!(Granule16KiB::table_index(addr, 0), 1);
+        assert_eq!(Granule16KiB::table_index(addr, 1), 0x012);
+        assert_eq!(Granule16KiB::table_index(addr, 2), 0x1a2);
+        assert_eq!(Granule16KiB::table_index(addr, 3), 0x59e);
+
+        // with 52-bit addresses, bits 51:48 of the upper VA range are set
+        #[cfg(not(feature = "lva"))]
+        assert_eq!(Granule64KiB::table_index(addr, 1), 0x20);
+        #[cfg(feature = "lva")]
+        assert_eq!(Granule64KiB::table_index(addr, 1), 0x3e0);
+        assert_eq!(Granule64KiB::table_index(addr, 2), 0x91a);
+        assert_eq!(Granule64KiB::table_index(addr, 3), 0x567);
+    }
+
+    #[test]
+    pub fn test_page_table_level() {
+        assert_eq!(PageTableLevel::new(2), Some(PageTableLevel::L2));
+        assert_eq!(PageTableLevel::new(4), None);
+        assert_eq!(PageTableLevel::L0.next_lower(), Some(PageTableLevel::L1));
+        assert_eq!(PageTableLevel::L3.next_lower(), None);
+        assert_eq!(PageTableLevel::L1.next_higher(), Some(PageTableLevel::L0));
+        assert_eq!(PageTableLevel::L0.next_higher(), None);
+
+        assert_eq!(
+            PageTableLevel::L3.entry_address_space_alignment::<Granule4KiB>(),
+            0x1000
+        );
+        assert_eq!(
+            PageTableLevel::L1.entry_address_space_alignment::<Granule4KiB>(),
+            0x4000_0000
+        );
+        assert_eq!(
+            PageTableLevel::L0.table_address_space_alignment::<Granule4KiB>(),
+            1 << 48
+        );
+        assert_eq!(
+            PageTableLevel::L2.entry_address_space_alignment::<Granule16KiB>(),
+            0x200_0000
+        );
+        assert_eq!(
+            PageTableLevel::L2.entry_address_space_alignment::<Granule64KiB>(),
+            0x2000_0000
+        );
+
+        let addr = VirtAddr::new(0xffff_8123_4567_89ab);
+        assert_eq!(
+            addr.page_table_index(PageTableLevel::L0),
+            u16::from(addr.p4_index()) as usize
+        );
+        assert_eq!(
+            addr.page_table_index(PageTableLevel::L2),
+            u16::from(addr.p2_index()) as usize
+        );
+        assert_eq!(PageTableLevel::L1.table_index::<Granule16KiB>(addr), 0x012);
+    }
+}
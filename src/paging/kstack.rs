@@ -0,0 +1,208 @@
+//! Kernel stacks with a guard page.
+//!
+//! A stack grows down, so a stack overflow writes below its lowest page. [`map_stack`] maps the
+//! pages of a stack above a guard page that stays unmapped, so that an overflow faults instead of
+//! silently corrupting the memory below, and returns a [`KernelStack`] with the initial stack
+//! pointer ([`top`](KernelStack::top)). Fault handlers can recognize a stack overflow with
+//! [`is_guard`](KernelStack::is_guard).
+
+use crate::{
+    paging::{
+        frame_alloc::{FrameAllocator, FrameDeallocator},
+        granule::TranslationGranule,
+        mapper::{EntryGetError, MapToError, Mapper, UnmapError},
+        page::{Page, PageRange, PageSize, Size4KiB},
+        page_table::{PageTableAttribute, PageTableFlags},
+    },
+    VirtAddr,
+};
+
+/// A kernel stack mapped by [`map_stack`]: pages of size `S` above an unmapped guard page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelStack<S: PageSize = Size4KiB> {
+    guard: Page<S>,
+    pages: PageRange<S>,
+}
+
+impl<S: PageSize> KernelStack<S> {
+    /// Returns the guard page, right below the stack.
+    pub fn guard(&self) -> Page<S> {
+        self.guard
+    }
+
+    /// Returns the mapped pages of the stack.
+    pub fn pages(&self) -> PageRange<S> {
+        self.pages
+    }
+
+    /// Returns the lowest address of the stack, right above the guard page.
+    pub fn bottom(&self) -> VirtAddr {
+        self.pages.start.start_address()
+    }
+
+    /// Returns the address after the highest byte of the stack, the initial stack pointer.
+    ///
+    /// It is page aligned, so it meets the 16-byte alignment of SP.
+    pub fn top(&self) -> VirtAddr {
+        self.pages.end.start_address()
+    }
+
+    /// Returns the size of the stack in bytes, without the guard page.
+    pub fn size(&self) -> u64 {
+        self.pages.len() * S::SIZE
+    }
+
+    /// Returns whether `addr` is in the stack.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.bottom() && addr < self.top()
+    }
+
+    /// Returns whether `addr` is in the guard page, e.g. the fault address of a stack overflow.
+    pub fn is_guard(&self, addr: VirtAddr) -> bool {
+        Page::containing_address(addr) == self.guard
+    }
+}
+
+/// Returns the flags of the pages of a kernel stack: privileged read-write, not executable at any
+/// exception level, and global.
+pub fn stack_flags() -> PageTableFlags {
+    PageTableFlags::default_page() | PageTableFlags::PXN | PageTableFlags::UXN
+}
+
+/// Maps a kernel stack of `pages` pages right above the page `guard`, which must not be mapped
+/// and is left unmapped.
+///
+/// The frames of the stack and the page tables are allocated from `allocator`, and the pages are
+/// mapped with [`stack_flags`] and the memory attribute `attr`, e.g. the index of Normal memory in
+/// MAIR_EL1. The frames are not zeroed. If mapping a page fails, the pages mapped before it are
+/// unmapped again and their frames returned to `allocator`.
+///
+/// Returns `MapToError::PageAlreadyMapped` if the guard page or a page of the stack is already
+/// mapped, and `MapToError::ParentEntryHugePage` if one of them is part of a block.
+///
+/// Panics if `pages` is 0.
+pub fn map_stack<M, S, A>(
+    mapper: &mut M,
+    guard: Page<S>,
+    pages: u64,
+    attr: PageTableAttribute,
+    allocator: &mut A,
+) -> Result<KernelStack<S>, MapToError>
+where
+    M: Mapper<S>,
+    S: PageSize,
+    S::Granule: TranslationGranule<Page = S>,
+    A: FrameAllocator<S> + FrameDeallocator<S>,
+{
+    assert!(pages != 0, "kernel stack without pages");
+    match mapper.get_entry(guard) {
+        Ok(entry) if !entry.is_unused() => return Err(MapToError::PageAlreadyMapped),
+        Err(EntryGetError::ParentEntryHugePage) => return Err(MapToError::ParentEntryHugePage),
+        _ => {}
+    }
+
+    let stack = KernelStack {
+        guard,
+        pages: Page::range(guard + 1, guard + 1 + pages),
+    };
+    for page in stack.pages {
+        let result = match allocator.allocate_frame() {
+            Some(frame) => {
+                let unused = *frame;
+                mapper
+                    .map_to(page, frame, stack_flags(), attr, allocator)
+                    .inspect_err(|_| allocator.deallocate_frame(unused))
+            }
+            None => Err(MapToError::FrameAllocationFailed),
+        };
+        match result {
+            // the page was not mapped, so the TLB holds no entries for it
+            Ok(flush) => flush.ignore(),
+            Err(error) => {
+                for mapped in Page::range(stack.pages.start, page) {
+                    if let Ok((frame, flush)) = mapper.unmap(mapped) {
+                        flush.flush();
+                        allocator.deallocate_frame(frame);
+                    }
+                }
+                return Err(error);
+            }
+        }
+    }
+    Ok(stack)
+}
+
+/// Unmaps the pages of `stack`, invalidates their TLB entries in all PEs, and frees their frames
+/// to `deallocator`.
+///
+/// The page tables are not freed.
+///
+/// # Safety
+///
+/// The stack must not be in use by any PE, and the pages must still map the frames of the stack,
+/// which are not used for anything else.
+pub unsafe fn unmap_stack<M, S, D>(
+    mapper: &mut M,
+    stack: KernelStack<S>,
+    deallocator: &mut D,
+) -> Result<(), UnmapError>
+where
+    M: Mapper<S>,
+    S: PageSize,
+    D: FrameDeallocator<S>,
+{
+    for page in stack.pages {
+        let (frame, flush) = mapper.unmap(page)?;
+        // the frame can only be reused once no PE can access it anymore
+        flush.flush();
+        deallocator.deallocate_frame(frame);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::{
+        mapper::TranslatePage,
+        test_util::{mapped_page_table, tables},
+        Granule4KiB,
+    };
+
+    #[test]
+    pub fn test_map_stack() {
+        let mut tables = tables::<Granule4KiB, 7>();
+        let (mut mapper, mut allocator) = mapped_page_table(&mut tables);
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let guard = Page::<Size4KiB>::containing_address(VirtAddr::new(0x40_0000));
+
+        let stack = map_stack(&mut mapper, guard, 2, attr, &mut allocator).unwrap();
+        assert_eq!(stack.bottom(), VirtAddr::new(0x40_1000));
+        assert_eq!(stack.top(), VirtAddr::new(0x40_3000));
+        assert_eq!(stack.size(), 0x2000);
+        assert!(stack.contains(VirtAddr::new(0x40_2ff0)));
+        assert!(!stack.contains(stack.top()));
+        assert!(stack.is_guard(VirtAddr::new(0x40_0ff8)));
+        assert!(mapper.get_entry(guard).unwrap().is_unused());
+        let flags = mapper.get_entry(guard + 1).unwrap().flags();
+        assert!(flags.contains(PageTableFlags::PXN | PageTableFlags::UXN));
+        assert!(!flags.contains(PageTableFlags::AP_EL0));
+
+        // the guard page of another stack can't be a page of this one
+        assert!(matches!(
+            map_stack(&mut mapper, guard + 2, 1, attr, &mut allocator),
+            Err(MapToError::PageAlreadyMapped)
+        ));
+        // out of frames after the first page: the page is unmapped again
+        assert!(matches!(
+            map_stack(&mut mapper, guard + 4, 2, attr, &mut allocator),
+            Err(MapToError::FrameAllocationFailed)
+        ));
+        assert_eq!(allocator.freed, 1);
+        assert!(mapper.get_entry(guard + 5).unwrap().is_unused());
+
+        unsafe { unmap_stack(&mut mapper, stack, &mut allocator) }.unwrap();
+        assert_eq!(allocator.freed, 3);
+        assert!(mapper.get_entry(guard + 1).unwrap().is_unused());
+    }
+}
@@ -0,0 +1,184 @@
+//! Object-safe counterparts of [`Mapper`] and [`MapperAllSizes`].
+//!
+//! [`Mapper`] takes the frame allocator as a type parameter, so it can't be used as a trait
+//! object. [`DynMapper`] and [`DynMapperAllSizes`] take `&mut dyn FrameAllocator` instead and are
+//! implemented for all mappers, so that a kernel can store a `&mut dyn DynMapperAllSizes` in its
+//! address space structures without being generic over the page table type.
+//!
+//! The methods have the same names as those of [`Mapper`], so they are meant to be called on
+//! trait objects: calling them on a concrete mapper with both traits in scope is ambiguous.
+
+use super::*;
+
+/// An object-safe subset of [`Mapper`], implemented for all mappers.
+pub trait DynMapper<S: PageSize> {
+    /// Creates a new mapping in the page table, see [`Mapper::map_to`].
+    fn map_to(
+        &mut self,
+        page: Page<S>,
+        frame: UnusedPhysFrame<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        frame_allocator: &mut dyn FrameAllocator<<S::Granule as TranslationGranule>::Page>,
+    ) -> Result<MapperFlush<S>, MapToError>;
+
+    /// Maps the given frames to the given pages, see [`Mapper::map_range_to`].
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that passed `frames` are unused, i.e. not used for any other
+    /// mappings.
+    unsafe fn map_range_to(
+        &mut self,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        frame_allocator: &mut dyn FrameAllocator<<S::Granule as TranslationGranule>::Page>,
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>>;
+
+    /// Removes a mapping from the page table, see [`Mapper::unmap`].
+    fn unmap(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError>;
+
+    /// Removes the mappings of all pages in `pages`, see [`Mapper::unmap_range`].
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, UnmapRangeError<S>>;
+
+    /// Updates the flags of an existing mapping, see [`Mapper::update_flags`].
+    fn update_flags(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> Result<MapperFlush<S>, FlagUpdateError>;
+
+    /// Returns the frame that the given page is mapped to, see [`TranslatePage::translate_page`].
+    fn translate_page(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError>;
+}
+
+impl<S: PageSize, M: Mapper<S>> DynMapper<S> for M {
+    #[inline]
+    fn map_to(
+        &mut self,
+        page: Page<S>,
+        frame: UnusedPhysFrame<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        mut frame_allocator: &mut dyn FrameAllocator<<S::Granule as TranslationGranule>::Page>,
+    ) -> Result<MapperFlush<S>, MapToError> {
+        Mapper::map_to(self, page, frame, flags, attr, &mut frame_allocator)
+    }
+
+    #[inline]
+    unsafe fn map_range_to(
+        &mut self,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        mut frame_allocator: &mut dyn FrameAllocator<<S::Granule as TranslationGranule>::Page>,
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>> {
+        Mapper::map_range_to(self, pages, frames, flags, attr, &mut frame_allocator)
+    }
+
+    #[inline]
+    fn unmap(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError> {
+        Mapper::unmap(self, page)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, UnmapRangeError<S>> {
+        Mapper::unmap_range(self, pages)
+    }
+
+    #[inline]
+    fn update_flags(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> Result<MapperFlush<S>, FlagUpdateError> {
+        Mapper::update_flags(self, page, flags)
+    }
+
+    #[inline]
+    fn translate_page(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError> {
+        TranslatePage::translate_page(self, page)
+    }
+}
+
+/// An object-safe counterpart of [`MapperAllSizes`], implemented for all mappers of the 4KiB
+/// granule.
+pub trait DynMapperAllSizes:
+    DynMapper<Size4KiB> + DynMapper<Size2MiB> + DynMapper<Size1GiB>
+{
+    /// Returns the frame that the given virtual address is mapped to and the offset within that
+    /// frame, see [`Translate::translate`].
+    fn translate(&self, addr: VirtAddr) -> TranslateResult;
+
+    /// Returns the physical address, flags and permissions of the mapping of the given virtual
+    /// address, see [`Translate::translate_with_flags`].
+    fn translate_with_flags(&self, addr: VirtAddr) -> Result<Translation, TranslateError>;
+
+    /// Translates the given virtual address to the physical address that it maps to, see
+    /// [`Translate::translate_addr`].
+    fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr>;
+}
+
+impl<M: MapperAllSizes> DynMapperAllSizes for M {
+    #[inline]
+    fn translate(&self, addr: VirtAddr) -> TranslateResult {
+        Translate::translate(self, addr)
+    }
+
+    #[inline]
+    fn translate_with_flags(&self, addr: VirtAddr) -> Result<Translation, TranslateError> {
+        Translate::translate_with_flags(self, addr)
+    }
+
+    #[inline]
+    fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        Translate::translate_addr(self, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::{
+        granule::Granule16KiB,
+        test_util::{mapped_page_table, tables},
+        Size16KiB,
+    };
+
+    #[test]
+    pub fn test_dyn_mapper() {
+        let mut tables = tables::<Granule16KiB, 4>();
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+        let mapper: &mut dyn DynMapper<Size16KiB> = &mut page_table;
+        let allocator: &mut dyn FrameAllocator<Size16KiB> = &mut allocator;
+
+        let page = Page::containing_address(VirtAddr::new(0x1234_0000_4000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        unsafe {
+            mapper
+                .map_to(
+                    page,
+                    UnusedPhysFrame::new(frame),
+                    PageTableFlags::default_page(),
+                    PageTableAttribute::new(0, 0, 0),
+                    allocator,
+                )
+                .unwrap()
+                .ignore();
+        }
+        assert_eq!(mapper.translate_page(page).unwrap(), frame);
+        let (unmapped, flush) = mapper.unmap(page).unwrap();
+        flush.ignore();
+        crate::paging::bbm::notify_tlb_invalidated();
+        assert_eq!(unmapped, frame);
+    }
+}
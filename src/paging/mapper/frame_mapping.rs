@@ -0,0 +1,102 @@
+//! Access to page table frames through a physical to virtual address conversion.
+//!
+//! [`MappedPageTable`], the [walker](crate::paging::walk), [copy-on-write
+//! cloning](crate::paging::cow) and the [`Stage2Mapper`](crate::paging::stage2::Stage2Mapper)
+//! reach the page tables of a hierarchy through a [`PageTableFrameMapping`]. A custom scheme,
+//! e.g. a window of fixed mappings, only needs to implement it once to work with all of them.
+//!
+//! A [`RecursivePageTable`](super::RecursivePageTable) doesn't use this trait: the address of a
+//! table there depends on its position in the hierarchy, not on its frame.
+//!
+//! [`MappedPageTable`]: super::MappedPageTable
+
+use crate::{
+    paging::{
+        frame::PhysFrame,
+        granule::{Granule4KiB, TranslationGranule},
+        page_table::PageTable,
+    },
+    VirtAddr,
+};
+
+/// Converts the physical frame of a page table to a pointer through which it can be accessed.
+///
+/// Implemented for all closures of type `Fn(PhysFrame<G::Page>) -> *mut PageTable<G>`.
+pub trait PageTableFrameMapping<G: TranslationGranule = Granule4KiB> {
+    /// Returns a pointer to the page table stored in `frame`.
+    fn frame_to_pointer(&self, frame: PhysFrame<G::Page>) -> *mut PageTable<G>;
+}
+
+impl<F, G> PageTableFrameMapping<G> for F
+where
+    G: TranslationGranule,
+    F: Fn(PhysFrame<G::Page>) -> *mut PageTable<G>,
+{
+    #[inline]
+    fn frame_to_pointer(&self, frame: PhysFrame<G::Page>) -> *mut PageTable<G> {
+        self(frame)
+    }
+}
+
+/// The mapping of the complete physical memory at an offset in the virtual address space, as
+/// used by [`OffsetPageTable`](super::OffsetPageTable).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysOffset {
+    phys_offset: VirtAddr,
+}
+
+impl PhysOffset {
+    /// Creates a mapping where physical address `p` is accessed at virtual address
+    /// `phys_offset + p`.
+    pub const fn new(phys_offset: VirtAddr) -> Self {
+        Self { phys_offset }
+    }
+
+    /// Returns the offset at which the physical memory is mapped.
+    pub const fn phys_offset(&self) -> VirtAddr {
+        self.phys_offset
+    }
+}
+
+impl<G: TranslationGranule> PageTableFrameMapping<G> for PhysOffset {
+    #[inline]
+    fn frame_to_pointer(&self, frame: PhysFrame<G::Page>) -> *mut PageTable<G> {
+        (self.phys_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+}
+
+/// The identity mapping: page tables are accessed at their physical address, e.g. before the
+/// MMU is enabled or with the physical memory identity-mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IdentityMapping;
+
+impl<G: TranslationGranule> PageTableFrameMapping<G> for IdentityMapping {
+    #[inline]
+    fn frame_to_pointer(&self, frame: PhysFrame<G::Page>) -> *mut PageTable<G> {
+        frame.start_address().as_u64() as *mut PageTable<G>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{paging::Granule64KiB, PhysAddr};
+
+    #[test]
+    pub fn test_frame_mapping() {
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8_1234_5000));
+        let offset = PhysOffset::new(VirtAddr::new(0xffff_8000_0000_0000));
+        assert_eq!(
+            PageTableFrameMapping::<Granule4KiB>::frame_to_pointer(&offset, frame) as u64,
+            0xffff_8008_1234_5000
+        );
+        assert_eq!(
+            PageTableFrameMapping::<Granule4KiB>::frame_to_pointer(&IdentityMapping, frame) as u64,
+            0x8_1234_5000
+        );
+
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x4_0001_0000));
+        let table: *mut PageTable<Granule64KiB> = offset.frame_to_pointer(frame);
+        assert_eq!(table as u64, 0xffff_8004_0001_0000);
+    }
+}
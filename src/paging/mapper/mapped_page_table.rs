@@ -1,11 +1,14 @@
 //! Access the page tables through a normal level 4 table.
 
+use core::marker::PhantomData;
+
 use crate::paging::{
-    frame::PhysFrame,
-    frame_alloc::FrameAllocator,
+    frame::{PhysFrame, PhysFrameRange},
+    frame_alloc::{FrameAllocator, FrameDeallocator},
+    granule::{va_mask, Granule4KiB, PageTableLevel, TranslationGranule, PAGE_LEVEL},
     mapper::*,
-    page::{Page, Size1GiB, Size2MiB, Size4KiB},
-    page_table::{FrameError, PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
+    page::{Page, PageRange, PageRangeInclusive, PageSize},
+    page_table::{PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
 };
 
 /// A Mapper implementation that relies on a PhysAddr to VirtAddr conversion function.
@@ -15,289 +18,565 @@ use crate::paging::{
 /// the virtual address space at some offset. Other mappings between physical and virtual
 /// memory are possible too, as long as they can be calculated as an `PhysAddr` to
 /// `VirtAddr` closure.
+///
+/// The page tables use the translation granule `G` (4KiB by default), and page table frames are
+/// of the granule's page size.
 #[derive(Debug)]
-pub struct MappedPageTable<'a, PhysToVirt>
+pub struct MappedPageTable<'a, PhysToVirt, G = Granule4KiB>
 where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
+    G: TranslationGranule,
+    PhysToVirt: PageTableFrameMapping<G>,
 {
-    page_table_walker: PageTableWalker<PhysToVirt>,
-    level_4_table: &'a mut PageTable,
+    page_table_walker: PageTableWalker<PhysToVirt, G>,
+    level_4_table: &'a mut PageTable<G>,
+    level_4_addr: Option<PhysAddr>,
 }
 
-impl<'a, PhysToVirt> MappedPageTable<'a, PhysToVirt>
+impl<'a, PhysToVirt, G> MappedPageTable<'a, PhysToVirt, G>
 where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
+    G: TranslationGranule,
+    PhysToVirt: PageTableFrameMapping<G>,
 {
-    /// Creates a new `MappedPageTable` that uses the passed closure for converting virtual
-    /// to physical addresses.
+    /// Creates a new `MappedPageTable` that uses the passed closure (or other
+    /// [`PageTableFrameMapping`]) for converting physical to virtual addresses.
     ///
     /// This function is unsafe because the caller must guarantee that the passed `phys_to_virt`
     /// closure is correct. Also, the passed `level_4_table` must point to the level 4 page table
     /// of a valid page table hierarchy. Otherwise this function might break memory safety, e.g.
     /// by writing to an illegal memory location.
-    pub unsafe fn new(level_4_table: &'a mut PageTable, phys_to_virt: PhysToVirt) -> Self {
+    pub unsafe fn new(level_4_table: &'a mut PageTable<G>, phys_to_virt: PhysToVirt) -> Self {
         Self {
             level_4_table,
             page_table_walker: PageTableWalker::new(phys_to_virt),
+            level_4_addr: None,
         }
     }
 
-    /// Helper function for implementing Mapper. Safe to limit the scope of unsafe, see
-    /// https://github.com/rust-lang/rfcs/pull/2585.
-    fn map_to_1gib<A>(
-        &mut self,
-        page: Page<Size1GiB>,
-        frame: PhysFrame<Size1GiB>,
-        flags: PageTableFlags,
-        attr: PageTableAttribute,
-        allocator: &mut A,
-    ) -> Result<MapperFlush<Size1GiB>, MapToError>
+    /// Creates a new `MappedPageTable` for the hierarchy whose level 4 table is stored in
+    /// `level_4_frame`.
+    ///
+    /// The hierarchy doesn't need to be active, so this can be used to build a new address
+    /// space, e.g. for a user process, and then install it with [`switch_to`].
+    ///
+    /// [`switch_to`]: MappedPageTable::switch_to
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed `phys_to_virt` closure is correct, and that
+    /// `level_4_frame` holds the level 4 table of a valid page table hierarchy (e.g. a zeroed
+    /// one), which is not accessed by other means for the lifetime `'a`.
+    pub unsafe fn from_frame(level_4_frame: PhysFrame<G::Page>, phys_to_virt: PhysToVirt) -> Self {
+        let level_4_table = &mut *phys_to_virt.frame_to_pointer(level_4_frame);
+        Self {
+            level_4_table,
+            page_table_walker: PageTableWalker::new(phys_to_virt),
+            level_4_addr: Some(level_4_frame.start_address()),
+        }
+    }
+
+    /// Returns the frame of the level 4 table, if the page table was created with
+    /// [`from_frame`](MappedPageTable::from_frame).
+    pub fn level_4_frame(&self) -> Option<PhysFrame<G::Page>> {
+        self.level_4_addr.map(PhysFrame::containing_address)
+    }
+
+    /// Installs the page table for the lower VA range (TTBR0_EL1) with the given ASID, see
+    /// [`switch_ttbr0`](crate::translation::switch_ttbr0).
+    ///
+    /// Panics if the page table was not created with [`from_frame`](MappedPageTable::from_frame).
+    ///
+    /// # Safety
+    ///
+    /// The page table must map the code and data in use at the time of the switch, and the TLB
+    /// must not hold entries of another address space tagged with `asid`.
+    pub unsafe fn switch_to(&self, asid: u16) {
+        let addr = self
+            .level_4_addr
+            .expect("the frame of the level 4 table is unknown");
+        crate::translation::switch_ttbr0(addr, asid);
+    }
+
+    /// Returns a mutable reference to the wrapped level 4 page table.
+    pub fn level_4_table(&mut self) -> &mut PageTable<G> {
+        self.level_4_table
+    }
+
+    /// Returns the conversion from page table frames to pointers used by this mapper.
+    pub fn page_table_frame_mapping(&self) -> &PhysToVirt {
+        &self.page_table_walker.phys_to_virt
+    }
+
+    /// Frees all empty page tables of the hierarchy to `deallocator`.
+    ///
+    /// The level 4 table itself is never freed. The TLB is not flushed, so if the hierarchy is
+    /// active the caller must invalidate it before the frames are reused.
+    ///
+    /// # Safety
+    ///
+    /// The page tables must not be shared with other hierarchies, and the freed frames must not
+    /// be in use by other means.
+    pub unsafe fn clean_up<D>(&mut self, deallocator: &mut D)
     where
-        A: FrameAllocator<Size4KiB>,
+        D: FrameDeallocator<G::Page>,
     {
-        let p4 = &mut self.level_4_table;
-        let p3 = self
-            .page_table_walker
-            .create_next_table(&mut p4[page.p4_index()], allocator)?;
+        self.clean_up_range(0, va_mask::<G>(), false, deallocator);
+    }
 
-        if !p3[page.p3_index()].is_unused() {
-            return Err(MapToError::PageAlreadyMapped);
-        }
-        p3[page.p3_index()].set_block::<Size1GiB>(frame.start_address(), flags, attr);
+    /// Frees the empty page tables translating addresses of `range` to `deallocator`.
+    ///
+    /// Tables that only partly translate `range` are freed too if they are empty. See
+    /// [`clean_up`](MappedPageTable::clean_up) for the TLB requirements.
+    ///
+    /// # Safety
+    ///
+    /// See [`clean_up`](MappedPageTable::clean_up).
+    pub unsafe fn clean_up_addr_range<D>(
+        &mut self,
+        range: PageRangeInclusive<G::Page>,
+        deallocator: &mut D,
+    ) where
+        D: FrameDeallocator<G::Page>,
+    {
+        let (start, end) = Self::range_bounds(range);
+        self.clean_up_range(start, end, false, deallocator);
+    }
 
-        Ok(MapperFlush::new(page))
+    /// Unmaps all pages and blocks of `range` and frees their frames, as pages of the granule,
+    /// to `deallocator`, then frees the tables that became empty as in
+    /// [`clean_up_addr_range`](MappedPageTable::clean_up_addr_range).
+    ///
+    /// Blocks that only partly lie in `range` are kept.
+    ///
+    /// # Safety
+    ///
+    /// The mapped frames must be owned by this hierarchy, e.g. allocated from the allocator
+    /// backing `deallocator` and mapped only once. See [`clean_up`](MappedPageTable::clean_up)
+    /// for the other requirements.
+    pub unsafe fn free_addr_range<D>(
+        &mut self,
+        range: PageRangeInclusive<G::Page>,
+        deallocator: &mut D,
+    ) where
+        D: FrameDeallocator<G::Page>,
+    {
+        let (start, end) = Self::range_bounds(range);
+        self.clean_up_range(start, end, true, deallocator);
+    }
+
+    /// Returns the first and last address of `range` within its VA range.
+    fn range_bounds(range: PageRangeInclusive<G::Page>) -> (u64, u64) {
+        let start = range.start.start_address().as_u64() & va_mask::<G>();
+        let end = range.end.start_address().as_u64() & va_mask::<G>();
+        (start, end + (G::Page::SIZE - 1))
+    }
+
+    unsafe fn clean_up_range<D>(
+        &mut self,
+        start: u64,
+        end: u64,
+        free_leaves: bool,
+        deallocator: &mut D,
+    ) where
+        D: FrameDeallocator<G::Page>,
+    {
+        if start > end {
+            return;
+        }
+        let walker = &self.page_table_walker;
+        walker.clean_up_table(
+            self.level_4_table,
+            G::START_LEVEL,
+            0,
+            (start, end),
+            free_leaves,
+            deallocator,
+        );
     }
 
     /// Helper function for implementing Mapper. Safe to limit the scope of unsafe, see
     /// https://github.com/rust-lang/rfcs/pull/2585.
-    fn map_to_2mib<A>(
+    fn map_to_level<S, A>(
         &mut self,
-        page: Page<Size2MiB>,
-        frame: PhysFrame<Size2MiB>,
+        page: Page<S>,
+        frame: UnusedPhysFrame<S>,
         flags: PageTableFlags,
         attr: PageTableAttribute,
         allocator: &mut A,
-    ) -> Result<MapperFlush<Size2MiB>, MapToError>
+    ) -> Result<MapperFlush<S>, MapToError>
     where
-        A: FrameAllocator<Size4KiB>,
+        S: PageSize<Granule = G>,
+        A: FrameAllocator<G::Page>,
     {
-        let p4 = &mut self.level_4_table;
-        let p3 = self
-            .page_table_walker
-            .create_next_table(&mut p4[page.p4_index()], allocator)?;
-        let p2 = self
-            .page_table_walker
-            .create_next_table(&mut p3[page.p3_index()], allocator)?;
-
-        if !p2[page.p2_index()].is_unused() {
-            return Err(MapToError::PageAlreadyMapped);
-        }
-        p2[page.p2_index()].set_block::<Size2MiB>(frame.start_address(), flags, attr);
-
+        let table = self.create_leaf_table(page, allocator)?;
+        Self::map_entry(
+            &mut table[page.table_index(S::LEVEL)],
+            frame.frame(),
+            flags,
+            attr,
+        )?;
         Ok(MapperFlush::new(page))
     }
 
-    /// Helper function for implementing Mapper. Safe to limit the scope of unsafe, see
-    /// https://github.com/rust-lang/rfcs/pull/2585.
-    fn map_to_4kib<A>(
+    /// Maps all pages of `pages`, walking the page tables only once per leaf table.
+    fn map_range_to_level<S, A>(
         &mut self,
-        page: Page<Size4KiB>,
-        frame: PhysFrame<Size4KiB>,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
         flags: PageTableFlags,
         attr: PageTableAttribute,
         allocator: &mut A,
-    ) -> Result<MapperFlush<Size4KiB>, MapToError>
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>>
     where
-        A: FrameAllocator<Size4KiB>,
+        S: PageSize<Granule = G>,
+        A: FrameAllocator<G::Page>,
     {
-        let p4 = &mut self.level_4_table;
-        let p3 = self
-            .page_table_walker
-            .create_next_table(&mut p4[page.p4_index()], allocator)?;
-        let p2 = self
-            .page_table_walker
-            .create_next_table(&mut p3[page.p3_index()], allocator)?;
-        let p1 = self
-            .page_table_walker
-            .create_next_table(&mut p2[page.p2_index()], allocator)?;
-
-        if !p1[page.p1_index()].is_unused() {
-            return Err(MapToError::PageAlreadyMapped);
+        assert_eq!(
+            pages.end - pages.start,
+            frames.end - frames.start,
+            "page and frame ranges differ in length"
+        );
+        let mut table: Option<*mut PageTable<G>> = None;
+        for (page, frame) in pages.zip(frames) {
+            let index = page.table_index(S::LEVEL);
+            let leaf = match table {
+                Some(leaf) if index != 0 => leaf,
+                _ => self
+                    .create_leaf_table(page, allocator)
+                    .map(|table| table as *mut _)
+                    .map_err(|error| RangeError::new(pages, page, error))?,
+            };
+            table = Some(leaf);
+            // only entries of the leaf table are changed, so the cached table stays valid
+            let entry = unsafe { &mut (&mut *leaf)[index] };
+            Self::map_entry(entry, frame, flags, attr)
+                .map_err(|error| RangeError::new(pages, page, error))?;
         }
-        p1[page.p1_index()].set_frame(frame, flags, attr);
+        Ok(MapperFlushRange::new(pages))
+    }
 
-        Ok(MapperFlush::new(page))
+    /// Unmaps all pages of `pages`, walking the page tables only once per leaf table.
+    fn unmap_range_level<S>(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, UnmapRangeError<S>>
+    where
+        S: PageSize<Granule = G>,
+    {
+        let mut table: Option<*mut PageTable<G>> = None;
+        for page in pages {
+            let index = page.table_index(S::LEVEL);
+            let leaf = match table {
+                Some(leaf) if index != 0 => leaf,
+                _ => self
+                    .leaf_table(page)
+                    .map(|table| table as *mut _)
+                    .map_err(|error| RangeError::new(pages, page, error.into()))?,
+            };
+            table = Some(leaf);
+            let entry = unsafe { &mut (&mut *leaf)[index] };
+            Self::unmap_entry::<S>(entry).map_err(|error| RangeError::new(pages, page, error))?;
+        }
+        Ok(MapperFlushRange::new(pages))
     }
-}
 
-impl<'a, PhysToVirt> Mapper<Size1GiB> for MappedPageTable<'a, PhysToVirt>
-where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
-{
-    unsafe fn map_to<A>(
+    /// Returns the table containing the entry of `page`, creating missing tables on the way.
+    fn create_leaf_table<S, A>(
         &mut self,
-        page: Page<Size1GiB>,
-        frame: PhysFrame<Size1GiB>,
-        flags: PageTableFlags,
-        attr: PageTableAttribute,
+        page: Page<S>,
         allocator: &mut A,
-    ) -> Result<MapperFlush<Size1GiB>, MapToError>
+    ) -> Result<&mut PageTable<G>, MapToError>
     where
-        A: FrameAllocator<Size4KiB>,
+        S: PageSize<Granule = G>,
+        A: FrameAllocator<G::Page>,
     {
-        self.map_to_1gib(page, frame, flags, attr, allocator)
+        check_canonical(page);
+        let walker = &self.page_table_walker;
+        let mut table = &mut *self.level_4_table;
+        for level in G::START_LEVEL..S::LEVEL {
+            table = walker.create_next_table(&mut table[page.table_index(level)], allocator)?;
+        }
+        Ok(table)
     }
 
-    fn unmap(
-        &mut self,
-        page: Page<Size1GiB>,
-    ) -> Result<(PhysFrame<Size1GiB>, MapperFlush<Size1GiB>), UnmapError> {
-        let entry = self.get_entry_mut(page)?;
+    /// Returns the table containing the entry of `page`.
+    fn leaf_table<S>(&mut self, page: Page<S>) -> Result<&mut PageTable<G>, EntryGetError>
+    where
+        S: PageSize<Granule = G>,
+    {
+        check_canonical(page);
+        let walker = &self.page_table_walker;
+        let mut table = &mut *self.level_4_table;
+        for level in G::START_LEVEL..S::LEVEL {
+            table = walker.next_table_mut(&mut table[page.table_index(level)])?;
+        }
+        Ok(table)
+    }
+
+    fn map_entry<S: PageSize>(
+        entry: &mut PageTableEntry,
+        frame: PhysFrame<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+    ) -> Result<(), MapToError> {
+        if !flags.is_valid_for_page() {
+            return Err(MapToError::InvalidFlags);
+        }
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped);
+        }
+        // the descriptor type follows from the level, whatever the flags say
+        if S::LEVEL == PAGE_LEVEL {
+            entry.set_addr_in::<S::Granule>(
+                frame.start_address(),
+                flags | PageTableFlags::TABLE_OR_PAGE,
+                attr,
+            );
+        } else {
+            entry.set_block::<S>(
+                frame.start_address(),
+                flags - PageTableFlags::TABLE_OR_PAGE,
+                attr,
+            );
+        }
+        Ok(())
+    }
 
+    fn unmap_entry<S: PageSize>(entry: &mut PageTableEntry) -> Result<PhysFrame<S>, UnmapError> {
         if !entry.flags().contains(PageTableFlags::VALID) {
             return Err(UnmapError::PageNotMapped);
-        } else if !entry.is_block() {
+        } else if entry.is_block() != (S::LEVEL != PAGE_LEVEL) {
             return Err(UnmapError::ParentEntryHugePage);
         }
 
-        let frame = PhysFrame::from_start_address(entry.addr())
-            .map_err(|()| UnmapError::InvalidFrameAddress(entry.addr()))?;
+        let addr = entry.addr_in::<S::Granule>();
+        let frame = PhysFrame::from_start_address(addr)
+            .map_err(|()| UnmapError::InvalidFrameAddress(addr))?;
 
         entry.set_unused();
-        Ok((frame, MapperFlush::new(page)))
+        Ok(frame)
     }
+}
 
-    fn get_entry(&self, page: Page<Size1GiB>) -> Result<&PageTableEntry, EntryGetError> {
-        let p4 = &self.level_4_table;
-        let p3 = self.page_table_walker.next_table(&p4[page.p4_index()])?;
-        Ok(&p3[page.p3_index()])
+impl<'a, PhysToVirt, G, S> TranslatePage<S> for MappedPageTable<'a, PhysToVirt, G>
+where
+    G: TranslationGranule,
+    S: PageSize<Granule = G>,
+    PhysToVirt: PageTableFrameMapping<G>,
+{
+    fn get_entry(&self, page: Page<S>) -> Result<&PageTableEntry, EntryGetError> {
+        check_canonical(page);
+        let mut table = &*self.level_4_table;
+        for level in G::START_LEVEL..S::LEVEL {
+            table = self
+                .page_table_walker
+                .next_table(&table[page.table_index(level)])?;
+        }
+        Ok(&table[page.table_index(S::LEVEL)])
     }
 }
 
-impl<'a, PhysToVirt> Mapper<Size2MiB> for MappedPageTable<'a, PhysToVirt>
+impl<'a, PhysToVirt, G, S> Mapper<S> for MappedPageTable<'a, PhysToVirt, G>
 where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
+    G: TranslationGranule,
+    S: PageSize<Granule = G>,
+    PhysToVirt: PageTableFrameMapping<G>,
 {
-    unsafe fn map_to<A>(
+    fn map_to<A>(
         &mut self,
-        page: Page<Size2MiB>,
-        frame: PhysFrame<Size2MiB>,
+        page: Page<S>,
+        frame: UnusedPhysFrame<S>,
         flags: PageTableFlags,
         attr: PageTableAttribute,
         allocator: &mut A,
-    ) -> Result<MapperFlush<Size2MiB>, MapToError>
+    ) -> Result<MapperFlush<S>, MapToError>
     where
-        A: FrameAllocator<Size4KiB>,
+        A: FrameAllocator<G::Page>,
     {
-        self.map_to_2mib(page, frame, flags, attr, allocator)
+        self.map_to_level(page, frame, flags, attr, allocator)
     }
 
-    fn unmap(
+    unsafe fn map_range_to<A>(
         &mut self,
-        page: Page<Size2MiB>,
-    ) -> Result<(PhysFrame<Size2MiB>, MapperFlush<Size2MiB>), UnmapError> {
-        let entry = self.get_entry_mut(page)?;
-
-        if !entry.flags().contains(PageTableFlags::VALID) {
-            return Err(UnmapError::PageNotMapped);
-        } else if !entry.is_block() {
-            return Err(UnmapError::ParentEntryHugePage);
-        }
-
-        let frame = PhysFrame::from_start_address(entry.addr())
-            .map_err(|()| UnmapError::InvalidFrameAddress(entry.addr()))?;
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>>
+    where
+        A: FrameAllocator<G::Page>,
+    {
+        self.map_range_to_level(pages, frames, flags, attr, allocator)
+    }
 
-        entry.set_unused();
+    fn unmap(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError> {
+        let frame = Self::unmap_entry(self.get_entry_mut(page)?)?;
         Ok((frame, MapperFlush::new(page)))
     }
 
-    fn get_entry(&self, page: Page<Size2MiB>) -> Result<&PageTableEntry, EntryGetError> {
-        let p4 = &self.level_4_table;
-        let p3 = self.page_table_walker.next_table(&p4[page.p4_index()])?;
-        let p2 = self.page_table_walker.next_table(&p3[page.p3_index()])?;
-        Ok(&p2[page.p2_index()])
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, UnmapRangeError<S>> {
+        self.unmap_range_level(pages)
     }
-}
 
-impl<'a, PhysToVirt> Mapper<Size4KiB> for MappedPageTable<'a, PhysToVirt>
-where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
-{
-    unsafe fn map_to<A>(
+    unsafe fn split_huge_page<A>(
         &mut self,
-        page: Page<Size4KiB>,
-        frame: PhysFrame<Size4KiB>,
-        flags: PageTableFlags,
-        attr: PageTableAttribute,
+        page: Page<S>,
         allocator: &mut A,
-    ) -> Result<MapperFlush<Size4KiB>, MapToError>
+    ) -> Result<MapperFlush<S>, SplitError>
     where
-        A: FrameAllocator<Size4KiB>,
+        A: FrameAllocator<G::Page>,
     {
-        self.map_to_4kib(page, frame, flags, attr, allocator)
+        let entry: *mut PageTableEntry = self.get_entry_mut(page)?;
+        let entry = &mut *entry;
+        if !entry.flags().contains(PageTableFlags::VALID) {
+            return Err(SplitError::PageNotMapped);
+        } else if S::LEVEL == PAGE_LEVEL || !entry.is_block() {
+            return Err(SplitError::NotHugePage);
+        }
+
+        let frame = allocator
+            .allocate_frame()
+            .ok_or(SplitError::FrameAllocationFailed)?
+            .frame();
+        let table = &mut *self.page_table_walker.phys_to_virt.frame_to_pointer(frame);
+        let (addr, attr) = (entry.addr_in::<G>(), entry.attr());
+        let mut flags = entry.flags() - PageTableFlags::Contiguous;
+        if S::LEVEL + 1 == PAGE_LEVEL {
+            flags |= PageTableFlags::TABLE_OR_PAGE;
+        }
+        let size = S::SIZE >> G::INDEX_BITS;
+        // the frame may hold stale descriptors: every child is written whole, from scratch
+        table.zero();
+        for (index, child) in table.iter_mut().enumerate() {
+            child.set_addr_in::<G>(addr + index as u64 * size, flags, attr);
+        }
+
+        entry.set_unused();
+        crate::translation::invalidate_tlb_vaddr(page.start_address());
+
+        entry.set_addr_in::<G>(
+            frame.start_address(),
+            PageTableFlags::default_table(),
+            PageTableAttribute::new(0, 0, 0),
+        );
+        Ok(MapperFlush::new(page))
     }
 
-    fn unmap(
+    unsafe fn merge_huge_page<D>(
         &mut self,
-        page: Page<Size4KiB>,
-    ) -> Result<(PhysFrame<Size4KiB>, MapperFlush<Size4KiB>), UnmapError> {
-        let entry = self.get_entry_mut(page)?;
-
+        page: Page<S>,
+        deallocator: &mut D,
+    ) -> Result<MapperFlush<S>, MergeError>
+    where
+        D: FrameDeallocator<G::Page>,
+    {
+        let entry: *mut PageTableEntry = self.get_entry_mut(page)?;
+        let entry = &mut *entry;
         if !entry.flags().contains(PageTableFlags::VALID) {
-            return Err(UnmapError::PageNotMapped);
-        } else if entry.is_block() {
-            return Err(UnmapError::ParentEntryHugePage);
+            return Err(MergeError::PageNotMapped);
+        } else if S::LEVEL == PAGE_LEVEL || entry.is_block() {
+            return Err(MergeError::NotTable);
         }
 
-        let frame = PhysFrame::from_start_address(entry.addr())
-            .map_err(|()| UnmapError::InvalidFrameAddress(entry.addr()))?;
+        let frame = PhysFrame::containing_address(entry.addr_in::<G>());
+        let table = &*self.page_table_walker.phys_to_virt.frame_to_pointer(frame);
+        let first = &table[0];
+        let (addr, flags, attr) = (first.addr_in::<G>(), first.flags(), first.attr().value);
+        // pages at the page level, blocks above
+        let kind = if S::LEVEL + 1 == PAGE_LEVEL {
+            PageTableFlags::TABLE_OR_PAGE
+        } else {
+            PageTableFlags::empty()
+        };
+        let size = S::SIZE >> G::INDEX_BITS;
+        if !flags.contains(PageTableFlags::VALID)
+            || flags & PageTableFlags::TABLE_OR_PAGE != kind
+            || !addr.is_aligned(S::SIZE)
+            || table.iter().enumerate().any(|(index, child)| {
+                child.addr_in::<G>() != addr + index as u64 * size
+                    || child.flags() != flags
+                    || child.attr().value != attr
+            })
+        {
+            return Err(MergeError::NotMergeable);
+        }
 
+        let attr = first.attr();
         entry.set_unused();
-        Ok((frame, MapperFlush::new(page)))
+        // the TLB may hold any of the pages of the table
+        crate::translation::invalidate_tlb_all();
+
+        entry.set_block::<S>(
+            addr,
+            flags - PageTableFlags::TABLE_OR_PAGE - PageTableFlags::Contiguous,
+            attr,
+        );
+        crate::paging::bbm::forget_table(table);
+        deallocator.deallocate_frame(frame);
+        Ok(MapperFlush::new(page))
     }
 
-    fn get_entry(&self, page: Page<Size4KiB>) -> Result<&PageTableEntry, EntryGetError> {
-        let p4 = &self.level_4_table;
-        let p3 = self.page_table_walker.next_table(&p4[page.p4_index()])?;
-        let p2 = self.page_table_walker.next_table(&p3[page.p3_index()])?;
-        let p1 = self.page_table_walker.next_table(&p2[page.p2_index()])?;
-        Ok(&p1[page.p1_index()])
+    fn get_entry_mut(&mut self, page: Page<S>) -> Result<&mut PageTableEntry, EntryGetError> {
+        check_canonical(page);
+        let mut table = &mut *self.level_4_table;
+        for level in G::START_LEVEL..S::LEVEL {
+            table = self
+                .page_table_walker
+                .next_table_mut(&mut table[page.table_index(level)])?;
+        }
+        Ok(&mut table[page.table_index(S::LEVEL)])
     }
 }
 
-impl<'a, PhysToVirt> MapperAllSizes for MappedPageTable<'a, PhysToVirt>
+impl<'a, PhysToVirt> Translate for MappedPageTable<'a, PhysToVirt, Granule4KiB>
 where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
+    PhysToVirt: PageTableFrameMapping,
 {
     fn translate(&self, addr: VirtAddr) -> TranslateResult {
         let p4 = &self.level_4_table;
-        let p3 = match self.page_table_walker.next_table(&p4[addr.p4_index()]) {
+        let p3 = match self
+            .page_table_walker
+            .next_table(&p4[addr.page_table_index(PageTableLevel::L0)])
+        {
             Ok(page_table) => page_table,
             Err(PageTableWalkError::NotMapped) => return TranslateResult::PageNotMapped,
             Err(PageTableWalkError::MappedToHugePage) => {
                 panic!("level 4 entry has huge page bit set")
             }
         };
-        let p2 = match self.page_table_walker.next_table(&p3[addr.p3_index()]) {
+        let p2 = match self
+            .page_table_walker
+            .next_table(&p3[addr.page_table_index(PageTableLevel::L1)])
+        {
             Ok(page_table) => page_table,
             Err(PageTableWalkError::NotMapped) => return TranslateResult::PageNotMapped,
             Err(PageTableWalkError::MappedToHugePage) => {
-                let frame = PhysFrame::containing_address(p3[addr.p3_index()].addr());
-                let offset = addr.as_u64() & 0o_777_777_7777;
+                let frame = PhysFrame::containing_address(
+                    p3[addr.page_table_index(PageTableLevel::L1)].addr(),
+                );
+                let offset = addr.as_u64() & (Size1GiB::SIZE - 1);
                 return TranslateResult::Frame1GiB { frame, offset };
             }
         };
-        let p1 = match self.page_table_walker.next_table(&p2[addr.p2_index()]) {
+        let p1 = match self
+            .page_table_walker
+            .next_table(&p2[addr.page_table_index(PageTableLevel::L2)])
+        {
             Ok(page_table) => page_table,
             Err(PageTableWalkError::NotMapped) => return TranslateResult::PageNotMapped,
             Err(PageTableWalkError::MappedToHugePage) => {
-                let frame = PhysFrame::containing_address(p2[addr.p2_index()].addr());
-                let offset = addr.as_u64() & 0o_777_7777;
+                let frame = PhysFrame::containing_address(
+                    p2[addr.page_table_index(PageTableLevel::L2)].addr(),
+                );
+                let offset = addr.as_u64() & (Size2MiB::SIZE - 1);
                 return TranslateResult::Frame2MiB { frame, offset };
             }
         };
 
-        let p1_entry = &p1[addr.p1_index()];
+        let p1_entry = &p1[addr.page_table_index(PageTableLevel::L3)];
 
         if p1_entry.is_unused() {
             return TranslateResult::PageNotMapped;
@@ -310,22 +589,55 @@ where
         let offset = u64::from(addr.page_offset());
         TranslateResult::Frame4KiB { frame, offset }
     }
+
+    fn translate_with_flags(&self, addr: VirtAddr) -> Result<Translation, TranslateError> {
+        translate_with_tables(addr, self.level_4_table, |_, entry| {
+            self.page_table_walker.next_table(entry).ok()
+        })
+    }
+}
+
+/// The tables translate the `G::VA_BITS` low bits of an address, for either half of the address
+/// space. Anything else in the upper bits is an invalid address that would alias a valid one.
+#[inline]
+fn check_canonical<S: PageSize>(page: Page<S>) {
+    debug_assert!(
+        VirtAddr::try_new_for::<S::Granule>(page.start_address().as_u64()).is_ok(),
+        "page address is not canonical"
+    );
 }
 
 #[derive(Debug)]
-struct PageTableWalker<PhysToVirt>
+struct PageTableWalker<PhysToVirt, G>
 where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
+    G: TranslationGranule,
+    PhysToVirt: PageTableFrameMapping<G>,
 {
     phys_to_virt: PhysToVirt,
+    granule: PhantomData<G>,
 }
 
-impl<PhysToVirt> PageTableWalker<PhysToVirt>
+impl<PhysToVirt, G> PageTableWalker<PhysToVirt, G>
 where
-    PhysToVirt: Fn(PhysFrame) -> *mut PageTable,
+    G: TranslationGranule,
+    PhysToVirt: PageTableFrameMapping<G>,
 {
     pub unsafe fn new(phys_to_virt: PhysToVirt) -> Self {
-        Self { phys_to_virt }
+        Self {
+            phys_to_virt,
+            granule: PhantomData,
+        }
+    }
+
+    /// Internal helper function to get the frame of the page table of the next level.
+    fn table_frame(entry: &PageTableEntry) -> Result<PhysFrame<G::Page>, PageTableWalkError> {
+        if !entry.flags().contains(PageTableFlags::VALID) {
+            Err(PageTableWalkError::NotMapped)
+        } else if entry.is_block() {
+            Err(PageTableWalkError::MappedToHugePage)
+        } else {
+            Ok(PhysFrame::containing_address(entry.addr_in::<G>()))
+        }
     }
 
     /// Internal helper function to get a reference to the page table of the next level.
@@ -336,9 +648,11 @@ where
     fn next_table<'b>(
         &self,
         entry: &'b PageTableEntry,
-    ) -> Result<&'b PageTable, PageTableWalkError> {
-        let page_table_ptr = (self.phys_to_virt)(entry.frame()?);
-        let page_table: &PageTable = unsafe { &*page_table_ptr };
+    ) -> Result<&'b PageTable<G>, PageTableWalkError> {
+        let page_table_ptr = self
+            .phys_to_virt
+            .frame_to_pointer(Self::table_frame(entry)?);
+        let page_table: &PageTable<G> = unsafe { &*page_table_ptr };
 
         Ok(page_table)
     }
@@ -351,13 +665,72 @@ where
     fn next_table_mut<'b>(
         &self,
         entry: &'b mut PageTableEntry,
-    ) -> Result<&'b mut PageTable, PageTableWalkError> {
-        let page_table_ptr = (self.phys_to_virt)(entry.frame()?);
-        let page_table: &mut PageTable = unsafe { &mut *page_table_ptr };
+    ) -> Result<&'b mut PageTable<G>, PageTableWalkError> {
+        let page_table_ptr = self
+            .phys_to_virt
+            .frame_to_pointer(Self::table_frame(entry)?);
+        let page_table: &mut PageTable<G> = unsafe { &mut *page_table_ptr };
 
         Ok(page_table)
     }
 
+    /// Internal helper function to free the empty tables below `table`, a table of the given
+    /// level translating the addresses from `base`, that translate addresses of `range`. If
+    /// `free_leaves` is set, the pages and blocks lying within `range` are unmapped and their
+    /// frames freed first.
+    ///
+    /// Returns whether `table` is empty afterwards.
+    unsafe fn clean_up_table<D>(
+        &self,
+        table: &mut PageTable<G>,
+        level: usize,
+        base: u64,
+        range: (u64, u64),
+        free_leaves: bool,
+        deallocator: &mut D,
+    ) -> bool
+    where
+        D: FrameDeallocator<G::Page>,
+    {
+        let shift = G::Page::SIZE.trailing_zeros() + (PAGE_LEVEL - level) as u32 * G::INDEX_BITS;
+        let size = 1u64 << shift;
+        let first = (range.0.max(base) - base) >> shift;
+        let last = (range.1 - base) >> shift;
+        for (index, entry) in table.iter_mut().enumerate() {
+            let index = index as u64;
+            if index < first || index > last || entry.is_unused() {
+                continue;
+            }
+            let start = base + index * size;
+            let end = start + (size - 1);
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::VALID) {
+                continue;
+            } else if level == PAGE_LEVEL || entry.is_block() {
+                if free_leaves && range.0 <= start && end <= range.1 {
+                    let frames = PhysFrame::range(
+                        PhysFrame::containing_address(entry.addr_in::<G>()),
+                        PhysFrame::containing_address(entry.addr_in::<G>() + size),
+                    );
+                    entry.set_unused();
+                    for frame in frames {
+                        deallocator.deallocate_frame(frame);
+                    }
+                }
+            } else {
+                let frame = PhysFrame::containing_address(entry.addr_in::<G>());
+                let child = &mut *self.phys_to_virt.frame_to_pointer(frame);
+                let range = (range.0.max(start), range.1.min(end));
+                if self.clean_up_table(child, level + 1, start, range, free_leaves, deallocator) {
+                    entry.set_unused();
+                    crate::paging::bbm::forget_table(child);
+                    deallocator.deallocate_frame(frame);
+                }
+            }
+        }
+        table.iter().all(PageTableEntry::is_unused)
+    }
+
     /// Internal helper function to create the page table of the next level if needed.
     ///
     /// If the passed entry is unused, a new frame is allocated from the given allocator, zeroed,
@@ -371,43 +744,34 @@ where
         &self,
         entry: &'b mut PageTableEntry,
         allocator: &mut A,
-    ) -> Result<&'b mut PageTable, PageTableCreateError>
+    ) -> Result<&'b mut PageTable<G>, PageTableCreateError>
     where
-        A: FrameAllocator<Size4KiB>,
+        A: FrameAllocator<G::Page>,
     {
-        let created;
-
         if entry.is_unused() {
-            if let Some(frame) = allocator.allocate_frame() {
-                entry.set_frame(
-                    frame,
-                    PageTableFlags::default_table(),
-                    PageTableAttribute::new(0, 0, 0),
-                );
-                created = true;
-            } else {
-                return Err(PageTableCreateError::FrameAllocationFailed);
+            let frame = allocator
+                .allocate_frame()
+                .ok_or(PageTableCreateError::FrameAllocationFailed)?
+                .frame();
+            // the table must be empty before the MMU can walk it
+            unsafe {
+                (*self.phys_to_virt.frame_to_pointer(frame)).zero();
+                crate::barrier::dsb(crate::barrier::ISHST);
             }
-        } else {
-            created = false;
+            entry.set_addr_in::<G>(
+                frame.start_address(),
+                PageTableFlags::default_table(),
+                PageTableAttribute::new(0, 0, 0),
+            );
         }
 
-        let page_table = match self.next_table_mut(entry) {
+        match self.next_table_mut(entry) {
             Err(PageTableWalkError::MappedToHugePage) => {
-                Err(PageTableCreateError::MappedToHugePage)?
+                Err(PageTableCreateError::MappedToHugePage)
             }
             Err(PageTableWalkError::NotMapped) => panic!("entry should be mapped at this point"),
-            Ok(page_table) => page_table,
-        };
-
-        if created {
-            #[cfg(target_arch = "aarch64")]
-            unsafe {
-                crate::barrier::dsb(crate::barrier::ISHST);
-            }
-            page_table.zero();
+            Ok(page_table) => Ok(page_table),
         }
-        Ok(page_table)
     }
 }
 
@@ -432,15 +796,6 @@ impl From<PageTableCreateError> for MapToError {
     }
 }
 
-impl From<FrameError> for PageTableWalkError {
-    fn from(err: FrameError) -> Self {
-        match err {
-            FrameError::HugeFrame => PageTableWalkError::MappedToHugePage,
-            FrameError::FrameNotPresent => PageTableWalkError::NotMapped,
-        }
-    }
-}
-
 impl From<PageTableWalkError> for EntryGetError {
     fn from(err: PageTableWalkError) -> Self {
         match err {
@@ -449,3 +804,677 @@ impl From<PageTableWalkError> for EntryGetError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        paging::{
+            bbm,
+            granule::Granule16KiB,
+            test_util::{mapped_page_table, tables},
+            Size16KiB, Size1GiB, Size2MiB, Size32MiB,
+        },
+        PhysAddr, VirtAddr,
+    };
+
+    #[test]
+    pub fn test_recycled_table_frames() {
+        // frames holding stale, valid-looking descriptors, as after a previous use: the last entry
+        // of the last table would be the most recently cleared descriptor
+        let stale = [0x4000_0000 | PageTableFlags::default_page().bits(); 512];
+        let mut tables = [
+            PageTable::new(),
+            PageTable::from(stale),
+            PageTable::from(stale),
+            PageTable::from(stale),
+            PageTable::from(stale),
+        ];
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0x805f_f000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x9000_0000));
+        unsafe {
+            page_table
+                .map_to(
+                    page,
+                    UnusedPhysFrame::new(frame),
+                    PageTableFlags::default_page(),
+                    PageTableAttribute::new(0, 0, 0),
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+        }
+        assert_eq!(page_table.translate_page(page).unwrap(), frame);
+        assert!(page_table.get_entry(page - 1).unwrap().is_unused());
+
+        // the table of a split block is filled from scratch
+        let block = Page::<Size2MiB>::containing_address(VirtAddr::new(0x8080_0000));
+        let flags = PageTableFlags::default_block() | PageTableFlags::UXN;
+        unsafe {
+            page_table
+                .map_to(
+                    block,
+                    UnusedPhysFrame::new(PhysFrame::containing_address(PhysAddr::new(0xa000_0000))),
+                    flags,
+                    PageTableAttribute::new(0, 0, 0),
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+            page_table
+                .split_huge_page(block, &mut allocator)
+                .unwrap()
+                .ignore();
+        }
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(0x809f_f000));
+        let entry = page_table.get_entry(last).unwrap();
+        assert_eq!(
+            entry.raw(),
+            0xa01f_f000 | (flags | PageTableFlags::TABLE_OR_PAGE).bits()
+        );
+    }
+
+    #[test]
+    pub fn test_16kib_granule() {
+        let mut tables = tables::<Granule16KiB, 4>();
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+        let attr = PageTableAttribute::new(0, 0, 0);
+
+        let page = Page::<Size16KiB>::containing_address(VirtAddr::new(0x1234_5678_c000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_4000));
+        unsafe {
+            page_table
+                .map_to(
+                    page,
+                    UnusedPhysFrame::new(frame),
+                    PageTableFlags::default_page(),
+                    attr,
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+        }
+        assert_eq!(page_table.translate_page(page).unwrap(), frame);
+
+        let block = Page::<Size32MiB>::containing_address(VirtAddr::new(0x1234_4000_0000));
+        let block_frame = PhysFrame::containing_address(PhysAddr::new(0x4000_0000));
+        unsafe {
+            page_table
+                .map_to(
+                    block,
+                    UnusedPhysFrame::new(block_frame),
+                    PageTableFlags::default_block(),
+                    attr,
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+        }
+        assert_eq!(page_table.translate_page(block).unwrap(), block_frame);
+        // the level 0 and level 1 tables are shared
+        assert_eq!(allocator.tables.len(), 0);
+
+        let (unmapped, flush) = page_table.unmap(page).unwrap();
+        flush.ignore();
+        bbm::notify_tlb_invalidated();
+        assert_eq!(unmapped, frame);
+        assert!(page_table.translate_page(page).is_err());
+        assert!(matches!(
+            Mapper::<Size16KiB>::unmap(
+                &mut page_table,
+                Page::containing_address(VirtAddr::new(0x1234_4000_0000))
+            ),
+            Err(UnmapError::ParentEntryHugePage)
+        ));
+    }
+
+    #[test]
+    pub fn test_4kib_blocks() {
+        let mut tables = tables::<Granule4KiB, 3>();
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+        let attr = PageTableAttribute::new(0, 0, 0);
+
+        // the page flags, with TABLE_OR_PAGE cleared for the block
+        let giant = Page::<Size1GiB>::containing_address(VirtAddr::new(0x80_4000_0000));
+        let giant_frame = PhysFrame::containing_address(PhysAddr::new(0x1_c000_0000));
+        unsafe {
+            page_table
+                .map_to(
+                    giant,
+                    UnusedPhysFrame::new(giant_frame),
+                    PageTableFlags::default_page() | PageTableFlags::nG,
+                    attr,
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+        }
+        let entry = page_table.get_entry(giant).unwrap();
+        assert!(entry.is_block());
+        assert_eq!(
+            entry.flags(),
+            PageTableFlags::default_block() | PageTableFlags::nG
+        );
+        assert_eq!(page_table.translate_page(giant).unwrap(), giant_frame);
+        assert_eq!(
+            page_table.translate_addr(VirtAddr::new(0x80_4123_4567)),
+            Some(PhysAddr::new(0x1_c123_4567))
+        );
+
+        let huge = Page::<Size2MiB>::containing_address(VirtAddr::new(0x80_8020_0000));
+        let huge_frame = PhysFrame::containing_address(PhysAddr::new(0x4060_0000));
+        unsafe {
+            page_table
+                .map_to(
+                    huge,
+                    UnusedPhysFrame::new(huge_frame),
+                    PageTableFlags::default_leaf::<Size2MiB>(),
+                    attr,
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+        }
+        let entry = page_table.get_entry(huge).unwrap();
+        assert!(entry.is_block());
+        assert!(entry.flags().contains(PageTableFlags::AF));
+        assert_eq!(page_table.translate_page(huge).unwrap(), huge_frame);
+        // the level 1 table is shared, the level 2 table is new
+        assert_eq!(allocator.tables.len(), 0);
+        assert!(matches!(
+            unsafe {
+                page_table.map_to(
+                    huge,
+                    UnusedPhysFrame::new(huge_frame),
+                    PageTableFlags::default_leaf::<Size2MiB>(),
+                    attr,
+                    &mut allocator,
+                )
+            },
+            Err(MapToError::PageAlreadyMapped)
+        ));
+
+        // the attributes of table descriptors have no meaning in a block
+        let table_flags = PageTableFlags::default_block() | PageTableFlags::XNTable;
+        assert!(matches!(
+            unsafe {
+                page_table.map_to(
+                    huge + 1,
+                    UnusedPhysFrame::new(huge_frame + 1),
+                    table_flags,
+                    attr,
+                    &mut allocator,
+                )
+            },
+            Err(MapToError::InvalidFlags)
+        ));
+        assert!(page_table.get_entry(huge + 1).unwrap().is_unused());
+        assert!(matches!(
+            page_table.update_flags(huge, table_flags),
+            Err(FlagUpdateError::InvalidFlags)
+        ));
+        // turning the block into a page, or setting its contiguous hint, needs break-before-make
+        #[cfg(any(debug_assertions, feature = "bbm-check"))]
+        for flags in [
+            PageTableFlags::default_page(),
+            PageTableFlags::default_block() | PageTableFlags::Contiguous,
+        ] {
+            assert!(matches!(
+                page_table.update_flags(huge, flags),
+                Err(FlagUpdateError::BreakBeforeMakeRequired)
+            ));
+        }
+        page_table
+            .update_flags(
+                huge,
+                PageTableFlags::default_block() | PageTableFlags::AP_RO,
+            )
+            .unwrap()
+            .ignore();
+    }
+
+    #[test]
+    pub fn test_map_device_region() {
+        let mut tables = tables::<Granule4KiB, 7>();
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+
+        // from the last page below 2GiB to past the first 2MiB above
+        let region = unsafe {
+            page_table.map_device_region(
+                VirtAddr::new(0x7fff_f000),
+                PhysAddr::new(0x0900_0123),
+                0x20_2000,
+                &mut allocator,
+            )
+        }
+        .unwrap();
+        assert_eq!(
+            region,
+            VirtAddr::new(0x7fff_f123)..VirtAddr::new(0x8020_1123)
+        );
+        assert_eq!(allocator.tables.len(), 0);
+
+        let translation = page_table
+            .translate_with_flags(VirtAddr::new(0x8020_1000))
+            .unwrap();
+        assert_eq!(translation.addr, PhysAddr::new(0x0920_2000));
+        assert_eq!(translation.attr.value, MairDevice::attr_value().value);
+        assert_eq!(
+            translation.permissions.access,
+            AccessPermission::PrivilegedReadWrite
+        );
+        assert!(translation.permissions.privileged_execute_never);
+        assert!(translation.permissions.user_execute_never);
+
+        // only the table of the 2MiB fully in the region is marked never executable
+        let table_flags = |page_table: &mut MappedPageTable<_>, addr| {
+            TranslatePage::<Size2MiB>::get_entry(
+                page_table,
+                Page::containing_address(VirtAddr::new(addr)),
+            )
+            .unwrap()
+            .flags()
+        };
+        let xn_table = PageTableFlags::PXNTable | PageTableFlags::XNTable;
+        assert!(table_flags(&mut page_table, 0x8000_0000).contains(xn_table));
+        assert!(!table_flags(&mut page_table, 0x7fe0_0000).intersects(xn_table));
+        assert!(!table_flags(&mut page_table, 0x8020_0000).intersects(xn_table));
+    }
+
+    #[test]
+    pub fn test_split_huge_page() {
+        let mut tables = tables::<Granule16KiB, 4>();
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+        let attr = PageTableAttribute::new(0, 0, 0);
+
+        let block = Page::<Size32MiB>::containing_address(VirtAddr::new(0x1234_4000_0000));
+        let flags = PageTableFlags::default_block() | PageTableFlags::PXN;
+        unsafe {
+            page_table
+                .map_to(
+                    block,
+                    UnusedPhysFrame::new(PhysFrame::containing_address(PhysAddr::new(0x4000_0000))),
+                    flags,
+                    attr,
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+            page_table
+                .split_huge_page(block, &mut allocator)
+                .unwrap()
+                .ignore();
+        }
+        assert_eq!(allocator.tables.len(), 0);
+
+        let page = Page::<Size16KiB>::containing_address(VirtAddr::new(0x1234_4123_4000));
+        assert_eq!(
+            page_table.translate_page(page).unwrap(),
+            PhysFrame::containing_address(PhysAddr::new(0x4123_4000))
+        );
+        assert_eq!(
+            page_table.get_entry(page).unwrap().flags(),
+            flags | PageTableFlags::TABLE_OR_PAGE
+        );
+        assert!(matches!(
+            unsafe { page_table.split_huge_page(block, &mut allocator) },
+            Err(SplitError::NotHugePage)
+        ));
+
+        struct Deallocator(Option<PhysFrame<Size16KiB>>);
+        impl FrameDeallocator<Size16KiB> for Deallocator {
+            fn deallocate_frame(&mut self, frame: PhysFrame<Size16KiB>) {
+                self.0 = Some(frame);
+            }
+        }
+        let mut deallocator = Deallocator(None);
+        let last = Page::<Size16KiB>::containing_address(VirtAddr::new(0x1234_41ff_c000));
+        let page_flags = page_table.get_entry(last).unwrap().flags();
+        page_table
+            .update_flags(last, page_flags | PageTableFlags::UXN)
+            .unwrap()
+            .ignore();
+        assert!(matches!(
+            unsafe { page_table.merge_huge_page(block, &mut deallocator) },
+            Err(MergeError::NotMergeable)
+        ));
+        page_table.update_flags(last, page_flags).unwrap().ignore();
+        unsafe {
+            page_table
+                .merge_huge_page(block, &mut deallocator)
+                .unwrap()
+                .ignore();
+        }
+        assert!(deallocator.0.is_some());
+        assert_eq!(page_table.get_entry(block).unwrap().flags(), flags);
+        assert!(page_table.translate_page(page).is_err());
+    }
+
+    #[test]
+    pub fn test_map_range() {
+        let mut tables = tables::<Granule16KiB, 5>();
+        let root = PhysFrame::containing_address(PhysAddr::new(tables.as_ptr() as u64));
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+        assert_eq!(page_table.level_4_frame(), Some(root));
+        let attr = PageTableAttribute::new(0, 0, 0);
+
+        // crosses a level 3 table boundary
+        let pages = Page::<Size16KiB>::range_of(0x1_01ff_8000, 0x1_0200_8000);
+        let frames = PhysFrame::range_of(0x8000_0000, 0x8001_0000);
+        let flush = unsafe {
+            page_table
+                .map_range_to(
+                    pages,
+                    frames,
+                    PageTableFlags::default_page(),
+                    attr,
+                    &mut allocator,
+                )
+                .unwrap()
+        };
+        assert_eq!(flush.pages(), pages);
+        flush.ignore();
+        assert_eq!(allocator.tables.len(), 0);
+        for (page, frame) in pages.zip(frames) {
+            assert_eq!(page_table.translate_page(page).unwrap(), frame);
+        }
+
+        let err = unsafe {
+            page_table
+                .map_range_to(
+                    Page::range_of(0x1_0200_0000, 0x1_0201_0000),
+                    PhysFrame::range_of(0x9000_0000, 0x9001_0000),
+                    PageTableFlags::default_page(),
+                    attr,
+                    &mut allocator,
+                )
+                .unwrap_err()
+        };
+        assert_eq!(err.page, pages.start + 2);
+        assert!(matches!(err.error, MapToError::PageAlreadyMapped));
+        assert!(err.flush.pages().is_empty());
+        err.flush.ignore();
+
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x9000_0000));
+        unsafe {
+            page_table
+                .remap(
+                    pages.start,
+                    UnusedPhysFrame::new(frame),
+                    PageTableFlags::default_page(),
+                    attr,
+                )
+                .unwrap()
+                .ignore();
+        }
+        assert_eq!(page_table.translate_page(pages.start).unwrap(), frame);
+
+        page_table.unmap_range(pages).unwrap().ignore();
+        bbm::notify_tlb_invalidated();
+        assert!(page_table.translate_page(pages.start + 3).is_err());
+    }
+
+    #[test]
+    pub fn test_contiguous_run() {
+        let mut tables = tables::<Granule16KiB, 4>();
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let flags = PageTableFlags::default_page();
+
+        // a run is 128 pages of 16KiB
+        assert_eq!(Granule16KiB::contiguous_entries(PAGE_LEVEL), 128);
+        let page = Page::<Size16KiB>::containing_address(VirtAddr::new(0x1_0020_0000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8020_0000));
+        unsafe {
+            assert!(matches!(
+                page_table.map_contiguous_run(page + 1, frame, flags, attr, &mut allocator),
+                Err(ContiguousError::Unaligned)
+            ));
+            page_table
+                .map_contiguous_run(page, frame, flags, attr, &mut allocator)
+                .unwrap()
+                .ignore();
+            assert!(matches!(
+                page_table.map_contiguous_run(page, frame, flags, attr, &mut allocator),
+                Err(ContiguousError::MapFailed(MapToError::PageAlreadyMapped))
+            ));
+        }
+        let last = page_table.get_entry(page + 127).unwrap();
+        assert_eq!(last.flags(), flags | PageTableFlags::Contiguous);
+        assert_eq!(last.addr(), PhysAddr::new(0x8020_0000 + 127 * 0x4000));
+        assert!(page_table.get_entry(page + 128).unwrap().is_unused());
+
+        page_table.clear_contiguous_run(page).unwrap().ignore();
+        assert_eq!(page_table.get_entry(page + 5).unwrap().flags(), flags);
+        assert_eq!(page_table.translate_page(page + 5).unwrap(), frame + 5);
+        assert!(matches!(
+            page_table.clear_contiguous_run(page),
+            Err(ContiguousError::NotContiguous)
+        ));
+        assert!(matches!(
+            page_table.clear_contiguous_run(page + 128),
+            Err(ContiguousError::PageNotMapped)
+        ));
+    }
+
+    /// Fails to map pages once `left` pages have been mapped.
+    struct FailingMapper<'a> {
+        inner: MappedPageTable<'a, IdentityMapping>,
+        left: usize,
+    }
+
+    impl TranslatePage<Size4KiB> for FailingMapper<'_> {
+        fn get_entry(&self, page: Page) -> Result<&PageTableEntry, EntryGetError> {
+            self.inner.get_entry(page)
+        }
+    }
+
+    impl Mapper<Size4KiB> for FailingMapper<'_> {
+        fn map_to<A>(
+            &mut self,
+            page: Page,
+            frame: UnusedPhysFrame,
+            flags: PageTableFlags,
+            attr: PageTableAttribute,
+            frame_allocator: &mut A,
+        ) -> Result<MapperFlush<Size4KiB>, MapToError>
+        where
+            A: FrameAllocator<Size4KiB>,
+        {
+            if self.left == 0 {
+                return Err(MapToError::FrameAllocationFailed);
+            }
+            self.left -= 1;
+            self.inner.map_to(page, frame, flags, attr, frame_allocator)
+        }
+
+        fn get_entry_mut(&mut self, page: Page) -> Result<&mut PageTableEntry, EntryGetError> {
+            self.inner.get_entry_mut(page)
+        }
+
+        fn unmap(&mut self, page: Page) -> Result<(PhysFrame, MapperFlush<Size4KiB>), UnmapError> {
+            self.inner.unmap(page)
+        }
+
+        unsafe fn split_huge_page<A>(
+            &mut self,
+            page: Page,
+            frame_allocator: &mut A,
+        ) -> Result<MapperFlush<Size4KiB>, SplitError>
+        where
+            A: FrameAllocator<Size4KiB>,
+        {
+            self.inner.split_huge_page(page, frame_allocator)
+        }
+
+        unsafe fn merge_huge_page<D>(
+            &mut self,
+            page: Page,
+            frame_deallocator: &mut D,
+        ) -> Result<MapperFlush<Size4KiB>, MergeError>
+        where
+            D: FrameDeallocator<Size4KiB>,
+        {
+            self.inner.merge_huge_page(page, frame_deallocator)
+        }
+    }
+
+    #[test]
+    pub fn test_contiguous_run_rollback() {
+        let mut tables = tables::<Granule4KiB, 4>();
+        let (inner, mut allocator) = mapped_page_table(&mut tables);
+        let mut page_table = FailingMapper { inner, left: 5 };
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0x40_0000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        let flags = PageTableFlags::default_page();
+        let attr = PageTableAttribute::new(0, 0, 0);
+
+        // other tests may flush at the same time
+        let tlbis_before = crate::sim::tlb_invalidations();
+        assert!(matches!(
+            unsafe { page_table.map_contiguous_run(page, frame, flags, attr, &mut allocator) },
+            Err(ContiguousError::MapFailed(
+                MapToError::FrameAllocationFailed
+            ))
+        ));
+        assert!(crate::sim::tlb_invalidations() > tlbis_before);
+        for page in Page::range(page, page + 16) {
+            assert!(page_table.get_entry(page).unwrap().is_unused());
+        }
+
+        // the rolled back pages can be mapped again, with other frames
+        page_table.left = usize::MAX;
+        unsafe {
+            page_table
+                .map_contiguous_run(page, frame + 16, flags, attr, &mut allocator)
+                .unwrap()
+                .ignore();
+        }
+        assert_eq!(page_table.translate_page(page + 3).unwrap(), frame + 19);
+    }
+
+    #[test]
+    pub fn test_translate_with_flags() {
+        let mut tables = [
+            PageTable::<Granule4KiB>::new(),
+            PageTable::new(),
+            PageTable::new(),
+        ];
+        let (root, rest) = tables.split_first_mut().unwrap();
+        let (p3, rest) = rest.split_first_mut().unwrap();
+        let p2 = &mut rest[0];
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let table_addr = |table: &PageTable| PhysAddr::new(table as *const _ as u64);
+
+        let rw = PageTableFlags::default_block() | PageTableFlags::AP_EL0;
+        p2[1].set_addr(PhysAddr::new(0x8020_0000), rw, attr);
+        p3[0].set_addr(
+            table_addr(p2),
+            PageTableFlags::default_table() | PageTableFlags::APTable_RO | PageTableFlags::XNTable,
+            attr,
+        );
+        root[0].set_addr(table_addr(p3), PageTableFlags::default_table(), attr);
+
+        let page_table = unsafe {
+            MappedPageTable::new(root, |frame: PhysFrame| {
+                frame.start_address().as_u64() as *mut PageTable
+            })
+        };
+        let translation = page_table
+            .translate_with_flags(VirtAddr::new(0x0023_4567))
+            .unwrap();
+        assert_eq!(translation.addr, PhysAddr::new(0x8023_4567));
+        assert_eq!(translation.size, Size2MiB::SIZE);
+        assert_eq!(translation.flags, rw);
+        assert_eq!(
+            translation.permissions,
+            EffectivePermissions {
+                access: AccessPermission::ReadOnly,
+                privileged_execute_never: false,
+                user_execute_never: true,
+            }
+        );
+        assert!(matches!(
+            page_table.translate_with_flags(VirtAddr::new(0x0043_4567)),
+            Err(TranslateError::PageNotMapped)
+        ));
+
+        // a read-only view, e.g. shared with the fault handlers of the other cores
+        let view: &(dyn Translate + Sync) = &page_table;
+        assert_eq!(
+            view.translate_addr(VirtAddr::new(0x0023_4567)),
+            Some(PhysAddr::new(0x8023_4567))
+        );
+        assert_eq!(
+            TranslatePage::<Size2MiB>::translate_page(
+                &page_table,
+                Page::containing_address(VirtAddr::new(0x0020_0000))
+            )
+            .unwrap(),
+            PhysFrame::containing_address(PhysAddr::new(0x8020_0000))
+        );
+    }
+
+    #[test]
+    pub fn test_clean_up() {
+        struct Counter(usize);
+        impl FrameDeallocator<Size16KiB> for Counter {
+            fn deallocate_frame(&mut self, _frame: PhysFrame<Size16KiB>) {
+                self.0 += 1;
+            }
+        }
+
+        let mut tables = tables::<Granule16KiB, 5>();
+        let (mut page_table, mut allocator) = mapped_page_table(&mut tables);
+
+        // crosses a level 3 table boundary
+        let pages = Page::<Size16KiB>::range_of(0x1_01ff_8000, 0x1_0200_8000);
+        unsafe {
+            page_table
+                .map_range_to(
+                    pages,
+                    PhysFrame::range_of(0x8000_0000, 0x8001_0000),
+                    PageTableFlags::default_page(),
+                    PageTableAttribute::new(0, 0, 0),
+                    &mut allocator,
+                )
+                .unwrap()
+                .ignore();
+        }
+
+        let mut counter = Counter(0);
+        let (first, second) = pages.split_at(pages.start + 2);
+        unsafe { page_table.clean_up(&mut counter) };
+        assert_eq!(counter.0, 0);
+
+        // the two pages and their level 3 table
+        unsafe {
+            page_table.free_addr_range(
+                Page::range_inclusive(first.start, first.end - 1),
+                &mut counter,
+            )
+        };
+        bbm::notify_tlb_invalidated();
+        assert_eq!(counter.0, 3);
+        assert!(page_table.translate_page(first.start).is_err());
+        assert!(page_table.translate_page(second.start).is_ok());
+
+        // the last level 3 table, the level 2 and level 1 tables
+        page_table.unmap_range(second).unwrap().ignore();
+        bbm::notify_tlb_invalidated();
+        unsafe {
+            page_table.clean_up_addr_range(
+                Page::range_inclusive(second.start, second.end - 1),
+                &mut counter,
+            )
+        };
+        bbm::notify_tlb_invalidated();
+        assert_eq!(counter.0, 6);
+        assert!(page_table
+            .level_4_table()
+            .iter()
+            .all(PageTableEntry::is_unused));
+    }
+}
@@ -1,23 +1,125 @@
 //! Abstractions for reading and modifying the mapping of pages.
 
+pub mod dynamic;
+mod frame_mapping;
 mod mapped_page_table;
+mod offset_page_table;
 mod recursive_page_table;
 
-pub use self::{mapped_page_table::MappedPageTable, recursive_page_table::RecursivePageTable};
+pub use self::{
+    frame_mapping::{IdentityMapping, PageTableFrameMapping, PhysOffset},
+    mapped_page_table::MappedPageTable,
+    offset_page_table::OffsetPageTable,
+    recursive_page_table::{InvalidPageTable, RecursivePageTable},
+};
 
 use crate::{
     paging::{
-        frame::PhysFrame,
-        frame_alloc::FrameAllocator,
-        page::{Page, PageSize, Size1GiB, Size2MiB, Size4KiB},
-        page_table::{PageTableAttribute, PageTableEntry, PageTableFlags},
+        frame::{PhysFrame, PhysFrameRange, UnusedPhysFrame},
+        frame_alloc::{FrameAllocator, FrameDeallocator},
+        granule::{Granule4KiB, PageTableLevel, TranslationGranule, PAGE_LEVEL},
+        memory_attribute::{MairDevice, MairType},
+        page::{Page, PageRange, PageSize, Size1GiB, Size2MiB, Size4KiB},
+        page_table::{
+            AccessPermission, PageTable, PageTableAttribute, PageTableEntry, PageTableFlags,
+        },
     },
     PhysAddr, VirtAddr,
 };
+use core::{fmt, ops::Range};
 
 /// This trait defines page table operations that work for all page sizes of the aarch64
 /// architecture.
-pub trait MapperAllSizes: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB> {
+///
+/// It is implemented for all mappers implementing [`Mapper`] and [`Translate`] for the three page
+/// sizes of the 4KiB granule.
+pub trait MapperAllSizes:
+    Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB> + Translate
+{
+    /// Maps the device memory [`phys`, `phys + size`) at the same offset from the page `virt`,
+    /// and returns the virtual address range of the region.
+    ///
+    /// The region is rounded to page boundaries and mapped with 4KiB pages as Device-nGnRE memory
+    /// ([`MairDevice`]), privileged read-write and never executable (`PXN` and `UXN`), so that
+    /// device registers can't be mapped as cacheable Normal memory or executed by accident. The
+    /// table entries whose tables only translate the region also get `PXNTable` and `XNTable`.
+    ///
+    /// See [`map_range_to`](Mapper::map_range_to) for the error handling.
+    ///
+    /// Panics if `size` is 0 or if `virt` is not page aligned.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the region is device memory, which is not mapped with other
+    /// memory attributes elsewhere.
+    unsafe fn map_device_region<A>(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        size: u64,
+        frame_allocator: &mut A,
+    ) -> Result<Range<VirtAddr>, MapRangeError<Size4KiB>>
+    where
+        A: FrameAllocator<Size4KiB>,
+        Self: Sized,
+    {
+        assert!(size != 0, "empty device region");
+        assert!(
+            virt.is_aligned(Size4KiB::SIZE),
+            "device region mapped at an unaligned address"
+        );
+        let frames = PhysFrame::range(
+            PhysFrame::containing_address(phys),
+            PhysFrame::containing_address(phys + (size - 1)) + 1,
+        );
+        let first = Page::<Size4KiB>::containing_address(virt);
+        let pages = Page::range(first, first + frames.len());
+        // no TLB entries can translate pages that were not mapped
+        self.map_range_to(
+            pages,
+            frames,
+            PageTableFlags::default_page() | PageTableFlags::PXN | PageTableFlags::UXN,
+            MairDevice::attr_value(),
+            frame_allocator,
+        )?
+        .ignore();
+
+        // the tables translating nothing but the region
+        let (start, end) = (pages.start.start_address(), pages.end.start_address());
+        let inside =
+            |addr: VirtAddr, size: u64| addr >= start && addr.as_u64() + size <= end.as_u64();
+        let xn_table = PageTableFlags::PXNTable | PageTableFlags::XNTable;
+        for page in Page::<Size2MiB>::range_of(start.as_u64(), end.as_u64()) {
+            if inside(page.start_address(), Size2MiB::SIZE) {
+                if let Ok(entry) = Mapper::<Size2MiB>::get_entry_mut(self, page) {
+                    entry.set_flags(entry.flags() | xn_table);
+                }
+            }
+        }
+        for page in Page::<Size1GiB>::range_of(start.as_u64(), end.as_u64()) {
+            if inside(page.start_address(), Size1GiB::SIZE) {
+                if let Ok(entry) = Mapper::<Size1GiB>::get_entry_mut(self, page) {
+                    entry.set_flags(entry.flags() | xn_table);
+                }
+            }
+        }
+
+        let start = virt + (phys.as_u64() & (Size4KiB::SIZE - 1));
+        Ok(start..start + size)
+    }
+}
+
+impl<T> MapperAllSizes for T where
+    T: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB> + Translate
+{
+}
+
+/// The translation of virtual addresses of any page size, which only reads the page tables.
+///
+/// Unlike [`Mapper`], it only needs a shared reference to the mapper, so that a read-only view of
+/// the page tables can be shared between cores and used from interrupt context, e.g. by a page
+/// fault handler, while another core changes unrelated mappings.
+pub trait Translate {
     /// Return the frame that the given virtual address is mapped to and the offset within that
     /// frame.
     ///
@@ -27,12 +129,19 @@ pub trait MapperAllSizes: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB>
     /// This function works with huge pages of all sizes.
     fn translate(&self, addr: VirtAddr) -> TranslateResult;
 
+    /// Return the physical address that the given virtual address is mapped to, together with the
+    /// flags and memory attributes of the page or block and the effective permissions.
+    ///
+    /// Page fault handlers can use this to tell copy-on-write and demand paging faults from
+    /// true faults.
+    fn translate_with_flags(&self, addr: VirtAddr) -> Result<Translation, TranslateError>;
+
     /// Translates the given virtual address to the physical address that it maps to.
     ///
     /// Returns `None` if there is no valid mapping for the given address.
     ///
     /// This is a convenience method. For more information about a mapping see the
-    /// [`translate`](MapperAllSizes::translate) method.
+    /// [`translate`](Translate::translate) method.
     fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr> {
         match self.translate(addr) {
             TranslateResult::PageNotMapped | TranslateResult::InvalidFrameAddress(_) => None,
@@ -43,7 +152,52 @@ pub trait MapperAllSizes: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB>
     }
 }
 
-/// The return value of the [`MapperAllSizes::translate`] function.
+/// Translates `addr` with the 4KiB granule, starting at the `root` table and reaching the next
+/// tables with `next_table`, which is called with the lookup level and the entry of each valid
+/// table descriptor walked through.
+fn translate_with_tables<'a, F>(
+    addr: VirtAddr,
+    root: &'a PageTable,
+    mut next_table: F,
+) -> Result<Translation, TranslateError>
+where
+    F: FnMut(usize, &'a PageTableEntry) -> Option<&'a PageTable>,
+{
+    let table_attrs = PageTableFlags::PXNTable
+        | PageTableFlags::XNTable
+        | PageTableFlags::APTable_nEL0
+        | PageTableFlags::APTable_RO;
+    let mut table = root;
+    let mut table_flags = PageTableFlags::empty();
+    for level in Granule4KiB::START_LEVEL..=PAGE_LEVEL {
+        let entry = &table[Granule4KiB::table_index(addr, level)];
+        let flags = entry.flags();
+        if level != PAGE_LEVEL && flags.contains(PageTableFlags::default_table()) {
+            table_flags |= flags & table_attrs;
+            table = next_table(level, entry).ok_or(TranslateError::PageNotMapped)?;
+            continue;
+        }
+        // the reserved encoding at the page level is treated as invalid by the MMU
+        if !flags.contains(PageTableFlags::VALID)
+            || (level == PAGE_LEVEL && !flags.contains(PageTableFlags::TABLE_OR_PAGE))
+        {
+            return Err(TranslateError::PageNotMapped);
+        }
+        let size = PageTableLevel::new(level)
+            .unwrap()
+            .entry_address_space_alignment::<Granule4KiB>();
+        return Ok(Translation {
+            addr: entry.addr() + (addr.as_u64() & (size - 1)),
+            size,
+            flags,
+            attr: entry.attr(),
+            permissions: EffectivePermissions::new(flags, table_flags),
+        });
+    }
+    unreachable!("no page level table")
+}
+
+/// The return value of the [`Translate::translate`] function.
 ///
 /// If the given address has a valid mapping, a `Frame4KiB`, `Frame2MiB`, or `Frame1GiB` variant
 /// is returned, depending on the size of the mapped page. The remaining variants indicate errors.
@@ -76,35 +230,134 @@ pub enum TranslateResult {
     InvalidFrameAddress(PhysAddr),
 }
 
+/// The return value of the [`Translate::translate_with_flags`] function.
+#[derive(Clone, Copy)]
+pub struct Translation {
+    /// The physical address that the virtual address is mapped to.
+    pub addr: PhysAddr,
+    /// The size of the mapped page or block.
+    pub size: u64,
+    /// The flags of the page or block entry.
+    pub flags: PageTableFlags,
+    /// The memory attribute fields of the page or block entry.
+    pub attr: PageTableAttribute,
+    /// The permissions of the mapping, including the restrictions of the table entries.
+    pub permissions: EffectivePermissions,
+}
+
+impl fmt::Debug for Translation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("Translation");
+        f.field("addr", &self.addr);
+        f.field("size", &self.size);
+        f.field("flags", &self.flags);
+        f.field("attr", &self.attr.value);
+        f.field("permissions", &self.permissions);
+        f.finish()
+    }
+}
+
+/// The permissions of a mapping, after applying the hierarchical attributes (`APTable`,
+/// `PXNTable` and `XNTable`) of the table entries translating it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectivePermissions {
+    /// The data access permissions.
+    pub access: AccessPermission,
+    /// Whether the mapping is not executable at EL1.
+    pub privileged_execute_never: bool,
+    /// Whether the mapping is not executable at EL0.
+    pub user_execute_never: bool,
+}
+
+impl EffectivePermissions {
+    /// Returns the permissions of a page or block entry with the given flags, translated through
+    /// table entries with `table_flags` combined.
+    pub fn new(flags: PageTableFlags, table_flags: PageTableFlags) -> Self {
+        let mut access = AccessPermission::from(flags);
+        if table_flags.contains(PageTableFlags::APTable_nEL0) {
+            access = match access {
+                AccessPermission::ReadWrite => AccessPermission::PrivilegedReadWrite,
+                AccessPermission::ReadOnly => AccessPermission::PrivilegedReadOnly,
+                access => access,
+            };
+        }
+        if table_flags.contains(PageTableFlags::APTable_RO) {
+            access = match access {
+                AccessPermission::ReadWrite => AccessPermission::ReadOnly,
+                AccessPermission::PrivilegedReadWrite => AccessPermission::PrivilegedReadOnly,
+                access => access,
+            };
+        }
+        Self {
+            access,
+            privileged_execute_never: flags.contains(PageTableFlags::PXN)
+                || table_flags.contains(PageTableFlags::PXNTable),
+            user_execute_never: flags.contains(PageTableFlags::UXN)
+                || table_flags.contains(PageTableFlags::XNTable),
+        }
+    }
+}
+
+/// The translation of pages of size `S`, which only reads the page tables.
+///
+/// Like [`Translate`], it only needs a shared reference to the mapper.
+pub trait TranslatePage<S: PageSize> {
+    /// Get the reference of the specified `page` entry
+    fn get_entry(&self, page: Page<S>) -> Result<&PageTableEntry, EntryGetError>;
+
+    /// Return the frame that the specified page is mapped to.
+    ///
+    /// This function assumes that the page is mapped to a frame of size `S` and returns an
+    /// error otherwise.
+    fn translate_page(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError> {
+        let entry = self.get_entry(page)?;
+        if entry.is_unused() {
+            return Err(TranslateError::PageNotMapped);
+        }
+        let addr = entry.addr_in::<S::Granule>();
+        PhysFrame::from_start_address(addr).map_err(|()| TranslateError::InvalidFrameAddress(addr))
+    }
+}
+
 /// A trait for common page table operations on pages of size `S`.
-pub trait Mapper<S: PageSize> {
+///
+/// The translation granule of the page tables is the granule of `S`.
+pub trait Mapper<S: PageSize>: TranslatePage<S> {
     /// Creates a new mapping in the page table.
     ///
     /// This function might need additional physical frames to create new page tables. These
-    /// frames are allocated from the `allocator` argument. At most three frames are required.
+    /// frames, of the page size of the translation granule, are allocated from the `allocator`
+    /// argument. At most three frames are required.
+    ///
+    /// The [`UnusedPhysFrame`] witness guarantees that `frame` is not used for any other
+    /// mappings.
     ///
-    /// This function is unsafe because the caller must guarantee that passed `frame` is
-    /// unused, i.e. not used for any other mappings.
-    unsafe fn map_to<A>(
+    /// Pages above the page level of the granule, e.g. [`Size2MiB`] and [`Size1GiB`] with the
+    /// 4KiB granule, are mapped with a block entry. The descriptor type bit (`TABLE_OR_PAGE`) of
+    /// `flags` is set or cleared accordingly by [`MappedPageTable`] and [`OffsetPageTable`], so
+    /// [`PageTableFlags::default_leaf`] or [`PageTableFlags::default_page`] can be used for all
+    /// sizes. The flags must include `AF`, unless the MMU manages the access flag.
+    ///
+    /// Returns `MapToError::InvalidFlags` if `flags` include any of the
+    /// [`TABLE_ATTRIBUTES`](PageTableFlags::TABLE_ATTRIBUTES), which only apply to table
+    /// descriptors.
+    fn map_to<A>(
         &mut self,
         page: Page<S>,
-        frame: PhysFrame<S>,
+        frame: UnusedPhysFrame<S>,
         flags: PageTableFlags,
         attr: PageTableAttribute,
         frame_allocator: &mut A,
     ) -> Result<MapperFlush<S>, MapToError>
     where
-        A: FrameAllocator<Size4KiB>;
-
-    /// Get the reference of the specified `page` entry
-    fn get_entry(&self, page: Page<S>) -> Result<&PageTableEntry, EntryGetError>;
+        A: FrameAllocator<<S::Granule as TranslationGranule>::Page>;
 
     /// Get the mutable reference of the specified `page` entry
-    fn get_entry_mut(&mut self, page: Page<S>) -> Result<&mut PageTableEntry, EntryGetError> {
-        let entry = self.get_entry(page)?;
-        let entry_mut = unsafe { &mut *(entry as *const _ as *mut PageTableEntry) };
-        Ok(entry_mut)
-    }
+    ///
+    /// Implementations must reach the entry through mutable references to the tables: casting the
+    /// shared reference returned by [`get_entry`](TranslatePage::get_entry) to a mutable one, as
+    /// the former default implementation did, is undefined behavior.
+    fn get_entry_mut(&mut self, page: Page<S>) -> Result<&mut PageTableEntry, EntryGetError>;
 
     /// Removes a mapping from the page table and returns the frame that used to be mapped.
     ///
@@ -112,51 +365,360 @@ pub trait Mapper<S: PageSize> {
     fn unmap(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError>;
 
     /// Updates the flags of an existing mapping.
+    ///
+    /// With `debug_assertions` or the `bbm-check` feature, returns
+    /// `FlagUpdateError::BreakBeforeMakeRequired` if the new flags change the descriptor type or
+    /// the contiguous hint, which requires the break-before-make sequence, see
+    /// [`remap`](Self::remap). Otherwise the flags are written as given.
     fn update_flags(
         &mut self,
         page: Page<S>,
         flags: PageTableFlags,
     ) -> Result<MapperFlush<S>, FlagUpdateError> {
+        if !flags.is_valid_for_page() {
+            return Err(FlagUpdateError::InvalidFlags);
+        }
         let entry = self.get_entry_mut(page)?;
         if entry.is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
+        #[cfg(any(debug_assertions, feature = "bbm-check"))]
+        {
+            let changed = (entry.flags() ^ flags).bits();
+            if crate::paging::bbm::requires_break_before_make(entry.raw(), entry.raw() ^ changed) {
+                return Err(FlagUpdateError::BreakBeforeMakeRequired);
+            }
+        }
         entry.set_flags(flags);
         Ok(MapperFlush::new(page))
     }
 
-    /// Return the frame that the specified page is mapped to.
+    /// Changes the frame, flags and memory attribute of an existing mapping, following the
+    /// break-before-make sequence.
     ///
-    /// This function assumes that the page is mapped to a frame of size `S` and returns an
-    /// error otherwise.
-    fn translate_page(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError> {
-        let entry = self.get_entry(page)?;
-        if entry.is_unused() {
-            return Err(TranslateError::PageNotMapped);
+    /// The entry is invalidated and its TLB entries are flushed from all PEs before the new entry
+    /// is written, so that no PE can hold translations for the old and the new mapping at the same
+    /// time. Accesses to the page by other PEs fault while the mapping is broken.
+    ///
+    /// Returns `FlagUpdateError::ParentEntryHugePage` if the page is not mapped with size `S`.
+    fn remap(
+        &mut self,
+        page: Page<S>,
+        frame: UnusedPhysFrame<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+    ) -> Result<MapperFlush<S>, FlagUpdateError> {
+        if !flags.is_valid_for_page() {
+            return Err(FlagUpdateError::InvalidFlags);
+        }
+        let entry = self.get_entry_mut(page)?;
+        if !entry.flags().contains(PageTableFlags::VALID) {
+            return Err(FlagUpdateError::PageNotMapped);
+        } else if entry.is_block() != (S::LEVEL != PAGE_LEVEL) {
+            return Err(FlagUpdateError::ParentEntryHugePage);
         }
-        PhysFrame::from_start_address(entry.addr())
-            .map_err(|()| TranslateError::InvalidFrameAddress(entry.addr()))
+
+        entry.set_unused();
+        crate::translation::invalidate_tlb_vaddr(page.start_address());
+
+        if S::LEVEL == PAGE_LEVEL {
+            entry.set_addr_in::<S::Granule>(frame.start_address(), flags, attr);
+        } else {
+            entry.set_block::<S>(frame.start_address(), flags, attr);
+        }
+        Ok(MapperFlush::new(page))
     }
 
-    /// Maps the given frame to the virtual page with the same address.
+    /// Splits the block mapping `page` into a table of the next lookup level, mapping the same
+    /// memory with the same flags and attributes.
+    ///
+    /// This is needed before changing the mapping of a part of the block, e.g. a 2MiB block is
+    /// split into 512 4KiB pages. The table frame is allocated from `frame_allocator`. The block
+    /// is replaced following the break-before-make sequence, so the TLB entries of the block are
+    /// already flushed when this returns.
+    ///
+    /// Returns `SplitError::NotHugePage` if the page is not mapped as a block of size `S`, in
+    /// particular if `S` is the page size of the granule.
     ///
-    /// This function is unsafe because the caller must guarantee that the passed `frame` is
-    /// unused, i.e. not used for any other mappings.
-    unsafe fn identity_map<A>(
+    /// # Safety
+    ///
+    /// Accesses to the block by other PEs fault while the mapping is broken, so the caller must
+    /// guarantee that the block is not used by code that can't handle that fault, e.g. the code
+    /// performing the split.
+    unsafe fn split_huge_page<A>(
         &mut self,
-        frame: PhysFrame<S>,
+        page: Page<S>,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<S>, SplitError>
+    where
+        A: FrameAllocator<<S::Granule as TranslationGranule>::Page>;
+
+    /// Replaces the table mapping `page` by a single block entry, if the table maps the whole
+    /// block with identical flags and attributes. The inverse of
+    /// [`split_huge_page`](Mapper::split_huge_page).
+    ///
+    /// All entries of the table must be valid and map contiguous memory starting at an address
+    /// aligned to `S`. The table is replaced following the break-before-make sequence, which
+    /// flushes the whole TLB, and its frame is freed to `frame_deallocator`.
+    ///
+    /// Returns `MergeError::NotTable` if `S` is the page size of the granule, or if the page is
+    /// already a block.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the table is not used by other page table hierarchies, and
+    /// that no accesses to the block by other PEs happen while the mapping is broken.
+    unsafe fn merge_huge_page<D>(
+        &mut self,
+        page: Page<S>,
+        frame_deallocator: &mut D,
+    ) -> Result<MapperFlush<S>, MergeError>
+    where
+        D: FrameDeallocator<<S::Granule as TranslationGranule>::Page>;
+
+    /// Clears the access flag of every page of `pages`, and calls `accessed` with each page
+    /// whose access flag was set.
+    ///
+    /// This is the scan of page aging algorithms like CLOCK. Pages that are not mapped with size
+    /// `S` are skipped. The returned flush must be performed for the next accesses to be recorded.
+    fn scan_accessed<F>(&mut self, pages: PageRange<S>, mut accessed: F) -> MapperFlushRange<S>
+    where
+        F: FnMut(Page<S>),
+    {
+        for page in pages {
+            if let Ok(entry) = self.get_entry_mut(page) {
+                if entry.flags().contains(PageTableFlags::VALID)
+                    && entry.is_block() == (S::LEVEL != PAGE_LEVEL)
+                    && entry.clear_accessed()
+                {
+                    accessed(page);
+                }
+            }
+        }
+        MapperFlushRange::new(pages)
+    }
+
+    /// Maps the given frame to the virtual page with the same address.
+    fn identity_map<A>(
+        &mut self,
+        frame: UnusedPhysFrame<S>,
         flags: PageTableFlags,
         attr: PageTableAttribute,
         frame_allocator: &mut A,
     ) -> Result<MapperFlush<S>, MapToError>
     where
-        A: FrameAllocator<Size4KiB>,
+        A: FrameAllocator<<S::Granule as TranslationGranule>::Page>,
         S: PageSize,
         Self: Mapper<S>,
     {
         let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
         self.map_to(page, frame, flags, attr, frame_allocator)
     }
+
+    /// Maps each page of `pages` to the frame at the same position in `frames`.
+    ///
+    /// Returns a single flush for the whole range. If mapping a page fails, the pages before it
+    /// stay mapped and the error reports the failed page.
+    ///
+    /// Panics if `pages` and `frames` have different lengths.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that passed `frames` are unused, i.e. not used for any other
+    /// mappings.
+    unsafe fn map_range_to<A>(
+        &mut self,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>>
+    where
+        A: FrameAllocator<<S::Granule as TranslationGranule>::Page>,
+    {
+        assert_eq!(
+            pages.end - pages.start,
+            frames.end - frames.start,
+            "page and frame ranges differ in length"
+        );
+        for (page, frame) in pages.zip(frames) {
+            self.map_to(
+                page,
+                UnusedPhysFrame::new(frame),
+                flags,
+                attr,
+                frame_allocator,
+            )
+            .map_err(|error| RangeError::new(pages, page, error))?
+            .ignore();
+        }
+        Ok(MapperFlushRange::new(pages))
+    }
+
+    /// Maps the given frames to the virtual pages with the same addresses.
+    ///
+    /// See [`map_range_to`](Mapper::map_range_to) for the error handling.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed `frames` are unused, i.e. not used for any other
+    /// mappings.
+    unsafe fn identity_map_range<A>(
+        &mut self,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>>
+    where
+        A: FrameAllocator<<S::Granule as TranslationGranule>::Page>,
+    {
+        let page = |frame: PhysFrame<S>| {
+            Page::containing_address(VirtAddr::new(frame.start_address().as_u64()))
+        };
+        let pages = Page::range(page(frames.start), page(frames.end));
+        self.map_range_to(pages, frames, flags, attr, frame_allocator)
+    }
+
+    /// Removes the mappings of all pages in `pages`.
+    ///
+    /// Returns a single flush for the whole range. If unmapping a page fails, the pages before it
+    /// are already unmapped: the error reports the failed page and holds the flush for them.
+    ///
+    /// Note that no page tables or pages are deallocated.
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, UnmapRangeError<S>> {
+        for page in pages {
+            self.unmap(page)
+                .map_err(|error| RangeError::new(pages, page, error))?
+                .1
+                .ignore();
+        }
+        Ok(MapperFlushRange::new(pages))
+    }
+
+    /// Maps the run of pages starting at `page` to the frames starting at `frame`, with the
+    /// contiguous hint, so that the TLB can cache the whole run in a single entry.
+    ///
+    /// A run has [`contiguous_entries`](TranslationGranule::contiguous_entries) pages of size
+    /// `S` (e.g. 16 with the 4KiB granule), and `page` and `frame` must be aligned to its size.
+    /// No page of the run may be mapped. If mapping a page fails, the pages of the run mapped
+    /// before it are unmapped again and flushed from the TLB, so that no partial run is left
+    /// behind.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed frames are unused, i.e. not used for any other
+    /// mappings.
+    unsafe fn map_contiguous_run<A>(
+        &mut self,
+        page: Page<S>,
+        frame: PhysFrame<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, ContiguousError>
+    where
+        A: FrameAllocator<<S::Granule as TranslationGranule>::Page>,
+    {
+        let count = <S::Granule as TranslationGranule>::contiguous_entries(S::LEVEL) as u64;
+        if !page.start_address().is_aligned(S::SIZE * count)
+            || !frame.start_address().is_aligned(S::SIZE * count)
+        {
+            return Err(ContiguousError::Unaligned);
+        }
+        let pages = Page::range(page, page + count);
+        for page in pages {
+            match self.get_entry(page) {
+                Ok(entry) if !entry.is_unused() => {
+                    return Err(ContiguousError::MapFailed(MapToError::PageAlreadyMapped))
+                }
+                Err(EntryGetError::ParentEntryHugePage) => {
+                    return Err(ContiguousError::MapFailed(MapToError::ParentEntryHugePage))
+                }
+                _ => {}
+            }
+        }
+
+        let flags = flags | PageTableFlags::Contiguous;
+        for (index, page) in pages.enumerate() {
+            let frame = UnusedPhysFrame::new(frame + index as u64);
+            match self.map_to(page, frame, flags, attr, frame_allocator) {
+                Ok(flush) => flush.ignore(),
+                Err(error) => {
+                    let mapped = Page::range(pages.start, page);
+                    for page in mapped {
+                        if let Ok((_, flush)) = self.unmap(page) {
+                            flush.ignore();
+                        }
+                    }
+                    // the pages were valid, so their frames may still be cached in the TLB
+                    MapperFlushRange::new(mapped).flush();
+                    return Err(ContiguousError::MapFailed(error));
+                }
+            }
+        }
+        Ok(MapperFlushRange::new(pages))
+    }
+
+    /// Removes the contiguous hint from the run of pages starting at `page`, keeping their
+    /// mappings, e.g. before changing the mapping or permissions of a part of the run.
+    ///
+    /// The run must be mapped as by [`map_contiguous_run`](Mapper::map_contiguous_run). Its
+    /// entries are replaced following the break-before-make sequence: all of them are
+    /// invalidated and their TLB entries flushed from all PEs before they are rewritten, so the
+    /// TLB entries of the run are already flushed when this returns. Accesses to the run by other
+    /// PEs fault while the mappings are broken.
+    fn clear_contiguous_run(
+        &mut self,
+        page: Page<S>,
+    ) -> Result<MapperFlushRange<S>, ContiguousError> {
+        let count = <S::Granule as TranslationGranule>::contiguous_entries(S::LEVEL) as u64;
+        if !page.start_address().is_aligned(S::SIZE * count) {
+            return Err(ContiguousError::Unaligned);
+        }
+        let pages = Page::range(page, page + count);
+        let first = *self
+            .get_entry(page)
+            .map_err(|_| ContiguousError::PageNotMapped)?;
+        let (addr, flags, attr) = (first.addr_in::<S::Granule>(), first.flags(), first.attr());
+        if !flags.contains(PageTableFlags::VALID) {
+            return Err(ContiguousError::PageNotMapped);
+        } else if !flags.contains(PageTableFlags::Contiguous)
+            || first.is_block() != (S::LEVEL != PAGE_LEVEL)
+        {
+            return Err(ContiguousError::NotContiguous);
+        }
+        for (index, page) in pages.enumerate() {
+            let entry = self
+                .get_entry(page)
+                .map_err(|_| ContiguousError::NotContiguous)?;
+            if entry.addr_in::<S::Granule>() != addr + index as u64 * S::SIZE
+                || entry.flags() != flags
+                || entry.attr().value != attr.value
+            {
+                return Err(ContiguousError::NotContiguous);
+            }
+        }
+
+        for page in pages {
+            if let Ok(entry) = self.get_entry_mut(page) {
+                entry.set_unused();
+            }
+        }
+        crate::translation::invalidate_tlb_pages(pages);
+
+        let flags = flags - PageTableFlags::Contiguous;
+        for (index, page) in pages.enumerate() {
+            if let Ok(entry) = self.get_entry_mut(page) {
+                entry.set_addr_in::<S::Granule>(addr + index as u64 * S::SIZE, flags, attr);
+            }
+        }
+        Ok(MapperFlushRange::new(pages))
+    }
 }
 
 /// This type represents a page whose mapping has changed in the page table.
@@ -174,18 +736,98 @@ impl<S: PageSize> MapperFlush<S> {
         MapperFlush(page)
     }
 
+    /// Returns the page to flush.
+    pub fn page(&self) -> Page<S> {
+        self.0
+    }
+
     /// Flush the page from the TLB to ensure that the newest mapping is used.
+    ///
+    /// Only the entries for the page are invalidated, for all ASIDs.
     pub fn flush(self) {
-        #[cfg(target_arch = "aarch64")]
         crate::translation::invalidate_tlb_vaddr(self.0.start_address());
     }
 
+    /// Flush the page from the TLB, only for the given ASID (and global mappings).
+    pub fn flush_asid(self, asid: u16) {
+        crate::translation::invalidate_tlb_vaddr_asid(self.0.start_address(), asid);
+    }
+
+    /// Flush the entire TLB instead of only the page.
+    pub fn flush_all(self) {
+        MapperFlushAll::new().flush();
+    }
+
     /// Don't flush the TLB and silence the “must be used” warning.
     pub fn ignore(self) {}
 }
 
-/// This error is returned from `map_to` and similar methods.
+/// This type represents a change of a page table that requires flushing the entire TLB.
+///
+/// Useful to combine many flushes into a single one, e.g. after changing a large part of an
+/// address space, where invalidating each page separately would be slower.
+#[derive(Debug, Default)]
+#[must_use = "Page Table changes must be flushed or ignored."]
+pub struct MapperFlushAll(());
+
+impl MapperFlushAll {
+    /// Create a new flush promise
+    pub fn new() -> Self {
+        MapperFlushAll(())
+    }
+
+    /// Flush all pages from the TLB to ensure that the newest mappings are used.
+    pub fn flush(self) {
+        crate::translation::invalidate_tlb_all();
+    }
+
+    /// Don't flush the TLB and silence the “must be used” warning.
+    pub fn ignore(self) {}
+}
+
+/// This type represents a range of pages whose mappings have changed in the page table.
+///
+/// Like [`MapperFlush`], but for a whole range of pages, e.g. returned by
+/// [`Mapper::map_range_to`].
 #[derive(Debug)]
+#[must_use = "Page Table changes must be flushed or ignored."]
+pub struct MapperFlushRange<S: PageSize>(PageRange<S>);
+
+impl<S: PageSize> MapperFlushRange<S> {
+    /// Create a new flush promise
+    fn new(pages: PageRange<S>) -> Self {
+        MapperFlushRange(pages)
+    }
+
+    /// Returns the range of pages to flush.
+    pub fn pages(&self) -> PageRange<S> {
+        self.0
+    }
+
+    /// Flush the pages from the TLB to ensure that the newest mappings are used.
+    ///
+    /// With the TLBI range instructions, the whole range takes a few TLBIs, see
+    /// [`invalidate_tlb_pages`](crate::translation::invalidate_tlb_pages).
+    pub fn flush(self) {
+        crate::translation::invalidate_tlb_pages(self.0);
+    }
+
+    /// Flush the pages from the TLB, only for the given ASID (and global mappings).
+    pub fn flush_asid(self, asid: u16) {
+        crate::translation::invalidate_tlb_pages_asid(self.0, asid);
+    }
+
+    /// Flush the entire TLB instead of only the pages.
+    pub fn flush_all(self) {
+        MapperFlushAll::new().flush();
+    }
+
+    /// Don't flush the TLB and silence the “must be used” warning.
+    pub fn ignore(self) {}
+}
+
+/// This error is returned from `map_to` and similar methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MapToError {
     /// An additional frame was needed for the mapping process, but the frame allocator
     /// returned `None`.
@@ -195,10 +837,40 @@ pub enum MapToError {
     ParentEntryHugePage,
     /// The given page is already mapped to a physical frame.
     PageAlreadyMapped,
+    /// The flags include attributes of table descriptors, which have no meaning in a page or
+    /// block descriptor, see [`PageTableFlags::is_valid_for_page`].
+    InvalidFlags,
 }
 
-/// An error indicating that an `get_entry` or `get_entry_mut` call failed.
+/// An error indicating that a range operation failed at some page.
 #[derive(Debug)]
+pub struct RangeError<S: PageSize, E> {
+    /// The page at which the operation failed.
+    pub page: Page<S>,
+    /// The error for `page`.
+    pub error: E,
+    /// The pages before `page`, whose mappings have already changed.
+    pub flush: MapperFlushRange<S>,
+}
+
+impl<S: PageSize, E> RangeError<S, E> {
+    fn new(pages: PageRange<S>, page: Page<S>, error: E) -> Self {
+        Self {
+            page,
+            error,
+            flush: MapperFlushRange::new(Page::range(pages.start, page)),
+        }
+    }
+}
+
+/// This error is returned from `map_range_to` and `identity_map_range`.
+pub type MapRangeError<S> = RangeError<S, MapToError>;
+
+/// This error is returned from `unmap_range`.
+pub type UnmapRangeError<S> = RangeError<S, UnmapError>;
+
+/// An error indicating that an `get_entry` or `get_entry_mut` call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EntryGetError {
     /// The given page is not mapped to a physical frame.
     PageNotMapped,
@@ -208,7 +880,7 @@ pub enum EntryGetError {
 }
 
 /// An error indicating that an `unmap` call failed.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UnmapError {
     /// An upper level page table entry has the `HUGE_PAGE` flag set, which means that the
     /// given page is part of a huge page and can't be freed individually.
@@ -219,18 +891,63 @@ pub enum UnmapError {
     InvalidFrameAddress(PhysAddr),
 }
 
+/// An error indicating that a `split_huge_page` call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitError {
+    /// The given page is not mapped to a physical frame.
+    PageNotMapped,
+    /// The given page is not mapped as a block, e.g. because it is already split.
+    NotHugePage,
+    /// The frame allocator returned `None` for the new table.
+    FrameAllocationFailed,
+}
+
+/// An error indicating that a `merge_huge_page` call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    /// The given page is not mapped to a physical frame.
+    PageNotMapped,
+    /// The given page is not mapped through a table, e.g. because it is already a block.
+    NotTable,
+    /// The entries of the table don't map contiguous, aligned memory with identical flags and
+    /// attributes.
+    NotMergeable,
+}
+
+/// An error indicating that a `map_contiguous_run` or `clear_contiguous_run` call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContiguousError {
+    /// The page or frame is not aligned to the size of a contiguous run.
+    Unaligned,
+    /// A page of the run could not be mapped. The pages of the run mapped before it were
+    /// unmapped again.
+    MapFailed(MapToError),
+    /// The first page of the run is not mapped.
+    PageNotMapped,
+    /// The entries of the run don't map contiguous memory with size `S`, identical flags and
+    /// attributes, and the contiguous hint.
+    NotContiguous,
+}
+
 /// An error indicating that an `update_flags` call failed.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FlagUpdateError {
     /// The given page is not mapped to a physical frame.
     PageNotMapped,
     /// An upper level page table entry has the `HUGE_PAGE` flag set, which means that the
     /// given page is part of a huge page and can't be freed individually.
     ParentEntryHugePage,
+    /// The flags include attributes of table descriptors, which have no meaning in a page or
+    /// block descriptor, see [`PageTableFlags::is_valid_for_page`].
+    InvalidFlags,
+    /// The flags change the descriptor type or the contiguous hint of the mapping, which can
+    /// only be done with break-before-make. Only returned with `debug_assertions` or the
+    /// `bbm-check` feature.
+    BreakBeforeMakeRequired,
 }
 
 /// An error indicating that an `translate` call failed.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TranslateError {
     /// The given page is not mapped to a physical frame.
     PageNotMapped,
@@ -259,6 +976,24 @@ impl From<EntryGetError> for FlagUpdateError {
     }
 }
 
+impl From<EntryGetError> for SplitError {
+    fn from(err: EntryGetError) -> Self {
+        match err {
+            EntryGetError::ParentEntryHugePage => SplitError::NotHugePage,
+            EntryGetError::PageNotMapped => SplitError::PageNotMapped,
+        }
+    }
+}
+
+impl From<EntryGetError> for MergeError {
+    fn from(err: EntryGetError) -> Self {
+        match err {
+            EntryGetError::ParentEntryHugePage => MergeError::NotTable,
+            EntryGetError::PageNotMapped => MergeError::PageNotMapped,
+        }
+    }
+}
+
 impl From<EntryGetError> for TranslateError {
     fn from(err: EntryGetError) -> Self {
         match err {
@@ -267,3 +1002,231 @@ impl From<EntryGetError> for TranslateError {
         }
     }
 }
+
+/// The errors of all the mapper operations, so that the layers of a kernel's memory management
+/// can propagate them with `?`.
+///
+/// Each mapper error converts into it with `From`, keeping its cause. The error of a range
+/// operation, [`RangeError`], converts through its `error` field, once the pages changed before
+/// it have been flushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingError {
+    /// The frame allocator returned `None` for a page table or a page.
+    FrameAllocationFailed,
+    /// The page is already mapped to a physical frame.
+    PageAlreadyMapped,
+    /// The page is not mapped to a physical frame.
+    PageNotMapped,
+    /// The page is part of a block mapped by an upper level entry.
+    ParentEntryHugePage,
+    /// The page table entry of the page points to an invalid physical address.
+    InvalidFrameAddress(PhysAddr),
+    /// The page is not mapped as a block.
+    NotHugePage,
+    /// The page is not mapped through a table.
+    NotTable,
+    /// The entries of the table can't be merged into a block.
+    NotMergeable,
+    /// The page or frame is not aligned to the size of a contiguous run.
+    Unaligned,
+    /// The entries are not a contiguous run.
+    NotContiguous,
+    /// The flags are not valid for the descriptor, e.g. table attributes in a page descriptor.
+    InvalidFlags,
+    /// The change of the descriptor requires break-before-make.
+    BreakBeforeMakeRequired,
+}
+
+impl fmt::Display for PagingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PagingError::FrameAllocationFailed => f.write_str("frame allocation failed"),
+            PagingError::PageAlreadyMapped => f.write_str("page already mapped"),
+            PagingError::PageNotMapped => f.write_str("page not mapped"),
+            PagingError::ParentEntryHugePage => f.write_str("page is part of a block"),
+            PagingError::InvalidFrameAddress(addr) => {
+                write!(f, "invalid frame address {:#x}", addr.as_u64())
+            }
+            PagingError::NotHugePage => f.write_str("page not mapped as a block"),
+            PagingError::NotTable => f.write_str("page not mapped through a table"),
+            PagingError::NotMergeable => f.write_str("entries not mergeable into a block"),
+            PagingError::Unaligned => f.write_str("page or frame not aligned to a contiguous run"),
+            PagingError::NotContiguous => f.write_str("entries not a contiguous run"),
+            PagingError::InvalidFlags => f.write_str("flags not valid for the descriptor"),
+            PagingError::BreakBeforeMakeRequired => f.write_str("break-before-make required"),
+        }
+    }
+}
+
+impl core::error::Error for PagingError {}
+
+impl From<MapToError> for PagingError {
+    fn from(err: MapToError) -> Self {
+        match err {
+            MapToError::FrameAllocationFailed => PagingError::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+            MapToError::PageAlreadyMapped => PagingError::PageAlreadyMapped,
+            MapToError::InvalidFlags => PagingError::InvalidFlags,
+        }
+    }
+}
+
+impl From<EntryGetError> for PagingError {
+    fn from(err: EntryGetError) -> Self {
+        match err {
+            EntryGetError::PageNotMapped => PagingError::PageNotMapped,
+            EntryGetError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+        }
+    }
+}
+
+impl From<UnmapError> for PagingError {
+    fn from(err: UnmapError) -> Self {
+        match err {
+            UnmapError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+            UnmapError::PageNotMapped => PagingError::PageNotMapped,
+            UnmapError::InvalidFrameAddress(addr) => PagingError::InvalidFrameAddress(addr),
+        }
+    }
+}
+
+impl From<SplitError> for PagingError {
+    fn from(err: SplitError) -> Self {
+        match err {
+            SplitError::PageNotMapped => PagingError::PageNotMapped,
+            SplitError::NotHugePage => PagingError::NotHugePage,
+            SplitError::FrameAllocationFailed => PagingError::FrameAllocationFailed,
+        }
+    }
+}
+
+impl From<MergeError> for PagingError {
+    fn from(err: MergeError) -> Self {
+        match err {
+            MergeError::PageNotMapped => PagingError::PageNotMapped,
+            MergeError::NotTable => PagingError::NotTable,
+            MergeError::NotMergeable => PagingError::NotMergeable,
+        }
+    }
+}
+
+impl From<ContiguousError> for PagingError {
+    fn from(err: ContiguousError) -> Self {
+        match err {
+            ContiguousError::Unaligned => PagingError::Unaligned,
+            ContiguousError::MapFailed(err) => err.into(),
+            ContiguousError::PageNotMapped => PagingError::PageNotMapped,
+            ContiguousError::NotContiguous => PagingError::NotContiguous,
+        }
+    }
+}
+
+impl From<FlagUpdateError> for PagingError {
+    fn from(err: FlagUpdateError) -> Self {
+        match err {
+            FlagUpdateError::PageNotMapped => PagingError::PageNotMapped,
+            FlagUpdateError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+            FlagUpdateError::InvalidFlags => PagingError::InvalidFlags,
+            FlagUpdateError::BreakBeforeMakeRequired => PagingError::BreakBeforeMakeRequired,
+        }
+    }
+}
+
+impl From<TranslateError> for PagingError {
+    fn from(err: TranslateError) -> Self {
+        match err {
+            TranslateError::PageNotMapped => PagingError::PageNotMapped,
+            TranslateError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+            TranslateError::InvalidFrameAddress(addr) => PagingError::InvalidFrameAddress(addr),
+        }
+    }
+}
+
+/// Implements `Display` and `Error` for the mapper errors, through their [`PagingError`].
+macro_rules! paging_error {
+    ($($error:ident),*) => {
+        $(
+            impl fmt::Display for $error {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    fmt::Display::fmt(&PagingError::from(*self), f)
+                }
+            }
+
+            impl core::error::Error for $error {}
+        )*
+    };
+}
+
+paging_error!(
+    MapToError,
+    EntryGetError,
+    UnmapError,
+    SplitError,
+    MergeError,
+    FlagUpdateError,
+    TranslateError
+);
+
+impl fmt::Display for ContiguousError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContiguousError::MapFailed(_) => f.write_str("mapping a page of the run failed"),
+            _ => fmt::Display::fmt(&PagingError::from(*self), f),
+        }
+    }
+}
+
+impl core::error::Error for ContiguousError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            ContiguousError::MapFailed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<S: PageSize, E: fmt::Display> fmt::Display for RangeError<S, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "page {:#x}: {}",
+            self.page.start_address().as_u64(),
+            self.error
+        )
+    }
+}
+
+impl<S: PageSize + fmt::Debug, E: core::error::Error + 'static> core::error::Error
+    for RangeError<S, E>
+{
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unmap_and_translate(unmap: UnmapError) -> Result<(), PagingError> {
+        Err(unmap)?
+    }
+
+    #[test]
+    pub fn test_paging_error() {
+        assert_eq!(
+            unmap_and_translate(UnmapError::InvalidFrameAddress(PhysAddr::new(0x1000))),
+            Err(PagingError::InvalidFrameAddress(PhysAddr::new(0x1000)))
+        );
+        assert_eq!(
+            PagingError::from(ContiguousError::MapFailed(MapToError::PageAlreadyMapped)),
+            PagingError::PageAlreadyMapped
+        );
+        assert_eq!(
+            PagingError::from(EntryGetError::ParentEntryHugePage),
+            PagingError::from(FlagUpdateError::ParentEntryHugePage)
+        );
+        let err = ContiguousError::MapFailed(MapToError::FrameAllocationFailed);
+        assert!(core::error::Error::source(&err).is_some());
+    }
+}
@@ -0,0 +1,225 @@
+//! Access the page tables through a complete mapping of physical memory at an offset.
+
+use crate::paging::{
+    frame::{PhysFrame, PhysFrameRange},
+    frame_alloc::{FrameAllocator, FrameDeallocator},
+    granule::{Granule4KiB, TranslationGranule},
+    mapper::*,
+    page::{Page, PageRange, PageRangeInclusive, PageSize},
+    page_table::{PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
+};
+
+/// A Mapper implementation that requires that the complete physical memory is mapped at some
+/// offset in the virtual address space.
+///
+/// This is the common setup of higher-half kernels, and a [`MappedPageTable`] with an
+/// offset-based [`PageTableFrameMapping`].
+#[derive(Debug)]
+pub struct OffsetPageTable<'a, G: TranslationGranule = Granule4KiB> {
+    inner: MappedPageTable<'a, PhysOffset, G>,
+}
+
+impl<'a, G: TranslationGranule> OffsetPageTable<'a, G> {
+    /// Creates a new `OffsetPageTable` that uses the given offset for converting physical to
+    /// virtual addresses.
+    ///
+    /// The complete physical memory must be mapped in the virtual address space starting at
+    /// address `phys_offset`. This means that for example physical address `0x5000` can be
+    /// accessed through virtual address `phys_offset + 0x5000`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed `phys_offset` is correct. Also, the passed
+    /// `level_4_table` must point to the level 4 page table of a valid page table hierarchy.
+    /// Otherwise this function might break memory safety, e.g. by writing to an illegal memory
+    /// location.
+    pub unsafe fn new(level_4_table: &'a mut PageTable<G>, phys_offset: VirtAddr) -> Self {
+        Self {
+            inner: MappedPageTable::new(level_4_table, PhysOffset::new(phys_offset)),
+        }
+    }
+
+    /// Creates a new `OffsetPageTable` for the hierarchy whose level 4 table is stored in
+    /// `level_4_frame`, which doesn't need to be active.
+    ///
+    /// See [`MappedPageTable::from_frame`].
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed `phys_offset` is correct, and that
+    /// `level_4_frame` holds the level 4 table of a valid page table hierarchy, which is not
+    /// accessed by other means for the lifetime `'a`.
+    pub unsafe fn from_frame(level_4_frame: PhysFrame<G::Page>, phys_offset: VirtAddr) -> Self {
+        Self {
+            inner: MappedPageTable::from_frame(level_4_frame, PhysOffset::new(phys_offset)),
+        }
+    }
+
+    /// Installs the page table for the lower VA range (TTBR0_EL1) with the given ASID.
+    ///
+    /// Panics if the page table was not created with
+    /// [`from_frame`](OffsetPageTable::from_frame).
+    ///
+    /// # Safety
+    ///
+    /// See [`MappedPageTable::switch_to`].
+    pub unsafe fn switch_to(&self, asid: u16) {
+        self.inner.switch_to(asid)
+    }
+
+    /// Returns the offset at which the physical memory is mapped.
+    pub fn phys_offset(&self) -> VirtAddr {
+        self.inner.page_table_frame_mapping().phys_offset()
+    }
+
+    /// Returns a mutable reference to the wrapped level 4 page table.
+    pub fn level_4_table(&mut self) -> &mut PageTable<G> {
+        self.inner.level_4_table()
+    }
+
+    /// Frees all empty page tables of the hierarchy, see [`MappedPageTable::clean_up`].
+    ///
+    /// # Safety
+    ///
+    /// See [`MappedPageTable::clean_up`].
+    pub unsafe fn clean_up<D>(&mut self, deallocator: &mut D)
+    where
+        D: FrameDeallocator<G::Page>,
+    {
+        self.inner.clean_up(deallocator)
+    }
+
+    /// Frees the empty page tables translating addresses of `range`, see
+    /// [`MappedPageTable::clean_up_addr_range`].
+    ///
+    /// # Safety
+    ///
+    /// See [`MappedPageTable::clean_up`].
+    pub unsafe fn clean_up_addr_range<D>(
+        &mut self,
+        range: PageRangeInclusive<G::Page>,
+        deallocator: &mut D,
+    ) where
+        D: FrameDeallocator<G::Page>,
+    {
+        self.inner.clean_up_addr_range(range, deallocator)
+    }
+
+    /// Unmaps `range`, frees the mapped frames and the empty page tables, see
+    /// [`MappedPageTable::free_addr_range`].
+    ///
+    /// # Safety
+    ///
+    /// See [`MappedPageTable::free_addr_range`].
+    pub unsafe fn free_addr_range<D>(
+        &mut self,
+        range: PageRangeInclusive<G::Page>,
+        deallocator: &mut D,
+    ) where
+        D: FrameDeallocator<G::Page>,
+    {
+        self.inner.free_addr_range(range, deallocator)
+    }
+}
+
+impl<'a, G, S> TranslatePage<S> for OffsetPageTable<'a, G>
+where
+    G: TranslationGranule,
+    S: PageSize<Granule = G>,
+{
+    #[inline]
+    fn get_entry(&self, page: Page<S>) -> Result<&PageTableEntry, EntryGetError> {
+        self.inner.get_entry(page)
+    }
+}
+
+impl<'a, G, S> Mapper<S> for OffsetPageTable<'a, G>
+where
+    G: TranslationGranule,
+    S: PageSize<Granule = G>,
+{
+    #[inline]
+    fn map_to<A>(
+        &mut self,
+        page: Page<S>,
+        frame: UnusedPhysFrame<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        allocator: &mut A,
+    ) -> Result<MapperFlush<S>, MapToError>
+    where
+        A: FrameAllocator<G::Page>,
+    {
+        self.inner.map_to(page, frame, flags, attr, allocator)
+    }
+
+    #[inline]
+    unsafe fn map_range_to<A>(
+        &mut self,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, MapRangeError<S>>
+    where
+        A: FrameAllocator<G::Page>,
+    {
+        self.inner
+            .map_range_to(pages, frames, flags, attr, allocator)
+    }
+
+    #[inline]
+    unsafe fn split_huge_page<A>(
+        &mut self,
+        page: Page<S>,
+        allocator: &mut A,
+    ) -> Result<MapperFlush<S>, SplitError>
+    where
+        A: FrameAllocator<G::Page>,
+    {
+        self.inner.split_huge_page(page, allocator)
+    }
+
+    #[inline]
+    unsafe fn merge_huge_page<D>(
+        &mut self,
+        page: Page<S>,
+        deallocator: &mut D,
+    ) -> Result<MapperFlush<S>, MergeError>
+    where
+        D: FrameDeallocator<G::Page>,
+    {
+        self.inner.merge_huge_page(page, deallocator)
+    }
+
+    #[inline]
+    fn get_entry_mut(&mut self, page: Page<S>) -> Result<&mut PageTableEntry, EntryGetError> {
+        self.inner.get_entry_mut(page)
+    }
+
+    #[inline]
+    fn unmap(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError> {
+        self.inner.unmap(page)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, UnmapRangeError<S>> {
+        self.inner.unmap_range(pages)
+    }
+}
+
+impl<'a> Translate for OffsetPageTable<'a> {
+    #[inline]
+    fn translate(&self, addr: VirtAddr) -> TranslateResult {
+        self.inner.translate(addr)
+    }
+
+    #[inline]
+    fn translate_with_flags(&self, addr: VirtAddr) -> Result<Translation, TranslateError> {
+        self.inner.translate_with_flags(addr)
+    }
+}
@@ -1,14 +1,32 @@
 //! Access the page tables through a recursively mapped level 4 table.
 
-use crate::paging::{
-    frame::PhysFrame,
-    frame_alloc::FrameAllocator,
-    mapper::*,
-    page::{NotGiantPageSize, Page, PageSize, Size4KiB},
-    page_table::{FrameError, PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
+use crate::{
+    addr::{PhysAddr, VirtAddr, VirtAddrRange},
+    paging::{
+        frame::PhysFrame,
+        frame_alloc::{FrameAllocator, FrameDeallocator},
+        granule::Granule4KiB,
+        mapper::*,
+        memory_attribute::{MairNormal, MairType},
+        page::{NotGiantPageSize, Page, PageSize, Size1GiB, Size2MiB, Size4KiB},
+        page_table::{FrameError, PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
+    },
+    registers::*,
 };
 use ux::u9;
 
+/// An error indicating that a page table is not recursively mapped as expected by
+/// [`RecursivePageTable::try_new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidPageTable {
+    /// The table is not accessed through the recursive address of the recursive index.
+    NotRecursiveAddress,
+    /// The recursive entry is not a valid, privileged writable table and page descriptor.
+    InvalidEntryFlags,
+    /// The recursive entry doesn't point to the level 4 table installed in TTBRx_EL1.
+    NotActive,
+}
+
 /// A recursive page table is a last level page table with an entry mapped to the table itself.
 ///
 /// This recursive mapping allows accessing all page tables in the hierarchy:
@@ -21,7 +39,7 @@ use ux::u9;
 /// - To access a level 1 page table, we “loop” once, then use the level 4 index, then the level 3
 ///   index, then the level 2 index.
 ///
-/// This struct implements the `Mapper` trait.
+/// This struct implements the `Mapper` trait for 4KiB pages, and the `Translate` trait.
 #[derive(Debug)]
 pub struct RecursivePageTable {
     recursive_index: u9,
@@ -37,6 +55,75 @@ impl RecursivePageTable {
         }
     }
 
+    /// Creates a new RecursivePageTable, checking that `table` is the active level 4 table
+    /// accessed through the recursive entry `recursive_index`.
+    ///
+    /// The table must be the one installed in TTBR0_EL1 or TTBR1_EL1, depending on the half of
+    /// the address space it is accessed in.
+    pub fn try_new(table: &PageTable, recursive_index: u16) -> Result<Self, InvalidPageTable> {
+        let table_addr = VirtAddr::new(table as *const _ as u64);
+        let root = if VirtAddrRange::TTBR1.contains(table_addr) {
+            TTBR1_EL1.get_baddr()
+        } else {
+            TTBR0_EL1.get_baddr()
+        };
+        let recursive_index = u9::new(recursive_index);
+        Self::validate(
+            table_addr,
+            &table[recursive_index],
+            recursive_index,
+            PhysAddr::new(root),
+        )?;
+        Ok(Self { recursive_index })
+    }
+
+    /// Checks the recursive entry `entry` of the level 4 table at `table_addr`, whose physical
+    /// address is `root`.
+    fn validate(
+        table_addr: VirtAddr,
+        entry: &PageTableEntry,
+        recursive_index: u9,
+        root: PhysAddr,
+    ) -> Result<(), InvalidPageTable> {
+        let va_range = table_addr
+            .va_range()
+            .map_err(|_| InvalidPageTable::NotRecursiveAddress)?;
+        let recursive_page = Page::<Size4KiB>::from_page_table_indices(
+            va_range,
+            recursive_index,
+            recursive_index,
+            recursive_index,
+            recursive_index,
+        );
+        if table_addr != recursive_page.start_address() {
+            return Err(InvalidPageTable::NotRecursiveAddress);
+        }
+
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::default_page())
+            || flags.intersects(PageTableFlags::AP_EL0 | PageTableFlags::AP_RO)
+        {
+            return Err(InvalidPageTable::InvalidEntryFlags);
+        }
+        if entry.addr() != root {
+            return Err(InvalidPageTable::NotActive);
+        }
+        Ok(())
+    }
+
+    /// Installs the recursive entry at `recursive_index` into `table`, a fresh level 4 table
+    /// stored in `frame`.
+    ///
+    /// The entry is a privileged, non-executable mapping of normal memory, so that the page
+    /// tables can be modified once `table` is active.
+    pub fn set_recursive_entry(table: &mut PageTable, frame: PhysFrame, recursive_index: u16) {
+        table[u9::new(recursive_index)].set_frame(
+            frame,
+            PageTableFlags::default_page() | PageTableFlags::PXN | PageTableFlags::UXN,
+            MairNormal::attr_value(),
+        );
+    }
+
     /// Internal helper function to create the page table of the next level if needed.
     ///
     /// If the passed entry is unused, a new frame is allocated from the given allocator, zeroed,
@@ -72,7 +159,7 @@ impl RecursivePageTable {
             if entry.is_unused() {
                 if let Some(frame) = allocator.allocate_frame() {
                     entry.set_frame(
-                        frame,
+                        frame.frame(),
                         PageTableFlags::default_table(),
                         PageTableAttribute::new(0, 0, 0),
                     );
@@ -91,7 +178,6 @@ impl RecursivePageTable {
             let page_table_ptr = next_table_page.start_address().as_mut_ptr();
             let page_table: &mut PageTable = unsafe { &mut *(page_table_ptr) };
             if created {
-                #[cfg(target_arch = "aarch64")]
                 unsafe {
                     crate::barrier::dsb(crate::barrier::ISHST);
                 }
@@ -103,11 +189,11 @@ impl RecursivePageTable {
         inner(entry, next_table_page, allocator)
     }
 
-    fn p4_ptr<S: PageSize>(&self, page: Page<S>) -> *mut PageTable {
+    fn p4_ptr<S: PageSize<Granule = Granule4KiB>>(&self, page: Page<S>) -> *mut PageTable {
         self.p4_page(page).start_address().as_mut_ptr()
     }
 
-    fn p3_ptr<S: PageSize>(&self, page: Page<S>) -> *mut PageTable {
+    fn p3_ptr<S: PageSize<Granule = Granule4KiB>>(&self, page: Page<S>) -> *mut PageTable {
         self.p3_page(page).start_address().as_mut_ptr()
     }
 
@@ -119,9 +205,9 @@ impl RecursivePageTable {
         self.p1_page(page).start_address().as_mut_ptr()
     }
 
-    fn p4_page<S: PageSize>(&self, page: Page<S>) -> Page {
+    fn p4_page<S: PageSize<Granule = Granule4KiB>>(&self, page: Page<S>) -> Page {
         Page::from_page_table_indices(
-            page.va_range().unwrap(),
+            page.va_range().expect("page address is not canonical"),
             self.recursive_index,
             self.recursive_index,
             self.recursive_index,
@@ -129,9 +215,9 @@ impl RecursivePageTable {
         )
     }
 
-    fn p3_page<S: PageSize>(&self, page: Page<S>) -> Page {
+    fn p3_page<S: PageSize<Granule = Granule4KiB>>(&self, page: Page<S>) -> Page {
         Page::from_page_table_indices(
-            page.va_range().unwrap(),
+            page.va_range().expect("page address is not canonical"),
             self.recursive_index,
             self.recursive_index,
             self.recursive_index,
@@ -141,7 +227,7 @@ impl RecursivePageTable {
 
     fn p2_page<S: NotGiantPageSize>(&self, page: Page<S>) -> Page {
         Page::from_page_table_indices(
-            page.va_range().unwrap(),
+            page.va_range().expect("page address is not canonical"),
             self.recursive_index,
             self.recursive_index,
             page.p4_index(),
@@ -151,7 +237,7 @@ impl RecursivePageTable {
 
     fn p1_page(&self, page: Page<Size4KiB>) -> Page {
         Page::from_page_table_indices(
-            page.va_range().unwrap(),
+            page.va_range().expect("page address is not canonical"),
             self.recursive_index,
             page.p4_index(),
             page.p3_index(),
@@ -160,11 +246,80 @@ impl RecursivePageTable {
     }
 }
 
+impl TranslatePage<Size4KiB> for RecursivePageTable {
+    fn get_entry(&self, page: Page<Size4KiB>) -> Result<&PageTableEntry, EntryGetError> {
+        let p4 = unsafe { &*(self.p4_ptr(page)) };
+
+        if p4[page.p4_index()].is_unused() {
+            return Err(EntryGetError::PageNotMapped);
+        }
+
+        let p3 = unsafe { &*(self.p3_ptr(page)) };
+
+        if p3[page.p3_index()].is_unused() {
+            return Err(EntryGetError::PageNotMapped);
+        }
+
+        let p2 = unsafe { &*(self.p2_ptr(page)) };
+
+        if p2[page.p2_index()].is_unused() {
+            return Err(EntryGetError::PageNotMapped);
+        }
+
+        let p1 = unsafe { &*(self.p1_ptr(page)) };
+
+        Ok(&p1[page.p1_index()])
+    }
+}
+
+impl Translate for RecursivePageTable {
+    fn translate(&self, addr: VirtAddr) -> TranslateResult {
+        let translation = match self.translate_with_flags(addr) {
+            Ok(translation) => translation,
+            Err(_) => return TranslateResult::PageNotMapped,
+        };
+        let offset = addr.as_u64() & (translation.size - 1);
+        let start = translation.addr - offset;
+        match translation.size {
+            Size1GiB::SIZE => TranslateResult::Frame1GiB {
+                frame: PhysFrame::containing_address(start),
+                offset,
+            },
+            Size2MiB::SIZE => TranslateResult::Frame2MiB {
+                frame: PhysFrame::containing_address(start),
+                offset,
+            },
+            _ => TranslateResult::Frame4KiB {
+                frame: PhysFrame::containing_address(start),
+                offset,
+            },
+        }
+    }
+
+    fn translate_with_flags(&self, addr: VirtAddr) -> Result<Translation, TranslateError> {
+        let page = Page::<Size4KiB>::containing_address(addr);
+        if page.va_range().is_err() {
+            return Err(TranslateError::PageNotMapped);
+        }
+        // the table of each level, only accessed once the entry of the previous level has been
+        // checked to be a valid table descriptor
+        let tables = [
+            self.p4_ptr(page),
+            self.p3_ptr(page),
+            self.p2_ptr(page),
+            self.p1_ptr(page),
+        ];
+        translate_with_tables(addr, unsafe { &*tables[0] }, |level, _| {
+            Some(unsafe { &*tables[level + 1] })
+        })
+    }
+}
+
 impl Mapper<Size4KiB> for RecursivePageTable {
-    unsafe fn map_to<A>(
+    fn map_to<A>(
         &mut self,
         page: Page<Size4KiB>,
-        frame: PhysFrame<Size4KiB>,
+        frame: UnusedPhysFrame<Size4KiB>,
         flags: PageTableFlags,
         attr: PageTableAttribute,
         allocator: &mut A,
@@ -172,26 +327,58 @@ impl Mapper<Size4KiB> for RecursivePageTable {
     where
         A: FrameAllocator<Size4KiB>,
     {
-        let p4 = &mut *(self.p4_ptr(page));
+        if !flags.is_valid_for_page() {
+            return Err(MapToError::InvalidFlags);
+        }
+        let p4 = unsafe { &mut *(self.p4_ptr(page)) };
 
         let p3_page = self.p3_page(page);
-        let p3 = Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)?;
+        let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)? };
 
         let p2_page = self.p2_page(page);
-        let p2 = Self::create_next_table(&mut p3[page.p3_index()], p2_page, allocator)?;
+        let p2 = unsafe { Self::create_next_table(&mut p3[page.p3_index()], p2_page, allocator)? };
 
         let p1_page = self.p1_page(page);
-        let p1 = Self::create_next_table(&mut p2[page.p2_index()], p1_page, allocator)?;
+        let p1 = unsafe { Self::create_next_table(&mut p2[page.p2_index()], p1_page, allocator)? };
 
         if !p1[page.p1_index()].is_unused() {
             return Err(MapToError::PageAlreadyMapped);
         }
-        p1[page.p1_index()].set_frame(frame, flags, attr);
+        p1[page.p1_index()].set_frame(frame.frame(), flags, attr);
 
         Ok(MapperFlush::new(page))
     }
 
-    fn get_entry(&self, page: Page<Size4KiB>) -> Result<&PageTableEntry, EntryGetError> {
+    unsafe fn split_huge_page<A>(
+        &mut self,
+        page: Page<Size4KiB>,
+        _allocator: &mut A,
+    ) -> Result<MapperFlush<Size4KiB>, SplitError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        // pages are the smallest mappings
+        self.get_entry(page)?;
+        Err(SplitError::NotHugePage)
+    }
+
+    unsafe fn merge_huge_page<D>(
+        &mut self,
+        page: Page<Size4KiB>,
+        _deallocator: &mut D,
+    ) -> Result<MapperFlush<Size4KiB>, MergeError>
+    where
+        D: FrameDeallocator<Size4KiB>,
+    {
+        // pages are mapped by the last level tables
+        self.get_entry(page)?;
+        Err(MergeError::NotTable)
+    }
+
+    fn get_entry_mut(
+        &mut self,
+        page: Page<Size4KiB>,
+    ) -> Result<&mut PageTableEntry, EntryGetError> {
         let p4 = unsafe { &mut *(self.p4_ptr(page)) };
 
         if p4[page.p4_index()].is_unused() {
@@ -212,7 +399,7 @@ impl Mapper<Size4KiB> for RecursivePageTable {
 
         let p1 = unsafe { &mut *(self.p1_ptr(page)) };
 
-        Ok(&p1[page.p1_index()])
+        Ok(&mut p1[page.p1_index()])
     }
 
     fn unmap(
@@ -253,3 +440,38 @@ impl Mapper<Size4KiB> for RecursivePageTable {
         Ok((frame, MapperFlush::new(page)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_validate_recursive_entry() {
+        let mut table = PageTable::new();
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x4000_0000));
+        RecursivePageTable::set_recursive_entry(&mut table, frame, 511);
+        let index = u9::new(511);
+        let entry = &table[index];
+        let table_addr = VirtAddr::new(0xffff_ffff_ffff_f000);
+
+        let validate = |table_addr, entry, root| {
+            RecursivePageTable::validate(table_addr, entry, index, PhysAddr::new(root))
+        };
+        assert_eq!(validate(table_addr, entry, 0x4000_0000), Ok(()));
+        assert_eq!(
+            validate(table_addr, entry, 0x5000_0000),
+            Err(InvalidPageTable::NotActive)
+        );
+        assert_eq!(
+            validate(VirtAddr::new(0xffff_ffff_ffff_e000), entry, 0x4000_0000),
+            Err(InvalidPageTable::NotRecursiveAddress)
+        );
+
+        let mut ro = *entry;
+        ro.set_flags(entry.flags() | PageTableFlags::AP_RO);
+        assert_eq!(
+            validate(table_addr, &ro, 0x4000_0000),
+            Err(InvalidPageTable::InvalidEntryFlags)
+        );
+    }
+}
@@ -3,6 +3,7 @@
 use crate::{
     paging::page_table::{PageTableAttribute, MEMORY_ATTRIBUTE},
     registers::*,
+    translation::Shareability,
 };
 use tock_registers::fields::FieldValue;
 
@@ -14,9 +15,25 @@ pub trait MairType {
     fn attr_value() -> PageTableAttribute;
 }
 
+/// Device-nGnRE memory: no gathering or reordering, early write acknowledgement.
 pub enum MairDevice {}
+/// Normal memory, write-back cacheable.
 pub enum MairNormal {}
+/// Normal memory, non-cacheable.
 pub enum MairNormalNonCacheable {}
+/// Device-nGnRnE memory: strongly ordered, e.g. for registers with side effects on reads.
+pub enum MairDeviceNGnRnE {}
+/// Device-GRE memory: gathering, reordering and early write acknowledgement allowed, e.g. for
+/// PCIe prefetchable BARs.
+pub enum MairDeviceGRE {}
+/// Normal memory, write-through cacheable, e.g. for frame buffers.
+pub enum MairNormalWriteThrough {}
+/// Tagged Normal memory, write-back cacheable, whose accesses are checked by MTE (see
+/// [`mte`](crate::mte)).
+pub enum MairNormalTagged {}
+
+/// Device-nGnRE memory, the same as [`MairDevice`].
+pub type MairDeviceNGnRE = MairDevice;
 
 impl MairType for MairNormal {
     const INDEX: u64 = 0;
@@ -60,3 +77,227 @@ impl MairType for MairNormalNonCacheable {
         MEMORY_ATTRIBUTE::SH::OuterShareable + MEMORY_ATTRIBUTE::AttrIndx.val(Self::INDEX)
     }
 }
+
+impl MairType for MairDeviceNGnRnE {
+    const INDEX: u64 = 3;
+
+    #[inline]
+    fn config_value() -> FieldValue<u64, MAIR_EL1::Register> {
+        MAIR_EL1::Attr3_Device::nonGathering_nonReordering_noEarlyWriteAck
+    }
+
+    #[inline]
+    fn attr_value() -> PageTableAttribute {
+        MEMORY_ATTRIBUTE::SH::OuterShareable + MEMORY_ATTRIBUTE::AttrIndx.val(Self::INDEX)
+    }
+}
+
+impl MairType for MairDeviceGRE {
+    const INDEX: u64 = 4;
+
+    #[inline]
+    fn config_value() -> FieldValue<u64, MAIR_EL1::Register> {
+        MAIR_EL1::Attr4_Device::Gathering_Reordering_EarlyWriteAck
+    }
+
+    #[inline]
+    fn attr_value() -> PageTableAttribute {
+        MEMORY_ATTRIBUTE::SH::OuterShareable + MEMORY_ATTRIBUTE::AttrIndx.val(Self::INDEX)
+    }
+}
+
+impl MairType for MairNormalWriteThrough {
+    const INDEX: u64 = 5;
+
+    #[inline]
+    fn config_value() -> FieldValue<u64, MAIR_EL1::Register> {
+        MAIR_EL1::Attr5_Normal_Outer::WriteThrough_NonTransient_ReadAlloc
+            + MAIR_EL1::Attr5_Normal_Inner::WriteThrough_NonTransient_ReadAlloc
+    }
+
+    #[inline]
+    fn attr_value() -> PageTableAttribute {
+        MEMORY_ATTRIBUTE::SH::InnerShareable + MEMORY_ATTRIBUTE::AttrIndx.val(Self::INDEX)
+    }
+}
+
+impl MairType for MairNormalTagged {
+    const INDEX: u64 = 6;
+
+    #[inline]
+    fn config_value() -> FieldValue<u64, MAIR_EL1::Register> {
+        // the Tagged Normal encoding has no field value in `MAIR_EL1`
+        FieldValue::<u64, MAIR_EL1::Register>::new(0xff, 8 * Self::INDEX as usize, 0xf0)
+    }
+
+    #[inline]
+    fn attr_value() -> PageTableAttribute {
+        MEMORY_ATTRIBUTE::SH::InnerShareable + MEMORY_ATTRIBUTE::AttrIndx.val(Self::INDEX)
+    }
+}
+
+/// An error indicating that an attribute could not be added to a [`MairConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MairError {
+    /// All eight attribute indices are in use.
+    Full,
+    /// The index of the [`MairType`] is already in use.
+    IndexInUse,
+}
+
+/// A memory attribute registered in a [`MairConfig`], usable as the `attr` of a mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MairAttribute {
+    index: u8,
+    shareability: Shareability,
+}
+
+impl MairAttribute {
+    /// Returns the index of the attribute in MAIR_EL1.
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Returns the memory attribute fields of the page table entries using this attribute.
+    pub fn attr(&self) -> PageTableAttribute {
+        let sh = match self.shareability {
+            Shareability::NonShareable => MEMORY_ATTRIBUTE::SH::NonShareable,
+            Shareability::OuterShareable => MEMORY_ATTRIBUTE::SH::OuterShareable,
+            Shareability::InnerShareable => MEMORY_ATTRIBUTE::SH::InnerShareable,
+        };
+        sh + MEMORY_ATTRIBUTE::AttrIndx.val(self.index as u64)
+    }
+}
+
+impl From<MairAttribute> for PageTableAttribute {
+    fn from(attr: MairAttribute) -> Self {
+        attr.attr()
+    }
+}
+
+/// A registry of the memory attributes of MAIR_EL1, assigning their indices.
+///
+/// [`MairType`]s keep their fixed index, so their `attr_value` stays valid, and other attributes
+/// take the next free index.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MairConfig {
+    encodings: [Option<u8>; 8],
+}
+
+impl MairConfig {
+    /// Creates an empty configuration.
+    pub const fn new() -> Self {
+        Self {
+            encodings: [None; 8],
+        }
+    }
+
+    /// Adds the attribute of the [`MairType`] `T` at its index.
+    pub fn add_type<T: MairType>(&mut self) -> Result<MairAttribute, MairError> {
+        let index = T::INDEX as usize;
+        if self.encodings[index].is_some() {
+            return Err(MairError::IndexInUse);
+        }
+        self.encodings[index] = Some((T::config_value().value >> (index * 8)) as u8);
+        let shareability = match T::attr_value().read(MEMORY_ATTRIBUTE::SH) {
+            0b10 => Shareability::OuterShareable,
+            0b11 => Shareability::InnerShareable,
+            _ => Shareability::NonShareable,
+        };
+        Ok(MairAttribute {
+            index: index as u8,
+            shareability,
+        })
+    }
+
+    /// Adds an attribute with the given 8-bit MAIR encoding at the next free index.
+    ///
+    /// `shareability` is used for the mappings with this attribute. It has no effect for Device
+    /// and Normal Non-cacheable memory, which is always treated as Outer Shareable.
+    pub fn add(
+        &mut self,
+        encoding: u8,
+        shareability: Shareability,
+    ) -> Result<MairAttribute, MairError> {
+        let index = self
+            .encodings
+            .iter()
+            .position(Option::is_none)
+            .ok_or(MairError::Full)?;
+        self.encodings[index] = Some(encoding);
+        Ok(MairAttribute {
+            index: index as u8,
+            shareability,
+        })
+    }
+
+    /// Returns the 8-bit MAIR encoding of the attribute at `index`, e.g. to compare with the
+    /// attributes of a translation reported by [`ParEl1::attr`](crate::translation::ParEl1::attr).
+    pub fn encoding(&self, index: u8) -> Option<u8> {
+        self.encodings.get(index as usize).copied().flatten()
+    }
+
+    /// Returns the value of MAIR_EL1. Unused indices are Device-nGnRnE.
+    pub fn value(&self) -> u64 {
+        self.encodings
+            .iter()
+            .enumerate()
+            .fold(0, |value, (index, encoding)| {
+                value | (encoding.unwrap_or(0) as u64) << (index * 8)
+            })
+    }
+
+    /// Writes MAIR_EL1.
+    ///
+    /// # Safety
+    ///
+    /// The attributes of the indices in use by live mappings must not change, unless the TLB is
+    /// invalidated and the caches are maintained for the change of memory type.
+    #[inline]
+    pub unsafe fn apply(&self) {
+        MAIR_EL1.set(self.value());
+        crate::barrier::isb();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_mair_config() {
+        let mut config = MairConfig::new();
+        let normal = config.add_type::<MairNormal>().unwrap();
+        let device = config.add_type::<MairDevice>().unwrap();
+        assert_eq!(normal.attr().value, MairNormal::attr_value().value);
+        assert_eq!(device.attr().value, MairDevice::attr_value().value);
+        assert_eq!(config.add_type::<MairNormal>(), Err(MairError::IndexInUse));
+
+        let wt = config.add(0xbb, Shareability::InnerShareable).unwrap();
+        assert_eq!(wt.index(), 2);
+        assert_eq!(config.encoding(wt.index()), Some(0xbb));
+        assert_eq!(config.encoding(3), None);
+        assert_eq!(
+            config.value(),
+            (MairNormal::config_value() + MairDevice::config_value()).value | 0xbb << 16
+        );
+        assert_eq!(
+            config.add_type::<MairDeviceNGnRnE>().unwrap().attr().value,
+            MairDeviceNGnRnE::attr_value().value
+        );
+        config.add_type::<MairDeviceGRE>().unwrap();
+        config.add_type::<MairNormalWriteThrough>().unwrap();
+        assert_eq!(config.value() >> 24 & 0xff_ffff, 0xaa_0c_00);
+        for _ in 6..8 {
+            config.add(0x44, Shareability::OuterShareable).unwrap();
+        }
+        assert_eq!(
+            config.add(0x44, Shareability::OuterShareable),
+            Err(MairError::Full)
+        );
+
+        let mut config = MairConfig::new();
+        config.add_type::<MairNormalTagged>().unwrap();
+        assert_eq!(config.value(), 0xf0 << 48);
+    }
+}
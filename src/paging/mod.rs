@@ -5,20 +5,41 @@
 #![allow(non_upper_case_globals)]
 
 pub use self::{
-    frame::PhysFrame,
+    frame::{PhysFrame, UnusedPhysFrame},
     frame_alloc::{FrameAllocator, FrameDeallocator},
 };
 
-pub use self::mapper::{MappedPageTable, Mapper, RecursivePageTable};
+pub use self::mapper::{
+    MappedPageTable, Mapper, MapperFlushAll, OffsetPageTable, PagingError, RecursivePageTable,
+    Translate, TranslatePage,
+};
 
 pub use self::{
-    page::{Page, PageSize, Size1GiB, Size2MiB, Size4KiB},
-    page_table::{PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
+    granule::{Granule16KiB, Granule4KiB, Granule64KiB, PageTableLevel, TranslationGranule},
+    page::{
+        Page, PageSize, Size16KiB, Size1GiB, Size2MiB, Size32MiB, Size4KiB, Size512MiB, Size64KiB,
+    },
+    page_table::{AccessPermission, PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
 };
 
+pub mod address_space;
+pub mod asid;
+pub mod bbm;
+#[cfg(feature = "software-flags")]
+pub mod cow;
+#[cfg(feature = "software-flags")]
+pub mod dirty_tracking;
 pub mod frame;
 mod frame_alloc;
+pub mod granule;
+pub mod kstack;
 pub mod mapper;
 pub mod memory_attribute;
 pub mod page;
 pub mod page_table;
+pub mod snapshot;
+pub mod stage2;
+pub mod temp_map;
+#[cfg(test)]
+mod test_util;
+pub mod walk;
@@ -1,5 +1,6 @@
 //! Abstractions for default-sized and huge virtual memory pages.
 
+use super::granule::{Granule16KiB, Granule4KiB, Granule64KiB, PageTableLevel, TranslationGranule};
 use crate::addr::{VirtAddr, VirtAddrNotValid, VirtAddrRange};
 use core::{
     fmt,
@@ -8,17 +9,25 @@ use core::{
 };
 use ux::*;
 
-/// Trait for abstracting over the three possible block/page sizes on aarch64, 4KiB, 2MiB, 1GiB.
+/// Trait for abstracting over the possible block/page sizes on aarch64: 4KiB, 2MiB and 1GiB with
+/// the 4KiB granule, 16KiB and 32MiB with the 16KiB granule, 64KiB and 512MiB with the 64KiB
+/// granule.
 pub trait PageSize: Copy + Eq + PartialOrd + Ord {
     /// The page size in bytes.
     const SIZE: u64;
 
     /// A string representation of the page size for debug output.
     const SIZE_AS_DEBUG_STR: &'static str;
+
+    /// The translation granule of the page size.
+    type Granule: TranslationGranule;
+
+    /// The lookup level of the translation table entries mapping pages of this size.
+    const LEVEL: usize;
 }
 
 /// This trait is implemented for 4KiB and 2MiB pages, but not for 1GiB pages.
-pub trait NotGiantPageSize: PageSize {}
+pub trait NotGiantPageSize: PageSize<Granule = Granule4KiB> {}
 
 /// A standard 4KiB page.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -32,9 +41,27 @@ pub enum Size2MiB {}
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Size1GiB {}
 
+/// A standard 16KiB page of the 16KiB granule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size16KiB {}
+
+/// A “huge” 32MiB page of the 16KiB granule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size32MiB {}
+
+/// A standard 64KiB page of the 64KiB granule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size64KiB {}
+
+/// A “huge” 512MiB page of the 64KiB granule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size512MiB {}
+
 impl PageSize for Size4KiB {
     const SIZE: u64 = 4096;
     const SIZE_AS_DEBUG_STR: &'static str = "4KiB";
+    type Granule = Granule4KiB;
+    const LEVEL: usize = 3;
 }
 
 impl NotGiantPageSize for Size4KiB {}
@@ -42,6 +69,8 @@ impl NotGiantPageSize for Size4KiB {}
 impl PageSize for Size2MiB {
     const SIZE: u64 = Size4KiB::SIZE * 512;
     const SIZE_AS_DEBUG_STR: &'static str = "2MiB";
+    type Granule = Granule4KiB;
+    const LEVEL: usize = 2;
 }
 
 impl NotGiantPageSize for Size2MiB {}
@@ -49,6 +78,36 @@ impl NotGiantPageSize for Size2MiB {}
 impl PageSize for Size1GiB {
     const SIZE: u64 = Size2MiB::SIZE * 512;
     const SIZE_AS_DEBUG_STR: &'static str = "1GiB";
+    type Granule = Granule4KiB;
+    const LEVEL: usize = 1;
+}
+
+impl PageSize for Size16KiB {
+    const SIZE: u64 = 16384;
+    const SIZE_AS_DEBUG_STR: &'static str = "16KiB";
+    type Granule = Granule16KiB;
+    const LEVEL: usize = 3;
+}
+
+impl PageSize for Size32MiB {
+    const SIZE: u64 = Size16KiB::SIZE * 2048;
+    const SIZE_AS_DEBUG_STR: &'static str = "32MiB";
+    type Granule = Granule16KiB;
+    const LEVEL: usize = 2;
+}
+
+impl PageSize for Size64KiB {
+    const SIZE: u64 = 65536;
+    const SIZE_AS_DEBUG_STR: &'static str = "64KiB";
+    type Granule = Granule64KiB;
+    const LEVEL: usize = 3;
+}
+
+impl PageSize for Size512MiB {
+    const SIZE: u64 = Size64KiB::SIZE * 8192;
+    const SIZE_AS_DEBUG_STR: &'static str = "512MiB";
+    type Granule = Granule64KiB;
+    const LEVEL: usize = 2;
 }
 
 /// A virtual memory page.
@@ -66,7 +125,7 @@ impl<S: PageSize> Page<S> {
     /// Returns the page that starts at the given virtual address.
     ///
     /// Returns an error if the address is not correctly aligned (i.e. is not a valid page start).
-    pub fn from_start_address(address: VirtAddr) -> Result<Self, ()> {
+    pub const fn from_start_address(address: VirtAddr) -> Result<Self, ()> {
         if !address.is_aligned(S::SIZE) {
             return Err(());
         }
@@ -74,7 +133,7 @@ impl<S: PageSize> Page<S> {
     }
 
     /// Returns the page that contains the given virtual address.
-    pub fn containing_address(address: VirtAddr) -> Self {
+    pub const fn containing_address(address: VirtAddr) -> Self {
         Page {
             start_address: address.align_down(S::SIZE),
             size: PhantomData,
@@ -82,11 +141,11 @@ impl<S: PageSize> Page<S> {
     }
 
     /// Returns the start address of the page.
-    pub fn start_address(&self) -> VirtAddr {
+    pub const fn start_address(&self) -> VirtAddr {
         self.start_address
     }
 
-    /// Returns the size the page (4KB, 2MB or 1GB).
+    /// Returns the size the page (e.g. 4KB, 2MB or 1GB).
     pub const fn size(&self) -> u64 {
         S::SIZE
     }
@@ -96,14 +155,17 @@ impl<S: PageSize> Page<S> {
         self.start_address().va_range()
     }
 
-    /// Returns the level 4 page table index of this page.
-    pub fn p4_index(&self) -> u9 {
-        self.start_address().p4_index()
+    /// Returns the index of this page in the page table of the given lookup level (0 to
+    /// `S::LEVEL`).
+    pub fn table_index(&self, level: usize) -> usize {
+        S::Granule::table_index(self.start_address(), level)
     }
 
-    /// Returns the level 3 page table index of this page.
-    pub fn p3_index(&self) -> u9 {
-        self.start_address().p3_index()
+    /// Returns the index of this page in the page table of the given lookup level, which must
+    /// not be below the level of `S`.
+    pub fn page_table_index(&self, level: PageTableLevel) -> usize {
+        debug_assert!(level.number() <= S::LEVEL);
+        level.table_index::<S::Granule>(self.start_address())
     }
 
     /// Returns a range of pages, exclusive `end`.
@@ -116,8 +178,11 @@ impl<S: PageSize> Page<S> {
         PageRangeInclusive { start, end }
     }
 
-    pub fn of_addr(address: u64) -> Self {
-        Self::containing_address(VirtAddr::new(address))
+    pub const fn of_addr(address: u64) -> Self {
+        match VirtAddr::try_new_for::<S::Granule>(address) {
+            Ok(addr) => Self::containing_address(addr),
+            Err(_) => panic!("invalid virtual address"),
+        }
     }
 
     pub fn range_of(begin: u64, end: u64) -> PageRange<S> {
@@ -125,6 +190,18 @@ impl<S: PageSize> Page<S> {
     }
 }
 
+impl<S: PageSize<Granule = Granule4KiB>> Page<S> {
+    /// Returns the level 4 page table index of this page.
+    pub fn p4_index(&self) -> u9 {
+        self.start_address().p4_index()
+    }
+
+    /// Returns the level 3 page table index of this page.
+    pub fn p3_index(&self) -> u9 {
+        self.start_address().p3_index()
+    }
+}
+
 impl<S: NotGiantPageSize> Page<S> {
     /// Returns the level 2 page table index of this page.
     pub fn p2_index(&self) -> u9 {
@@ -201,10 +278,17 @@ impl<S: PageSize> fmt::Debug for Page<S> {
     }
 }
 
+/// Returns the address of a page of size `S`, which must be canonical for the granule of `S`.
+fn page_addr<S: PageSize>(addr: u64) -> VirtAddr {
+    VirtAddr::try_new_for::<S::Granule>(addr).expect("invalid virtual address")
+}
+
 impl<S: PageSize> Add<u64> for Page<S> {
     type Output = Self;
     fn add(self, rhs: u64) -> Self::Output {
-        Page::containing_address(self.start_address() + rhs * u64::from(S::SIZE))
+        Page::containing_address(page_addr::<S>(
+            self.start_address().as_u64() + rhs * S::SIZE,
+        ))
     }
 }
 
@@ -217,7 +301,9 @@ impl<S: PageSize> AddAssign<u64> for Page<S> {
 impl<S: PageSize> Sub<u64> for Page<S> {
     type Output = Self;
     fn sub(self, rhs: u64) -> Self::Output {
-        Page::containing_address(self.start_address() - rhs * u64::from(S::SIZE))
+        let offset = rhs.checked_mul(S::SIZE).unwrap();
+        let addr = self.start_address().as_u64().checked_sub(offset);
+        Page::containing_address(page_addr::<S>(addr.unwrap()))
     }
 }
 
@@ -249,6 +335,55 @@ impl<S: PageSize> PageRange<S> {
     pub fn is_empty(&self) -> bool {
         !(self.start < self.end)
     }
+
+    /// Returns the number of pages in the range.
+    pub fn len(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.end - self.start
+        }
+    }
+
+    /// Returns the size of the range in bytes.
+    pub fn size(&self) -> u64 {
+        self.len() * S::SIZE
+    }
+
+    /// Returns whether the range contains `page`.
+    pub fn contains(&self, page: Page<S>) -> bool {
+        self.start <= page && page < self.end
+    }
+
+    /// Returns whether the range and `other` have pages in common.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        !self.intersection(other).is_empty()
+    }
+
+    /// Returns the pages contained in both the range and `other`, which may be empty.
+    pub fn intersection(&self, other: &Self) -> Self {
+        PageRange {
+            start: self.start.max(other.start),
+            end: self.end.min(other.end),
+        }
+    }
+
+    /// Splits the range into the pages before `page` and the pages from `page` on.
+    ///
+    /// `page` is clamped to the range, so one of the halves may be empty.
+    pub fn split_at(&self, page: Page<S>) -> (Self, Self) {
+        let mid = page.max(self.start).min(self.end.max(self.start));
+        (
+            PageRange {
+                start: self.start,
+                end: mid,
+            },
+            PageRange {
+                start: mid,
+                end: self.end,
+            },
+        )
+    }
 }
 
 impl<S: PageSize> Iterator for PageRange<S> {
@@ -263,6 +398,33 @@ impl<S: PageSize> Iterator for PageRange<S> {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len() as usize;
+        (len, Some(len))
+    }
+}
+
+impl<S: PageSize> DoubleEndedIterator for PageRange<S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.start < self.end {
+            self.end -= 1;
+            Some(self.end)
+        } else {
+            None
+        }
+    }
+}
+
+impl<S: PageSize> ExactSizeIterator for PageRange<S> {}
+
+impl<S: PageSize> From<PageRangeInclusive<S>> for PageRange<S> {
+    fn from(range: PageRangeInclusive<S>) -> Self {
+        PageRange {
+            start: range.start,
+            end: range.end + 1,
+        }
+    }
 }
 
 impl PageRange<Size2MiB> {
@@ -299,6 +461,30 @@ impl<S: PageSize> PageRangeInclusive<S> {
     pub fn is_empty(&self) -> bool {
         !(self.start <= self.end)
     }
+
+    /// Returns the number of pages in the range.
+    pub fn len(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.end - self.start + 1
+        }
+    }
+
+    /// Returns the size of the range in bytes.
+    pub fn size(&self) -> u64 {
+        self.len() * S::SIZE
+    }
+
+    /// Returns whether the range contains `page`.
+    pub fn contains(&self, page: Page<S>) -> bool {
+        self.start <= page && page <= self.end
+    }
+
+    /// Returns whether the range and `other` have pages in common.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        !self.is_empty() && !other.is_empty() && self.start <= other.end && other.start <= self.end
+    }
 }
 
 impl<S: PageSize> Iterator for PageRangeInclusive<S> {
@@ -307,7 +493,13 @@ impl<S: PageSize> Iterator for PageRangeInclusive<S> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.start <= self.end {
             let page = self.start.clone();
-            self.start += 1;
+            // the page after the last one of a VA range is not canonical: the range is then
+            // emptied by moving its end below its start instead
+            let next = page.start_address().as_u64().checked_add(S::SIZE);
+            match next.map(VirtAddr::try_new_for::<S::Granule>) {
+                Some(Ok(next)) => self.start = Page::containing_address(next),
+                _ => self.end = page - 1,
+            }
             Some(page)
         } else {
             None
@@ -327,6 +519,7 @@ impl<S: PageSize> fmt::Debug for PageRangeInclusive<S> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{paging::PhysFrame, PhysAddr};
 
     #[test]
     pub fn test_page_ranges() {
@@ -355,4 +548,58 @@ mod tests {
         }
         assert_eq!(range_inclusive.next(), None);
     }
+
+    #[test]
+    pub fn test_page_range_helpers() {
+        let start = Page::<Size4KiB>::of_addr(0x10_0000);
+        let range = Page::range(start, start + 8);
+        assert_eq!(range.len(), 8);
+        assert_eq!(range.size(), 0x8000);
+        assert!(range.contains(start + 7) && !range.contains(start + 8));
+        assert_eq!(range.clone().next_back(), Some(start + 7));
+
+        let other = Page::range(start + 6, start + 10);
+        assert!(range.overlaps(&other));
+        assert_eq!(
+            range.intersection(&other),
+            Page::range(start + 6, start + 8)
+        );
+        assert!(!range.overlaps(&Page::range(start + 8, start + 9)));
+
+        let (low, high) = range.split_at(start + 3);
+        assert_eq!((low.len(), high.len()), (3, 5));
+        assert_eq!(range.split_at(start + 20).1.len(), 0);
+
+        let inclusive = Page::range_inclusive(start, start + 7);
+        assert_eq!(inclusive.len(), 8);
+        assert!(inclusive.overlaps(&Page::range_inclusive(start + 7, start + 9)));
+        assert_eq!(PageRange::from(inclusive), range);
+
+        // the last page of each VA range has no canonical successor
+        for last in [0x0000_ffff_ffff_f000, 0xffff_ffff_ffff_f000] {
+            let last = Page::<Size4KiB>::of_addr(last);
+            let mut inclusive = Page::range_inclusive(last - 1, last);
+            assert_eq!(inclusive.next(), Some(last - 1));
+            assert_eq!(inclusive.next(), Some(last));
+            assert_eq!(inclusive.next(), None);
+            assert!(inclusive.is_empty());
+        }
+    }
+
+    #[test]
+    pub fn test_const_constructors() {
+        const KERNEL_BASE: Page<Size2MiB> =
+            Page::containing_address(VirtAddr::new(0xffff_0000_4012_3456));
+        const KERNEL_FRAME: PhysFrame<Size2MiB> =
+            PhysFrame::containing_address(PhysAddr::new(0x4012_3456));
+        const KERNEL_END: VirtAddr = KERNEL_BASE.start_address().align_up(Size1GiB::SIZE);
+
+        assert_eq!(
+            KERNEL_BASE.start_address(),
+            VirtAddr::new(0xffff_0000_4000_0000)
+        );
+        assert_eq!(KERNEL_FRAME.start_address().as_u64(), 0x4000_0000);
+        assert_eq!(KERNEL_END, VirtAddr::new(0xffff_0000_4000_0000));
+        assert!(Page::<Size1GiB>::from_start_address(VirtAddr::new(0x4020_0000)).is_err());
+    }
 }
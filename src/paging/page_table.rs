@@ -4,17 +4,34 @@ use bitflags::bitflags;
 use core::{
     fmt,
     ops::{Index, IndexMut},
+    sync::atomic::{AtomicU64, Ordering},
 };
 use tock_registers::{fields::FieldValue, register_bitfields};
 use ux::*;
 
-use super::{PageSize, PhysFrame, Size4KiB};
+use super::{
+    granule::{Granule4KiB, TranslationGranule, PAGE_LEVEL},
+    PageSize, PhysFrame,
+};
 use crate::PhysAddr;
 
 /// Output address mask
 pub const ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+/// The bits of the output address field holding bits 51:48 of a 52-bit output address, with
+/// the 64KiB granule and the `lpa` feature.
+const ADDR_HIGH_MASK: u64 = 0xf000;
 /// Other flags mask
 pub const FLAGS_MASK: u64 = !(MEMORY_ATTR_MASK | ADDR_MASK);
+/// The bits reserved for software use, 55 to 58
+pub const SOFTWARE_MASK: u64 = 0xf << SOFTWARE_SHIFT;
+const SOFTWARE_SHIFT: u64 = 55;
+
+/// The software dirty flag, used by [`PageTableEntry::is_dirty`] with the `software-flags`
+/// feature.
+#[cfg(feature = "software-flags")]
+const SOFTWARE_DIRTY: PageTableFlags = PageTableFlags::DIRTY;
+#[cfg(not(feature = "software-flags"))]
+const SOFTWARE_DIRTY: PageTableFlags = PageTableFlags::empty();
 
 /// Memory attribute fields
 pub type PageTableAttribute = FieldValue<u64, MEMORY_ATTRIBUTE::Register>;
@@ -43,6 +60,12 @@ impl PageTableEntry {
         Self { entry: 0 }
     }
 
+    /// Creates an entry holding the raw descriptor `entry`.
+    #[inline]
+    pub const fn from_raw(entry: u64) -> Self {
+        Self { entry }
+    }
+
     /// Returns whether this entry is zero.
     #[inline]
     pub fn is_unused(&self) -> bool {
@@ -52,7 +75,7 @@ impl PageTableEntry {
     /// Sets this entry to zero.
     #[inline]
     pub fn set_unused(&mut self) {
-        self.entry = 0;
+        self.set_raw(0);
     }
 
     /// Returns the flags of this entry.
@@ -62,11 +85,24 @@ impl PageTableEntry {
     }
 
     /// Returns the physical address mapped by this entry, might be zero.
+    ///
+    /// The address has at most 48 bits: see [`addr_in`](Self::addr_in) for the 52-bit output
+    /// addresses of the 64KiB granule.
     #[inline]
     pub fn addr(&self) -> PhysAddr {
         PhysAddr::new(self.entry & ADDR_MASK)
     }
 
+    /// Returns the physical address mapped by this entry of a table of the granule `G`, might be
+    /// zero.
+    ///
+    /// With the 64KiB granule and the `lpa` feature, bits 51:48 of the address are held in bits
+    /// 15:12 of the descriptor. Otherwise, this is [`addr`](Self::addr).
+    #[inline]
+    pub fn addr_in<G: TranslationGranule>(&self) -> PhysAddr {
+        PhysAddr::new(decode_addr::<G>(self.entry))
+    }
+
     /// Returns the memory attribute fields of this entry.
     #[inline]
     pub fn attr(&self) -> PageTableAttribute {
@@ -99,15 +135,41 @@ impl PageTableEntry {
 
     /// Map the entry to the specified physical address with the specified flags and memory
     /// attribute.
+    ///
+    /// The address must have at most 48 bits: see [`set_addr_in`](Self::set_addr_in) for the
+    /// 52-bit output addresses of the 64KiB granule.
     pub fn set_addr(&mut self, addr: PhysAddr, flags: PageTableFlags, attr: PageTableAttribute) {
-        debug_assert!(addr.is_aligned(Size4KiB::SIZE));
-        self.entry = (addr.as_u64()) | flags.bits() | attr.value;
+        self.set_addr_in::<Granule4KiB>(addr, flags, attr);
+    }
+
+    /// Map the entry of a table of the granule `G` to the specified physical address with the
+    /// specified flags and memory attribute.
+    ///
+    /// See [`addr_in`](Self::addr_in) for the encoding of 52-bit addresses.
+    pub fn set_addr_in<G: TranslationGranule>(
+        &mut self,
+        addr: PhysAddr,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+    ) {
+        debug_assert!(addr.is_aligned(G::Page::SIZE));
+        debug_assert!(
+            addr.as_u64() >> G::OA_BITS == 0,
+            "output address too large for the granule"
+        );
+        self.set_raw(encode_addr::<G>(addr.as_u64()) | flags.bits() | attr.value);
     }
 
     /// Map the entry to the specified physical frame with the specified flags and memory attribute.
     pub fn set_frame(&mut self, frame: PhysFrame, flags: PageTableFlags, attr: PageTableAttribute) {
         // is not a block
         debug_assert!(flags.contains(PageTableFlags::TABLE_OR_PAGE));
+        // a table or a page, but not both
+        debug_assert!(
+            flags.is_valid_for_page() || flags.is_valid_for_table(),
+            "mixed page and table flags: {:?}",
+            flags
+        );
         self.set_addr(frame.start_address(), flags, attr);
     }
 
@@ -121,20 +183,164 @@ impl PageTableEntry {
     ) {
         // is a block
         debug_assert!(!flags.contains(PageTableFlags::TABLE_OR_PAGE));
-        self.set_addr(addr.align_down(S::SIZE), flags, attr);
+        debug_assert!(
+            flags.is_valid_for_page(),
+            "table flags in a block descriptor: {:?}",
+            flags
+        );
+        self.set_addr_in::<S::Granule>(addr.align_down(S::SIZE), flags, attr);
     }
 
     /// Sets the flags of this entry.
+    ///
+    /// The software bits that are not flags, i.e. all of them without the `software-flags`
+    /// feature, are kept: see [`software_bits`](Self::software_bits).
     pub fn set_flags(&mut self, flags: PageTableFlags) {
-        self.entry = (self.entry & !FLAGS_MASK) | flags.bits();
+        let keep = !FLAGS_MASK | (SOFTWARE_MASK & !PageTableFlags::all().bits());
+        self.set_raw((self.entry & keep) | flags.bits());
+    }
+
+    /// Returns the bits 55 to 58 of the descriptor, ignored by the MMU and free for software
+    /// use.
+    ///
+    /// With the `software-flags` feature, they hold the `DIRTY`, `SWAPPED`, `WRITABLE_SHARED`
+    /// and `READONLY_SHARED` flags used by this crate. Without it, the kernel defines their
+    /// meaning.
+    #[inline]
+    pub fn software_bits(&self) -> u4 {
+        u4::new(((self.entry & SOFTWARE_MASK) >> SOFTWARE_SHIFT) as u8)
+    }
+
+    /// Sets the bits 55 to 58 of the descriptor, keeping all other bits. See
+    /// [`software_bits`](Self::software_bits).
+    #[inline]
+    pub fn set_software_bits(&mut self, bits: u4) {
+        let bits = (u8::from(bits) as u64) << SOFTWARE_SHIFT;
+        self.set_raw((self.entry & !SOFTWARE_MASK) | bits);
+    }
+
+    /// Returns the access permissions of this entry.
+    #[inline]
+    pub fn ap(&self) -> AccessPermission {
+        AccessPermission::from(self.flags())
+    }
+
+    /// Sets the access permissions of this entry, keeping all other bits.
+    pub fn set_ap(&mut self, ap: AccessPermission) {
+        let ap = PageTableFlags::from(ap).bits();
+        self.set_raw((self.entry & !AccessPermission::MASK.bits()) | ap);
     }
 
     /// Sets the memory attribute of this entry.
     pub fn set_attr(&mut self, attr: PageTableAttribute) {
-        self.entry = (self.entry & !MEMORY_ATTR_MASK) | attr.value;
+        self.set_raw((self.entry & !MEMORY_ATTR_MASK) | attr.value);
+    }
+
+    /// Returns whether the page or block was accessed, i.e. the access flag is set.
+    #[inline]
+    pub fn is_accessed(&self) -> bool {
+        self.flags().contains(PageTableFlags::AF)
+    }
+
+    /// Clears the access flag, and returns whether it was set.
+    ///
+    /// The flag is cleared atomically, so that a concurrent hardware update of the access flag
+    /// or dirty state isn't lost. The TLB entries of the page must be invalidated for the next
+    /// access to be recorded.
+    pub fn clear_accessed(&mut self) -> bool {
+        let old = self
+            .atomic()
+            .fetch_and(!PageTableFlags::AF.bits(), Ordering::AcqRel);
+        old & PageTableFlags::AF.bits() != 0
+    }
+
+    /// Returns whether the page or block was written to.
+    ///
+    /// Understands both the hardware scheme (`DBM` set and `AP_RO` cleared by the MMU on the
+    /// first write) and, with the `software-flags` feature, the software scheme (`DIRTY` set by
+    /// the kernel on a write fault).
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        Self::dirty(self.flags())
+    }
+
+    /// Marks the page or block as clean, and returns whether it was dirty.
+    ///
+    /// Clears `DIRTY` if defined, and makes hardware managed mappings (with `DBM`) read-only again,
+    /// so that the MMU records the next write. Like [`clear_accessed`](Self::clear_accessed),
+    /// this is atomic, and the TLB entries of the page must be invalidated.
+    pub fn clear_dirty(&mut self) -> bool {
+        let atomic = self.atomic();
+        let mut old = atomic.load(Ordering::Acquire);
+        loop {
+            let flags = PageTableFlags::from_bits_truncate(old);
+            let mut new = old & !SOFTWARE_DIRTY.bits();
+            if flags.contains(PageTableFlags::DBM) {
+                new |= PageTableFlags::AP_RO.bits();
+            }
+            match atomic.compare_exchange_weak(old, new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Self::dirty(flags),
+                Err(current) => old = current,
+            }
+        }
+    }
+
+    #[inline]
+    fn dirty(flags: PageTableFlags) -> bool {
+        flags.intersects(SOFTWARE_DIRTY)
+            || (flags.contains(PageTableFlags::DBM) && !flags.contains(PageTableFlags::AP_RO))
+    }
+
+    /// Returns the entry as an atomic, for updates racing with the MMU. These only change bits
+    /// that don't require break-before-make.
+    #[inline]
+    pub(super) fn atomic(&mut self) -> &AtomicU64 {
+        unsafe { &*(&mut self.entry as *mut u64 as *const AtomicU64) }
+    }
+
+    /// Returns the raw descriptor.
+    #[inline]
+    pub fn raw(&self) -> u64 {
+        self.entry
+    }
+
+    /// Writes the raw descriptor, checking for break-before-make violations in debug builds or
+    /// with the `bbm-check` feature.
+    #[inline]
+    pub(super) fn set_raw(&mut self, entry: u64) {
+        super::bbm::check_transition(&self.entry, self.entry, entry);
+        self.entry = entry;
     }
 }
 
+/// Returns the output address field of a descriptor of the granule `G` for `addr`, with bits
+/// 51:48 moved to bits 15:12 for a 52-bit output address.
+#[inline]
+pub(super) fn encode_addr<G: TranslationGranule>(addr: u64) -> u64 {
+    if G::OA_BITS > 48 {
+        (addr & ADDR_MASK & !ADDR_HIGH_MASK) | (addr >> 36 & ADDR_HIGH_MASK)
+    } else {
+        addr
+    }
+}
+
+/// Returns the output address of the descriptor `entry` of the granule `G`, with bits 51:48
+/// taken from bits 15:12 for a 52-bit output address.
+#[inline]
+fn decode_addr<G: TranslationGranule>(entry: u64) -> u64 {
+    if G::OA_BITS > 48 {
+        (entry & ADDR_MASK & !ADDR_HIGH_MASK) | (entry & ADDR_HIGH_MASK) << 36
+    } else {
+        entry & ADDR_MASK
+    }
+}
+
+#[cfg(feature = "bytemuck")]
+unsafe impl bytemuck::Zeroable for PageTableEntry {}
+
+#[cfg(feature = "bytemuck")]
+unsafe impl bytemuck::Pod for PageTableEntry {}
+
 impl fmt::Debug for PageTableEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut f = f.debug_struct("PageTableEntry");
@@ -198,12 +404,16 @@ bitflags! {
         /// Software Dirty Bit Modifier
         const WRITE =           1 << 51;
         /// Software dirty bit
+        #[cfg(feature = "software-flags")]
         const DIRTY =           1 << 55;
         /// Software swapped bit
+        #[cfg(feature = "software-flags")]
         const SWAPPED =         1 << 56;
         /// Software writable shared bit for COW
+        #[cfg(feature = "software-flags")]
         const WRITABLE_SHARED = 1 << 57;
         /// Software readonly shared bit for COW
+        #[cfg(feature = "software-flags")]
         const READONLY_SHARED = 1 << 58;
 
         /// Privileged Execute-never for table descriptors
@@ -220,6 +430,44 @@ bitflags! {
 }
 
 impl PageTableFlags {
+    /// The hierarchical attributes of a table descriptor, which limit the permissions of all the
+    /// mappings below it. The same bits are ignored, or IMPLEMENTATION DEFINED, in page and block
+    /// descriptors.
+    pub const TABLE_ATTRIBUTES: PageTableFlags = PageTableFlags::from_bits_truncate(
+        PageTableFlags::PXNTable.bits()
+            | PageTableFlags::XNTable.bits()
+            | PageTableFlags::APTable_nEL0.bits()
+            | PageTableFlags::APTable_RO.bits()
+            | PageTableFlags::NSTable.bits(),
+    );
+
+    /// The attributes of a page or block descriptor, which are ignored in table descriptors.
+    pub const PAGE_ATTRIBUTES: PageTableFlags = PageTableFlags::from_bits_truncate(
+        PageTableFlags::NS.bits()
+            | PageTableFlags::AP_EL0.bits()
+            | PageTableFlags::AP_RO.bits()
+            | PageTableFlags::AF.bits()
+            | PageTableFlags::nG.bits()
+            | PageTableFlags::DBM.bits()
+            | PageTableFlags::Contiguous.bits()
+            | PageTableFlags::PXN.bits()
+            | PageTableFlags::UXN.bits(),
+    );
+
+    /// Returns whether the flags can be those of a page or block descriptor, i.e. whether they
+    /// don't include any of the [`TABLE_ATTRIBUTES`](Self::TABLE_ATTRIBUTES).
+    #[inline]
+    pub fn is_valid_for_page(&self) -> bool {
+        !self.intersects(Self::TABLE_ATTRIBUTES)
+    }
+
+    /// Returns whether the flags can be those of a table descriptor, i.e. whether they don't
+    /// include any of the [`PAGE_ATTRIBUTES`](Self::PAGE_ATTRIBUTES).
+    #[inline]
+    pub fn is_valid_for_table(&self) -> bool {
+        !self.intersects(Self::PAGE_ATTRIBUTES)
+    }
+
     /// default flags for the table entry
     #[inline]
     pub fn default_table() -> Self {
@@ -237,60 +485,207 @@ impl PageTableFlags {
     pub fn default_page() -> Self {
         Self::VALID | Self::TABLE_OR_PAGE | Self::AF
     }
+
+    /// default flags for a page or block entry mapping a page of size `S`: the page flags at the
+    /// page level, the block flags above.
+    ///
+    /// The mapping is global: add `nG` for the mappings of a single address space, e.g. user
+    /// memory, so that they are tagged with its ASID.
+    #[inline]
+    pub fn default_leaf<S: PageSize>() -> Self {
+        if S::LEVEL == PAGE_LEVEL {
+            Self::default_page()
+        } else {
+            Self::default_block()
+        }
+    }
+}
+
+/// The data access permissions of a page or block, encoded in the AP[2:1] bits.
+///
+/// Using this instead of the `AP_EL0` and `AP_RO` flags directly avoids creating mappings
+/// writable at EL0 by accident.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessPermission {
+    /// Read/write at EL1, no access at EL0.
+    PrivilegedReadWrite,
+    /// Read/write at EL1 and EL0.
+    ReadWrite,
+    /// Read-only at EL1, no access at EL0.
+    PrivilegedReadOnly,
+    /// Read-only at EL1 and EL0.
+    ReadOnly,
+}
+
+impl AccessPermission {
+    /// The flags encoding the access permissions.
+    pub const MASK: PageTableFlags = PageTableFlags::from_bits_truncate(
+        PageTableFlags::AP_EL0.bits() | PageTableFlags::AP_RO.bits(),
+    );
+
+    /// Returns whether the memory is accessible at EL0.
+    #[inline]
+    pub fn el0_accessible(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadOnly)
+    }
+
+    /// Returns whether the memory is writable.
+    #[inline]
+    pub fn writable(self) -> bool {
+        matches!(self, Self::PrivilegedReadWrite | Self::ReadWrite)
+    }
 }
 
-/// The number of entries in a page table.
-const ENTRY_COUNT: usize = 512;
+impl From<PageTableFlags> for AccessPermission {
+    /// Decodes the access permissions of `flags`, ignoring all other flags.
+    fn from(flags: PageTableFlags) -> Self {
+        match (
+            flags.contains(PageTableFlags::AP_RO),
+            flags.contains(PageTableFlags::AP_EL0),
+        ) {
+            (false, false) => Self::PrivilegedReadWrite,
+            (false, true) => Self::ReadWrite,
+            (true, false) => Self::PrivilegedReadOnly,
+            (true, true) => Self::ReadOnly,
+        }
+    }
+}
 
-/// Represents a page table.
+impl From<AccessPermission> for PageTableFlags {
+    fn from(ap: AccessPermission) -> Self {
+        match ap {
+            AccessPermission::PrivilegedReadWrite => Self::empty(),
+            AccessPermission::ReadWrite => Self::AP_EL0,
+            AccessPermission::PrivilegedReadOnly => Self::AP_RO,
+            AccessPermission::ReadOnly => Self::AP_EL0 | Self::AP_RO,
+        }
+    }
+}
+
+impl PageTableFlags {
+    /// Returns the flags with the access permissions replaced by `ap`.
+    #[inline]
+    pub fn with_ap(self, ap: AccessPermission) -> Self {
+        (self - AccessPermission::MASK) | ap.into()
+    }
+}
+
+/// Represents a page table of the translation granule `G`.
 ///
-/// Always page-sized.
+/// Always page-sized: 512 entries with the 4KiB granule, 2048 with the 16KiB granule and 8192
+/// with the 64KiB granule.
 ///
 /// This struct implements the `Index` and `IndexMut` traits, so the entries can be accessed
 /// through index operations. For example, `page_table[15]` returns the 15th page table entry.
-#[repr(align(4096))]
 #[repr(C)]
-pub struct PageTable {
-    entries: [PageTableEntry; ENTRY_COUNT],
+pub struct PageTable<G: TranslationGranule = Granule4KiB> {
+    entries: G::Entries,
 }
 
-impl PageTable {
+impl<G: TranslationGranule> PageTable<G> {
     /// Creates an empty page table.
     pub const fn new() -> Self {
         Self {
-            entries: [PageTableEntry::new(); ENTRY_COUNT],
+            entries: G::EMPTY_TABLE,
         }
     }
 
-    /// Clears all entries.
+    /// Clears all entries, e.g. of a newly allocated table before it is linked into a hierarchy.
+    ///
+    /// The previous contents are not descriptors published by this crate, e.g. the stale words of
+    /// a recycled frame, so the writes bypass the
+    /// [break-before-make checker](super::bbm), and the invalidations it tracked in the table are
+    /// forgotten. To unmap the entries of a table in use, call
+    /// [`set_unused`](PageTableEntry::set_unused) on them.
     pub fn zero(&mut self) {
-        for entry in self.entries.iter_mut() {
-            entry.set_unused();
+        super::bbm::forget_table(self);
+        for entry in self.iter_mut() {
+            entry.entry = 0;
         }
     }
 
     /// Returns an iterator over the entries of the page table.
     pub fn iter(&self) -> impl Iterator<Item = &PageTableEntry> {
-        self.entries.iter()
+        self.entries.as_ref().iter()
     }
 
     /// Returns an iterator that allows modifying the entries of the page table.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut PageTableEntry> {
-        self.entries.iter_mut()
+        self.entries.as_mut().iter_mut()
+    }
+
+    /// Returns the raw descriptors of the entries, e.g. to save a snapshot of the table.
+    pub fn as_raw(&self) -> &[u64] {
+        let entries = self.entries.as_ref();
+        // `PageTableEntry` is a transparent `u64`
+        unsafe { core::slice::from_raw_parts(entries.as_ptr() as *const u64, entries.len()) }
+    }
+
+    /// Overwrites the entries with the raw descriptors `raw`, e.g. to restore a snapshot of the
+    /// table. The writes are checked for break-before-make violations like any other.
+    ///
+    /// Panics if `raw` does not hold one descriptor per entry.
+    pub fn copy_from_raw(&mut self, raw: &[u64]) {
+        let entries = self.entries.as_mut();
+        assert_eq!(raw.len(), entries.len(), "wrong number of descriptors");
+        for (entry, &raw) in entries.iter_mut().zip(raw) {
+            entry.set_raw(raw);
+        }
+    }
+}
+
+#[cfg(feature = "bytemuck")]
+impl<G: TranslationGranule> PageTable<G>
+where
+    G::Entries: bytemuck::Pod,
+{
+    /// Returns the bytes of the table, with the descriptors in the byte order of the PE.
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(&self.entries)
+    }
+
+    /// Returns the table stored in `bytes`, which must have the size of a table and be aligned to
+    /// it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<&Self, bytemuck::PodCastError> {
+        let entries: &G::Entries = bytemuck::try_from_bytes(bytes)?;
+        // `PageTable` is `repr(C)` with the entries as its only field
+        Ok(unsafe { &*(entries as *const G::Entries as *const Self) })
+    }
+
+    /// Returns the table stored in `bytes` mutably, see [`from_bytes`](PageTable::from_bytes).
+    pub fn from_bytes_mut(bytes: &mut [u8]) -> Result<&mut Self, bytemuck::PodCastError> {
+        let entries: &mut G::Entries = bytemuck::try_from_bytes_mut(bytes)?;
+        Ok(unsafe { &mut *(entries as *mut G::Entries as *mut Self) })
+    }
+}
+
+impl From<[u64; 512]> for PageTable {
+    fn from(raw: [u64; 512]) -> Self {
+        let mut table = Self::new();
+        table.copy_from_raw(&raw);
+        table
+    }
+}
+
+impl From<&PageTable> for [u64; 512] {
+    fn from(table: &PageTable) -> Self {
+        let mut raw = [0; 512];
+        raw.copy_from_slice(table.as_raw());
+        raw
     }
 }
 
-impl Index<usize> for PageTable {
+impl<G: TranslationGranule> Index<usize> for PageTable<G> {
     type Output = PageTableEntry;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.entries[index]
+        &self.entries.as_ref()[index]
     }
 }
 
-impl IndexMut<usize> for PageTable {
+impl<G: TranslationGranule> IndexMut<usize> for PageTable<G> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.entries[index]
+        &mut self.entries.as_mut()[index]
     }
 }
 
@@ -298,18 +693,161 @@ impl Index<u9> for PageTable {
     type Output = PageTableEntry;
 
     fn index(&self, index: u9) -> &Self::Output {
-        &self.entries[cast::usize(u16::from(index))]
+        &self[cast::usize(u16::from(index))]
     }
 }
 
 impl IndexMut<u9> for PageTable {
     fn index_mut(&mut self, index: u9) -> &mut Self::Output {
-        &mut self.entries[cast::usize(u16::from(index))]
+        &mut self[cast::usize(u16::from(index))]
     }
 }
 
-impl fmt::Debug for PageTable {
+impl<G: TranslationGranule> fmt::Debug for PageTable<G> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.entries[..].fmt(f)
+        self.entries.as_ref().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_accessed_dirty() {
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let mut entry = PageTableEntry::new();
+        // hardware managed, written to
+        entry.set_addr(
+            PhysAddr::new(0x1000),
+            PageTableFlags::default_page() | PageTableFlags::DBM,
+            attr,
+        );
+        assert!(entry.is_accessed() && entry.is_dirty());
+        assert!(entry.clear_accessed());
+        assert!(!entry.clear_accessed());
+        assert!(entry.clear_dirty());
+        assert!(!entry.is_dirty());
+        assert_eq!(entry.ap(), AccessPermission::PrivilegedReadOnly);
+
+        // software managed
+        #[cfg(feature = "software-flags")]
+        {
+            entry.set_flags(PageTableFlags::default_page() | PageTableFlags::DIRTY);
+            assert!(entry.is_dirty());
+            assert!(entry.clear_dirty());
+            assert_eq!(entry.flags(), PageTableFlags::default_page());
+        }
+    }
+
+    #[test]
+    pub fn test_software_bits() {
+        let mut entry = PageTableEntry::new();
+        entry.set_addr(
+            PhysAddr::new(0x1000),
+            PageTableFlags::default_page(),
+            PageTableAttribute::new(0, 0, 0),
+        );
+        entry.set_software_bits(u4::new(0b1010));
+        assert_eq!(entry.software_bits(), u4::new(0b1010));
+        assert_eq!(entry.addr(), PhysAddr::new(0x1000));
+        entry.set_flags(PageTableFlags::default_page() | PageTableFlags::UXN);
+        #[cfg(feature = "software-flags")]
+        assert_eq!(entry.software_bits(), u4::new(0));
+        #[cfg(not(feature = "software-flags"))]
+        assert_eq!(entry.software_bits(), u4::new(0b1010));
+    }
+
+    #[test]
+    pub fn test_access_permission() {
+        let mut entry = PageTableEntry::new();
+        entry.set_addr(
+            PhysAddr::new(0x1000),
+            PageTableFlags::default_page() | PageTableFlags::AP_EL0,
+            PageTableAttribute::new(0, 0, 0),
+        );
+        assert_eq!(entry.ap(), AccessPermission::ReadWrite);
+        entry.set_ap(AccessPermission::PrivilegedReadOnly);
+        assert_eq!(entry.ap(), AccessPermission::PrivilegedReadOnly);
+        assert_eq!(
+            entry.flags(),
+            PageTableFlags::default_page() | PageTableFlags::AP_RO
+        );
+        assert_eq!(entry.addr(), PhysAddr::new(0x1000));
+        assert_eq!(
+            PageTableFlags::default_page().with_ap(AccessPermission::ReadOnly),
+            PageTableFlags::default_page() | PageTableFlags::AP_EL0 | PageTableFlags::AP_RO
+        );
+    }
+
+    #[test]
+    pub fn test_descriptor_flags() {
+        let table = PageTableFlags::default_table() | PageTableFlags::PXNTable;
+        assert!(table.is_valid_for_table());
+        assert!(!table.is_valid_for_page());
+        let page = PageTableFlags::default_page() | PageTableFlags::UXN;
+        assert!(page.is_valid_for_page());
+        assert!(!page.is_valid_for_table());
+
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let mut entry = PageTableEntry::new();
+        entry.set_frame(
+            PhysFrame::containing_address(PhysAddr::new(0x1000)),
+            table,
+            attr,
+        );
+        assert_eq!(entry.flags(), table);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic]
+    pub fn test_table_flags_in_block() {
+        let mut entry = PageTableEntry::new();
+        entry.set_block::<crate::paging::Size2MiB>(
+            PhysAddr::new(0x20_0000),
+            PageTableFlags::default_block() | PageTableFlags::APTable_RO,
+            PageTableAttribute::new(0, 0, 0),
+        );
+    }
+
+    #[test]
+    pub fn test_output_address() {
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let mut entry = PageTableEntry::new();
+        entry.set_addr(
+            PhysAddr::new(0xffff_ffff_0000),
+            PageTableFlags::default_page(),
+            attr,
+        );
+        assert_eq!(entry.addr(), PhysAddr::new(0xffff_ffff_0000));
+
+        // bits 15:12 are address bits with the 4KiB granule, whatever the features
+        let mut entry = PageTableEntry::new();
+        entry.set_addr_in::<Granule4KiB>(
+            PhysAddr::new(0xffff_ffff_a000),
+            PageTableFlags::default_page(),
+            attr,
+        );
+        assert_eq!(
+            entry.addr_in::<Granule4KiB>(),
+            PhysAddr::new(0xffff_ffff_a000)
+        );
+
+        #[cfg(feature = "lpa")]
+        {
+            use crate::paging::Granule64KiB;
+            let mut entry = PageTableEntry::new();
+            entry.set_addr_in::<Granule64KiB>(
+                PhysAddr::new(0xa_1234_5678_0000),
+                PageTableFlags::default_page(),
+                attr,
+            );
+            assert_eq!(entry.entry & ADDR_HIGH_MASK, 0xa000);
+            assert_eq!(
+                entry.addr_in::<Granule64KiB>(),
+                PhysAddr::new(0xa_1234_5678_0000)
+            );
+        }
     }
 }
@@ -0,0 +1,258 @@
+//! Raw snapshots of page table hierarchies, e.g. to save the address spaces of a virtual machine,
+//! or to check the tables built by a mapper in host unit tests.
+//!
+//! A table is saved with [`PageTable::as_raw`] and restored with [`PageTable::copy_from_raw`], or
+//! cast from bytes with the `bytemuck` feature. A hierarchy is saved as its tables, each
+//! identified by its physical address, and [`validate`] checks its structural invariants before
+//! it is restored or handed to the MMU.
+//!
+//! [`PageTable::as_raw`]: super::PageTable::as_raw
+//! [`PageTable::copy_from_raw`]: super::PageTable::copy_from_raw
+
+use super::{
+    granule::{TranslationGranule, PAGE_LEVEL},
+    page_table::{PageTableEntry, PageTableFlags},
+    PageSize,
+};
+use crate::{
+    addr::PhysAddr,
+    fault::{self, FaultRecord, FaultSource, Severity},
+};
+use core::fmt;
+
+/// The invariant of a page table hierarchy broken by a descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotErrorKind {
+    /// The table pointed to is not in the snapshot.
+    MissingTable(PhysAddr),
+    /// The table does not have one descriptor per entry of a table of the granule.
+    WrongTableSize(PhysAddr),
+    /// The output address is not aligned to the size of the table, block or page pointed to.
+    MisalignedAddress(PhysAddr),
+    /// A block descriptor at a level without blocks, or the reserved encoding at the page level.
+    BlockNotAllowed,
+}
+
+/// A descriptor breaking an invariant of a page table hierarchy, found by [`validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotError {
+    /// The physical address of the table containing the descriptor.
+    pub table: PhysAddr,
+    /// The lookup level of the table.
+    pub level: usize,
+    /// The index of the descriptor in the table, `None` for the root table itself.
+    pub index: Option<usize>,
+    /// The broken invariant.
+    pub kind: SnapshotErrorKind,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "L{} table {:#x}", self.level, self.table.as_u64())?;
+        if let Some(index) = self.index {
+            write!(f, " entry {}", index)?;
+        }
+        f.write_str(": ")?;
+        match self.kind {
+            SnapshotErrorKind::MissingTable(addr) => {
+                write!(f, "table {:#x} missing", addr.as_u64())
+            }
+            SnapshotErrorKind::WrongTableSize(addr) => {
+                write!(f, "table {:#x} of the wrong size", addr.as_u64())
+            }
+            SnapshotErrorKind::MisalignedAddress(addr) => {
+                write!(f, "misaligned output address {:#x}", addr.as_u64())
+            }
+            SnapshotErrorKind::BlockNotAllowed => write!(f, "block not allowed"),
+        }
+    }
+}
+
+impl core::error::Error for SnapshotError {}
+
+/// Checks the structural invariants of the saved page table hierarchy starting at `root`, and
+/// returns the number of tables in the hierarchy.
+///
+/// `tables` returns the raw descriptors of the table saved at a physical address. Every valid
+/// table descriptor must point to a saved table aligned to its size, blocks must only appear at
+/// the levels that have them, and the blocks and pages must be aligned to their size.
+///
+/// The first broken invariant is also reported as an [`Error`](Severity::Error) of
+/// [`FaultSource::Snapshot`].
+pub fn validate<'a, G, F>(root: PhysAddr, mut tables: F) -> Result<usize, SnapshotError>
+where
+    G: TranslationGranule,
+    F: FnMut(PhysAddr) -> Option<&'a [u64]>,
+{
+    let result = table::<G, F>(root, &mut tables)
+        .map_err(|kind| SnapshotError {
+            table: root,
+            level: G::START_LEVEL,
+            index: None,
+            kind,
+        })
+        .and_then(|raw| validate_table::<G, F>(root, raw, G::START_LEVEL, &mut tables));
+    if let Err(error) = &result {
+        fault::report(&FaultRecord {
+            source: FaultSource::Snapshot,
+            severity: Severity::Error,
+            message: format_args!("invalid snapshot: {}", error),
+        });
+    }
+    result
+}
+
+fn validate_table<'a, G, F>(
+    addr: PhysAddr,
+    raw: &'a [u64],
+    level: usize,
+    tables: &mut F,
+) -> Result<usize, SnapshotError>
+where
+    G: TranslationGranule,
+    F: FnMut(PhysAddr) -> Option<&'a [u64]>,
+{
+    // blocks are 1GiB and 2MiB with the 4KiB granule, and 32MiB or 512MiB with the others
+    let block_levels = if G::INDEX_BITS == 9 {
+        1..PAGE_LEVEL
+    } else {
+        2..PAGE_LEVEL
+    };
+    let mut count = 1;
+    for (index, &raw) in raw.iter().enumerate() {
+        let entry = PageTableEntry::from_raw(raw);
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::VALID) {
+            continue;
+        }
+        let error = |kind| SnapshotError {
+            table: addr,
+            level,
+            index: Some(index),
+            kind,
+        };
+        let is_table = level != PAGE_LEVEL && flags.contains(PageTableFlags::TABLE_OR_PAGE);
+        if !flags.contains(PageTableFlags::TABLE_OR_PAGE) && !block_levels.contains(&level) {
+            return Err(error(SnapshotErrorKind::BlockNotAllowed));
+        }
+        let size = if is_table {
+            G::Page::SIZE
+        } else {
+            G::Page::SIZE << ((PAGE_LEVEL - level) as u32 * G::INDEX_BITS)
+        };
+        let output = entry.addr_in::<G>();
+        if !output.is_aligned(size) {
+            return Err(error(SnapshotErrorKind::MisalignedAddress(output)));
+        }
+        if is_table {
+            let next = table::<G, F>(output, tables).map_err(error)?;
+            count += validate_table::<G, F>(output, next, level + 1, tables)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Returns the descriptors of the table saved at `addr`.
+fn table<'a, G, F>(addr: PhysAddr, tables: &mut F) -> Result<&'a [u64], SnapshotErrorKind>
+where
+    G: TranslationGranule,
+    F: FnMut(PhysAddr) -> Option<&'a [u64]>,
+{
+    if !addr.is_aligned(G::Page::SIZE) {
+        return Err(SnapshotErrorKind::MisalignedAddress(addr));
+    }
+    let raw = tables(addr).ok_or(SnapshotErrorKind::MissingTable(addr))?;
+    if raw.len() != 1 << G::INDEX_BITS {
+        return Err(SnapshotErrorKind::WrongTableSize(addr));
+    }
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::{
+        page_table::PageTableAttribute, Granule4KiB, PageTable, Size1GiB, Size2MiB,
+    };
+
+    #[test]
+    pub fn test_validate() {
+        let attr = PageTableAttribute::new(0, 0, 0);
+        let mut root = PageTable::<Granule4KiB>::new();
+        let mut p1 = PageTable::<Granule4KiB>::new();
+        let mut p2 = PageTable::<Granule4KiB>::new();
+        root[0].set_addr(PhysAddr::new(0x1000), PageTableFlags::default_table(), attr);
+        p1[1].set_block::<Size1GiB>(
+            PhysAddr::new(0x4000_0000),
+            PageTableFlags::default_block(),
+            attr,
+        );
+        p1[2].set_addr(PhysAddr::new(0x2000), PageTableFlags::default_table(), attr);
+        p2[3].set_block::<Size2MiB>(
+            PhysAddr::new(0x8060_0000),
+            PageTableFlags::default_block(),
+            attr,
+        );
+
+        let saved = [
+            <[u64; 512]>::from(&root),
+            <[u64; 512]>::from(&p1),
+            <[u64; 512]>::from(&p2),
+        ];
+        let tables = |addr: PhysAddr| saved.get(addr.as_u64() as usize / 0x1000).map(|t| &t[..]);
+        assert_eq!(validate::<Granule4KiB, _>(PhysAddr::new(0), tables), Ok(3));
+        assert_eq!(
+            PageTable::<Granule4KiB>::from(saved[2]).as_raw(),
+            p2.as_raw()
+        );
+
+        let mut broken = saved;
+        // a block at level 0, where the 4KiB granule has no blocks
+        broken[0][5] = PageTableFlags::default_block().bits() | 0x4000_0000;
+        let err = validate::<Granule4KiB, _>(PhysAddr::new(0), |addr| {
+            broken.get(addr.as_u64() as usize / 0x1000).map(|t| &t[..])
+        })
+        .unwrap_err();
+        assert_eq!((err.level, err.index), (0, Some(5)));
+        assert_eq!(err.kind, SnapshotErrorKind::BlockNotAllowed);
+
+        let mut broken = saved;
+        broken[2][3] += 0x1000;
+        let err = validate::<Granule4KiB, _>(PhysAddr::new(0), |addr| {
+            broken.get(addr.as_u64() as usize / 0x1000).map(|t| &t[..])
+        })
+        .unwrap_err();
+        assert_eq!((err.table, err.level), (PhysAddr::new(0x2000), 2));
+        assert_eq!(
+            err.kind,
+            SnapshotErrorKind::MisalignedAddress(PhysAddr::new(0x8060_1000))
+        );
+
+        let err = validate::<Granule4KiB, _>(PhysAddr::new(0), |addr| {
+            saved[..2]
+                .get(addr.as_u64() as usize / 0x1000)
+                .map(|t| &t[..])
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.kind,
+            SnapshotErrorKind::MissingTable(PhysAddr::new(0x2000))
+        );
+    }
+
+    #[cfg(feature = "bytemuck")]
+    #[test]
+    pub fn test_bytes() {
+        let mut table = PageTable::<Granule4KiB>::new();
+        table[7].set_addr(
+            PhysAddr::new(0x1000),
+            PageTableFlags::default_page(),
+            PageTableAttribute::new(0, 0, 0),
+        );
+        let bytes = table.as_bytes();
+        assert_eq!(bytes.len(), 4096);
+        let cast = PageTable::<Granule4KiB>::from_bytes(bytes).unwrap();
+        assert_eq!(cast as *const _, &table as *const _);
+        assert!(PageTable::<Granule4KiB>::from_bytes(&bytes[8..]).is_err());
+    }
+}
@@ -0,0 +1,543 @@
+//! Stage 2 translation tables, translating the intermediate physical addresses (IPAs) of a
+//! virtual machine to physical addresses.
+//!
+//! Stage 2 descriptors have the layout of stage 1 descriptors, but the access permissions
+//! (S2AP) and memory attributes (MemAttr) are encoded in the descriptor itself instead of
+//! through MAIR_EL1. The tables are stored in [`PageTable`]s and built with a [`Stage2Mapper`].
+//! [`VtcrBuilder`] configures VTCR_EL2 for them, and [`set_vttbr`] installs them for a VMID.
+//!
+//! The initial lookup level is the lowest one translating the whole IPA range in a single
+//! table; concatenated initial tables are not used.
+
+use bitflags::bitflags;
+use tock_registers::{fields::FieldValue, register_bitfields, LocalRegisterCopy};
+
+use super::{
+    frame_alloc::FrameAllocator,
+    granule::{Granule4KiB, TranslationGranule, PAGE_LEVEL},
+    mapper::{MapToError, PageTableFrameMapping, UnmapError},
+    page_table::{encode_addr, PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
+    PageSize, PhysFrame,
+};
+use crate::{
+    barrier,
+    registers::*,
+    translation::{Cacheability, Shareability},
+    PhysAddr,
+};
+
+register_bitfields! {u64,
+    // Memory attribute fields in the stage 2 descriptors
+    pub STAGE2_MEMORY_ATTRIBUTE [
+        /// Shareability field
+        SH       OFFSET(8) NUMBITS(2) [
+            NonShareable = 0b00,
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ],
+
+        /// Memory type and cacheability, combined with the stage 1 attributes
+        MemAttr  OFFSET(2) NUMBITS(4) [
+            Device_nGnRnE = 0b0000,
+            Device_nGnRE = 0b0001,
+            Device_nGRE = 0b0010,
+            Device_GRE = 0b0011,
+            NormalNonCacheable = 0b0101,
+            NormalWriteThrough = 0b1010,
+            NormalWriteBack = 0b1111
+        ]
+    ]
+}
+
+/// Stage 2 memory attribute fields
+pub type Stage2Attribute = FieldValue<u64, STAGE2_MEMORY_ATTRIBUTE::Register>;
+
+bitflags! {
+    /// Possible flags for a stage 2 page table entry.
+    pub struct Stage2PageTableFlags: u64 {
+        /// identifies whether the descriptor is valid
+        const VALID =           1 << 0;
+        /// the descriptor type
+        /// 0, Block
+        /// 1, Table/Page
+        const TABLE_OR_PAGE =   1 << 1;
+        /// Stage 2 access permission: readable
+        const S2AP_R =          1 << 6;
+        /// Stage 2 access permission: writable
+        const S2AP_W =          1 << 7;
+        /// Access flag
+        const AF =              1 << 10;
+        /// Dirty Bit Modifier
+        const DBM =             1 << 51;
+        /// A hint bit indicating that the translation table entry is one of a contiguous set or
+        /// entries
+        const Contiguous =      1 << 52;
+        /// With FEAT_XNX, inverts the execute-never control of `XN` at EL1
+        const XNX =             1 << 53;
+        /// Execute-never
+        const XN =              1 << 54;
+    }
+}
+
+impl Stage2PageTableFlags {
+    /// default flags for the block entry
+    #[inline]
+    pub fn default_block() -> Self {
+        Self::VALID | Self::AF
+    }
+
+    /// default flags for the page entry
+    #[inline]
+    pub fn default_page() -> Self {
+        Self::VALID | Self::TABLE_OR_PAGE | Self::AF
+    }
+}
+
+/// An error indicating that a VTCR_EL2 configuration is not valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VtcrError {
+    /// The IPA size is not between 25 and 48 bits, exceeds the physical address size of the PE,
+    /// or can't be translated by a single initial table of the granule.
+    IpaBitsOutOfRange,
+    /// The PE doesn't support the translation granule at stage 2.
+    GranuleNotSupported,
+    /// The PE doesn't support 16-bit VMIDs.
+    VmidSizeNotSupported,
+}
+
+/// Returns the index of the entry translating `ipa` in a table of the given lookup level.
+///
+/// Unlike a VA, an IPA has no VA range to strip, and up to [`TranslationGranule::OA_BITS`] bits.
+#[inline]
+fn table_index<G: TranslationGranule>(ipa: u64, level: usize) -> usize {
+    let shift = G::Page::SIZE.trailing_zeros() + (PAGE_LEVEL - level) as u32 * G::INDEX_BITS;
+    (ipa >> shift) as usize & ((1 << G::INDEX_BITS) - 1)
+}
+
+/// Returns the initial lookup level of the stage 2 translation of `ipa_bits` IPAs with the
+/// granule `G`, or `None` if it can't be done with a single initial table.
+pub fn start_level<G: TranslationGranule>(ipa_bits: u8) -> Option<usize> {
+    if !(25..=G::OA_BITS as u8).contains(&ipa_bits) {
+        return None;
+    }
+    let resolved = ipa_bits as u32 - G::Page::SIZE.trailing_zeros();
+    let levels = resolved.div_ceil(G::INDEX_BITS) as usize;
+    let start = PAGE_LEVEL + 1 - levels;
+    // only the 4KiB granule starts at level 0 without FEAT_TTST
+    let min_start = if G::Page::SIZE == 0x1000 { 0 } else { 1 };
+    Some(start).filter(|&start| start >= min_start)
+}
+
+/// A builder for VTCR_EL2, the translation control register of stage 2 of the EL1&0 regime.
+#[derive(Debug, Clone, Copy)]
+pub struct VtcrBuilder {
+    ipa_bits: u8,
+    start_level: Option<usize>,
+    granule_size: u64,
+    tg0: u64,
+    cacheability: Cacheability,
+    shareability: Shareability,
+    vmid16: bool,
+}
+
+impl VtcrBuilder {
+    /// Creates a builder for an IPA range of `ipa_bits` bits with the translation granule `G`,
+    /// Write-Back Write-Allocate Inner Shareable table walks and 8-bit VMIDs.
+    pub fn new<G: TranslationGranule>(ipa_bits: u8) -> Self {
+        Self {
+            ipa_bits,
+            start_level: start_level::<G>(ipa_bits),
+            granule_size: G::Page::SIZE,
+            tg0: G::TCR_TG0.value >> TCR_EL1::TG0.shift,
+            cacheability: Cacheability::WriteBackWriteAllocate,
+            shareability: Shareability::InnerShareable,
+            vmid16: false,
+        }
+    }
+
+    /// Sets the inner and outer cacheability of translation table walks.
+    pub fn cacheability(mut self, cacheability: Cacheability) -> Self {
+        self.cacheability = cacheability;
+        self
+    }
+
+    /// Sets the shareability of translation table walks.
+    pub fn shareability(mut self, shareability: Shareability) -> Self {
+        self.shareability = shareability;
+        self
+    }
+
+    /// Selects 16-bit VMIDs instead of 8-bit ones (VS).
+    pub fn vmid16(mut self, vmid16: bool) -> Self {
+        self.vmid16 = vmid16;
+        self
+    }
+
+    /// Returns the register value, validated against the features of the PE described by the
+    /// values of ID_AA64MMFR0_EL1 and ID_AA64MMFR1_EL1. The output size of stage 2 is the
+    /// physical address size of the PE.
+    pub fn value(&self, mmfr0: u64, mmfr1: u64) -> Result<u64, VtcrError> {
+        use ID_AA64MMFR0_EL1::*;
+
+        let mmfr0 = LocalRegisterCopy::<u64, ID_AA64MMFR0_EL1::Register>::new(mmfr0);
+        let pa_range = mmfr0.read(PARange);
+        let pa_bits = match pa_range {
+            0 => 32,
+            1 => 36,
+            2 => 40,
+            3 => 42,
+            4 => 44,
+            5 => 48,
+            _ => 52,
+        };
+        let start_level = match self.start_level {
+            Some(start_level) if self.ipa_bits <= pa_bits => start_level,
+            _ => return Err(VtcrError::IpaBitsOutOfRange),
+        };
+        // TGranX_2 is 0b0000 when the support is the same as at stage 1, and 0b0001 when the
+        // granule is not supported
+        let (stage1, shift) = match self.granule_size {
+            0x1000 => (mmfr0.matches_all(TGran4::Supported), 40),
+            0x4000 => (mmfr0.matches_all(TGran16::Supported), 32),
+            _ => (mmfr0.matches_all(TGran64::Supported), 36),
+        };
+        let supported = match (mmfr0.get() >> shift) & 0xf {
+            0 => stage1,
+            1 => false,
+            _ => true,
+        };
+        if !supported {
+            return Err(VtcrError::GranuleNotSupported);
+        }
+        let mmfr1 = LocalRegisterCopy::<u64, ID_AA64MMFR1_EL1::Register>::new(mmfr1);
+        if self.vmid16 && !mmfr1.matches_all(ID_AA64MMFR1_EL1::VMIDBits::Bits16) {
+            return Err(VtcrError::VmidSizeNotSupported);
+        }
+
+        let cacheability = self.cacheability as u64;
+        let shareability = match self.shareability {
+            Shareability::NonShareable => 0b00,
+            Shareability::OuterShareable => 0b10,
+            Shareability::InnerShareable => 0b11,
+        };
+        let sl0 = if self.granule_size == 0x1000 {
+            2 - start_level
+        } else {
+            3 - start_level
+        };
+        let vtcr = VTCR_EL2::T0SZ.val(64 - self.ipa_bits as u64)
+            + VTCR_EL2::SL0.val(sl0 as u64)
+            + VTCR_EL2::IRGN0.val(cacheability)
+            + VTCR_EL2::ORGN0.val(cacheability)
+            + VTCR_EL2::SH0.val(shareability)
+            + VTCR_EL2::TG0.val(self.tg0)
+            + VTCR_EL2::PS.val(pa_range)
+            + VTCR_EL2::VS.val(self.vmid16 as u64);
+        // bit 31 is RES1
+        Ok(vtcr.value | 1 << 31)
+    }
+
+    /// Validates the configuration against the features of the PE and writes VTCR_EL2.
+    ///
+    /// Must be executed at EL2.
+    ///
+    /// # Safety
+    ///
+    /// The stage 2 translation tables in use must match the new configuration, or stage 2
+    /// translation must be disabled. TLB entries created with another configuration must be
+    /// invalidated.
+    #[inline]
+    pub unsafe fn apply(self) -> Result<(), VtcrError> {
+        let value = self.value(ID_AA64MMFR0_EL1.get(), ID_AA64MMFR1_EL1.get())?;
+        VTCR_EL2.set(value);
+        barrier::isb();
+        Ok(())
+    }
+}
+
+/// Returns the VTTBR_EL2 value for the stage 2 tables at `root`, tagged with `vmid`.
+pub fn vttbr_value(root: PhysAddr, vmid: u16) -> u64 {
+    (VTTBR_EL2::VMID.val(vmid as u64) + VTTBR_EL2::BADDR.val(root.as_u64() >> 1)).value
+}
+
+/// Installs the stage 2 tables at `root` for the virtual machine `vmid` (VTTBR_EL2).
+///
+/// Must be executed at EL2.
+///
+/// # Safety
+///
+/// `root` must be the initial table of stage 2 tables matching VTCR_EL2, and the TLB must not
+/// hold entries of another virtual machine tagged with `vmid`.
+#[inline]
+pub unsafe fn set_vttbr(root: PhysAddr, vmid: u16) {
+    VTTBR_EL2.set(vttbr_value(root, vmid));
+    barrier::isb();
+}
+
+/// Builds the stage 2 translation tables of a virtual machine.
+///
+/// Like a [`MappedPageTable`](super::MappedPageTable), the tables are accessed through a
+/// [`PageTableFrameMapping`], e.g. a closure converting physical to virtual addresses. Guest
+/// IPAs are passed as frames, to be mapped to host frames of the same size.
+///
+/// The mapper doesn't invalidate the TLB: after changing or removing a valid mapping, the stage 2
+/// TLB entries of its IPA must be invalidated for the VMID of the tables.
+#[derive(Debug)]
+pub struct Stage2Mapper<'a, PhysToVirt, G = Granule4KiB>
+where
+    G: TranslationGranule,
+    PhysToVirt: PageTableFrameMapping<G>,
+{
+    root: &'a mut PageTable<G>,
+    phys_to_virt: PhysToVirt,
+    ipa_bits: u8,
+    start_level: usize,
+}
+
+impl<'a, PhysToVirt, G> Stage2Mapper<'a, PhysToVirt, G>
+where
+    G: TranslationGranule,
+    PhysToVirt: PageTableFrameMapping<G>,
+{
+    /// Creates a mapper for the stage 2 tables with the initial table `root`, translating IPAs
+    /// of `ipa_bits` bits.
+    ///
+    /// Returns [`VtcrError::IpaBitsOutOfRange`] if the IPA size is not supported with the
+    /// granule, see [`start_level`].
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that `phys_to_virt` is correct, and that `root` is the initial
+    /// table of a valid hierarchy (e.g. a zeroed one) of the initial lookup level for `ipa_bits`.
+    pub unsafe fn new(
+        root: &'a mut PageTable<G>,
+        ipa_bits: u8,
+        phys_to_virt: PhysToVirt,
+    ) -> Result<Self, VtcrError> {
+        let start_level = start_level::<G>(ipa_bits).ok_or(VtcrError::IpaBitsOutOfRange)?;
+        Ok(Self {
+            root,
+            phys_to_virt,
+            ipa_bits,
+            start_level,
+        })
+    }
+
+    /// Returns the initial lookup level of the tables.
+    pub fn start_level(&self) -> usize {
+        self.start_level
+    }
+
+    /// Returns a mutable reference to the initial table.
+    pub fn root(&mut self) -> &mut PageTable<G> {
+        self.root
+    }
+
+    /// Maps the guest frame `ipa` to the host frame `frame`, with blocks or pages of size `S`,
+    /// creating the missing tables with `allocator`.
+    ///
+    /// `flags` are added to the default block or page flags, e.g. the access permissions and
+    /// execute-never bits. Panics if `ipa` is outside of the IPA range.
+    ///
+    /// # Safety
+    ///
+    /// The host frame is made accessible to the guest with the given permissions.
+    pub unsafe fn map_to<S, A>(
+        &mut self,
+        ipa: PhysFrame<S>,
+        frame: PhysFrame<S>,
+        flags: Stage2PageTableFlags,
+        attr: Stage2Attribute,
+        allocator: &mut A,
+    ) -> Result<(), MapToError>
+    where
+        S: PageSize<Granule = G>,
+        A: FrameAllocator<G::Page> + ?Sized,
+    {
+        let addr = self.ipa(ipa.start_address());
+        let mut table: *mut PageTable<G> = self.root;
+        for level in self.start_level..S::LEVEL {
+            let entry = &mut (&mut *table)[table_index::<G>(addr, level)];
+            if entry.is_unused() {
+                let next = allocator
+                    .allocate_frame()
+                    .ok_or(MapToError::FrameAllocationFailed)?
+                    .frame();
+                (*self.phys_to_virt.frame_to_pointer(next)).zero();
+                entry.set_addr_in::<G>(
+                    next.start_address(),
+                    PageTableFlags::default_table(),
+                    PageTableAttribute::new(0, 0, 0),
+                );
+            } else if entry.is_block() {
+                return Err(MapToError::ParentEntryHugePage);
+            }
+            table = self.next_table(entry);
+        }
+
+        let entry = &mut (&mut *table)[table_index::<G>(addr, S::LEVEL)];
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped);
+        }
+        let flags = if S::LEVEL == PAGE_LEVEL {
+            flags | Stage2PageTableFlags::default_page()
+        } else {
+            (flags | Stage2PageTableFlags::default_block()) - Stage2PageTableFlags::TABLE_OR_PAGE
+        };
+        let addr = encode_addr::<G>(frame.start_address().as_u64());
+        entry.set_raw(addr | flags.bits() | attr.value);
+        Ok(())
+    }
+
+    /// Removes the mapping of the guest frame `ipa`, and returns the host frame it was mapped to.
+    pub fn unmap<S>(&mut self, ipa: PhysFrame<S>) -> Result<PhysFrame<S>, UnmapError>
+    where
+        S: PageSize<Granule = G>,
+    {
+        let addr = self.ipa(ipa.start_address());
+        let mut table: *mut PageTable<G> = self.root;
+        for level in self.start_level..S::LEVEL {
+            let entry = unsafe { &(&*table)[table_index::<G>(addr, level)] };
+            if !Self::flags(entry).contains(Stage2PageTableFlags::VALID) {
+                return Err(UnmapError::PageNotMapped);
+            } else if entry.is_block() {
+                return Err(UnmapError::ParentEntryHugePage);
+            }
+            table = self.next_table(entry);
+        }
+
+        let entry = unsafe { &mut (&mut *table)[table_index::<G>(addr, S::LEVEL)] };
+        if !Self::flags(entry).contains(Stage2PageTableFlags::VALID)
+            || entry.is_block() != (S::LEVEL != PAGE_LEVEL)
+        {
+            return Err(UnmapError::PageNotMapped);
+        }
+        let addr = entry.addr_in::<G>();
+        let frame = PhysFrame::from_start_address(addr)
+            .map_err(|()| UnmapError::InvalidFrameAddress(addr))?;
+        entry.set_unused();
+        Ok(frame)
+    }
+
+    /// Returns the physical address and flags of the block or page that `ipa` is mapped to, or
+    /// `None` if it is not mapped.
+    pub fn translate(&self, ipa: PhysAddr) -> Option<(PhysAddr, Stage2PageTableFlags)> {
+        if ipa.as_u64() >> self.ipa_bits != 0 {
+            return None;
+        }
+        let addr = ipa.as_u64();
+        let mut table: *const PageTable<G> = &*self.root;
+        for level in self.start_level..=PAGE_LEVEL {
+            let entry = unsafe { &(&*table)[table_index::<G>(addr, level)] };
+            let flags = Self::flags(entry);
+            if !flags.contains(Stage2PageTableFlags::VALID) {
+                return None;
+            }
+            if level == PAGE_LEVEL || entry.is_block() {
+                let size = G::Page::SIZE << ((PAGE_LEVEL - level) as u32 * G::INDEX_BITS);
+                return Some((entry.addr_in::<G>() + (ipa.as_u64() & (size - 1)), flags));
+            }
+            table = self.next_table(entry);
+        }
+        None
+    }
+
+    /// Returns `ipa` as the input address of the walk, checking that it is in the IPA range.
+    fn ipa(&self, ipa: PhysAddr) -> u64 {
+        assert!(
+            ipa.as_u64() >> self.ipa_bits == 0,
+            "{:?} is outside of the IPA range",
+            ipa
+        );
+        ipa.as_u64()
+    }
+
+    /// Returns the table pointed to by the table descriptor `entry`.
+    fn next_table(&self, entry: &PageTableEntry) -> *mut PageTable<G> {
+        self.phys_to_virt
+            .frame_to_pointer(PhysFrame::containing_address(entry.addr_in::<G>()))
+    }
+
+    fn flags(entry: &PageTableEntry) -> Stage2PageTableFlags {
+        Stage2PageTableFlags::from_bits_truncate(entry.raw())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::{
+        mapper::IdentityMapping,
+        test_util::{split_tables, tables},
+        Granule64KiB, Size2MiB, Size4KiB,
+    };
+
+    #[test]
+    pub fn test_stage2() {
+        assert_eq!(start_level::<Granule4KiB>(40), Some(0));
+        assert_eq!(start_level::<Granule4KiB>(39), Some(1));
+        assert_eq!(start_level::<Granule64KiB>(48), Some(1));
+        assert_eq!(start_level::<Granule64KiB>(42), Some(2));
+        assert_eq!(start_level::<crate::paging::Granule16KiB>(48), None);
+
+        // 44-bit PAs, 16-bit VMIDs
+        let (mmfr0, mmfr1) = (0x4, 0x20);
+        let builder = VtcrBuilder::new::<Granule4KiB>(40);
+        assert_eq!(
+            builder.value(mmfr0, mmfr1),
+            Ok(0x8004_3598 /* RES1, PS 44 bits, SH0 IS, WBWA, SL0 2, T0SZ 24 */)
+        );
+        assert_eq!(
+            builder.vmid16(true).value(mmfr0, 0),
+            Err(VtcrError::VmidSizeNotSupported)
+        );
+        assert_eq!(
+            VtcrBuilder::new::<Granule4KiB>(48).value(mmfr0, mmfr1),
+            Err(VtcrError::IpaBitsOutOfRange)
+        );
+        assert_eq!(
+            vttbr_value(PhysAddr::new(0x8000_0000), 5),
+            0x0005_0000_8000_0000
+        );
+
+        let mut tables = tables::<Granule4KiB, 3>();
+        let (root, mut allocator) = split_tables(&mut tables);
+        let mut mapper = unsafe { Stage2Mapper::new(root, 39, IdentityMapping).unwrap() };
+        assert_eq!(mapper.start_level(), 1);
+        let attr = STAGE2_MEMORY_ATTRIBUTE::MemAttr::NormalWriteBack
+            + STAGE2_MEMORY_ATTRIBUTE::SH::InnerShareable;
+        let rw = Stage2PageTableFlags::S2AP_R | Stage2PageTableFlags::S2AP_W;
+
+        let ipa = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(0x4000_1000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8_8000_3000));
+        let block = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(0x4020_0000));
+        let block_frame = PhysFrame::containing_address(PhysAddr::new(0x9000_0000));
+        unsafe {
+            mapper.map_to(ipa, frame, rw, attr, &mut allocator).unwrap();
+            mapper
+                .map_to(block, block_frame, rw, attr, &mut allocator)
+                .unwrap();
+            assert!(matches!(
+                mapper.map_to(ipa, frame, rw, attr, &mut allocator),
+                Err(MapToError::PageAlreadyMapped)
+            ));
+        }
+
+        let (addr, flags) = mapper.translate(PhysAddr::new(0x4000_1234)).unwrap();
+        assert_eq!(addr, PhysAddr::new(0x8_8000_3234));
+        assert_eq!(flags, rw | Stage2PageTableFlags::default_page());
+        let (addr, flags) = mapper.translate(PhysAddr::new(0x4023_4567)).unwrap();
+        assert_eq!(addr, PhysAddr::new(0x9003_4567));
+        assert!(!flags.contains(Stage2PageTableFlags::TABLE_OR_PAGE));
+        assert_eq!(mapper.translate(PhysAddr::new(0x4040_0000)), None);
+        assert_eq!(mapper.translate(PhysAddr::new(1 << 39)), None);
+
+        let leaf = unsafe { &*(mapper.next_table(&mapper.root[1])) };
+        let leaf = unsafe { &*(mapper.next_table(&leaf[0])) };
+        assert_eq!(leaf[1].raw() & 0x3fc, 0x3c | 0x300 | 0xc0);
+
+        assert_eq!(mapper.unmap(ipa).unwrap(), frame);
+        assert!(matches!(mapper.unmap(ipa), Err(UnmapError::PageNotMapped)));
+        assert_eq!(mapper.translate(PhysAddr::new(0x4000_1234)), None);
+    }
+}
@@ -0,0 +1,205 @@
+//! Temporary mappings of frames through a reserved window, for kernels without a mapping of the
+//! complete physical memory.
+//!
+//! A [`TempMapper`] owns the entries of a leaf page table translating a small window of virtual
+//! addresses, split into slots of one page, with a few slots reserved for each core.
+//! [`TempMapper::map`] maps a frame in a free slot of the current core, e.g. to zero a new page
+//! table, and the returned [`TempMapping`] unmaps it and invalidates its TLB entries when dropped.
+
+use crate::{
+    paging::{
+        page_table::{PageTable, PageTableAttribute, PageTableEntry, PageTableFlags},
+        PageSize, PhysFrame, Size4KiB,
+    },
+    percpu::cpu_index,
+    VirtAddr,
+};
+use core::{
+    fmt,
+    marker::PhantomData,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Claims a free slot in the bitmap `used` of `slots` slots, and returns its index.
+fn claim_slot(used: &AtomicU32, slots: usize) -> Option<usize> {
+    let mut current = used.load(Ordering::Relaxed);
+    loop {
+        let slot = (!current).trailing_zeros() as usize;
+        if slot >= slots {
+            return None;
+        }
+        match used.compare_exchange_weak(
+            current,
+            current | 1 << slot,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return Some(slot),
+            Err(value) => current = value,
+        }
+    }
+}
+
+/// A window of virtual addresses for temporary mappings, with per-CPU slots for up to `N` cores
+/// indexed by [`cpu_index`].
+pub struct TempMapper<'a, const N: usize> {
+    table: *mut PageTable,
+    base: VirtAddr,
+    first_index: usize,
+    slots_per_cpu: usize,
+    flags: PageTableFlags,
+    attr: PageTableAttribute,
+    used: [AtomicU32; N],
+    _table: PhantomData<&'a mut PageTable>,
+}
+
+unsafe impl<'a, const N: usize> Send for TempMapper<'a, N> {}
+unsafe impl<'a, const N: usize> Sync for TempMapper<'a, N> {}
+
+impl<'a, const N: usize> TempMapper<'a, N> {
+    /// Creates a mapper for the window of `slots_per_cpu * N` pages starting at `base`, translated
+    /// by `table`.
+    ///
+    /// Frames are mapped with the given flags and memory attributes, e.g.
+    /// [`PageTableFlags::default_page`] and the index of Normal memory in MAIR_EL1.
+    ///
+    /// Panics if `base` is not page aligned, if `slots_per_cpu` is 0 or above 32, or if the window
+    /// doesn't fit in `table`.
+    ///
+    /// # Safety
+    ///
+    /// `table` must be the level 3 table translating `base`, in the page tables of all the cores,
+    /// and its entries for the window must be unused and not changed by anything else for the
+    /// lifetime `'a`.
+    pub unsafe fn new(
+        table: &'a mut PageTable,
+        base: VirtAddr,
+        slots_per_cpu: usize,
+        flags: PageTableFlags,
+        attr: PageTableAttribute,
+    ) -> Self {
+        assert!(base.is_aligned(Size4KiB::SIZE), "window not page aligned");
+        assert!(
+            (1..=32).contains(&slots_per_cpu),
+            "1 to 32 slots per CPU supported"
+        );
+        let first_index = ((base.as_u64() / Size4KiB::SIZE) % 512) as usize;
+        assert!(
+            first_index + slots_per_cpu * N <= 512,
+            "window doesn't fit in the table"
+        );
+        Self {
+            table,
+            base,
+            first_index,
+            slots_per_cpu,
+            flags,
+            attr,
+            used: core::array::from_fn(|_| AtomicU32::new(0)),
+            _table: PhantomData,
+        }
+    }
+
+    /// Returns the virtual address of the window.
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// Maps `frame` in a free slot of the current core.
+    ///
+    /// Returns `None` if all the slots of the current core are in use.
+    ///
+    /// Panics if the index of the current core is not below `N`.
+    pub fn map(&self, frame: PhysFrame) -> Option<TempMapping<'_>> {
+        let cpu = cpu_index();
+        let used = &self.used[cpu];
+        let slot = claim_slot(used, self.slots_per_cpu)?;
+        let index = cpu * self.slots_per_cpu + slot;
+        // Each slot is only written by the owner of its bit in `used`, so the other entries of
+        // the table may be in use: only this one is referenced.
+        let entry = unsafe { (self.table as *mut PageTableEntry).add(self.first_index + index) };
+        unsafe { (*entry).set_frame(frame, self.flags, self.attr) };
+        unsafe {
+            crate::barrier::dsb(crate::barrier::ISHST);
+            crate::barrier::isb();
+        }
+        Some(TempMapping {
+            addr: self.base + index as u64 * Size4KiB::SIZE,
+            frame,
+            entry,
+            used,
+            slot,
+        })
+    }
+}
+
+impl<'a, const N: usize> fmt::Debug for TempMapper<'a, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TempMapper")
+            .field("base", &self.base)
+            .field("slots_per_cpu", &self.slots_per_cpu)
+            .field("flags", &self.flags)
+            .field("attr", &format_args!("{:#x}", self.attr.value))
+            .field("used", &self.used)
+            .finish()
+    }
+}
+
+/// A frame mapped by [`TempMapper::map`], unmapped when dropped.
+#[derive(Debug)]
+#[must_use = "the frame is unmapped again when the mapping is dropped"]
+pub struct TempMapping<'m> {
+    addr: VirtAddr,
+    frame: PhysFrame,
+    entry: *mut PageTableEntry,
+    used: &'m AtomicU32,
+    slot: usize,
+}
+
+impl<'m> TempMapping<'m> {
+    /// Returns the virtual address at which the frame is mapped.
+    pub fn addr(&self) -> VirtAddr {
+        self.addr
+    }
+
+    /// Returns the mapped frame.
+    pub fn frame(&self) -> PhysFrame {
+        self.frame
+    }
+
+    /// Returns a pointer to the start of the frame.
+    ///
+    /// The pointer is only valid while the mapping is alive.
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+        self.addr.as_mut_ptr()
+    }
+}
+
+impl<'m> Drop for TempMapping<'m> {
+    fn drop(&mut self) {
+        unsafe { (*self.entry).set_unused() };
+        // The mapping may be cached by any core if the owner migrated while it was alive.
+        crate::translation::invalidate_tlb_vaddr_leaf_only(self.addr);
+        self.used.fetch_and(!(1 << self.slot), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_claim_slot() {
+        let used = AtomicU32::new(0);
+        assert_eq!(claim_slot(&used, 3), Some(0));
+        assert_eq!(claim_slot(&used, 3), Some(1));
+        assert_eq!(claim_slot(&used, 3), Some(2));
+        assert_eq!(claim_slot(&used, 3), None);
+        used.fetch_and(!0b10, Ordering::Relaxed);
+        assert_eq!(claim_slot(&used, 3), Some(1));
+
+        let used = AtomicU32::new(u32::MAX >> 1);
+        assert_eq!(claim_slot(&used, 32), Some(31));
+        assert_eq!(claim_slot(&used, 32), None);
+    }
+}
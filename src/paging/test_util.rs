@@ -0,0 +1,69 @@
+//! Helpers shared by the unit tests of the paging modules.
+
+use super::{
+    mapper::IdentityMapping, FrameAllocator, FrameDeallocator, Granule4KiB, MappedPageTable,
+    PageTable, PhysFrame, TranslationGranule, UnusedPhysFrame,
+};
+use crate::PhysAddr;
+
+/// Allocates the tables of a slice as frames, at the addresses of the tables, for mappers using
+/// an [`IdentityMapping`](super::mapper::IdentityMapping). Freed frames are only counted.
+pub(crate) struct TableAllocator<'a, G: TranslationGranule = Granule4KiB> {
+    pub tables: core::slice::IterMut<'a, PageTable<G>>,
+    pub freed: usize,
+}
+
+impl<'a, G: TranslationGranule> TableAllocator<'a, G> {
+    pub fn new(tables: &'a mut [PageTable<G>]) -> Self {
+        TableAllocator {
+            tables: tables.iter_mut(),
+            freed: 0,
+        }
+    }
+}
+
+unsafe impl<G: TranslationGranule> FrameAllocator<G::Page> for TableAllocator<'_, G> {
+    fn allocate_frame(&mut self) -> Option<UnusedPhysFrame<G::Page>> {
+        let table = self.tables.next()?;
+        Some(unsafe {
+            UnusedPhysFrame::new(PhysFrame::containing_address(PhysAddr::new(
+                table as *mut _ as u64,
+            )))
+        })
+    }
+}
+
+impl<G: TranslationGranule> FrameDeallocator<G::Page> for TableAllocator<'_, G> {
+    fn deallocate_frame(&mut self, _frame: PhysFrame<G::Page>) {
+        self.freed += 1;
+    }
+}
+
+/// Returns `N` empty tables, to back the page tables of a test.
+pub(crate) fn tables<G: TranslationGranule, const N: usize>() -> [PageTable<G>; N] {
+    core::array::from_fn(|_| PageTable::new())
+}
+
+/// Splits `tables` into the root table of a page table and an allocator of the others.
+pub(crate) fn split_tables<G: TranslationGranule>(
+    tables: &mut [PageTable<G>],
+) -> (&mut PageTable<G>, TableAllocator<'_, G>) {
+    let (root, rest) = tables.split_first_mut().unwrap();
+    (root, TableAllocator::new(rest))
+}
+
+/// Returns an identity-mapped page table whose root is the first of `tables`, created with
+/// [`MappedPageTable::from_frame`], and an allocator of the others, see [`split_tables`].
+pub(crate) fn mapped_page_table<G: TranslationGranule>(
+    tables: &mut [PageTable<G>],
+) -> (
+    MappedPageTable<'_, IdentityMapping, G>,
+    TableAllocator<'_, G>,
+) {
+    let (root, allocator) = split_tables(tables);
+    let root = PhysFrame::containing_address(PhysAddr::new(root as *mut _ as u64));
+    (
+        unsafe { MappedPageTable::from_frame(root, IdentityMapping) },
+        allocator,
+    )
+}
@@ -0,0 +1,257 @@
+//! Walking page table hierarchies, e.g. to dump an address space from a kernel debugger.
+//!
+//! [`walk`] visits every valid entry of a hierarchy, tables included, in address order. The page
+//! tables are accessed through a [`PageTableFrameMapping`], like with a [`MappedPageTable`].
+//!
+//! [`MappedPageTable`]: super::MappedPageTable
+
+use super::{
+    granule::{va_mask, Granule4KiB, TranslationGranule, PAGE_LEVEL},
+    mapper::PageTableFrameMapping,
+    page_table::{PageTable, PageTableAttribute, PageTableFlags},
+    PageSize, PhysFrame,
+};
+use crate::addr::{PhysAddr, VirtAddr, VirtAddrRange};
+use core::fmt;
+
+/// The kind of a descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Points to the translation table of the next level.
+    Table,
+    /// Maps a block of memory.
+    Block,
+    /// Maps a page.
+    Page,
+}
+
+/// A valid entry visited by [`walk`].
+#[derive(Clone, Copy)]
+pub struct WalkEntry {
+    /// The lookup level of the table containing the entry.
+    pub level: usize,
+    /// The first virtual address translated through the entry.
+    pub start: VirtAddr,
+    /// The size of the virtual address range translated through the entry.
+    pub size: u64,
+    /// The kind of the entry.
+    pub kind: EntryKind,
+    /// The output address: the next table, or the mapped block or page.
+    pub addr: PhysAddr,
+    /// The flags of the entry.
+    pub flags: PageTableFlags,
+    /// The memory attribute fields of the entry.
+    pub attr: PageTableAttribute,
+}
+
+impl WalkEntry {
+    /// Returns the last virtual address translated through the entry.
+    pub fn last(&self) -> VirtAddr {
+        self.start + (self.size - 1)
+    }
+
+    /// Returns whether `addr` is translated through the entry.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr <= self.last()
+    }
+}
+
+impl fmt::Debug for WalkEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("WalkEntry");
+        f.field("level", &self.level);
+        f.field("start", &self.start);
+        f.field("size", &self.size);
+        f.field("kind", &self.kind);
+        f.field("addr", &self.addr);
+        f.field("flags", &self.flags);
+        f.field("attr", &self.attr.value);
+        f.finish()
+    }
+}
+
+impl fmt::Display for WalkEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "L{} {:#018x}-{:#018x} {:?} {:#x} {:?} attr {:#x}",
+            self.level,
+            self.start.as_u64(),
+            self.last().as_u64(),
+            self.kind,
+            self.addr.as_u64(),
+            self.flags,
+            self.attr.value
+        )
+    }
+}
+
+/// Visits every valid entry of the page table hierarchy starting at `root`.
+///
+/// `range` is the half of the virtual address space translated by the hierarchy, i.e. whether
+/// `root` is used with TTBR0_EL1 or TTBR1_EL1. Table entries are visited before the entries of
+/// the table they point to.
+///
+/// # Safety
+///
+/// The caller must guarantee that `phys_to_virt` is correct and that `root` is the root of a
+/// valid page table hierarchy, which is not modified during the walk.
+pub unsafe fn walk<G, P, F>(
+    root: &PageTable<G>,
+    range: VirtAddrRange,
+    phys_to_virt: &P,
+    mut visit: F,
+) where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+    F: FnMut(&WalkEntry),
+{
+    let base = match range {
+        VirtAddrRange::BottomRange => 0,
+        VirtAddrRange::TopRange => !va_mask::<G>(),
+    };
+    walk_table(root, G::START_LEVEL, base, phys_to_virt, &mut visit);
+}
+
+unsafe fn walk_table<G, P, F>(
+    table: &PageTable<G>,
+    level: usize,
+    base: u64,
+    phys_to_virt: &P,
+    visit: &mut F,
+) where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+    F: FnMut(&WalkEntry),
+{
+    let shift = G::Page::SIZE.trailing_zeros() + (PAGE_LEVEL - level) as u32 * G::INDEX_BITS;
+    // the initial table can have more entries than needed for the address space
+    let count = 1usize << (G::VA_BITS - shift).min(G::INDEX_BITS);
+    for (index, entry) in table.iter().take(count).enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::VALID) {
+            continue;
+        }
+        let kind = match (
+            level == PAGE_LEVEL,
+            flags.contains(PageTableFlags::TABLE_OR_PAGE),
+        ) {
+            (false, true) => EntryKind::Table,
+            (false, false) => EntryKind::Block,
+            (true, true) => EntryKind::Page,
+            // reserved encoding, treated as invalid by the MMU
+            (true, false) => continue,
+        };
+        let start = base | (index as u64) << shift;
+        visit(&WalkEntry {
+            level,
+            start: VirtAddr::new_unchecked(start),
+            size: 1 << shift,
+            kind,
+            addr: entry.addr_in::<G>(),
+            flags,
+            attr: entry.attr(),
+        });
+        if kind == EntryKind::Table {
+            let next =
+                phys_to_virt.frame_to_pointer(PhysFrame::containing_address(entry.addr_in::<G>()));
+            walk_table(&*next, level + 1, start, phys_to_virt, visit);
+        }
+    }
+}
+
+/// Returns a dump of the page table hierarchy starting at `root`, printing one line per valid
+/// entry when formatted with `Display`.
+///
+/// # Safety
+///
+/// See [`walk`]. The requirements must hold whenever the dump is formatted.
+pub unsafe fn dump<'a, G, P>(
+    root: &'a PageTable<G>,
+    range: VirtAddrRange,
+    phys_to_virt: &'a P,
+) -> PageTableDump<'a, P, G>
+where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+{
+    PageTableDump {
+        root,
+        range,
+        phys_to_virt,
+    }
+}
+
+/// A page table hierarchy formatted by [`walk`]ing it, returned by [`dump`].
+///
+/// Entries are indented by their lookup level.
+pub struct PageTableDump<'a, P, G = Granule4KiB>
+where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+{
+    root: &'a PageTable<G>,
+    range: VirtAddrRange,
+    phys_to_virt: &'a P,
+}
+
+impl<P, G> fmt::Display for PageTableDump<'_, P, G>
+where
+    G: TranslationGranule,
+    P: PageTableFrameMapping<G>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut result = Ok(());
+        unsafe {
+            walk(self.root, self.range, self.phys_to_virt, |entry| {
+                if result.is_ok() {
+                    let indent = 2 * (entry.level - G::START_LEVEL);
+                    result = writeln!(f, "{:indent$}{}", "", entry, indent = indent);
+                }
+            });
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::Size1GiB;
+
+    #[test]
+    pub fn test_walk() {
+        let mut root = PageTable::<Granule4KiB>::new();
+        let mut p3 = PageTable::<Granule4KiB>::new();
+        let attr = PageTableAttribute::new(0, 0, 0);
+        p3[2].set_block::<Size1GiB>(
+            PhysAddr::new(0x8000_0000),
+            PageTableFlags::default_block(),
+            attr,
+        );
+        root[1].set_addr(
+            PhysAddr::new(&p3 as *const _ as u64),
+            PageTableFlags::default_table(),
+            attr,
+        );
+
+        let phys_to_virt = |frame: PhysFrame| frame.start_address().as_u64() as *mut PageTable;
+        let mut entries = [None; 4];
+        let mut count = 0;
+        unsafe {
+            walk(&root, VirtAddrRange::TopRange, &phys_to_virt, |entry| {
+                entries[count] = Some(*entry);
+                count += 1;
+            });
+        }
+        assert_eq!(count, 2);
+        let (table, block) = (entries[0].unwrap(), entries[1].unwrap());
+        assert_eq!((table.level, table.kind), (0, EntryKind::Table));
+        assert_eq!(table.start, VirtAddr::new(0xffff_0080_0000_0000));
+        assert_eq!(table.last(), VirtAddr::new(0xffff_00ff_ffff_ffff));
+        assert_eq!((block.level, block.kind), (1, EntryKind::Block));
+        assert_eq!(block.start, VirtAddr::new(0xffff_0080_8000_0000));
+        assert_eq!(block.addr, PhysAddr::new(0x8000_0000));
+        assert!(table.contains(block.last()));
+    }
+}
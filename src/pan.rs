@@ -0,0 +1,183 @@
+//! Privileged Access Never (PAN) and User Access Override (UAO).
+//!
+//! With PAN set, EL1 data accesses to memory accessible at EL0 generate a Permission fault, so
+//! that the kernel only touches user memory where it means to. [`with_user_access`] clears PAN
+//! for the duration of a user-memory copy and restores it afterwards.
+//!
+//! PAN is an ARMv8.1 feature and UAO an ARMv8.2 feature. The helpers of this module check their
+//! support in ID_AA64MMFR1_EL1 and ID_AA64MMFR2_EL1 before touching PSTATE, so they are no-ops
+//! on cores without them.
+
+use crate::{barrier::isb, registers::*};
+
+/// SCTLR_EL1.SPAN: when clear, PSTATE.PAN is set on taking an exception to EL1.
+const SCTLR_SPAN: u64 = 1 << 23;
+
+/// The level of support for PAN, from ID_AA64MMFR1_EL1.PAN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PanSupport {
+    /// PAN is not implemented.
+    None,
+    /// PAN is implemented (FEAT_PAN).
+    Pan,
+    /// PAN is implemented with the AT S1E1RP and AT S1E1WP instructions (FEAT_PAN2).
+    Pan2,
+    /// PAN is implemented with SCTLR_EL1.EPAN (FEAT_PAN3).
+    Pan3,
+}
+
+impl PanSupport {
+    /// Decodes the value of ID_AA64MMFR1_EL1.
+    pub fn from_mmfr1(mmfr1: u64) -> Self {
+        match (mmfr1 >> 20) & 0xf {
+            0 => Self::None,
+            1 => Self::Pan,
+            2 => Self::Pan2,
+            _ => Self::Pan3,
+        }
+    }
+
+    /// Returns the PAN support of the current PE.
+    #[inline]
+    pub fn current() -> Self {
+        Self::from_mmfr1(ID_AA64MMFR1_EL1.get())
+    }
+}
+
+/// Returns whether the current PE implements PAN.
+#[inline]
+pub fn is_pan_supported() -> bool {
+    PanSupport::current() != PanSupport::None
+}
+
+/// Returns whether the current PE implements UAO.
+#[inline]
+pub fn is_uao_supported() -> bool {
+    ID_AA64MMFR2_EL1.read(ID_AA64MMFR2_EL1::UAO) != 0
+}
+
+/// Returns whether PAN is set in PSTATE. Always false if PAN is not implemented.
+#[inline]
+pub fn is_pan_enabled() -> bool {
+    is_pan_supported() && PAN.is_set(PAN::PAN)
+}
+
+/// Enables PAN: sets PSTATE.PAN, and clears SCTLR_EL1.SPAN so that PAN is set again on every
+/// exception taken to EL1.
+///
+/// Returns false, without changing anything, if PAN is not implemented.
+#[inline]
+pub fn enable_pan() -> bool {
+    if !is_pan_supported() {
+        return false;
+    }
+    SCTLR_EL1.set(SCTLR_EL1.get() & !SCTLR_SPAN);
+    unsafe { isb() };
+    PAN.write(PAN::PAN::SET);
+    true
+}
+
+/// Disables PAN: clears PSTATE.PAN, and sets SCTLR_EL1.SPAN so that exceptions taken to EL1
+/// leave PSTATE.PAN unchanged.
+#[inline]
+pub fn disable_pan() {
+    if !is_pan_supported() {
+        return;
+    }
+    PAN.write(PAN::PAN::CLEAR);
+    SCTLR_EL1.set(SCTLR_EL1.get() | SCTLR_SPAN);
+    unsafe { isb() };
+}
+
+/// Returns whether UAO is set in PSTATE. Always false if UAO is not implemented.
+#[inline]
+pub fn is_uao_enabled() -> bool {
+    is_uao_supported() && UAO.is_set(UAO::UAO)
+}
+
+/// Sets or clears PSTATE.UAO. With UAO set, the unprivileged load and store instructions
+/// executed at EL1 behave as privileged ones, e.g. to reuse user-access routines on kernel
+/// memory.
+///
+/// Returns false, without changing anything, if UAO is not implemented.
+#[inline]
+pub fn set_uao(enabled: bool) -> bool {
+    if !is_uao_supported() {
+        return false;
+    }
+    UAO.write(if enabled {
+        UAO::UAO::SET
+    } else {
+        UAO::UAO::CLEAR
+    });
+    true
+}
+
+/// Clears PSTATE.PAN while it is alive, and restores it when dropped.
+///
+/// Guards must be dropped in the reverse order of their creation. An exception taken while the
+/// guard is alive may set PAN again (see [`enable_pan`]); handlers returning with ERET restore
+/// the interrupted PSTATE.
+#[derive(Debug)]
+#[must_use = "PAN is restored when the guard is dropped"]
+pub struct UserAccessGuard {
+    saved: bool,
+}
+
+impl UserAccessGuard {
+    /// Saves PSTATE.PAN and clears it.
+    #[inline]
+    pub fn new() -> Self {
+        let saved = is_pan_enabled();
+        if saved {
+            PAN.write(PAN::PAN::CLEAR);
+        }
+        Self { saved }
+    }
+
+    /// Returns whether PAN was set when the guard was created.
+    pub fn saved(&self) -> bool {
+        self.saved
+    }
+}
+
+impl Default for UserAccessGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for UserAccessGuard {
+    #[inline]
+    fn drop(&mut self) {
+        if self.saved {
+            PAN.write(PAN::PAN::SET);
+        }
+    }
+}
+
+/// Runs `f` with PAN cleared, so that it can access user memory, and restores PAN afterwards.
+///
+/// Keep `f` to the accesses of user memory, e.g. the copy loop of `copy_from_user`: any bug
+/// dereferencing a user pointer within it goes unnoticed by PAN.
+#[inline]
+pub fn with_user_access<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = UserAccessGuard::new();
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_pan_support() {
+        assert_eq!(PanSupport::from_mmfr1(0x0000_0000), PanSupport::None);
+        assert_eq!(PanSupport::from_mmfr1(0x0010_0122), PanSupport::Pan);
+        assert_eq!(PanSupport::from_mmfr1(0x0030_0000), PanSupport::Pan3);
+        assert!(PanSupport::Pan2 > PanSupport::Pan);
+    }
+}
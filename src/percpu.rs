@@ -0,0 +1,148 @@
+//! Per-CPU data through the thread ID registers.
+//!
+//! TPIDR_EL1 is only accessible at EL1, so it is the natural place for the kernel to find the
+//! data of the current core. [`PerCpu`] expects it to hold the dense index of the core, e.g. from
+//! [`Topology::index`](crate::cpu::Topology::index), written once per core with
+//! [`set_cpu_index`] early during boot. TPIDR_EL0 is usually the thread pointer of user space.
+
+use crate::registers::*;
+
+/// Returns the value of TPIDR_EL1 as a pointer.
+#[inline]
+pub fn tpidr_el1<T>() -> *mut T {
+    TPIDR_EL1.get() as *mut T
+}
+
+/// Writes a pointer to TPIDR_EL1.
+///
+/// # Safety
+///
+/// Code reading TPIDR_EL1, e.g. [`PerCpu`] if it holds the core index, must agree with the new
+/// value.
+#[inline]
+pub unsafe fn set_tpidr_el1<T>(ptr: *mut T) {
+    TPIDR_EL1.set(ptr as u64);
+}
+
+/// Returns the value of TPIDR_EL0 as a pointer.
+#[inline]
+pub fn tpidr_el0<T>() -> *mut T {
+    TPIDR_EL0.get() as *mut T
+}
+
+/// Writes a pointer to TPIDR_EL0.
+///
+/// # Safety
+///
+/// Code reading TPIDR_EL0, e.g. the thread-local storage of the current thread, must agree with
+/// the new value.
+#[inline]
+pub unsafe fn set_tpidr_el0<T>(ptr: *mut T) {
+    TPIDR_EL0.set(ptr as u64);
+}
+
+/// Returns the index of the current core, as written by [`set_cpu_index`].
+#[inline]
+pub fn cpu_index() -> usize {
+    TPIDR_EL1.get() as usize
+}
+
+/// Writes the index of the current core to TPIDR_EL1.
+///
+/// # Safety
+///
+/// Each core must get a different index, which must not change while [`PerCpu`] values are in
+/// use.
+#[inline]
+pub unsafe fn set_cpu_index(index: usize) {
+    TPIDR_EL1.set(index as u64);
+}
+
+/// A value per core, for up to `N` cores, indexed by [`cpu_index`].
+///
+/// The slot of the current core is found from TPIDR_EL1. Values that are `Sync` are accessed
+/// with [`get`](PerCpu::get). Others, e.g. a `RefCell`, must only be accessed by their own core
+/// with [`current_unchecked`](PerCpu::current_unchecked), which is what makes `PerCpu` `Sync`.
+#[derive(Debug)]
+pub struct PerCpu<T, const N: usize> {
+    slots: [T; N],
+}
+
+unsafe impl<T: Send, const N: usize> Sync for PerCpu<T, N> {}
+
+impl<T, const N: usize> PerCpu<T, N> {
+    /// Creates per-CPU values with the given initial values, one per core index.
+    pub const fn new(slots: [T; N]) -> Self {
+        Self { slots }
+    }
+
+    /// Returns the value of the current core.
+    ///
+    /// Panics if the index of the current core is not below `N`.
+    #[inline]
+    pub fn get(&self) -> &T
+    where
+        T: Sync,
+    {
+        &self.slots[cpu_index()]
+    }
+
+    /// Returns the value of the current core, which doesn't need to be `Sync`.
+    ///
+    /// Panics if the index of the current core is not below `N`.
+    ///
+    /// # Safety
+    ///
+    /// The returned reference must only be used on the current core, i.e. the caller must not be
+    /// migrated to another core while using it, e.g. by running with preemption disabled.
+    #[inline]
+    pub unsafe fn current_unchecked(&self) -> &T {
+        &self.slots[cpu_index()]
+    }
+
+    /// Returns the value of the core with the given index, if it is below `N`.
+    pub fn get_for(&self, index: usize) -> Option<&T>
+    where
+        T: Sync,
+    {
+        self.slots.get(index)
+    }
+
+    /// Returns an iterator over the values of all cores, e.g. to sum per-CPU counters.
+    pub fn iter(&self) -> impl Iterator<Item = &T>
+    where
+        T: Sync,
+    {
+        self.slots.iter()
+    }
+
+    /// Returns mutable references to the values of all cores.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    pub fn test_per_cpu() {
+        static COUNTERS: PerCpu<AtomicUsize, 4> = PerCpu::new([
+            AtomicUsize::new(0),
+            AtomicUsize::new(1),
+            AtomicUsize::new(2),
+            AtomicUsize::new(3),
+        ]);
+        COUNTERS.get_for(2).unwrap().fetch_add(1, Ordering::Relaxed);
+        assert!(COUNTERS.get_for(4).is_none());
+        assert_eq!(
+            COUNTERS
+                .iter()
+                .map(|counter| counter.load(Ordering::Relaxed))
+                .sum::<usize>(),
+            7
+        );
+    }
+}
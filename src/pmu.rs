@@ -0,0 +1,274 @@
+//! Performance Monitors Extension (PMU) counters.
+//!
+//! A core implementing FEAT_PMUv3 ([`is_supported`]) has a 64-bit cycle counter and up to 31
+//! event counters ([`num_counters`]), which each count one [`Event`], e.g. the TLB refills or the
+//! cache misses of the code being measured. A [`PmuCounter`] is programmed through the event
+//! counter selection register PMSELR_EL0, and only counts once the PMU is enabled with
+//! [`enable`]. The cycle counter has its own functions, [`enable_cycle_counter`] and
+//! [`cycle_count`], and [`cycles`] measures a closure.
+//!
+//! The counters are per core, and count at EL0 and EL1 unless filtered with
+//! [`PmuCounter::enable_at`]. Selecting an event counter is not atomic: code using
+//! [`PmuCounter`] must not be preempted by other users of the PMU on the same core.
+
+use crate::{barrier::isb, registers::*};
+
+/// The bit of the cycle counter in PMCNTENSET_EL0, PMCNTENCLR_EL0 and PMOVSCLR_EL0.
+const CYCLE_COUNTER: u64 = 1 << 31;
+
+/// PMXEVTYPER_EL0 fields.
+const EVTYPER_P: u64 = 1 << 31;
+const EVTYPER_U: u64 = 1 << 30;
+
+/// An event counted by an event counter, identified by its number.
+///
+/// The constants are the common architectural and microarchitectural events, whose
+/// implementation is reported by PMCEID0_EL0 and PMCEID1_EL0. Other events, including the
+/// IMPLEMENTATION DEFINED ones from 0x40, are created with [`Event::new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Event(u16);
+
+impl Event {
+    /// Software increment, through PMSWINC_EL0.
+    pub const SW_INCR: Event = Event(0x00);
+    /// Level 1 instruction cache refill.
+    pub const L1I_CACHE_REFILL: Event = Event(0x01);
+    /// Level 1 instruction TLB refill.
+    pub const L1I_TLB_REFILL: Event = Event(0x02);
+    /// Level 1 data cache refill.
+    pub const L1D_CACHE_REFILL: Event = Event(0x03);
+    /// Level 1 data cache access.
+    pub const L1D_CACHE: Event = Event(0x04);
+    /// Level 1 data TLB refill.
+    pub const L1D_TLB_REFILL: Event = Event(0x05);
+    /// Instruction architecturally executed.
+    pub const INST_RETIRED: Event = Event(0x08);
+    /// Exception taken.
+    pub const EXC_TAKEN: Event = Event(0x09);
+    /// Branch mispredicted or not predicted.
+    pub const BR_MIS_PRED: Event = Event(0x10);
+    /// Cycle.
+    pub const CPU_CYCLES: Event = Event(0x11);
+    /// Predictable branch speculatively executed.
+    pub const BR_PRED: Event = Event(0x12);
+    /// Data memory access.
+    pub const MEM_ACCESS: Event = Event(0x13);
+    /// Level 1 instruction cache access.
+    pub const L1I_CACHE: Event = Event(0x14);
+    /// Level 2 data cache access.
+    pub const L2D_CACHE: Event = Event(0x16);
+    /// Level 2 data cache refill.
+    pub const L2D_CACHE_REFILL: Event = Event(0x17);
+    /// Bus access.
+    pub const BUS_ACCESS: Event = Event(0x19);
+    /// Instruction speculatively executed.
+    pub const INST_SPEC: Event = Event(0x1b);
+    /// No operation sent for execution, because of the frontend.
+    pub const STALL_FRONTEND: Event = Event(0x23);
+    /// No operation sent for execution, because of the backend.
+    pub const STALL_BACKEND: Event = Event(0x24);
+    /// Level 1 data TLB access.
+    pub const L1D_TLB: Event = Event(0x25);
+    /// Level 1 instruction TLB access.
+    pub const L1I_TLB: Event = Event(0x26);
+    /// Level 2 data TLB refill.
+    pub const L2D_TLB_REFILL: Event = Event(0x2d);
+    /// Level 2 data TLB access.
+    pub const L2D_TLB: Event = Event(0x2f);
+    /// Data TLB access with at least one translation table walk.
+    pub const DTLB_WALK: Event = Event(0x34);
+    /// Instruction TLB access with at least one translation table walk.
+    pub const ITLB_WALK: Event = Event(0x35);
+    /// Last level cache miss, read.
+    pub const LL_CACHE_MISS_RD: Event = Event(0x37);
+
+    /// Creates the event numbered `number`.
+    pub const fn new(number: u16) -> Self {
+        Event(number)
+    }
+
+    /// Returns the event number.
+    pub const fn number(self) -> u16 {
+        self.0
+    }
+}
+
+/// An error indicating that an event counter could not be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PmuError {
+    /// The core does not implement FEAT_PMUv3.
+    NotSupported,
+    /// The counter number is not implemented by the core.
+    InvalidIndex,
+}
+
+/// Returns whether the current core implements FEAT_PMUv3 (ID_AA64DFR0_EL1.PMUVer).
+#[inline]
+pub fn is_supported() -> bool {
+    !matches!(ID_AA64DFR0_EL1.read(ID_AA64DFR0_EL1::PMUVer), 0 | 0xf)
+}
+
+/// Returns the number of event counters of the current core (PMCR_EL0.N), without the cycle
+/// counter.
+#[inline]
+pub fn num_counters() -> usize {
+    PMCR_EL0.read(PMCR_EL0::N) as usize
+}
+
+/// Enables the counters enabled individually, with a 64-bit cycle counter (PMCR_EL0.{E, LC}).
+#[inline]
+pub fn enable() {
+    PMCR_EL0.modify(PMCR_EL0::E::SET + PMCR_EL0::LC::SET);
+    unsafe { isb() };
+}
+
+/// Stops all the counters (PMCR_EL0.E), without changing which are enabled individually.
+#[inline]
+pub fn disable() {
+    PMCR_EL0.modify(PMCR_EL0::E::CLEAR);
+    unsafe { isb() };
+}
+
+/// Resets the cycle counter and all the event counters to zero, and clears their overflow flags.
+#[inline]
+pub fn reset() {
+    PMCR_EL0.modify(PMCR_EL0::P::SET + PMCR_EL0::C::SET);
+    PMOVSCLR_EL0.set(u64::MAX);
+    unsafe { isb() };
+}
+
+/// An event counter of the current core.
+#[derive(Debug)]
+pub struct PmuCounter {
+    index: usize,
+}
+
+impl PmuCounter {
+    /// Returns event counter `index` of the current core.
+    pub fn new(index: usize) -> Result<Self, PmuError> {
+        if !is_supported() {
+            return Err(PmuError::NotSupported);
+        }
+        if index >= num_counters() {
+            return Err(PmuError::InvalidIndex);
+        }
+        Ok(PmuCounter { index })
+    }
+
+    /// Returns the counter number.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Resets the counter to zero and counts `event` at EL0 and EL1.
+    ///
+    /// Any previous event of the counter is replaced. The counter only counts while the PMU is
+    /// enabled, see [`enable`].
+    pub fn enable(&self, event: Event) {
+        self.enable_at(event, true, true)
+    }
+
+    /// Resets the counter to zero and counts `event` at EL0 if `el0` is set, and at EL1 if `el1`
+    /// is set.
+    pub fn enable_at(&self, event: Event, el0: bool, el1: bool) {
+        let mask = 1 << self.index;
+        PMCNTENCLR_EL0.set(mask);
+        self.select();
+        PMXEVTYPER_EL0.set(event_type(event, el0, el1));
+        PMXEVCNTR_EL0.set(0);
+        PMOVSCLR_EL0.set(mask);
+        PMCNTENSET_EL0.set(mask);
+        unsafe { isb() };
+    }
+
+    /// Stops the counter, keeping its value.
+    pub fn disable(&self) {
+        PMCNTENCLR_EL0.set(1 << self.index);
+        unsafe { isb() };
+    }
+
+    /// Returns the value of the counter.
+    pub fn read(&self) -> u64 {
+        self.select();
+        PMXEVCNTR_EL0.get()
+    }
+
+    /// Sets the value of the counter.
+    pub fn write(&self, value: u64) {
+        self.select();
+        PMXEVCNTR_EL0.set(value);
+    }
+
+    fn select(&self) {
+        PMSELR_EL0.write(PMSELR_EL0::SEL.val(self.index as u64));
+        unsafe { isb() };
+    }
+}
+
+/// Resets the cycle counter to zero and counts the cycles at EL0 and EL1.
+///
+/// The cycle counter only counts while the PMU is enabled, see [`enable`].
+#[inline]
+pub fn enable_cycle_counter() {
+    PMCCFILTR_EL0.set(0);
+    PMCR_EL0.modify(PMCR_EL0::C::SET + PMCR_EL0::LC::SET);
+    PMOVSCLR_EL0.set(CYCLE_COUNTER);
+    PMCNTENSET_EL0.set(CYCLE_COUNTER);
+    unsafe { isb() };
+}
+
+/// Stops the cycle counter, keeping its value.
+#[inline]
+pub fn disable_cycle_counter() {
+    PMCNTENCLR_EL0.set(CYCLE_COUNTER);
+    unsafe { isb() };
+}
+
+/// Returns the value of the cycle counter (PMCCNTR_EL0).
+#[inline]
+pub fn cycle_count() -> u64 {
+    PMCCNTR_EL0.get()
+}
+
+/// Resets the cycle counter to zero.
+#[inline]
+pub fn reset_cycle_counter() {
+    PMCCNTR_EL0.set(0);
+    unsafe { isb() };
+}
+
+/// Runs `f` and returns its result with the number of cycles it took, as counted by the cycle
+/// counter, which must be enabled.
+#[inline]
+pub fn cycles<R>(f: impl FnOnce() -> R) -> (R, u64) {
+    unsafe { isb() };
+    let start = cycle_count();
+    let ret = f();
+    unsafe { isb() };
+    (ret, cycle_count().wrapping_sub(start))
+}
+
+/// Computes the PMXEVTYPER_EL0 value counting `event` at the selected exception levels.
+fn event_type(event: Event, el0: bool, el1: bool) -> u64 {
+    let mut value = event.number() as u64;
+    if !el0 {
+        value |= EVTYPER_U;
+    }
+    if !el1 {
+        value |= EVTYPER_P;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_event_type() {
+        assert_eq!(event_type(Event::L1D_TLB_REFILL, true, true), 0x05);
+        assert_eq!(event_type(Event::DTLB_WALK, false, true), 0x4000_0034);
+        assert_eq!(event_type(Event::new(0xc0), true, false), 0x8000_00c0);
+        assert_eq!(Event::new(0x11), Event::CPU_CYCLES);
+    }
+}
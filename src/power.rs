@@ -0,0 +1,92 @@
+//! CPU power management helpers.
+
+use crate::{barrier::isb, registers::*};
+
+/// The EL1 system register context that is lost when a core enters a power-down state.
+///
+/// Used together with [`psci::cpu_suspend`](crate::psci::cpu_suspend). The layout is `repr(C)`
+/// with the translation registers first, so that low-level resume code can turn the MMU back on
+/// from this context before calling [`restore`](CpuSuspendContext::restore).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct CpuSuspendContext {
+    pub mair_el1: u64,
+    pub tcr_el1: u64,
+    pub ttbr0_el1: u64,
+    pub ttbr1_el1: u64,
+    pub sctlr_el1: u64,
+    pub vbar_el1: u64,
+    pub cpacr_el1: u64,
+    pub cntkctl_el1: u64,
+    pub contextidr_el1: u64,
+    pub tpidr_el0: u64,
+    pub tpidrro_el0: u64,
+    pub tpidr_el1: u64,
+    pub sp_el0: u64,
+}
+
+impl CpuSuspendContext {
+    /// Creates an empty context.
+    pub const fn new() -> Self {
+        Self {
+            mair_el1: 0,
+            tcr_el1: 0,
+            ttbr0_el1: 0,
+            ttbr1_el1: 0,
+            sctlr_el1: 0,
+            vbar_el1: 0,
+            cpacr_el1: 0,
+            cntkctl_el1: 0,
+            contextidr_el1: 0,
+            tpidr_el0: 0,
+            tpidrro_el0: 0,
+            tpidr_el1: 0,
+            sp_el0: 0,
+        }
+    }
+
+    /// Saves the current EL1 system register context.
+    pub fn save(&mut self) {
+        self.mair_el1 = MAIR_EL1.get();
+        self.tcr_el1 = TCR_EL1.get();
+        self.ttbr0_el1 = TTBR0_EL1.get();
+        self.ttbr1_el1 = TTBR1_EL1.get();
+        self.sctlr_el1 = SCTLR_EL1.get();
+        self.vbar_el1 = VBAR_EL1.get();
+        self.cpacr_el1 = CPACR_EL1.get();
+        self.cntkctl_el1 = CNTKCTL_EL1.get();
+        self.contextidr_el1 = CONTEXTIDR_EL1.get();
+        self.tpidr_el0 = TPIDR_EL0.get();
+        self.tpidrro_el0 = TPIDRRO_EL0.get();
+        self.tpidr_el1 = TPIDR_EL1.get();
+        self.sp_el0 = SP_EL0.get();
+    }
+
+    /// Restores the saved EL1 system register context.
+    ///
+    /// The translation registers are written before SCTLR_EL1, so this can also be used to turn
+    /// the MMU back on if the resume code runs with identity-mapped addresses.
+    ///
+    /// # Safety
+    ///
+    /// The context must have been saved on this core with [`save`](CpuSuspendContext::save), and
+    /// the saved translation tables must still be valid.
+    pub unsafe fn restore(&self) {
+        MAIR_EL1.set(self.mair_el1);
+        TCR_EL1.set(self.tcr_el1);
+        TTBR0_EL1.set(self.ttbr0_el1);
+        TTBR1_EL1.set(self.ttbr1_el1);
+        isb();
+        SCTLR_EL1.set(self.sctlr_el1);
+        isb();
+        VBAR_EL1.set(self.vbar_el1);
+        CPACR_EL1.set(self.cpacr_el1);
+        CNTKCTL_EL1.set(self.cntkctl_el1);
+        CONTEXTIDR_EL1.set(self.contextidr_el1);
+        TPIDR_EL0.set(self.tpidr_el0);
+        TPIDRRO_EL0.set(self.tpidrro_el0);
+        TPIDR_EL1.set(self.tpidr_el1);
+        SP_EL0.set(self.sp_el0);
+        isb();
+    }
+}
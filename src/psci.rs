@@ -0,0 +1,322 @@
+//! Power State Coordination Interface (PSCI) client.
+//!
+//! The calls follow the SMC Calling Convention and are issued through the conduit selected with
+//! [`set_conduit`] (`smc #0` by default). See the Arm Power State Coordination Interface
+//! specification (DEN0022) for the meaning of the arguments.
+
+use crate::{power::CpuSuspendContext, PhysAddr};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Returns the version of the implemented PSCI specification.
+pub const PSCI_VERSION: u32 = 0x8400_0000;
+/// Suspends execution on a core or higher level topology node.
+pub const CPU_SUSPEND: u32 = 0xC400_0001;
+/// Powers down the calling core.
+pub const CPU_OFF: u32 = 0x8400_0002;
+/// Powers up a core.
+pub const CPU_ON: u32 = 0xC400_0003;
+/// Returns the power state of a core or higher level topology node.
+pub const AFFINITY_INFO: u32 = 0xC400_0004;
+/// Shuts down the system.
+pub const SYSTEM_OFF: u32 = 0x8400_0008;
+/// Resets the system.
+pub const SYSTEM_RESET: u32 = 0x8400_0009;
+/// Queries whether a PSCI function is implemented, and its features.
+pub const PSCI_FEATURES: u32 = 0x8400_000A;
+/// Returns the true hardware state of a node in the power domain topology.
+pub const NODE_HW_STATE: u32 = 0xC400_000D;
+/// Suspends the whole system to RAM.
+pub const SYSTEM_SUSPEND: u32 = 0xC400_000E;
+/// Resets the system with an architectural or vendor-specific reset type.
+pub const SYSTEM_RESET2: u32 = 0xC400_0012;
+
+/// The instruction used to call into the PSCI firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Conduit {
+    /// Secure Monitor Call, for firmware running at EL3.
+    Smc = 0,
+    /// Hypervisor Call, for firmware (or a hypervisor) running at EL2.
+    Hvc = 1,
+}
+
+static CONDUIT: AtomicU8 = AtomicU8::new(Conduit::Smc as u8);
+
+/// Selects the conduit used by all PSCI and [SMCCC](crate::smccc) calls, usually as described by
+/// the `method` property of the `/psci` device tree node.
+pub fn set_conduit(conduit: Conduit) {
+    CONDUIT.store(conduit as u8, Ordering::Relaxed);
+}
+
+/// Returns the conduit used by all PSCI and [SMCCC](crate::smccc) calls.
+pub fn conduit() -> Conduit {
+    match CONDUIT.load(Ordering::Relaxed) {
+        0 => Conduit::Smc,
+        _ => Conduit::Hvc,
+    }
+}
+
+/// The error codes returned by the PSCI functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsciError {
+    /// The function is not implemented by the firmware.
+    NotSupported,
+    /// An argument is invalid, e.g. an unknown power state or target core.
+    InvalidParameters,
+    /// The request was denied by the firmware.
+    Denied,
+    /// The target core is already on.
+    AlreadyOn,
+    /// A `CPU_ON` request for the target core is still pending.
+    OnPending,
+    /// The firmware failed to complete the request.
+    InternalFailure,
+    /// The target core or node is not present.
+    NotPresent,
+    /// The target core or node is disabled.
+    Disabled,
+    /// The entry point address is invalid.
+    InvalidAddress,
+    /// A negative return value not defined by the specification.
+    Unknown(i32),
+}
+
+impl PsciError {
+    /// Converts the raw return value of a PSCI call into a `Result`.
+    ///
+    /// Non-negative values are returned unchanged.
+    pub fn check(ret: u64) -> Result<u64, PsciError> {
+        let ret = ret as i32;
+        match ret {
+            0.. => Ok(ret as u64),
+            -1 => Err(PsciError::NotSupported),
+            -2 => Err(PsciError::InvalidParameters),
+            -3 => Err(PsciError::Denied),
+            -4 => Err(PsciError::AlreadyOn),
+            -5 => Err(PsciError::OnPending),
+            -6 => Err(PsciError::InternalFailure),
+            -7 => Err(PsciError::NotPresent),
+            -8 => Err(PsciError::Disabled),
+            -9 => Err(PsciError::InvalidAddress),
+            other => Err(PsciError::Unknown(other)),
+        }
+    }
+}
+
+/// Issues a PSCI call with up to three arguments and returns the raw value of `x0`.
+///
+/// # Safety
+///
+/// Some PSCI functions power down cores or transfer control to an arbitrary entry point.
+#[inline]
+pub unsafe fn call(function: u32, arg0: u64, arg1: u64, arg2: u64) -> u64 {
+    crate::smccc::call(function, &[arg0, arg1, arg2])[0]
+}
+
+/// Returns the (major, minor) version of the PSCI implementation.
+pub fn version() -> (u16, u16) {
+    let version = unsafe { call(PSCI_VERSION, 0, 0, 0) };
+    ((version >> 16) as u16, version as u16)
+}
+
+/// Suspends the calling core (CPU_SUSPEND).
+///
+/// `ctx` is saved before the call, and `ctx_phys`, its physical address, is passed as the context
+/// ID. If the requested `power_state` is a power-down state, the call does not return: the core
+/// is woken up at the physical address `entry` with the MMU off and `ctx_phys` in `x0`, and the
+/// resume code must call [`CpuSuspendContext::restore`] before returning to the kernel. For
+/// standby states the call returns `Ok(())` once the core is woken up.
+///
+/// # Safety
+///
+/// `entry` must point to valid resume code, `ctx_phys` must be the physical address of `ctx`, and
+/// `ctx` must stay alive until it has been restored.
+pub unsafe fn cpu_suspend(
+    power_state: u32,
+    entry: PhysAddr,
+    ctx: &mut CpuSuspendContext,
+    ctx_phys: PhysAddr,
+) -> Result<(), PsciError> {
+    ctx.save();
+    let ret = call(
+        CPU_SUSPEND,
+        power_state as u64,
+        entry.as_u64(),
+        ctx_phys.as_u64(),
+    );
+    PsciError::check(ret).map(|_| ())
+}
+
+/// Powers down the calling core (CPU_OFF).
+///
+/// Only returns on failure, e.g. `PsciError::Denied` if the core is the last one running a
+/// Trusted OS that can't migrate.
+///
+/// # Safety
+///
+/// The state of the core is lost: its caches must have been cleaned, and it must have been
+/// removed from the coherency domain and from the scheduling of the kernel.
+pub unsafe fn cpu_off() -> PsciError {
+    match PsciError::check(call(CPU_OFF, 0, 0, 0)) {
+        Ok(_) => PsciError::InternalFailure,
+        Err(err) => err,
+    }
+}
+
+/// Powers up the core with the MPIDR affinity value `target_cpu` (CPU_ON).
+///
+/// The core starts at the physical address `entry` with the MMU off, in the Exception level of
+/// the caller, with `context_id` in `x0`.
+///
+/// # Safety
+///
+/// `entry` must point to valid startup code, which must find everything it needs, e.g. a stack,
+/// from `context_id` or from memory visible with the MMU and caches off.
+pub unsafe fn cpu_on(target_cpu: u64, entry: PhysAddr, context_id: u64) -> Result<(), PsciError> {
+    let ret = call(CPU_ON, target_cpu, entry.as_u64(), context_id);
+    PsciError::check(ret).map(|_| ())
+}
+
+/// The power state of a node in the power domain topology, returned by [`affinity_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AffinityState {
+    /// At least one core of the node is on.
+    On,
+    /// All the cores of the node are off.
+    Off,
+    /// A core of the node is being powered up.
+    OnPending,
+}
+
+/// Returns the power state of the node at `lowest_affinity_level` containing the core with the
+/// MPIDR affinity value `target_affinity` (AFFINITY_INFO).
+pub fn affinity_info(
+    target_affinity: u64,
+    lowest_affinity_level: u32,
+) -> Result<AffinityState, PsciError> {
+    let ret = unsafe {
+        call(
+            AFFINITY_INFO,
+            target_affinity,
+            lowest_affinity_level as u64,
+            0,
+        )
+    };
+    match PsciError::check(ret)? {
+        0 => Ok(AffinityState::On),
+        1 => Ok(AffinityState::Off),
+        2 => Ok(AffinityState::OnPending),
+        other => Err(PsciError::Unknown(other as i32)),
+    }
+}
+
+/// Queries the features of the PSCI function `function` (PSCI_FEATURES).
+///
+/// Returns `Err(PsciError::NotSupported)` if the function is not implemented. For `CPU_SUSPEND`
+/// the returned flags describe the power state parameter format, and are zero for most other
+/// functions.
+pub fn features(function: u32) -> Result<u32, PsciError> {
+    let ret = unsafe { call(PSCI_FEATURES, function as u64, 0, 0) };
+    PsciError::check(ret).map(|flags| flags as u32)
+}
+
+/// Returns whether the PSCI function `function` is implemented by the firmware.
+pub fn is_supported(function: u32) -> bool {
+    features(function).is_ok()
+}
+
+/// The hardware state of a node in the power domain topology, returned by [`node_hw_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeHwState {
+    /// The node is powered on.
+    On,
+    /// The node is powered off.
+    Off,
+    /// The node is in a standby or retention state.
+    Standby,
+}
+
+/// Returns the hardware power state of the node at `power_level` containing the core with the
+/// MPIDR affinity value `target_cpu` (NODE_HW_STATE).
+pub fn node_hw_state(target_cpu: u64, power_level: u32) -> Result<NodeHwState, PsciError> {
+    let ret = unsafe { call(NODE_HW_STATE, target_cpu, power_level as u64, 0) };
+    match PsciError::check(ret)? {
+        0 => Ok(NodeHwState::On),
+        1 => Ok(NodeHwState::Off),
+        2 => Ok(NodeHwState::Standby),
+        other => Err(PsciError::Unknown(other as i32)),
+    }
+}
+
+/// Suspends the whole system to RAM (SYSTEM_SUSPEND).
+///
+/// Must be called on the last running core. On success the call does not return and the system
+/// resumes at `entry` on the calling core with `ctx_phys`, the physical address of `ctx`, in
+/// `x0`, exactly like a power-down [`cpu_suspend`].
+///
+/// # Safety
+///
+/// See [`cpu_suspend`].
+pub unsafe fn system_suspend(
+    entry: PhysAddr,
+    ctx: &mut CpuSuspendContext,
+    ctx_phys: PhysAddr,
+) -> Result<(), PsciError> {
+    ctx.save();
+    let ret = call(SYSTEM_SUSPEND, entry.as_u64(), ctx_phys.as_u64(), 0);
+    PsciError::check(ret).map(|_| ())
+}
+
+/// Shuts down the system (SYSTEM_OFF). Only returns on failure.
+pub fn system_off() -> PsciError {
+    match PsciError::check(unsafe { call(SYSTEM_OFF, 0, 0, 0) }) {
+        Ok(_) => PsciError::InternalFailure,
+        Err(err) => err,
+    }
+}
+
+/// Resets the system with a cold reset (SYSTEM_RESET). Only returns on failure.
+pub fn system_reset() -> PsciError {
+    match PsciError::check(unsafe { call(SYSTEM_RESET, 0, 0, 0) }) {
+        Ok(_) => PsciError::InternalFailure,
+        Err(err) => err,
+    }
+}
+
+/// The reset types accepted by [`system_reset2`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+    /// Architectural warm reset, which preserves the content of memory.
+    WarmReset,
+    /// A vendor-specific reset type, in the range `0..0x8000_0000`.
+    Vendor(u32),
+}
+
+impl ResetType {
+    /// Returns the raw `reset_type` argument of SYSTEM_RESET2, or `None` for a vendor-specific
+    /// reset type out of its range.
+    pub fn bits(self) -> Option<u32> {
+        match self {
+            ResetType::WarmReset => Some(0),
+            ResetType::Vendor(kind) if kind < 1 << 31 => Some((1 << 31) | kind),
+            ResetType::Vendor(_) => None,
+        }
+    }
+}
+
+/// Resets the system with the given reset type (SYSTEM_RESET2).
+///
+/// `cookie` is passed unchanged to vendor-specific reset types. Only returns on failure, with
+/// `PsciError::InvalidParameters` and without calling the firmware for a vendor-specific reset
+/// type out of its range.
+pub fn system_reset2(reset_type: ResetType, cookie: u64) -> PsciError {
+    let bits = match reset_type.bits() {
+        Some(bits) => bits,
+        None => return PsciError::InvalidParameters,
+    };
+    let ret = unsafe { call(SYSTEM_RESET2, bits as u64, cookie, 0) };
+    match PsciError::check(ret) {
+        Ok(_) => PsciError::InternalFailure,
+        Err(err) => err,
+    }
+}
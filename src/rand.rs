@@ -0,0 +1,142 @@
+//! Random numbers from the PE's random number generator (FEAT_RNG).
+//!
+//! ARMv8.5 adds two registers returning a 64-bit random number on each read: RNDR, from a
+//! deterministic generator reseeded at an IMPLEMENTATION DEFINED rate, and RNDRRS, reseeded
+//! before each read. A read fails if the generator can't produce a number in a reasonable time,
+//! so the helpers of this module retry up to [`RETRIES`] times before giving up. They return
+//! `None` on cores without FEAT_RNG, where the registers are UNDEFINED.
+
+use crate::registers::*;
+
+/// The number of failed reads after which [`random_u64`] and [`reseeded_u64`] give up.
+pub const RETRIES: usize = 10;
+
+/// Returns whether the current PE implements RNDR and RNDRRS (ID_AA64ISAR0_EL1.RNDR).
+#[inline]
+pub fn is_supported() -> bool {
+    ID_AA64ISAR0_EL1.read(ID_AA64ISAR0_EL1::RNDR) != 0
+}
+
+/// Reads a random number register once, by its encoding so that no architecture extension is
+/// needed to assemble it. The read sets PSTATE.Z on failure.
+macro_rules! read_rndr {
+    ($encoding:literal) => {
+        match () {
+            #[cfg(target_arch = "aarch64")]
+            () => {
+                let value: u64;
+                let ok: u64;
+                unsafe {
+                    core::arch::asm!(
+                        concat!("mrs {value}, ", $encoding),
+                        "cset {ok}, ne",
+                        value = out(reg) value,
+                        ok = out(reg) ok,
+                        options(nomem, nostack)
+                    )
+                };
+                if ok != 0 {
+                    Some(value)
+                } else {
+                    None
+                }
+            }
+
+            #[cfg(not(target_arch = "aarch64"))]
+            () => unimplemented!(),
+        }
+    };
+}
+
+/// Reads RNDR once, without checking that it is implemented.
+///
+/// # Safety
+///
+/// The PE must implement FEAT_RNG.
+#[inline]
+pub unsafe fn rndr() -> Option<u64> {
+    read_rndr!("S3_3_C2_C4_0")
+}
+
+/// Reads RNDRRS once, without checking that it is implemented.
+///
+/// # Safety
+///
+/// The PE must implement FEAT_RNG.
+#[inline]
+pub unsafe fn rndrrs() -> Option<u64> {
+    read_rndr!("S3_3_C2_C4_1")
+}
+
+/// Calls `read` until it succeeds, at most `RETRIES + 1` times.
+fn retry(mut read: impl FnMut() -> Option<u64>) -> Option<u64> {
+    (0..=RETRIES).find_map(|_| read())
+}
+
+/// Returns a random number from RNDR, or `None` if FEAT_RNG is not implemented or the
+/// generator keeps failing.
+#[inline]
+pub fn random_u64() -> Option<u64> {
+    if !is_supported() {
+        return None;
+    }
+    retry(|| unsafe { rndr() })
+}
+
+/// Returns a random number from RNDRRS, freshly reseeded from the entropy source, or `None` if
+/// FEAT_RNG is not implemented or the generator keeps failing.
+///
+/// Slower than [`random_u64`], for seeding other generators.
+#[inline]
+pub fn reseeded_u64() -> Option<u64> {
+    if !is_supported() {
+        return None;
+    }
+    retry(|| unsafe { rndrrs() })
+}
+
+/// Fills `buf` with random bytes from RNDRRS.
+///
+/// Returns false if FEAT_RNG is not implemented or the generator keeps failing, in which case
+/// `buf` may be partially filled.
+pub fn fill_seed(buf: &mut [u8]) -> bool {
+    for chunk in buf.chunks_mut(8) {
+        match reseeded_u64() {
+            Some(value) => chunk.copy_from_slice(&value.to_ne_bytes()[..chunk.len()]),
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_retry() {
+        let mut reads = 0;
+        assert_eq!(
+            retry(|| {
+                reads += 1;
+                if reads == 3 {
+                    Some(42)
+                } else {
+                    None
+                }
+            }),
+            Some(42)
+        );
+        assert_eq!(reads, 3);
+
+        reads = 0;
+        assert_eq!(
+            retry(|| {
+                reads += 1;
+                None
+            }),
+            None
+        );
+        assert_eq!(reads, RETRIES + 1);
+    }
+}
@@ -0,0 +1,33 @@
+//! Current Cache Size ID Register - EL1
+//!
+//! Provides information about the architecture of the currently selected cache, see
+//! [`CSSELR_EL1`](super::CSSELR_EL1).
+//!
+//! The fields are those of the format without FEAT_CCIDX, see
+//! [`cache::topology`](crate::cache::topology) for both formats.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub CCSIDR_EL1 [
+        /// Number of sets in cache, minus 1.
+        NumSets OFFSET(13) NUMBITS(15) [],
+
+        /// Associativity of cache, minus 1.
+        Associativity OFFSET(3) NUMBITS(10) [],
+
+        /// Log2 of the number of bytes in cache line, minus 4.
+        LineSize OFFSET(0) NUMBITS(3) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = CCSIDR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "CCSIDR_EL1", "x");
+}
+
+pub const CCSIDR_EL1: Reg = Reg {};
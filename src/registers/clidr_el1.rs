@@ -0,0 +1,43 @@
+//! Cache Level ID Register - EL1
+//!
+//! Identifies the type of cache, or caches, that are implemented at each level, up to a maximum
+//! of seven levels, and the Level of Coherence and Level of Unification for the cache hierarchy.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub CLIDR_EL1 [
+        /// Inner cache boundary. The lowest level of the inner cache hierarchy, 0 if not
+        /// disclosed.
+        ICB OFFSET(30) NUMBITS(3) [],
+
+        /// Level of Unification Uniprocessor for the cache hierarchy.
+        LoUU OFFSET(27) NUMBITS(3) [],
+
+        /// Level of Coherence for the cache hierarchy.
+        LoC OFFSET(24) NUMBITS(3) [],
+
+        /// Level of Unification Inner Shareable for the cache hierarchy.
+        LoUIS OFFSET(21) NUMBITS(3) [],
+
+        /// Cache type fields, 3 bits for each of the levels 1 to 7. Possible values are:
+        ///
+        /// 0b000 No cache
+        /// 0b001 Instruction cache only
+        /// 0b010 Data cache only
+        /// 0b011 Separate instruction and data caches
+        /// 0b100 Unified cache
+        Ctype OFFSET(0) NUMBITS(21) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = CLIDR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "CLIDR_EL1", "x");
+}
+
+pub const CLIDR_EL1: Reg = Reg {};
@@ -0,0 +1,24 @@
+//! Counter-timer Physical Timer CompareValue register - EL0
+//!
+//! Holds the compare value for the EL1 physical timer. When CNTP_CTL_EL0.ENABLE is 1, the timer
+//! condition is met when CNTPCT_EL0 is greater than or equal to this value.
+
+use tock_registers::interfaces::{Readable, Writeable};
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ();
+
+    sys_coproc_read_raw!(u64, "CNTP_CVAL_EL0", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = ();
+
+    sys_coproc_write_raw!(u64, "CNTP_CVAL_EL0", "x");
+}
+
+pub const CNTP_CVAL_EL0: Reg = Reg {};
@@ -0,0 +1,33 @@
+//! Context ID Register - EL1
+//!
+//! Identifies the current Process Identifier, for use by debug and trace logic.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub CONTEXTIDR_EL1 [
+        /// Process Identifier.
+        PROCID OFFSET(0) NUMBITS(32) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = CONTEXTIDR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "CONTEXTIDR_EL1", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = CONTEXTIDR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "CONTEXTIDR_EL1", "x");
+}
+
+pub const CONTEXTIDR_EL1: Reg = Reg {};
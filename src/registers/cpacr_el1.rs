@@ -0,0 +1,51 @@
+//! Architectural Feature Access Control Register - EL1
+//!
+//! Controls access to trace, SVE, Advanced SIMD and floating-point functionality.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub CPACR_EL1 [
+        /// Traps EL0 and EL1 System register accesses to all implemented trace registers to
+        /// EL1.
+        TTA OFFSET(28) NUMBITS(1) [],
+
+        /// Traps execution at EL1 and EL0 of instructions that access the Advanced SIMD and
+        /// floating-point registers to EL1.
+        FPEN OFFSET(20) NUMBITS(2) [
+            TrapEl0El1 = 0b00,
+            TrapEl0 = 0b01,
+            TrapAll = 0b10,
+            TrapNothing = 0b11
+        ],
+
+        /// Traps execution at EL1 and EL0 of SVE instructions to EL1.
+        ZEN OFFSET(16) NUMBITS(2) [
+            TrapEl0El1 = 0b00,
+            TrapEl0 = 0b01,
+            TrapAll = 0b10,
+            TrapNothing = 0b11
+        ]
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = CPACR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "CPACR_EL1", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = CPACR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "CPACR_EL1", "x");
+}
+
+pub const CPACR_EL1: Reg = Reg {};
@@ -0,0 +1,40 @@
+//! Cache Size Selection Register - EL1
+//!
+//! Selects the current Cache Size ID Register, [`CCSIDR_EL1`](super::CCSIDR_EL1), by
+//! specifying the required cache level and the cache type.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub CSSELR_EL1 [
+        /// Cache level of required cache, minus 1.
+        Level OFFSET(1) NUMBITS(3) [],
+
+        /// Instruction not Data bit.
+        InD OFFSET(0) NUMBITS(1) [
+            Data = 0,
+            Instruction = 1
+        ]
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = CSSELR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "CSSELR_EL1", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = CSSELR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "CSSELR_EL1", "x");
+}
+
+pub const CSSELR_EL1: Reg = Reg {};
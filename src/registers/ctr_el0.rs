@@ -23,6 +23,14 @@ use tock_registers::{
 
 register_bitfields! {u64,
     pub CTR_EL0 [
+        /// Instruction cache invalidation to the Point of Unification is not
+        /// required for data to instruction coherence.
+        DIC OFFSET(29) NUMBITS(1) [],
+
+        /// Data cache clean to the Point of Unification is not required for
+        /// instruction to data coherence.
+        IDC OFFSET(28) NUMBITS(1) [],
+
         /// Log2 of the number of words in the smallest cache line of all the
         /// data caches and unified caches that are controlled by the PE.
         DminLine OFFSET(16) NUMBITS(4) [],
@@ -0,0 +1,27 @@
+//! Data Cache Zero ID register
+//!
+//! Indicates the block size that is written with byte values of 0 by the DC ZVA instruction,
+//! and whether the instruction is permitted.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub DCZID_EL0 [
+        /// Data Zero Prohibited. When set, DC ZVA is not permitted.
+        DZP OFFSET(4) NUMBITS(1) [],
+
+        /// Log2 of the block size in words. The maximum size supported is 2KB (value == 9).
+        BS OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = DCZID_EL0::Register;
+
+    sys_coproc_read_raw!(u64, "DCZID_EL0", "x");
+}
+
+pub const DCZID_EL0: Reg = Reg {};
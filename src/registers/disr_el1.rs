@@ -0,0 +1,47 @@
+//! Deferred Interrupt Status Register - EL1
+//!
+//! Records an SError deferred by an Error Synchronization Barrier while SErrors were masked
+//! (FEAT_RAS). Accessed by its encoding, `S3_0_C12_C1_1`, so that no architecture extension is
+//! needed to assemble it.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub DISR_EL1 [
+        /// Set when an SError was deferred.
+        A OFFSET(31) NUMBITS(1) [],
+
+        /// The syndrome is IMPLEMENTATION DEFINED.
+        IDS OFFSET(24) NUMBITS(1) [],
+
+        /// Asynchronous Error Type.
+        AET OFFSET(10) NUMBITS(3) [],
+
+        /// External abort type.
+        EA OFFSET(9) NUMBITS(1) [],
+
+        /// Data Fault Status Code.
+        DFSC OFFSET(0) NUMBITS(6) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = DISR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C12_C1_1", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = DISR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "S3_0_C12_C1_1", "x");
+}
+
+pub const DISR_EL1: Reg = Reg {};
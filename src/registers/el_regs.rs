@@ -0,0 +1,126 @@
+//! The translation and system control registers of the current Exception level.
+//!
+//! Under the Virtualization Host Extensions (FEAT_VHE), setting HCR_EL2.E2H redirects the
+//! accesses to the `*_EL1` encodings made at EL2 to the `*_EL2` registers, so that a kernel
+//! written for EL1 runs unchanged at EL2. Without E2H, EL2 has its own registers, with its own
+//! encodings. [`current_el_regs`] selects the right encodings for the current Exception level,
+//! and [`ElRegs::El12`] gives a VHE host access to the EL1 registers of its guests.
+//!
+//! The values are raw: at EL2 without E2H, TCR_EL2 and SCTLR_EL2 have the layout of the EL2
+//! registers, not that of `TCR_EL1` and `SCTLR_EL1`.
+
+use super::{CurrentEL, HCR_EL2};
+use tock_registers::interfaces::Readable;
+
+/// The register encodings to use for TTBR0, TTBR1, TCR, MAIR and SCTLR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElRegs {
+    /// The `*_EL1` encodings: the EL1 registers at EL1, the EL2 registers at EL2 with E2H.
+    El1,
+    /// The `*_EL2` encodings, at EL2 without E2H. TTBR1_EL2 is only used with E2H.
+    El2,
+    /// The `*_EL12` encodings, accessing the EL1 registers from EL2 with E2H.
+    El12,
+}
+
+/// Returns the registers controlling the translation regime of the current Exception level,
+/// from CurrentEL and HCR_EL2.E2H.
+///
+/// Panics at EL3.
+#[inline]
+pub fn current_el_regs() -> ElRegs {
+    match CurrentEL.read(CurrentEL::EL) {
+        0 | 1 => ElRegs::El1,
+        2 if HCR_EL2.is_set(HCR_EL2::E2H) => ElRegs::El1,
+        2 => ElRegs::El2,
+        _ => panic!("EL3 is not supported"),
+    }
+}
+
+macro_rules! el_regs {
+    ($(
+        $name:literal, $get:ident, $set:ident => $el1:literal, $el2:literal, $el12:literal;
+    )*) => {
+        impl ElRegs {
+            $(
+                #[doc = concat!("Reads ", $name, ".")]
+                #[inline]
+                pub fn $get(self) -> u64 {
+                    match () {
+                        #[cfg(target_arch = "aarch64")]
+                        () => {
+                            let value;
+                            unsafe {
+                                match self {
+                                    ElRegs::El1 => core::arch::asm!(
+                                        concat!("mrs {}, ", $el1),
+                                        out(reg) value,
+                                        options(nomem, nostack)
+                                    ),
+                                    ElRegs::El2 => core::arch::asm!(
+                                        concat!("mrs {}, ", $el2),
+                                        out(reg) value,
+                                        options(nomem, nostack)
+                                    ),
+                                    ElRegs::El12 => core::arch::asm!(
+                                        concat!("mrs {}, ", $el12),
+                                        out(reg) value,
+                                        options(nomem, nostack)
+                                    ),
+                                }
+                            }
+                            value
+                        }
+
+                        #[cfg(not(target_arch = "aarch64"))]
+                        () => unimplemented!(),
+                    }
+                }
+
+                #[doc = concat!("Writes ", $name, ".")]
+                ///
+                /// # Safety
+                ///
+                /// The new value changes the translation or the behavior of the PE, see the
+                /// requirements of the corresponding EL1 register.
+                #[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+                #[inline]
+                pub unsafe fn $set(self, value: u64) {
+                    match () {
+                        #[cfg(target_arch = "aarch64")]
+                        () => match self {
+                            ElRegs::El1 => core::arch::asm!(
+                                concat!("msr ", $el1, ", {}"),
+                                in(reg) value,
+                                options(nomem, nostack)
+                            ),
+                            ElRegs::El2 => core::arch::asm!(
+                                concat!("msr ", $el2, ", {}"),
+                                in(reg) value,
+                                options(nomem, nostack)
+                            ),
+                            ElRegs::El12 => core::arch::asm!(
+                                concat!("msr ", $el12, ", {}"),
+                                in(reg) value,
+                                options(nomem, nostack)
+                            ),
+                        },
+
+                        #[cfg(not(target_arch = "aarch64"))]
+                        () => unimplemented!(),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+// TTBR1_EL2 and the EL12 registers by their encodings, so that no architecture extension is
+// needed to assemble them
+el_regs! {
+    "TTBR0", ttbr0, set_ttbr0 => "TTBR0_EL1", "TTBR0_EL2", "S3_5_C2_C0_0";
+    "TTBR1", ttbr1, set_ttbr1 => "TTBR1_EL1", "S3_4_C2_C0_1", "S3_5_C2_C0_1";
+    "TCR", tcr, set_tcr => "TCR_EL1", "TCR_EL2", "S3_5_C2_C0_2";
+    "MAIR", mair, set_mair => "MAIR_EL1", "MAIR_EL2", "S3_5_C10_C2_0";
+    "SCTLR", sctlr, set_sctlr => "SCTLR_EL1", "SCTLR_EL2", "S3_5_C1_C0_0";
+}
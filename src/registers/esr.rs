@@ -0,0 +1,517 @@
+//! Decoding of the Exception Syndrome Register - EL1
+//!
+//! [`EsrEl1`] splits a syndrome into its exception class and the class-specific syndrome (ISS),
+//! so exception handlers can match on typed values. Only the classes that can be taken to EL1
+//! from AArch64 are named, the others are reported as [`ExceptionClass::Other`].
+
+use super::ESR_EL1;
+use core::fmt;
+use tock_registers::interfaces::Readable;
+
+/// The exception class (EC) of a syndrome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionClass {
+    /// Unknown reason, e.g. an undefined instruction.
+    Unknown,
+    /// Trapped WFI or WFE instruction.
+    TrappedWfx,
+    /// Access to SVE, Advanced SIMD or floating-point functionality trapped by CPACR_EL1.FPEN.
+    TrappedFp,
+    /// Branch Target Exception.
+    BranchTarget,
+    /// Illegal Execution state.
+    IllegalExecutionState,
+    /// SVC instruction.
+    Svc,
+    /// HVC instruction.
+    Hvc,
+    /// SMC instruction.
+    Smc,
+    /// Trapped MSR, MRS or system instruction.
+    TrappedMsrMrs,
+    /// Access to SVE functionality trapped by CPACR_EL1.ZEN.
+    TrappedSve,
+    /// Pointer authentication failure.
+    PointerAuth,
+    /// Instruction Abort from a lower Exception level.
+    InstrAbortLowerEl,
+    /// Instruction Abort taken without a change in Exception level.
+    InstrAbortCurrentEl,
+    /// PC alignment fault.
+    PcAlignment,
+    /// Data Abort from a lower Exception level.
+    DataAbortLowerEl,
+    /// Data Abort taken without a change in Exception level.
+    DataAbortCurrentEl,
+    /// SP alignment fault.
+    SpAlignment,
+    /// Trapped floating-point exception.
+    FpException,
+    /// SError interrupt.
+    SError,
+    /// Breakpoint exception from a lower Exception level.
+    BreakpointLowerEl,
+    /// Breakpoint exception taken without a change in Exception level.
+    BreakpointCurrentEl,
+    /// Software Step exception from a lower Exception level.
+    SoftwareStepLowerEl,
+    /// Software Step exception taken without a change in Exception level.
+    SoftwareStepCurrentEl,
+    /// Watchpoint exception from a lower Exception level.
+    WatchpointLowerEl,
+    /// Watchpoint exception taken without a change in Exception level.
+    WatchpointCurrentEl,
+    /// BRK instruction.
+    Brk,
+    /// Any other exception class.
+    Other(u8),
+}
+
+impl ExceptionClass {
+    /// Decodes the 6-bit EC field.
+    pub fn from_bits(ec: u8) -> Self {
+        use ExceptionClass::*;
+        match ec {
+            0x00 => Unknown,
+            0x01 => TrappedWfx,
+            0x07 => TrappedFp,
+            0x0d => BranchTarget,
+            0x0e => IllegalExecutionState,
+            0x15 => Svc,
+            0x16 => Hvc,
+            0x17 => Smc,
+            0x18 => TrappedMsrMrs,
+            0x19 => TrappedSve,
+            0x1c => PointerAuth,
+            0x20 => InstrAbortLowerEl,
+            0x21 => InstrAbortCurrentEl,
+            0x22 => PcAlignment,
+            0x24 => DataAbortLowerEl,
+            0x25 => DataAbortCurrentEl,
+            0x26 => SpAlignment,
+            0x2c => FpException,
+            0x2f => SError,
+            0x30 => BreakpointLowerEl,
+            0x31 => BreakpointCurrentEl,
+            0x32 => SoftwareStepLowerEl,
+            0x33 => SoftwareStepCurrentEl,
+            0x34 => WatchpointLowerEl,
+            0x35 => WatchpointCurrentEl,
+            0x3c => Brk,
+            ec => Other(ec),
+        }
+    }
+}
+
+/// The fault status code (DFSC or IFSC) of an abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultStatus {
+    /// Address size fault at the given lookup level.
+    AddressSize(u8),
+    /// Translation fault at the given lookup level.
+    Translation(u8),
+    /// Access flag fault at the given lookup level.
+    AccessFlag(u8),
+    /// Permission fault at the given lookup level.
+    Permission(u8),
+    /// Synchronous External abort, not on a translation table walk.
+    SynchronousExternal,
+    /// Synchronous External abort on a translation table walk at the given lookup level.
+    SynchronousExternalOnWalk(u8),
+    /// Synchronous parity or ECC error, not on a translation table walk.
+    SynchronousParity,
+    /// Synchronous parity or ECC error on a translation table walk at the given lookup level.
+    SynchronousParityOnWalk(u8),
+    /// Alignment fault.
+    Alignment,
+    /// TLB conflict abort.
+    TlbConflict,
+    /// Unsupported atomic hardware update fault.
+    UnsupportedAtomicUpdate,
+    /// Any other fault status code.
+    Other(u8),
+}
+
+impl FaultStatus {
+    /// Decodes the 6-bit DFSC or IFSC field.
+    pub fn from_bits(fsc: u8) -> Self {
+        let level = fsc & 0b11;
+        match fsc & 0x3f {
+            0x00..=0x03 => FaultStatus::AddressSize(level),
+            0x04..=0x07 => FaultStatus::Translation(level),
+            0x09..=0x0b => FaultStatus::AccessFlag(level),
+            0x0d..=0x0f => FaultStatus::Permission(level),
+            0x10 => FaultStatus::SynchronousExternal,
+            0x14..=0x17 => FaultStatus::SynchronousExternalOnWalk(level),
+            0x18 => FaultStatus::SynchronousParity,
+            0x1c..=0x1f => FaultStatus::SynchronousParityOnWalk(level),
+            0x21 => FaultStatus::Alignment,
+            0x30 => FaultStatus::TlbConflict,
+            0x31 => FaultStatus::UnsupportedAtomicUpdate,
+            fsc => FaultStatus::Other(fsc),
+        }
+    }
+
+    /// Returns the lookup level at which the fault happened, if it is related to a level.
+    pub fn level(&self) -> Option<u8> {
+        match *self {
+            FaultStatus::AddressSize(level)
+            | FaultStatus::Translation(level)
+            | FaultStatus::AccessFlag(level)
+            | FaultStatus::Permission(level)
+            | FaultStatus::SynchronousExternalOnWalk(level)
+            | FaultStatus::SynchronousParityOnWalk(level) => Some(level),
+            _ => None,
+        }
+    }
+}
+
+/// The size of the access that caused a Data Abort (SAS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessSize {
+    /// 8 bits.
+    Byte,
+    /// 16 bits.
+    Halfword,
+    /// 32 bits.
+    Word,
+    /// 64 bits.
+    Doubleword,
+}
+
+/// The syndrome of a Data Abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataAbortIss(u32);
+
+impl DataAbortIss {
+    /// Returns whether the instruction syndrome (SAS, SSE, SRT, SF and AR) is valid.
+    pub fn isv(&self) -> bool {
+        self.0 & 1 << 24 != 0
+    }
+
+    /// Returns the size of the faulting access, if the instruction syndrome is valid.
+    pub fn access_size(&self) -> Option<AccessSize> {
+        if !self.isv() {
+            return None;
+        }
+        Some(match (self.0 >> 22) & 0b11 {
+            0b00 => AccessSize::Byte,
+            0b01 => AccessSize::Halfword,
+            0b10 => AccessSize::Word,
+            _ => AccessSize::Doubleword,
+        })
+    }
+
+    /// Returns whether the faulting load sign-extends the data.
+    pub fn sign_extend(&self) -> bool {
+        self.isv() && self.0 & 1 << 21 != 0
+    }
+
+    /// Returns the register transferred by the faulting access, if the instruction syndrome is
+    /// valid.
+    pub fn register(&self) -> Option<u8> {
+        if self.isv() {
+            Some(((self.0 >> 16) & 0x1f) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the faulting access transfers a 64-bit register.
+    pub fn sixty_four(&self) -> bool {
+        self.isv() && self.0 & 1 << 15 != 0
+    }
+
+    /// Returns whether the faulting access has acquire or release semantics.
+    pub fn acquire_release(&self) -> bool {
+        self.isv() && self.0 & 1 << 14 != 0
+    }
+
+    /// Returns whether FAR_EL1 is not valid (FnV).
+    pub fn far_not_valid(&self) -> bool {
+        self.0 & 1 << 10 != 0
+    }
+
+    /// Returns the External abort type bit (EA).
+    pub fn external_abort(&self) -> bool {
+        self.0 & 1 << 9 != 0
+    }
+
+    /// Returns whether the fault came from a cache maintenance or address translation
+    /// instruction (CM).
+    pub fn cache_maintenance(&self) -> bool {
+        self.0 & 1 << 8 != 0
+    }
+
+    /// Returns whether the fault happened on a stage 2 translation of a stage 1 table walk
+    /// (S1PTW).
+    pub fn s1ptw(&self) -> bool {
+        self.0 & 1 << 7 != 0
+    }
+
+    /// Returns whether the fault was caused by a write (WnR).
+    ///
+    /// Cache maintenance and address translation instructions report a read.
+    pub fn write(&self) -> bool {
+        self.0 & 1 << 6 != 0
+    }
+
+    /// Returns the data fault status code (DFSC).
+    pub fn fault_status(&self) -> FaultStatus {
+        FaultStatus::from_bits(self.0 as u8 & 0x3f)
+    }
+}
+
+/// The syndrome of an Instruction Abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionAbortIss(u32);
+
+impl InstructionAbortIss {
+    /// Returns whether FAR_EL1 is not valid (FnV).
+    pub fn far_not_valid(&self) -> bool {
+        self.0 & 1 << 10 != 0
+    }
+
+    /// Returns the External abort type bit (EA).
+    pub fn external_abort(&self) -> bool {
+        self.0 & 1 << 9 != 0
+    }
+
+    /// Returns whether the fault happened on a stage 2 translation of a stage 1 table walk
+    /// (S1PTW).
+    pub fn s1ptw(&self) -> bool {
+        self.0 & 1 << 7 != 0
+    }
+
+    /// Returns the instruction fault status code (IFSC).
+    pub fn fault_status(&self) -> FaultStatus {
+        FaultStatus::from_bits(self.0 as u8 & 0x3f)
+    }
+}
+
+/// The type of an error reported by an SError with the RAS Extension (AET).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSeverity {
+    /// Uncontainable: the error may have corrupted any state of the PE (UC).
+    Uncontainable,
+    /// Unrecoverable: the error is contained, but the interrupted context can't continue (UEU).
+    Unrecoverable,
+    /// Restartable: the interrupted context can be restarted, e.g. a process killed (UEO).
+    Restartable,
+    /// Recoverable: the error was not yet consumed, and can be handled (UER).
+    Recoverable,
+    /// Corrected: the error was corrected by the hardware (CE).
+    Corrected,
+    /// Any other, reserved, value.
+    Other(u8),
+}
+
+impl ErrorSeverity {
+    /// Decodes the 3-bit AET field.
+    pub fn from_bits(aet: u8) -> Self {
+        match aet & 0b111 {
+            0b000 => ErrorSeverity::Uncontainable,
+            0b001 => ErrorSeverity::Unrecoverable,
+            0b010 => ErrorSeverity::Restartable,
+            0b011 => ErrorSeverity::Recoverable,
+            0b110 => ErrorSeverity::Corrected,
+            aet => ErrorSeverity::Other(aet),
+        }
+    }
+}
+
+/// The syndrome of an SError interrupt, from ESR_EL1 or from DISR_EL1 for a deferred one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SErrorIss(u32);
+
+impl SErrorIss {
+    /// Creates a decoder for the given syndrome, the low 25 bits of ESR_EL1 or DISR_EL1.
+    pub const fn new(iss: u32) -> Self {
+        SErrorIss(iss & 0x1ff_ffff)
+    }
+
+    /// Returns the raw syndrome.
+    pub const fn value(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns whether the syndrome is IMPLEMENTATION DEFINED (IDS), in which case the other
+    /// fields are not valid.
+    pub fn implementation_defined(&self) -> bool {
+        self.0 & 1 << 24 != 0
+    }
+
+    /// Returns whether the error was synchronized by the implicit error synchronization barrier
+    /// at an exception entry (IESB).
+    pub fn implicit_barrier(&self) -> bool {
+        !self.implementation_defined() && self.0 & 1 << 13 != 0
+    }
+
+    /// Returns the External abort type bit (EA).
+    pub fn external_abort(&self) -> bool {
+        !self.implementation_defined() && self.0 & 1 << 9 != 0
+    }
+
+    /// Returns the data fault status code (DFSC), 0 for an uncategorized error and `0x11` for
+    /// an asynchronous SError interrupt.
+    pub fn fault_status(&self) -> Option<u8> {
+        if self.implementation_defined() {
+            None
+        } else {
+            Some(self.0 as u8 & 0x3f)
+        }
+    }
+
+    /// Returns the type of the error (AET), only reported for an asynchronous SError interrupt
+    /// with the RAS Extension.
+    pub fn severity(&self) -> Option<ErrorSeverity> {
+        match self.fault_status() {
+            Some(0x11) => Some(ErrorSeverity::from_bits((self.0 >> 10) as u8)),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded value of ESR_EL1.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EsrEl1(u64);
+
+impl EsrEl1 {
+    /// Creates a decoder for the given value of ESR_EL1.
+    pub const fn new(value: u64) -> Self {
+        EsrEl1(value)
+    }
+
+    /// Reads ESR_EL1.
+    #[inline]
+    pub fn read() -> Self {
+        EsrEl1(ESR_EL1.get())
+    }
+
+    /// Returns the raw value.
+    pub const fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the exception class.
+    pub fn class(&self) -> ExceptionClass {
+        ExceptionClass::from_bits((self.0 >> 26) as u8 & 0x3f)
+    }
+
+    /// Returns whether the trapped instruction is 32 bits long (IL).
+    pub fn il(&self) -> bool {
+        self.0 & 1 << 25 != 0
+    }
+
+    /// Returns the raw Instruction Specific Syndrome.
+    pub fn iss(&self) -> u32 {
+        self.0 as u32 & 0x1ff_ffff
+    }
+
+    /// Returns the syndrome of a Data Abort, from the current or a lower Exception level.
+    pub fn data_abort(&self) -> Option<DataAbortIss> {
+        match self.class() {
+            ExceptionClass::DataAbortLowerEl | ExceptionClass::DataAbortCurrentEl => {
+                Some(DataAbortIss(self.iss()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the syndrome of an Instruction Abort, from the current or a lower Exception
+    /// level.
+    pub fn instruction_abort(&self) -> Option<InstructionAbortIss> {
+        match self.class() {
+            ExceptionClass::InstrAbortLowerEl | ExceptionClass::InstrAbortCurrentEl => {
+                Some(InstructionAbortIss(self.iss()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the syndrome of an SError interrupt.
+    pub fn serror(&self) -> Option<SErrorIss> {
+        match self.class() {
+            ExceptionClass::SError => Some(SErrorIss::new(self.iss())),
+            _ => None,
+        }
+    }
+
+    /// Returns the immediate of an SVC, HVC, SMC or BRK instruction.
+    pub fn imm16(&self) -> Option<u16> {
+        match self.class() {
+            ExceptionClass::Svc
+            | ExceptionClass::Hvc
+            | ExceptionClass::Smc
+            | ExceptionClass::Brk => Some(self.iss() as u16),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for EsrEl1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EsrEl1")
+            .field("class", &self.class())
+            .field("il", &self.il())
+            .field("iss", &format_args!("{:#x}", self.iss()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_esr_decode() {
+        // a 64-bit store from x3 at EL0, faulting with a level 3 permission fault
+        let esr = EsrEl1::new(0x93c3_804f);
+        assert_eq!(esr.class(), ExceptionClass::DataAbortLowerEl);
+        assert!(esr.il());
+        let iss = esr.data_abort().unwrap();
+        assert_eq!(iss.access_size(), Some(AccessSize::Doubleword));
+        assert_eq!(iss.register(), Some(3));
+        assert!(iss.sixty_four());
+        assert!(iss.write());
+        assert!(!iss.far_not_valid());
+        assert_eq!(iss.fault_status(), FaultStatus::Permission(3));
+        assert_eq!(iss.fault_status().level(), Some(3));
+        assert!(esr.instruction_abort().is_none());
+
+        let svc = EsrEl1::new(0x5600_002a);
+        assert_eq!(svc.class(), ExceptionClass::Svc);
+        assert_eq!(svc.imm16(), Some(42));
+        assert_eq!(
+            EsrEl1::new(0x8600_0021)
+                .instruction_abort()
+                .unwrap()
+                .fault_status(),
+            FaultStatus::Alignment
+        );
+        assert_eq!(FaultStatus::from_bits(0x06), FaultStatus::Translation(2));
+    }
+
+    #[test]
+    pub fn test_serror_decode() {
+        // an asynchronous recoverable error, synchronized at exception entry
+        let esr = EsrEl1::new(0xbe00_2c11);
+        assert_eq!(esr.class(), ExceptionClass::SError);
+        let iss = esr.serror().unwrap();
+        assert!(!iss.implementation_defined());
+        assert!(iss.implicit_barrier());
+        assert!(!iss.external_abort());
+        assert_eq!(iss.fault_status(), Some(0x11));
+        assert_eq!(iss.severity(), Some(ErrorSeverity::Recoverable));
+        assert!(esr.data_abort().is_none());
+
+        let uncategorized = SErrorIss::new(0x200);
+        assert!(uncategorized.external_abort());
+        assert_eq!(uncategorized.severity(), None);
+
+        let implementation_defined = SErrorIss::new(0x100_0e11);
+        assert_eq!(implementation_defined.fault_status(), None);
+        assert_eq!(implementation_defined.severity(), None);
+        assert!(EsrEl1::new(0x5600_002a).serror().is_none());
+    }
+}
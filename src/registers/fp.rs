@@ -0,0 +1,165 @@
+//! Advanced SIMD and floating-point access control and context switching.
+//!
+//! CPACR_EL1.FPEN traps the FP/SIMD instructions of EL0 and EL1. A kernel that switches the
+//! FP/SIMD registers lazily enables the trap with [`enable_fp_trap`] when switching threads, and
+//! in the handler of the trapped access ([`ExceptionClass::TrappedFp`]) saves the registers of
+//! the previous owner into its [`FpState`], restores those of the current thread and calls
+//! [`disable_fp_trap`].
+//!
+//! [`ExceptionClass::TrappedFp`]: super::esr::ExceptionClass::TrappedFp
+
+use super::CPACR_EL1;
+use crate::barrier;
+use tock_registers::interfaces::ReadWriteable;
+
+/// Allows FP/SIMD instructions at EL1, and traps them at EL0.
+#[inline]
+pub fn enable_fp_el1() {
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapEl0);
+    unsafe { barrier::isb() };
+}
+
+/// Allows FP/SIMD instructions at EL0 and EL1.
+#[inline]
+pub fn enable_fp_el0() {
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapNothing);
+    unsafe { barrier::isb() };
+}
+
+/// Traps FP/SIMD instructions at EL0 and EL1, e.g. to switch the FP/SIMD registers lazily.
+///
+/// # Safety
+///
+/// The kernel itself must not use FP/SIMD instructions until the trap is disabled, which the
+/// compiler may emit on targets with the `neon` feature.
+#[inline]
+pub unsafe fn enable_fp_trap() {
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapEl0El1);
+    barrier::isb();
+}
+
+/// Stops trapping FP/SIMD instructions at EL0 and EL1, the same as [`enable_fp_el0`].
+#[inline]
+pub fn disable_fp_trap() {
+    enable_fp_el0();
+}
+
+/// The FP/SIMD register context of a thread: the 32 128-bit registers V0-V31, FPCR and FPSR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, align(16))]
+pub struct FpState {
+    /// The registers V0-V31.
+    pub q: [u128; 32],
+    /// The Floating-point Control Register.
+    pub fpcr: u64,
+    /// The Floating-point Status Register.
+    pub fpsr: u64,
+}
+
+impl Default for FpState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FpState {
+    /// Creates a context with all registers zero, the reset state of FPCR and FPSR.
+    pub const fn new() -> Self {
+        Self {
+            q: [0; 32],
+            fpcr: 0,
+            fpsr: 0,
+        }
+    }
+
+    /// Saves the FP/SIMD registers of the current PE into this context.
+    ///
+    /// # Safety
+    ///
+    /// FP/SIMD instructions must not be trapped at the current Exception level.
+    #[inline]
+    pub unsafe fn save(&mut self) {
+        core::arch::asm!(
+            ".arch_extension fp",
+            ".arch_extension simd",
+            "stp q0, q1, [{0}, #0x0]",
+            "stp q2, q3, [{0}, #0x20]",
+            "stp q4, q5, [{0}, #0x40]",
+            "stp q6, q7, [{0}, #0x60]",
+            "stp q8, q9, [{0}, #0x80]",
+            "stp q10, q11, [{0}, #0xa0]",
+            "stp q12, q13, [{0}, #0xc0]",
+            "stp q14, q15, [{0}, #0xe0]",
+            "stp q16, q17, [{0}, #0x100]",
+            "stp q18, q19, [{0}, #0x120]",
+            "stp q20, q21, [{0}, #0x140]",
+            "stp q22, q23, [{0}, #0x160]",
+            "stp q24, q25, [{0}, #0x180]",
+            "stp q26, q27, [{0}, #0x1a0]",
+            "stp q28, q29, [{0}, #0x1c0]",
+            "stp q30, q31, [{0}, #0x1e0]",
+            "mrs {1}, fpcr",
+            "mrs {2}, fpsr",
+            "str {1}, [{0}, #0x200]",
+            "str {2}, [{0}, #0x208]",
+            in(reg) self as *mut Self,
+            out(reg) _,
+            out(reg) _,
+            options(nostack, preserves_flags)
+        );
+    }
+
+    /// Restores the FP/SIMD registers of the current PE from this context.
+    ///
+    /// # Safety
+    ///
+    /// FP/SIMD instructions must not be trapped at the current Exception level, and the compiler
+    /// must not hold values in the FP/SIMD registers across the call, e.g. because the kernel is
+    /// built without the `neon` feature.
+    #[inline]
+    pub unsafe fn restore(&self) {
+        core::arch::asm!(
+            ".arch_extension fp",
+            ".arch_extension simd",
+            "ldp q0, q1, [{0}, #0x0]",
+            "ldp q2, q3, [{0}, #0x20]",
+            "ldp q4, q5, [{0}, #0x40]",
+            "ldp q6, q7, [{0}, #0x60]",
+            "ldp q8, q9, [{0}, #0x80]",
+            "ldp q10, q11, [{0}, #0xa0]",
+            "ldp q12, q13, [{0}, #0xc0]",
+            "ldp q14, q15, [{0}, #0xe0]",
+            "ldp q16, q17, [{0}, #0x100]",
+            "ldp q18, q19, [{0}, #0x120]",
+            "ldp q20, q21, [{0}, #0x140]",
+            "ldp q22, q23, [{0}, #0x160]",
+            "ldp q24, q25, [{0}, #0x180]",
+            "ldp q26, q27, [{0}, #0x1a0]",
+            "ldp q28, q29, [{0}, #0x1c0]",
+            "ldp q30, q31, [{0}, #0x1e0]",
+            "ldr {1}, [{0}, #0x200]",
+            "ldr {2}, [{0}, #0x208]",
+            "msr fpcr, {1}",
+            "msr fpsr, {2}",
+            in(reg) self as *const Self,
+            out(reg) _,
+            out(reg) _,
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_fp_state_layout() {
+        let state = FpState::new();
+        let base = &state as *const _ as usize;
+        assert_eq!(core::mem::size_of::<FpState>(), 0x210);
+        assert_eq!(core::mem::align_of::<FpState>(), 16);
+        assert_eq!(&state.fpcr as *const _ as usize - base, 0x200);
+        assert_eq!(&state.fpsr as *const _ as usize - base, 0x208);
+    }
+}
@@ -0,0 +1,38 @@
+//! Tag Control Register - EL1
+//!
+//! Configures the tags generated by the IRG instruction. Accessed by its encoding,
+//! `S3_0_C1_C0_6`, so that no architecture extension is needed to assemble it.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub GCR_EL1 [
+        /// Random Allocation Tag Selection. When set, IRG generates IMPLEMENTATION DEFINED
+        /// random tags instead of using RGSR_EL1.
+        RRND OFFSET(16) NUMBITS(1) [],
+
+        /// Allocation Tag Exclude mask, one bit per tag value that IRG does not generate.
+        Exclude OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = GCR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C1_C0_6", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = GCR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "S3_0_C1_C0_6", "x");
+}
+
+pub const GCR_EL1: Reg = Reg {};
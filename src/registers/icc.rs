@@ -0,0 +1,165 @@
+//! GIC CPU interface system registers - EL1
+//!
+//! The `ICC_*` registers of the GICv3 and GICv4 CPU interface, enabled by ICC_SRE_EL1.SRE. Only
+//! the Group 1 registers, used by a kernel running in Non-secure EL1, are wrapped. Accessed by
+//! their encodings, so that no GIC support is needed in the assembler.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub ICC_SRE_EL1 [
+        /// Disable IRQ bypass.
+        DIB OFFSET(2) NUMBITS(1) [],
+
+        /// Disable FIQ bypass.
+        DFB OFFSET(1) NUMBITS(1) [],
+
+        /// System Register Enable: the CPU interface is accessed through the `ICC_*` registers.
+        SRE OFFSET(0) NUMBITS(1) []
+    ],
+
+    pub ICC_PMR_EL1 [
+        /// The priority mask: only interrupts of a higher priority, i.e. a lower value, are
+        /// signaled.
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+
+    pub ICC_BPR1_EL1 [
+        /// The split of the priority into the group priority, which decides preemption, and the
+        /// subpriority.
+        BinaryPoint OFFSET(0) NUMBITS(3) []
+    ],
+
+    pub ICC_CTLR_EL1 [
+        /// Extended INTID range supported (GICv3.1).
+        ExtRange OFFSET(19) NUMBITS(1) [],
+
+        /// Range Selector Support: SGIs can target the PEs with an Aff0 above 15.
+        RSS OFFSET(18) NUMBITS(1) [],
+
+        /// Affinity 3 valid in ICC_SGI1R_EL1.
+        A3V OFFSET(15) NUMBITS(1) [],
+
+        /// SEI supported.
+        SEIS OFFSET(14) NUMBITS(1) [],
+
+        /// The number of INTID bits: 16 or 24.
+        IDbits OFFSET(11) NUMBITS(3) [
+            Bits16 = 0b000,
+            Bits24 = 0b001
+        ],
+
+        /// The number of priority bits implemented, minus one.
+        PRIbits OFFSET(8) NUMBITS(3) [],
+
+        /// Priority Mask Hint Enable.
+        PMHE OFFSET(6) NUMBITS(1) [],
+
+        /// EOI mode: writes to ICC_EOIR1_EL1 only drop the priority, and ICC_DIR_EL1 deactivates
+        /// the interrupt.
+        EOImode OFFSET(1) NUMBITS(1) [],
+
+        /// Common Binary Point Register.
+        CBPR OFFSET(0) NUMBITS(1) []
+    ],
+
+    pub ICC_IGRPEN1_EL1 [
+        /// Enables Group 1 interrupts.
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    pub ICC_IAR1_EL1 [
+        /// The INTID of the acknowledged interrupt.
+        INTID OFFSET(0) NUMBITS(24) []
+    ],
+
+    pub ICC_EOIR1_EL1 [
+        /// The INTID of the interrupt to complete.
+        INTID OFFSET(0) NUMBITS(24) []
+    ],
+
+    pub ICC_DIR_EL1 [
+        /// The INTID of the interrupt to deactivate.
+        INTID OFFSET(0) NUMBITS(24) []
+    ],
+
+    pub ICC_HPPIR1_EL1 [
+        /// The INTID of the highest priority pending Group 1 interrupt.
+        INTID OFFSET(0) NUMBITS(24) []
+    ],
+
+    pub ICC_RPR_EL1 [
+        /// The priority of the highest priority active interrupt.
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+
+    pub ICC_SGI1R_EL1 [
+        /// The affinity 3 of the targets.
+        Aff3 OFFSET(48) NUMBITS(8) [],
+
+        /// Range Selector: the targets have an Aff0 between RS * 16 and RS * 16 + 15.
+        RS OFFSET(44) NUMBITS(4) [],
+
+        /// Interrupt Routing Mode: all PEs but the current one are targeted.
+        IRM OFFSET(40) NUMBITS(1) [],
+
+        /// The affinity 2 of the targets.
+        Aff2 OFFSET(32) NUMBITS(8) [],
+
+        /// The INTID of the SGI.
+        INTID OFFSET(24) NUMBITS(4) [],
+
+        /// The affinity 1 of the targets.
+        Aff1 OFFSET(16) NUMBITS(8) [],
+
+        /// The targets, one bit for each Aff0 in the range selected by RS.
+        TargetList OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+macro_rules! icc_register {
+    ($reg:ident, $name:ident, $encoding:tt, $($trait:ident),+) => {
+        pub struct $reg;
+
+        $(icc_register!(@impl $trait, $reg, $name, $encoding);)+
+
+        pub const $name: $reg = $reg {};
+    };
+    (@impl Readable, $reg:ident, $name:ident, $encoding:tt) => {
+        impl Readable for $reg {
+            type T = u64;
+            type R = $name::Register;
+
+            sys_coproc_read_raw!(u64, $encoding, "x");
+        }
+    };
+    (@impl Writeable, $reg:ident, $name:ident, $encoding:tt) => {
+        impl Writeable for $reg {
+            type T = u64;
+            type R = $name::Register;
+
+            sys_coproc_write_raw!(u64, $encoding, "x");
+        }
+    };
+}
+
+icc_register!(SreReg, ICC_SRE_EL1, "S3_0_C12_C12_5", Readable, Writeable);
+icc_register!(PmrReg, ICC_PMR_EL1, "S3_0_C4_C6_0", Readable, Writeable);
+icc_register!(Bpr1Reg, ICC_BPR1_EL1, "S3_0_C12_C12_3", Readable, Writeable);
+icc_register!(CtlrReg, ICC_CTLR_EL1, "S3_0_C12_C12_4", Readable, Writeable);
+icc_register!(
+    Igrpen1Reg,
+    ICC_IGRPEN1_EL1,
+    "S3_0_C12_C12_7",
+    Readable,
+    Writeable
+);
+icc_register!(Iar1Reg, ICC_IAR1_EL1, "S3_0_C12_C12_0", Readable);
+icc_register!(Eoir1Reg, ICC_EOIR1_EL1, "S3_0_C12_C12_1", Writeable);
+icc_register!(DirReg, ICC_DIR_EL1, "S3_0_C12_C11_1", Writeable);
+icc_register!(Hppir1Reg, ICC_HPPIR1_EL1, "S3_0_C12_C12_2", Readable);
+icc_register!(RprReg, ICC_RPR_EL1, "S3_0_C12_C11_3", Readable);
+icc_register!(Sgi1rReg, ICC_SGI1R_EL1, "S3_0_C12_C11_5", Writeable);
@@ -0,0 +1,43 @@
+//! AArch64 Debug Feature Register 0 - EL1
+//!
+//! Provides top level information about the debug system in AArch64 state.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64DFR0_EL1 [
+        /// Number of breakpoints that are context-aware, minus 1.
+        CTX_CMPs OFFSET(28) NUMBITS(4) [],
+
+        /// Number of watchpoints, minus 1.
+        WRPs OFFSET(20) NUMBITS(4) [],
+
+        /// Number of breakpoints, minus 1.
+        BRPs OFFSET(12) NUMBITS(4) [],
+
+        /// Performance Monitors Extension version.
+        PMUVer OFFSET(8) NUMBITS(4) [],
+
+        /// Trace support.
+        TraceVer OFFSET(4) NUMBITS(4) [],
+
+        /// Debug architecture version.
+        DebugVer OFFSET(0) NUMBITS(4) [
+            V8 = 0b0110,
+            V8_VHE = 0b0111,
+            V8_2 = 0b1000,
+            V8_4 = 0b1001
+        ]
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64DFR0_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ID_AA64DFR0_EL1", "x");
+}
+
+pub const ID_AA64DFR0_EL1: Reg = Reg {};
@@ -0,0 +1,57 @@
+//! AArch64 Instruction Set Attribute Register 0 - EL1
+//!
+//! Provides information about the instructions implemented in AArch64 state.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64ISAR0_EL1 [
+        /// Indicates support for the RNDR and RNDRRS registers (FEAT_RNG).
+        RNDR OFFSET(60) NUMBITS(4) [],
+
+        /// Indicates support for the outer shareable (FEAT_TLBIOS) and range (FEAT_TLBIRANGE)
+        /// TLB maintenance instructions.
+        TLB OFFSET(56) NUMBITS(4) [],
+
+        /// Indicates support for the flag manipulation instructions (FEAT_FlagM, FEAT_FlagM2).
+        TS OFFSET(52) NUMBITS(4) [],
+
+        /// Indicates support for the SDOT and UDOT instructions (FEAT_DotProd).
+        DP OFFSET(44) NUMBITS(4) [],
+
+        /// Indicates support for the SHA3 instructions (FEAT_SHA3).
+        SHA3 OFFSET(32) NUMBITS(4) [],
+
+        /// Indicates support for the SQRDMLAH and SQRDMLSH instructions (FEAT_RDM).
+        RDM OFFSET(28) NUMBITS(4) [],
+
+        /// Indicates support for the Large System Extensions atomic instructions (FEAT_LSE).
+        Atomic OFFSET(20) NUMBITS(4) [
+            None = 0b0000,
+            LSE = 0b0010
+        ],
+
+        /// Indicates support for the CRC32 instructions.
+        CRC32 OFFSET(16) NUMBITS(4) [],
+
+        /// Indicates support for the SHA2 instructions (FEAT_SHA256, FEAT_SHA512).
+        SHA2 OFFSET(12) NUMBITS(4) [],
+
+        /// Indicates support for the SHA1 instructions (FEAT_SHA1).
+        SHA1 OFFSET(8) NUMBITS(4) [],
+
+        /// Indicates support for the AES (FEAT_AES) and PMULL (FEAT_PMULL) instructions.
+        AES OFFSET(4) NUMBITS(4) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64ISAR0_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ID_AA64ISAR0_EL1", "x");
+}
+
+pub const ID_AA64ISAR0_EL1: Reg = Reg {};
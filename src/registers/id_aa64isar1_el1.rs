@@ -0,0 +1,48 @@
+//! AArch64 Instruction Set Attribute Register 1 - EL1
+//!
+//! Provides information about the features and instructions implemented in AArch64 state.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64ISAR1_EL1 [
+        /// Indicates support for the generic code authentication instruction (PACGA) with an
+        /// IMPLEMENTATION DEFINED algorithm.
+        GPI OFFSET(28) NUMBITS(4) [],
+
+        /// Indicates support for the generic code authentication instruction (PACGA) with the
+        /// QARMA5 algorithm.
+        GPA OFFSET(24) NUMBITS(4) [],
+
+        /// Indicates support for the Release Consistent processor consistent memory model
+        /// (FEAT_LRCPC).
+        LRCPC OFFSET(20) NUMBITS(4) [],
+
+        /// Indicates support for the FCMLA and FCADD instructions (FEAT_FCMA).
+        FCMA OFFSET(16) NUMBITS(4) [],
+
+        /// Indicates support for the FJCVTZS instruction (FEAT_JSCVT).
+        JSCVT OFFSET(12) NUMBITS(4) [],
+
+        /// Indicates support for address authentication with an IMPLEMENTATION DEFINED
+        /// algorithm.
+        API OFFSET(8) NUMBITS(4) [],
+
+        /// Indicates support for address authentication with the QARMA5 algorithm.
+        APA OFFSET(4) NUMBITS(4) [],
+
+        /// Data Persistence writeback: DC CVAP (FEAT_DPB) and DC CVADP (FEAT_DPB2).
+        DPB OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64ISAR1_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ID_AA64ISAR1_EL1", "x");
+}
+
+pub const ID_AA64ISAR1_EL1: Reg = Reg {};
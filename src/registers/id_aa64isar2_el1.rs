@@ -0,0 +1,40 @@
+//! AArch64 Instruction Set Attribute Register 2 - EL1
+//!
+//! Provides information about the instructions implemented in AArch64 state. Accessed by its
+//! encoding, `S3_0_C0_C6_2`, so that older assemblers accept it; reads as zero on cores
+//! predating it.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64ISAR2_EL1 [
+        /// Indicates support for the BC instruction (FEAT_HBC).
+        BC OFFSET(20) NUMBITS(4) [],
+
+        /// Indicates support for the memory copy and set instructions (FEAT_MOPS).
+        MOPS OFFSET(16) NUMBITS(4) [],
+
+        /// Indicates support for address authentication with the QARMA3 algorithm.
+        APA3 OFFSET(12) NUMBITS(4) [],
+
+        /// Indicates support for generic code authentication with the QARMA3 algorithm.
+        GPA3 OFFSET(8) NUMBITS(4) [],
+
+        /// Indicates support for the WFET and WFIT instructions (FEAT_WFxT).
+        WFxT OFFSET(0) NUMBITS(4) [
+            NotSupported = 0b0000,
+            Supported = 0b0010
+        ]
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64ISAR2_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C0_C6_2", "x");
+}
+
+pub const ID_AA64ISAR2_EL1: Reg = Reg {};
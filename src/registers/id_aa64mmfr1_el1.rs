@@ -0,0 +1,56 @@
+//! AArch64 Memory Model Feature Register 1 - EL1
+//!
+//! Provides information about the implemented memory model and memory management support in
+//! AArch64 state.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64MMFR1_EL1 [
+        /// Indicates support for execute-never control distinction by Exception level at stage 2
+        /// (FEAT_XNX).
+        XNX OFFSET(28) NUMBITS(4) [],
+
+        /// Privileged Access Never. Possible values are:
+        ///
+        /// 0b0000 PAN is not supported
+        /// 0b0001 PAN is supported (FEAT_PAN)
+        /// 0b0010 PAN is supported, and AT S1E1RP and AT S1E1WP are supported (FEAT_PAN2)
+        /// 0b0011 PAN is supported, with SCTLR_EL1.EPAN (FEAT_PAN3)
+        PAN OFFSET(20) NUMBITS(4) [],
+
+        /// Indicates support for LORegions (FEAT_LOR).
+        LO OFFSET(16) NUMBITS(4) [],
+
+        /// Indicates support for disabling hierarchical controls in translation tables
+        /// (FEAT_HPDS).
+        HPDS OFFSET(12) NUMBITS(4) [],
+
+        /// Virtualization Host Extensions (FEAT_VHE).
+        VH OFFSET(8) NUMBITS(4) [],
+
+        /// Number of VMID bits.
+        VMIDBits OFFSET(4) NUMBITS(4) [
+            Bits8 = 0b0000,
+            Bits16 = 0b0010
+        ],
+
+        /// Hardware updates to Access flag and Dirty state in translation tables.
+        HAFDBS OFFSET(0) NUMBITS(4) [
+            NotSupported = 0b0000,
+            AccessFlag = 0b0001,
+            AccessFlagDirtyState = 0b0010
+        ]
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64MMFR1_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ID_AA64MMFR1_EL1", "x");
+}
+
+pub const ID_AA64MMFR1_EL1: Reg = Reg {};
@@ -0,0 +1,45 @@
+//! AArch64 Memory Model Feature Register 2 - EL1
+//!
+//! Provides information about the implemented memory model and memory management support in
+//! AArch64 state.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64MMFR2_EL1 [
+        /// Support for the 64-bit format of CCSIDR_EL1 (FEAT_CCIDX).
+        CCIDX OFFSET(20) NUMBITS(4) [
+            Format32 = 0b0000,
+            Format64 = 0b0001
+        ],
+
+        /// Indicates support for a larger virtual address (FEAT_LVA).
+        VARange OFFSET(16) NUMBITS(4) [
+            Bits_48 = 0b0000,
+            Bits_52 = 0b0001
+        ],
+
+        /// Indicates support for the implicit error synchronization event (FEAT_IESB).
+        IESB OFFSET(12) NUMBITS(4) [],
+
+        /// Indicates support for LSMAOE and nTLSMD bits in SCTLR_EL1 (FEAT_LSMAOC).
+        LSM OFFSET(8) NUMBITS(4) [],
+
+        /// User Access Override (FEAT_UAO).
+        UAO OFFSET(4) NUMBITS(4) [],
+
+        /// Common not Private translations (FEAT_TTCNP).
+        CnP OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64MMFR2_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ID_AA64MMFR2_EL1", "x");
+}
+
+pub const ID_AA64MMFR2_EL1: Reg = Reg {};
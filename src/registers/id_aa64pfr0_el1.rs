@@ -0,0 +1,61 @@
+//! AArch64 Processor Feature Register 0 - EL1
+//!
+//! Provides information about the implemented PE features in AArch64 state.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64PFR0_EL1 [
+        /// Indicates whether data loaded under speculation with a permission or domain fault
+        /// can be used to form an address (FEAT_CSV3).
+        CSV3 OFFSET(60) NUMBITS(4) [],
+
+        /// Indicates whether branch targets trained in one context can affect speculative
+        /// execution in another (FEAT_CSV2).
+        CSV2 OFFSET(56) NUMBITS(4) [],
+
+        /// Data Independent Timing (FEAT_DIT).
+        DIT OFFSET(48) NUMBITS(4) [],
+
+        /// Indicates support for the Memory Partitioning and Monitoring Extension (FEAT_MPAM).
+        MPAM OFFSET(40) NUMBITS(4) [],
+
+        /// Indicates support for the Scalable Vector Extension (FEAT_SVE).
+        SVE OFFSET(32) NUMBITS(4) [],
+
+        /// Indicates support for the RAS Extension (FEAT_RAS).
+        RAS OFFSET(28) NUMBITS(4) [],
+
+        /// Indicates support for the System register interface of the GIC CPU interface.
+        GIC OFFSET(24) NUMBITS(4) [],
+
+        /// Advanced SIMD. 0b1111 if not implemented.
+        AdvSIMD OFFSET(20) NUMBITS(4) [],
+
+        /// Floating-point. 0b1111 if not implemented.
+        FP OFFSET(16) NUMBITS(4) [],
+
+        /// EL3 Exception level handling. 0b0000 if EL3 is not implemented.
+        EL3 OFFSET(12) NUMBITS(4) [],
+
+        /// EL2 Exception level handling. 0b0000 if EL2 is not implemented.
+        EL2 OFFSET(8) NUMBITS(4) [],
+
+        /// EL1 Exception level handling.
+        EL1 OFFSET(4) NUMBITS(4) [],
+
+        /// EL0 Exception level handling.
+        EL0 OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64PFR0_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ID_AA64PFR0_EL1", "x");
+}
+
+pub const ID_AA64PFR0_EL1: Reg = Reg {};
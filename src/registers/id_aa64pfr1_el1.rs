@@ -0,0 +1,34 @@
+//! AArch64 Processor Feature Register 1 - EL1
+//!
+//! Provides additional information about implemented PE features in AArch64 state.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ID_AA64PFR1_EL1 [
+        /// Support for the Memory Tagging Extension. Possible values are:
+        ///
+        /// 0b0000 MTE is not implemented
+        /// 0b0001 Instruction-only MTE is implemented (FEAT_MTE)
+        /// 0b0010 Full MTE is implemented (FEAT_MTE2)
+        /// 0b0011 Full MTE with asymmetric tag check fault handling (FEAT_MTE3)
+        MTE OFFSET(8) NUMBITS(4) [],
+
+        /// Speculative Store Bypassing controls (FEAT_SSBS).
+        SSBS OFFSET(4) NUMBITS(4) [],
+
+        /// Branch Target Identification (FEAT_BTI).
+        BT OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ID_AA64PFR1_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ID_AA64PFR1_EL1", "x");
+}
+
+pub const ID_AA64PFR1_EL1: Reg = Reg {};
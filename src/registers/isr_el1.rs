@@ -0,0 +1,29 @@
+//! Interrupt Status Register - EL1
+//!
+//! Shows the pending IRQ, FIQ and SError interrupts, whether they are masked or not.
+
+use tock_registers::{interfaces::Readable, register_bitfields};
+
+register_bitfields! {u64,
+    pub ISR_EL1 [
+        /// An SError interrupt is pending.
+        A OFFSET(8) NUMBITS(1) [],
+
+        /// An IRQ interrupt is pending.
+        I OFFSET(7) NUMBITS(1) [],
+
+        /// An FIQ interrupt is pending.
+        F OFFSET(6) NUMBITS(1) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = ISR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "ISR_EL1", "x");
+}
+
+pub const ISR_EL1: Reg = Reg {};
@@ -0,0 +1,189 @@
+//! Decoding of the Main ID Register - EL1
+//!
+//! [`Midr`] identifies the implementation of the PE: its implementer, its part number and its
+//! `rXpY` revision. Errata workarounds are keyed on a [`CpuModel`] and the range of revisions
+//! they affect, a [`MidrRange`], checked with [`Midr::matches`].
+
+use super::MIDR_EL1;
+use core::fmt;
+use tock_registers::interfaces::Readable;
+
+/// The implementer code of Arm Limited.
+pub const IMPLEMENTER_ARM: u8 = 0x41;
+/// The implementer code of Apple Inc.
+pub const IMPLEMENTER_APPLE: u8 = 0x61;
+
+/// A processor model: an implementer and one of its part numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuModel {
+    /// The implementer code.
+    pub implementer: u8,
+    /// The 12-bit part number.
+    pub part_num: u16,
+}
+
+impl CpuModel {
+    /// Creates a model from its implementer code and part number.
+    pub const fn new(implementer: u8, part_num: u16) -> Self {
+        CpuModel {
+            implementer,
+            part_num,
+        }
+    }
+}
+
+/// Arm Cortex-A53.
+pub const CORTEX_A53: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd03);
+/// Arm Cortex-A55.
+pub const CORTEX_A55: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd05);
+/// Arm Cortex-A57.
+pub const CORTEX_A57: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd07);
+/// Arm Cortex-A72.
+pub const CORTEX_A72: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd08);
+/// Arm Cortex-A73.
+pub const CORTEX_A73: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd09);
+/// Arm Cortex-A75.
+pub const CORTEX_A75: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd0a);
+/// Arm Cortex-A76.
+pub const CORTEX_A76: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd0b);
+/// Arm Cortex-A77.
+pub const CORTEX_A77: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd0d);
+/// Arm Cortex-A78.
+pub const CORTEX_A78: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd41);
+/// Arm Cortex-X1.
+pub const CORTEX_X1: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd44);
+/// Arm Cortex-A710.
+pub const CORTEX_A710: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd47);
+/// Arm Neoverse N1.
+pub const NEOVERSE_N1: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd0c);
+/// Arm Neoverse V1.
+pub const NEOVERSE_V1: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd40);
+/// Arm Neoverse N2.
+pub const NEOVERSE_N2: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd49);
+/// Arm Neoverse V2.
+pub const NEOVERSE_V2: CpuModel = CpuModel::new(IMPLEMENTER_ARM, 0xd4f);
+/// The efficiency cores of the Apple M1 (Icestorm).
+pub const APPLE_M1_ICESTORM: CpuModel = CpuModel::new(IMPLEMENTER_APPLE, 0x022);
+/// The performance cores of the Apple M1 (Firestorm).
+pub const APPLE_M1_FIRESTORM: CpuModel = CpuModel::new(IMPLEMENTER_APPLE, 0x023);
+/// The efficiency cores of the Apple M2 (Blizzard).
+pub const APPLE_M2_BLIZZARD: CpuModel = CpuModel::new(IMPLEMENTER_APPLE, 0x032);
+/// The performance cores of the Apple M2 (Avalanche).
+pub const APPLE_M2_AVALANCHE: CpuModel = CpuModel::new(IMPLEMENTER_APPLE, 0x033);
+
+/// A model and an inclusive range of its `rXpY` revisions, given as `(variant, revision)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidrRange {
+    /// The model.
+    pub model: CpuModel,
+    /// The first matching `(variant, revision)`.
+    pub min: (u8, u8),
+    /// The last matching `(variant, revision)`.
+    pub max: (u8, u8),
+}
+
+impl MidrRange {
+    /// Creates a range of the revisions of `model` from `min` to `max` included.
+    pub const fn new(model: CpuModel, min: (u8, u8), max: (u8, u8)) -> Self {
+        MidrRange { model, min, max }
+    }
+
+    /// Creates a range of all the revisions of `model`.
+    pub const fn all(model: CpuModel) -> Self {
+        Self::new(model, (0, 0), (0xf, 0xf))
+    }
+}
+
+/// A decoded value of MIDR_EL1.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Midr(u64);
+
+impl Midr {
+    /// Creates a decoder for the given value of MIDR_EL1.
+    pub const fn new(value: u64) -> Self {
+        Midr(value)
+    }
+
+    /// Reads MIDR_EL1.
+    #[inline]
+    pub fn read() -> Self {
+        Midr(MIDR_EL1.get())
+    }
+
+    /// Returns the raw value.
+    pub const fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the implementer code.
+    pub const fn implementer(&self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    /// Returns the variant, the `X` of `rXpY`.
+    pub const fn variant(&self) -> u8 {
+        (self.0 >> 20) as u8 & 0xf
+    }
+
+    /// Returns the part number.
+    pub const fn part_num(&self) -> u16 {
+        (self.0 >> 4) as u16 & 0xfff
+    }
+
+    /// Returns the revision, the `Y` of `rXpY`.
+    pub const fn revision(&self) -> u8 {
+        self.0 as u8 & 0xf
+    }
+
+    /// Returns the processor model.
+    pub const fn model(&self) -> CpuModel {
+        CpuModel::new(self.implementer(), self.part_num())
+    }
+
+    /// Returns whether the PE is of the model and in the revisions of `range`.
+    pub fn matches(&self, range: &MidrRange) -> bool {
+        let rv = (self.variant(), self.revision());
+        self.model() == range.model && range.min <= rv && rv <= range.max
+    }
+}
+
+impl fmt::Debug for Midr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Midr")
+            .field("implementer", &format_args!("{:#x}", self.implementer()))
+            .field("part_num", &format_args!("{:#x}", self.part_num()))
+            .field(
+                "revision",
+                &format_args!("r{}p{}", self.variant(), self.revision()),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_midr_decode() {
+        // Cortex-A72 r0p3
+        let midr = Midr::new(0x410f_d083);
+        assert_eq!(midr.implementer(), IMPLEMENTER_ARM);
+        assert_eq!(midr.part_num(), 0xd08);
+        assert_eq!(midr.variant(), 0);
+        assert_eq!(midr.revision(), 3);
+        assert_eq!(midr.model(), CORTEX_A72);
+        assert!(midr.matches(&MidrRange::all(CORTEX_A72)));
+        assert!(midr.matches(&MidrRange::new(CORTEX_A72, (0, 0), (0, 3))));
+        assert!(!midr.matches(&MidrRange::new(CORTEX_A72, (0, 4), (1, 0))));
+        assert!(!midr.matches(&MidrRange::all(CORTEX_A57)));
+
+        // Neoverse N1 r3p1
+        let n1 = Midr::new(0x413f_d0c1);
+        assert_eq!(n1.model(), NEOVERSE_N1);
+        assert!(n1.matches(&MidrRange::new(NEOVERSE_N1, (2, 0), (4, 0))));
+        assert!(!n1.matches(&MidrRange::new(NEOVERSE_N1, (0, 0), (3, 0))));
+
+        assert_eq!(Midr::new(0x611f_0231).model(), APPLE_M1_FIRESTORM);
+    }
+}
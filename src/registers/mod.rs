@@ -1,8 +1,80 @@
 #[macro_use]
 mod macros;
+mod ccsidr_el1;
+mod clidr_el1;
+mod cntkctl_el1;
+mod cntp_cval_el0;
+mod contextidr_el1;
+mod cpacr_el1;
+mod csselr_el1;
 mod ctr_el0;
+mod dczid_el0;
+mod disr_el1;
+mod el_regs;
+mod gcr_el1;
+mod icc;
+mod id_aa64dfr0_el1;
+mod id_aa64isar0_el1;
+mod id_aa64isar1_el1;
+mod id_aa64isar2_el1;
+mod id_aa64mmfr1_el1;
+mod id_aa64mmfr2_el1;
+mod id_aa64pfr0_el1;
+mod id_aa64pfr1_el1;
+mod isr_el1;
+mod mdscr_el1;
+mod pan;
+mod pmu;
+mod rgsr_el1;
+mod tfsr_el1;
+mod uao;
+mod vsesr_el2;
+mod vtcr_el2;
+mod vttbr_el2;
+
+pub mod esr;
+pub mod fp;
+pub mod midr;
 
 pub use cortex_a::registers::*;
 pub use tock_registers::interfaces::*;
 
-pub use self::ctr_el0::CTR_EL0;
+pub use self::{
+    ccsidr_el1::CCSIDR_EL1,
+    clidr_el1::CLIDR_EL1,
+    cntkctl_el1::CNTKCTL_EL1,
+    cntp_cval_el0::CNTP_CVAL_EL0,
+    contextidr_el1::CONTEXTIDR_EL1,
+    cpacr_el1::CPACR_EL1,
+    csselr_el1::CSSELR_EL1,
+    ctr_el0::CTR_EL0,
+    dczid_el0::DCZID_EL0,
+    disr_el1::DISR_EL1,
+    el_regs::{current_el_regs, ElRegs},
+    gcr_el1::GCR_EL1,
+    icc::{
+        ICC_BPR1_EL1, ICC_CTLR_EL1, ICC_DIR_EL1, ICC_EOIR1_EL1, ICC_HPPIR1_EL1, ICC_IAR1_EL1,
+        ICC_IGRPEN1_EL1, ICC_PMR_EL1, ICC_RPR_EL1, ICC_SGI1R_EL1, ICC_SRE_EL1,
+    },
+    id_aa64dfr0_el1::ID_AA64DFR0_EL1,
+    id_aa64isar0_el1::ID_AA64ISAR0_EL1,
+    id_aa64isar1_el1::ID_AA64ISAR1_EL1,
+    id_aa64isar2_el1::ID_AA64ISAR2_EL1,
+    id_aa64mmfr1_el1::ID_AA64MMFR1_EL1,
+    id_aa64mmfr2_el1::ID_AA64MMFR2_EL1,
+    id_aa64pfr0_el1::ID_AA64PFR0_EL1,
+    id_aa64pfr1_el1::ID_AA64PFR1_EL1,
+    isr_el1::ISR_EL1,
+    mdscr_el1::MDSCR_EL1,
+    pan::PAN,
+    pmu::{
+        PMCCFILTR_EL0, PMCCNTR_EL0, PMCNTENCLR_EL0, PMCNTENSET_EL0, PMCR_EL0, PMOVSCLR_EL0,
+        PMSELR_EL0, PMUSERENR_EL0, PMXEVCNTR_EL0, PMXEVTYPER_EL0,
+    },
+    rgsr_el1::RGSR_EL1,
+    tfsr_el1::{TFSRE0_EL1, TFSR_EL1},
+    uao::UAO,
+    vsesr_el2::VSESR_EL2,
+    vtcr_el2::VTCR_EL2,
+    vttbr_el2::VTTBR_EL2,
+};
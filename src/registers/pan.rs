@@ -0,0 +1,35 @@
+//! Privileged Access Never
+//!
+//! When set, privileged data accesses from EL1 to memory accessible at EL0 generate a Permission
+//! fault. Accessed by its encoding, `S3_0_C4_C2_3`, so that no architecture extension is needed
+//! to assemble it.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub PAN [
+        /// Privileged data accesses to memory accessible at EL0 are not permitted.
+        PAN OFFSET(22) NUMBITS(1) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = PAN::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C4_C2_3", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = PAN::Register;
+
+    sys_coproc_write_raw!(u64, "S3_0_C4_C2_3", "x");
+}
+
+pub const PAN: Reg = Reg {};
@@ -0,0 +1,215 @@
+//! Performance Monitors registers - EL0
+//!
+//! The `PM*` registers of the Performance Monitors Extension (FEAT_PMUv3): the control register,
+//! the cycle counter and the event counters, selected through PMSELR_EL0. Accessible from EL0 only
+//! when enabled by PMUSERENR_EL0.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub PMCR_EL0 [
+        /// Long event counter enable: the event counters overflow at 64 bits (FEAT_PMUv3p5).
+        LP OFFSET(7) NUMBITS(1) [],
+
+        /// Long cycle counter enable: the cycle counter overflows at 64 bits instead of 32.
+        LC OFFSET(6) NUMBITS(1) [],
+
+        /// Disables the cycle counter when event counting is prohibited.
+        DP OFFSET(5) NUMBITS(1) [],
+
+        /// Enables the export of events to an external trace unit.
+        X OFFSET(4) NUMBITS(1) [],
+
+        /// Clock divider: the cycle counter counts every 64 cycles.
+        D OFFSET(3) NUMBITS(1) [],
+
+        /// Resets the cycle counter to zero (write-only).
+        C OFFSET(2) NUMBITS(1) [],
+
+        /// Resets all event counters, but not the cycle counter, to zero (write-only).
+        P OFFSET(1) NUMBITS(1) [],
+
+        /// Enables all the counters enabled in PMCNTENSET_EL0.
+        E OFFSET(0) NUMBITS(1) [],
+
+        /// The number of event counters implemented (read-only).
+        N OFFSET(11) NUMBITS(5) []
+    ],
+
+    pub PMSELR_EL0 [
+        /// The event counter accessed through PMXEVTYPER_EL0 and PMXEVCNTR_EL0, or 31 for
+        /// PMCCFILTR_EL0.
+        SEL OFFSET(0) NUMBITS(5) []
+    ],
+
+    pub PMXEVTYPER_EL0 [
+        /// Don't count at EL1.
+        P OFFSET(31) NUMBITS(1) [],
+
+        /// Don't count at EL0.
+        U OFFSET(30) NUMBITS(1) [],
+
+        /// Non-secure EL1 filtering: count at Non-secure EL1 if different from P.
+        NSK OFFSET(29) NUMBITS(1) [],
+
+        /// Non-secure EL0 filtering: count at Non-secure EL0 if different from U.
+        NSU OFFSET(28) NUMBITS(1) [],
+
+        /// Count at EL2.
+        NSH OFFSET(27) NUMBITS(1) [],
+
+        /// Secure EL3 filtering: count at EL3 if different from P.
+        M OFFSET(26) NUMBITS(1) [],
+
+        /// Count the events of all the threads of a multi-threaded PE.
+        MT OFFSET(25) NUMBITS(1) [],
+
+        /// The number of the counted event.
+        evtCount OFFSET(0) NUMBITS(16) []
+    ],
+
+    pub PMCCFILTR_EL0 [
+        /// Don't count at EL1.
+        P OFFSET(31) NUMBITS(1) [],
+
+        /// Don't count at EL0.
+        U OFFSET(30) NUMBITS(1) [],
+
+        /// Non-secure EL1 filtering: count at Non-secure EL1 if different from P.
+        NSK OFFSET(29) NUMBITS(1) [],
+
+        /// Non-secure EL0 filtering: count at Non-secure EL0 if different from U.
+        NSU OFFSET(28) NUMBITS(1) [],
+
+        /// Count at EL2.
+        NSH OFFSET(27) NUMBITS(1) [],
+
+        /// Secure EL3 filtering: count at EL3 if different from P.
+        M OFFSET(26) NUMBITS(1) []
+    ],
+
+    pub PMUSERENR_EL0 [
+        /// Event counter read enable: EL0 can read the event counters.
+        ER OFFSET(3) NUMBITS(1) [],
+
+        /// Cycle counter read enable: EL0 can read the cycle counter.
+        CR OFFSET(2) NUMBITS(1) [],
+
+        /// Software increment write enable: EL0 can write PMSWINC_EL0.
+        SW OFFSET(1) NUMBITS(1) [],
+
+        /// EL0 can access all the Performance Monitors registers.
+        EN OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+macro_rules! pmu_register {
+    ($reg:ident, $name:ident, $register:ty, $asm_name:tt, $($trait:ident),+) => {
+        pub struct $reg;
+
+        $(pmu_register!(@impl $trait, $reg, $register, $asm_name);)+
+
+        pub const $name: $reg = $reg {};
+    };
+    (@impl Readable, $reg:ident, $register:ty, $asm_name:tt) => {
+        impl Readable for $reg {
+            type T = u64;
+            type R = $register;
+
+            sys_coproc_read_raw!(u64, $asm_name, "x");
+        }
+    };
+    (@impl Writeable, $reg:ident, $register:ty, $asm_name:tt) => {
+        impl Writeable for $reg {
+            type T = u64;
+            type R = $register;
+
+            sys_coproc_write_raw!(u64, $asm_name, "x");
+        }
+    };
+}
+
+pmu_register!(
+    PmcrReg,
+    PMCR_EL0,
+    PMCR_EL0::Register,
+    "PMCR_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmselrReg,
+    PMSELR_EL0,
+    PMSELR_EL0::Register,
+    "PMSELR_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmxevtyperReg,
+    PMXEVTYPER_EL0,
+    PMXEVTYPER_EL0::Register,
+    "PMXEVTYPER_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmccfiltrReg,
+    PMCCFILTR_EL0,
+    PMCCFILTR_EL0::Register,
+    "PMCCFILTR_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmuserenrReg,
+    PMUSERENR_EL0,
+    PMUSERENR_EL0::Register,
+    "PMUSERENR_EL0",
+    Readable,
+    Writeable
+);
+// one bit per counter, the cycle counter at bit 31
+pmu_register!(
+    PmcntensetReg,
+    PMCNTENSET_EL0,
+    (),
+    "PMCNTENSET_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmcntenclrReg,
+    PMCNTENCLR_EL0,
+    (),
+    "PMCNTENCLR_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmovsclrReg,
+    PMOVSCLR_EL0,
+    (),
+    "PMOVSCLR_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmccntrReg,
+    PMCCNTR_EL0,
+    (),
+    "PMCCNTR_EL0",
+    Readable,
+    Writeable
+);
+pmu_register!(
+    PmxevcntrReg,
+    PMXEVCNTR_EL0,
+    (),
+    "PMXEVCNTR_EL0",
+    Readable,
+    Writeable
+);
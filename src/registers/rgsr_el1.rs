@@ -0,0 +1,37 @@
+//! Random Allocation Tag Seed Register - EL1
+//!
+//! Holds the state of the pseudo-random tag generator of the IRG instruction. Accessed by its
+//! encoding, `S3_0_C1_C0_5`, so that no architecture extension is needed to assemble it.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub RGSR_EL1 [
+        /// Seed of the pseudo-random generator.
+        SEED OFFSET(8) NUMBITS(16) [],
+
+        /// The last tag generated.
+        TAG OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = RGSR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C1_C0_5", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = RGSR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "S3_0_C1_C0_5", "x");
+}
+
+pub const RGSR_EL1: Reg = Reg {};
@@ -0,0 +1,55 @@
+//! Tag Fault Status Registers - EL1
+//!
+//! Hold the accumulated asynchronous tag check faults: TFSR_EL1 those of EL1 and TFSRE0_EL1
+//! those of EL0. Accessed by their encodings, `S3_0_C5_C6_0` and `S3_0_C5_C6_1`, so that no
+//! architecture extension is needed to assemble them.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub TFSR_EL1 [
+        /// Tag check fault in TTBR1_EL1 addresses.
+        TF1 OFFSET(1) NUMBITS(1) [],
+
+        /// Tag check fault in TTBR0_EL1 addresses.
+        TF0 OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = TFSR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C5_C6_0", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = TFSR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "S3_0_C5_C6_0", "x");
+}
+
+pub struct RegE0;
+
+impl Readable for RegE0 {
+    type T = u64;
+    type R = TFSR_EL1::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C5_C6_1", "x");
+}
+
+impl Writeable for RegE0 {
+    type T = u64;
+    type R = TFSR_EL1::Register;
+
+    sys_coproc_write_raw!(u64, "S3_0_C5_C6_1", "x");
+}
+
+pub const TFSR_EL1: Reg = Reg {};
+pub const TFSRE0_EL1: RegE0 = RegE0 {};
@@ -0,0 +1,35 @@
+//! User Access Override
+//!
+//! When set, the unprivileged load and store instructions (LDTR, STTR, ...) executed at EL1
+//! behave as the corresponding privileged instructions. Accessed by its encoding,
+//! `S3_0_C4_C2_4`, so that no architecture extension is needed to assemble it.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub UAO [
+        /// Unprivileged load and store instructions at EL1 behave as privileged ones.
+        UAO OFFSET(23) NUMBITS(1) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = UAO::Register;
+
+    sys_coproc_read_raw!(u64, "S3_0_C4_C2_4", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = UAO::Register;
+
+    sys_coproc_write_raw!(u64, "S3_0_C4_C2_4", "x");
+}
+
+pub const UAO: Reg = Reg {};
@@ -0,0 +1,38 @@
+//! Virtual SError Exception Syndrome Register
+//!
+//! The syndrome reported in ESR_EL1 for a virtual SError injected with HCR_EL2.VSE (FEAT_RAS).
+//! Accessed by its encoding, `S3_4_C5_C2_3`, so that no architecture extension is needed to
+//! assemble it.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub VSESR_EL2 [
+        /// The syndrome is IMPLEMENTATION DEFINED.
+        IDS OFFSET(24) NUMBITS(1) [],
+
+        /// The ESR_EL1.ISS value of the virtual SError.
+        ISS OFFSET(0) NUMBITS(24) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = VSESR_EL2::Register;
+
+    sys_coproc_read_raw!(u64, "S3_4_C5_C2_3", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = VSESR_EL2::Register;
+
+    sys_coproc_write_raw!(u64, "S3_4_C5_C2_3", "x");
+}
+
+pub const VSESR_EL2: Reg = Reg {};
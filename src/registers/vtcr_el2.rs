@@ -0,0 +1,78 @@
+//! Virtualization Translation Control Register - EL2
+//!
+//! The control register for stage 2 of the EL1&0 translation regime.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub VTCR_EL2 [
+        /// VMID Size. Selects 16-bit VMIDs, if supported (ID_AA64MMFR1_EL1.VMIDBits).
+        VS OFFSET(19) NUMBITS(1) [
+            Bits8 = 0,
+            Bits16 = 1
+        ],
+
+        /// Physical Address Size, the size of the output of stage 2.
+        PS OFFSET(16) NUMBITS(3) [
+            Bits_32 = 0b000,
+            Bits_36 = 0b001,
+            Bits_40 = 0b010,
+            Bits_42 = 0b011,
+            Bits_44 = 0b100,
+            Bits_48 = 0b101,
+            Bits_52 = 0b110
+        ],
+
+        /// Granule size for VTTBR_EL2.
+        TG0 OFFSET(14) NUMBITS(2) [
+            KiB_4 = 0b00,
+            KiB_64 = 0b01,
+            KiB_16 = 0b10
+        ],
+
+        /// Shareability attribute for memory associated with translation table walks using
+        /// VTTBR_EL2.
+        SH0 OFFSET(12) NUMBITS(2) [
+            None = 0b00,
+            Outer = 0b10,
+            Inner = 0b11
+        ],
+
+        /// Outer cacheability attribute for memory associated with translation table walks using
+        /// VTTBR_EL2.
+        ORGN0 OFFSET(10) NUMBITS(2) [],
+
+        /// Inner cacheability attribute for memory associated with translation table walks using
+        /// VTTBR_EL2.
+        IRGN0 OFFSET(8) NUMBITS(2) [],
+
+        /// Starting level of the stage 2 translation lookup. The encoding depends on the
+        /// granule size.
+        SL0 OFFSET(6) NUMBITS(2) [],
+
+        /// The size offset of the memory region addressed by VTTBR_EL2. The region size is
+        /// 2^(64-T0SZ) bytes.
+        T0SZ OFFSET(0) NUMBITS(6) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = VTCR_EL2::Register;
+
+    sys_coproc_read_raw!(u64, "VTCR_EL2", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = VTCR_EL2::Register;
+
+    sys_coproc_write_raw!(u64, "VTCR_EL2", "x");
+}
+
+pub const VTCR_EL2: Reg = Reg {};
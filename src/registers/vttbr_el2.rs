@@ -0,0 +1,40 @@
+//! Virtualization Translation Table Base Register - EL2
+//!
+//! Holds the base address of the stage 2 translation tables of the EL1&0 translation regime,
+//! and the VMID of the virtual machine they belong to.
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+};
+
+register_bitfields! {u64,
+    pub VTTBR_EL2 [
+        /// The VMID for the translation table. Bits 63:56 are RES0 with 8-bit VMIDs.
+        VMID OFFSET(48) NUMBITS(16) [],
+
+        /// Translation table base address.
+        BADDR OFFSET(1) NUMBITS(47) [],
+
+        /// Common not Private.
+        CnP OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+pub struct Reg;
+
+impl Readable for Reg {
+    type T = u64;
+    type R = VTTBR_EL2::Register;
+
+    sys_coproc_read_raw!(u64, "VTTBR_EL2", "x");
+}
+
+impl Writeable for Reg {
+    type T = u64;
+    type R = VTTBR_EL2::Register;
+
+    sys_coproc_write_raw!(u64, "VTTBR_EL2", "x");
+}
+
+pub const VTTBR_EL2: Reg = Reg {};
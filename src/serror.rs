@@ -0,0 +1,134 @@
+//! Masking, synchronization and injection of SError interrupts, the asynchronous aborts.
+//!
+//! An SError reports an error the PE could not attribute to the instruction that caused it, e.g.
+//! a write to a device that failed after the store retired. It is taken whenever PSTATE.A is
+//! clear, possibly long after the access. At boundaries such as the unmapping of a device or a
+//! context switch, [`synchronize`] waits for the outstanding accesses and collects the pending
+//! SError, so that it is reported on the side of the boundary that caused it.
+//!
+//! With the RAS Extension (FEAT_RAS), the Error Synchronization Barrier ([`esb`]) defers a pending
+//! SError to DISR_EL1 while SErrors are masked, so that its syndrome can be read without taking
+//! the exception. Without it, [`synchronize`] briefly unmasks SErrors so that a pending one is
+//! taken by the SError handler.
+
+use crate::{
+    barrier,
+    el::{debug_require_el, ExceptionLevel},
+    interrupts::{DaifGuard, DaifMask},
+    registers::{esr::SErrorIss, *},
+};
+
+/// The Virtual SError interrupt bit of HCR_EL2 (VSE).
+const HCR_EL2_VSE: u64 = 1 << 8;
+
+/// Returns whether SErrors are masked (PSTATE.A).
+#[inline]
+pub fn is_masked() -> bool {
+    crate::interrupts::masked().contains(DaifMask::SERROR)
+}
+
+/// Masks SErrors.
+#[inline]
+pub fn mask() {
+    unsafe { core::arch::asm!("msr daifset, #4", options(nostack)) };
+}
+
+/// Unmasks SErrors, so that a pending one is taken.
+#[inline]
+pub fn unmask() {
+    unsafe { core::arch::asm!("msr daifclr, #4", options(nostack)) };
+}
+
+/// Returns whether an SError is pending, masked or not (ISR_EL1.A).
+#[inline]
+pub fn is_pending() -> bool {
+    ISR_EL1.is_set(ISR_EL1::A)
+}
+
+/// Error Synchronization Barrier.
+///
+/// Synchronizes the SErrors of the instructions before it: with SErrors masked, a pending one is
+/// deferred to DISR_EL1, otherwise it is taken. A NOP without the RAS Extension.
+#[inline]
+pub fn esb() {
+    // ESB, in the hint space so that no architecture extension is needed to assemble it
+    unsafe { core::arch::asm!("hint #16", options(nostack)) };
+}
+
+/// Returns the syndrome of the SError deferred by an [`esb`], if any, and clears it.
+///
+/// Requires the RAS Extension: DISR_EL1 is UNDEFINED without it.
+#[inline]
+pub fn take_deferred() -> Option<SErrorIss> {
+    let disr = DISR_EL1.extract();
+    if !disr.is_set(DISR_EL1::A) {
+        return None;
+    }
+    DISR_EL1.set(0);
+    Some(SErrorIss::new(disr.get() as u32))
+}
+
+/// Completes the outstanding memory accesses and synchronizes their SErrors.
+///
+/// With the RAS Extension, a pending SError is deferred and its syndrome returned, and the
+/// SError exception is not taken. Without it, SErrors are unmasked for a moment, so that a
+/// pending one is taken by the SError handler, and `None` is returned. SErrors are masked again
+/// afterwards if they were masked before.
+#[inline]
+pub fn synchronize() -> Option<SErrorIss> {
+    unsafe { barrier::dsb(barrier::SY) };
+    if ID_AA64PFR0_EL1.read(ID_AA64PFR0_EL1::RAS) != 0 {
+        let _guard = DaifGuard::new(DaifMask::SERROR);
+        esb();
+        take_deferred()
+    } else {
+        unsafe { barrier::isb() };
+        if is_masked() {
+            unmask();
+            unsafe { barrier::isb() };
+            mask();
+        }
+        None
+    }
+}
+
+/// Injects a virtual SError into EL1 and EL0, taken when it is not masked by PSTATE.A there.
+///
+/// With the RAS Extension, `syndrome` is the ISS reported in ESR_EL1, and the implementation
+/// chooses it when `None`. The injection stays pending until the virtual SError is taken, see
+/// [`is_virtual_pending`].
+///
+/// # Safety
+///
+/// Must run at EL2, with HCR_EL2.AMO set so that the virtual SError is routed to EL1. A
+/// `syndrome` requires the RAS Extension.
+#[inline]
+pub unsafe fn inject_virtual(syndrome: Option<SErrorIss>) {
+    debug_require_el(ExceptionLevel::EL2);
+    if let Some(syndrome) = syndrome {
+        VSESR_EL2.set(syndrome.value() as u64);
+    }
+    HCR_EL2.set(HCR_EL2.get() | HCR_EL2_VSE);
+    barrier::isb();
+}
+
+/// Returns whether a virtual SError injected with [`inject_virtual`] was not taken yet.
+///
+/// Must run at EL2.
+#[inline]
+pub fn is_virtual_pending() -> bool {
+    debug_require_el(ExceptionLevel::EL2);
+    HCR_EL2.get() & HCR_EL2_VSE != 0
+}
+
+/// Cancels a virtual SError injected with [`inject_virtual`] that was not taken yet.
+///
+/// # Safety
+///
+/// Must run at EL2.
+#[inline]
+pub unsafe fn cancel_virtual() {
+    debug_require_el(ExceptionLevel::EL2);
+    HCR_EL2.set(HCR_EL2.get() & !HCR_EL2_VSE);
+    barrier::isb();
+}
@@ -0,0 +1,206 @@
+//! Simulation of the barriers and TLB maintenance on other architectures, for host unit tests.
+//!
+//! Most of the paging code is data manipulation, which runs the same on any host: only its
+//! barriers and TLB invalidations need an AArch64 PE. Built for another architecture, e.g. by
+//! `cargo test` on an x86 host, the [barriers](crate::barrier) and the TLB maintenance functions
+//! of [`translation`](crate::translation) used by the mappers do nothing but record an [`Event`].
+//! Kernels can thus unit test their virtual memory code on the host, and check the maintenance it
+//! performs with a [`Recorder`], installed once with [`set_recorder`], or with the counters.
+//!
+//! The events of all threads go to the same recorder and counters: tests running in parallel see
+//! each other's events.
+//!
+//! This module only exists when not building for AArch64.
+
+use crate::{PhysAddr, VirtAddr};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// The TLB entries targeted by a simulated invalidation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlbiTarget {
+    /// All the stage 1 entries used at EL1.
+    All,
+    /// The non-global entries of an ASID.
+    Asid(u16),
+    /// The entries translating a virtual address.
+    Vaddr {
+        /// The address.
+        vaddr: VirtAddr,
+        /// The ASID of the entries, for all ASIDs if `None`. Global entries are always targeted.
+        asid: Option<u16>,
+        /// Only the entries of the last level, the blocks and pages, are targeted.
+        last_level: bool,
+    },
+    /// The entries translating the addresses in [start, end).
+    Range {
+        /// The first address of the range.
+        start: VirtAddr,
+        /// The address after the range.
+        end: VirtAddr,
+        /// The ASID of the entries, for all ASIDs if `None`. Global entries are always targeted.
+        asid: Option<u16>,
+    },
+    /// All the stage 1 entries used at EL2.
+    AllEl2,
+    /// All the stage 1 and stage 2 entries of a guest, of the current VMID if `None`.
+    Guest(Option<u16>),
+    /// The stage 2 entries of the current VMID translating an intermediate physical address,
+    /// with all the stage 1 entries of the VMID.
+    Ipa(PhysAddr),
+}
+
+/// An operation simulated on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A Data Memory Barrier.
+    Dmb,
+    /// A Data Synchronization Barrier.
+    Dsb,
+    /// An Instruction Synchronization Barrier.
+    Isb,
+    /// A completed TLB invalidation, including its barriers.
+    Tlbi {
+        /// The entries invalidated.
+        target: TlbiTarget,
+        /// Only the TLB of the current PE is invalidated, instead of those of the Inner
+        /// Shareable domain.
+        local: bool,
+    },
+}
+
+/// A receiver of the simulated events.
+pub trait Recorder: Sync {
+    /// Records an event.
+    fn record(&self, event: Event);
+}
+
+/// The error returned by [`set_recorder`] if a recorder has already been installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetRecorderError;
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const INITIALIZED: usize = 2;
+
+static STATE: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
+static mut RECORDER: Option<&dyn Recorder> = None;
+
+static BARRIERS: AtomicU64 = AtomicU64::new(0);
+static TLB_INVALIDATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Installs the recorder that receives all simulated events.
+///
+/// The recorder can only be installed once.
+pub fn set_recorder(recorder: &'static dyn Recorder) -> Result<(), SetRecorderError> {
+    STATE
+        .compare_exchange(
+            UNINITIALIZED,
+            INITIALIZING,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .map_err(|_| SetRecorderError)?;
+    unsafe { RECORDER = Some(recorder) };
+    STATE.store(INITIALIZED, Ordering::Release);
+    Ok(())
+}
+
+/// Returns the number of barriers simulated so far.
+pub fn barriers() -> u64 {
+    BARRIERS.load(Ordering::Relaxed)
+}
+
+/// Returns the number of TLB invalidations simulated so far.
+pub fn tlb_invalidations() -> u64 {
+    TLB_INVALIDATIONS.load(Ordering::Relaxed)
+}
+
+/// Counts `event` and passes it to the installed recorder.
+pub(crate) fn record(event: Event) {
+    match event {
+        Event::Tlbi { .. } => &TLB_INVALIDATIONS,
+        _ => &BARRIERS,
+    }
+    .fetch_add(1, Ordering::Relaxed);
+    if STATE.load(Ordering::Acquire) == INITIALIZED {
+        if let Some(recorder) = unsafe { RECORDER } {
+            recorder.record(event);
+        }
+    }
+}
+
+/// Records a TLB invalidation of `target` in the Inner Shareable domain.
+#[inline]
+pub(crate) fn tlbi(target: TlbiTarget) {
+    record(Event::Tlbi {
+        target,
+        local: false,
+    });
+}
+
+/// Records a TLB invalidation of `target` in the current PE.
+#[inline]
+pub(crate) fn local_tlbi(target: TlbiTarget) {
+    record(Event::Tlbi {
+        target,
+        local: true,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{barrier, translation};
+
+    struct LastAsidTlbi(AtomicU64);
+
+    impl Recorder for LastAsidTlbi {
+        fn record(&self, event: Event) {
+            if let Event::Tlbi {
+                target:
+                    TlbiTarget::Vaddr {
+                        vaddr,
+                        asid: Some(7),
+                        ..
+                    },
+                local: false,
+            } = event
+            {
+                self.0.store(vaddr.as_u64(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    static RECORDER: LastAsidTlbi = LastAsidTlbi(AtomicU64::new(0));
+
+    #[test]
+    pub fn test_simulation() {
+        set_recorder(&RECORDER).unwrap();
+        assert_eq!(set_recorder(&RECORDER), Err(SetRecorderError));
+
+        // other tests may run at the same time
+        let (barriers_before, tlbis_before) = (barriers(), tlb_invalidations());
+        unsafe {
+            barrier::dsb(barrier::ISH);
+            barrier::isb();
+        }
+        translation::invalidate_tlb_vaddr_asid(VirtAddr::new(0x1234_5000), 7);
+        assert!(barriers() >= barriers_before + 2);
+        assert!(tlb_invalidations() > tlbis_before);
+        assert_eq!(RECORDER.0.load(Ordering::Relaxed), 0x1234_5000);
+
+        let mut yields = 0;
+        translation::invalidate_tlb_range_chunked(
+            VirtAddr::new(0x10_0000),
+            VirtAddr::new(0x10_5000),
+            2,
+            || yields += 1,
+        );
+        assert_eq!(yields, 2);
+
+        let tlbis_before = tlb_invalidations();
+        translation::invalidate_tlb_vmid(3);
+        translation::local_invalidate_tlb_ipa(PhysAddr::new(0x8000_0000));
+        assert!(tlb_invalidations() >= tlbis_before + 2);
+    }
+}
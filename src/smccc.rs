@@ -0,0 +1,281 @@
+//! SMC Calling Convention (SMCCC) client, for the firmware services other than PSCI.
+//!
+//! The calls are issued through the conduit selected with [`psci::set_conduit`], which is the
+//! same for all the services of a system. [`call`] follows the SMCCC 1.1 register usage: up to
+//! seven arguments in `x1` to `x7`, and the results in `x0` to `x3`. [`call_extended`] follows
+//! SMCCC 1.2, for the services passing up to 17 arguments and 18 results in `x0` to `x17`. See
+//! the Arm SMC Calling Convention specification (DEN0028) for the function identifiers.
+//!
+//! The version of the convention implemented by the firmware, and whether it implements an
+//! architecture function, are probed with [`version`] and [`arch_features`]. The Spectre
+//! mitigations provided by the firmware are [`workaround_1`], [`workaround_2`] and
+//! [`workaround_3`].
+//!
+//! [`psci::set_conduit`]: crate::psci::set_conduit
+
+use crate::psci;
+#[cfg(target_arch = "aarch64")]
+use crate::psci::{conduit, Conduit};
+
+/// Returns the version of the implemented SMC Calling Convention.
+pub const SMCCC_VERSION: u32 = 0x8000_0000;
+/// Queries whether an architecture function is implemented, and its features.
+pub const SMCCC_ARCH_FEATURES: u32 = 0x8000_0001;
+/// Returns the SoC identification.
+pub const SMCCC_ARCH_SOC_ID: u32 = 0x8000_0002;
+/// Invalidates the branch predictor, mitigating Spectre variant 2 (CVE-2017-5715).
+pub const SMCCC_ARCH_WORKAROUND_1: u32 = 0x8000_8000;
+/// Enables or disables the mitigation of Spectre variant 4 (CVE-2018-3639) for the caller.
+pub const SMCCC_ARCH_WORKAROUND_2: u32 = 0x8000_7FFF;
+/// Invalidates the branch history, mitigating Spectre-BHB (CVE-2022-23960) and variant 2.
+pub const SMCCC_ARCH_WORKAROUND_3: u32 = 0x8000_3FFF;
+
+/// The value of `x0` returned by the calls to unknown function identifiers.
+pub const UNKNOWN_FUNCTION: u64 = 0xFFFF_FFFF;
+
+/// The service owning a function identifier, in bits [29:24].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Service {
+    /// The architecture calls of SMCCC itself.
+    Arm = 0,
+    /// CPU service calls.
+    Cpu = 1,
+    /// Silicon partner (SiP) service calls.
+    SiP = 2,
+    /// OEM service calls.
+    Oem = 3,
+    /// Standard secure service calls, e.g. PSCI, TRNG and SDEI.
+    StandardSecure = 4,
+    /// Standard hypervisor service calls.
+    StandardHypervisor = 5,
+    /// Vendor specific hypervisor service calls.
+    VendorHypervisor = 6,
+}
+
+/// Returns the identifier of the fast call `number` of `service`, with the SMC64 calling
+/// convention if `smc64` is set, and SMC32 otherwise.
+pub const fn fast_call(service: Service, smc64: bool, number: u16) -> u32 {
+    1 << 31 | (smc64 as u32) << 30 | (service as u32) << 24 | number as u32
+}
+
+/// The error codes returned by the SMCCC architecture functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmcccError {
+    /// The function or the queried feature is not implemented by the firmware.
+    NotSupported,
+    /// The call is not needed on the calling PE, e.g. a workaround for an unaffected core.
+    NotRequired,
+    /// An argument is invalid.
+    InvalidParameter,
+    /// A negative return value not defined by the specification.
+    Unknown(i32),
+}
+
+impl SmcccError {
+    /// Converts the raw return value of an SMCCC architecture call into a `Result`.
+    ///
+    /// Non-negative values are returned unchanged.
+    pub fn check(ret: u64) -> Result<u64, SmcccError> {
+        let ret = ret as i32;
+        match ret {
+            0.. => Ok(ret as u64),
+            -1 => Err(SmcccError::NotSupported),
+            -2 => Err(SmcccError::NotRequired),
+            -3 => Err(SmcccError::InvalidParameter),
+            other => Err(SmcccError::Unknown(other)),
+        }
+    }
+}
+
+/// Issues the call with the conduit instruction, passing `$args` from `x1` and returning `$ret`
+/// from `x0`.
+#[cfg(target_arch = "aarch64")]
+macro_rules! smccc_asm {
+    ($insn:literal, $function:expr, $args:expr, $ret:expr) => {
+        core::arch::asm!(
+            $insn,
+            inlateout("x0") $function as u64 => $ret[0],
+            inlateout("x1") $args[0] => $ret[1],
+            inlateout("x2") $args[1] => $ret[2],
+            inlateout("x3") $args[2] => $ret[3],
+            in("x4") $args[3],
+            in("x5") $args[4],
+            in("x6") $args[5],
+            in("x7") $args[6],
+            clobber_abi("C"),
+            options(nostack)
+        )
+    };
+}
+
+/// Issues an SMCCC 1.2 call with the conduit instruction, passing `$args` from `x1` and
+/// returning `$ret` from `x0`.
+#[cfg(target_arch = "aarch64")]
+macro_rules! smccc_asm_extended {
+    ($insn:literal, $function:expr, $args:expr, $ret:expr) => {
+        core::arch::asm!(
+            $insn,
+            inlateout("x0") $function as u64 => $ret[0],
+            inlateout("x1") $args[0] => $ret[1],
+            inlateout("x2") $args[1] => $ret[2],
+            inlateout("x3") $args[2] => $ret[3],
+            inlateout("x4") $args[3] => $ret[4],
+            inlateout("x5") $args[4] => $ret[5],
+            inlateout("x6") $args[5] => $ret[6],
+            inlateout("x7") $args[6] => $ret[7],
+            inlateout("x8") $args[7] => $ret[8],
+            inlateout("x9") $args[8] => $ret[9],
+            inlateout("x10") $args[9] => $ret[10],
+            inlateout("x11") $args[10] => $ret[11],
+            inlateout("x12") $args[11] => $ret[12],
+            inlateout("x13") $args[12] => $ret[13],
+            inlateout("x14") $args[13] => $ret[14],
+            inlateout("x15") $args[14] => $ret[15],
+            inlateout("x16") $args[15] => $ret[16],
+            inlateout("x17") $args[16] => $ret[17],
+            clobber_abi("C"),
+            options(nostack)
+        )
+    };
+}
+
+/// Issues an SMCCC call with up to seven arguments, and returns the values of `x0` to `x3`.
+///
+/// The missing arguments are passed as 0. Panics if there are more than seven arguments.
+///
+/// # Safety
+///
+/// Some firmware functions power down cores, transfer control to an arbitrary entry point, or
+/// access memory at the addresses passed as arguments.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub unsafe fn call(function: u32, args: &[u64]) -> [u64; 4] {
+    assert!(args.len() <= 7, "too many SMCCC arguments");
+    let mut regs = [0; 7];
+    regs[..args.len()].copy_from_slice(args);
+    match () {
+        #[cfg(target_arch = "aarch64")]
+        () => {
+            let mut ret = [0; 4];
+            match conduit() {
+                Conduit::Smc => smccc_asm!("smc #0", function, regs, ret),
+                Conduit::Hvc => smccc_asm!("hvc #0", function, regs, ret),
+            }
+            ret
+        }
+
+        #[cfg(not(target_arch = "aarch64"))]
+        () => unimplemented!(),
+    }
+}
+
+/// Issues an SMCCC 1.2 call with up to 17 arguments, and returns the values of `x0` to `x17`.
+///
+/// The missing arguments are passed as 0. Panics if there are more than 17 arguments. The
+/// firmware must implement SMCCC 1.2 or later, see [`version`].
+///
+/// # Safety
+///
+/// See [`call`].
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub unsafe fn call_extended(function: u32, args: &[u64]) -> [u64; 18] {
+    assert!(args.len() <= 17, "too many SMCCC arguments");
+    let mut regs = [0; 17];
+    regs[..args.len()].copy_from_slice(args);
+    match () {
+        #[cfg(target_arch = "aarch64")]
+        () => {
+            let mut ret = [0; 18];
+            match conduit() {
+                Conduit::Smc => smccc_asm_extended!("smc #0", function, regs, ret),
+                Conduit::Hvc => smccc_asm_extended!("hvc #0", function, regs, ret),
+            }
+            ret
+        }
+
+        #[cfg(not(target_arch = "aarch64"))]
+        () => unimplemented!(),
+    }
+}
+
+/// Returns the (major, minor) version of the SMC Calling Convention implemented by the firmware.
+///
+/// `SMCCC_VERSION` is only called if PSCI reports it as implemented: firmware without it
+/// implements SMCCC 1.0.
+pub fn version() -> (u16, u16) {
+    if !psci::is_supported(SMCCC_VERSION) {
+        return (1, 0);
+    }
+    match SmcccError::check(unsafe { call(SMCCC_VERSION, &[]) }[0]) {
+        Ok(version) => ((version >> 16) as u16, version as u16),
+        Err(_) => (1, 0),
+    }
+}
+
+/// Queries whether the architecture function `function` is implemented (SMCCC_ARCH_FEATURES),
+/// and returns its feature flags, or 0 if it has none.
+///
+/// Only available from SMCCC 1.1, see [`version`]. For the workarounds, `Err(NotRequired)` means
+/// that the calling PE is not affected.
+pub fn arch_features(function: u32) -> Result<u32, SmcccError> {
+    if version() < (1, 1) {
+        return Err(SmcccError::NotSupported);
+    }
+    let ret = unsafe { call(SMCCC_ARCH_FEATURES, &[function as u64]) }[0];
+    SmcccError::check(ret).map(|features| features as u32)
+}
+
+/// Invalidates the branch predictor of the calling PE (SMCCC_ARCH_WORKAROUND_1), e.g. on entry
+/// from a lower exception level.
+///
+/// Should only be called if [`arch_features`] reports it implemented and required.
+#[inline]
+pub fn workaround_1() {
+    unsafe { call(SMCCC_ARCH_WORKAROUND_1, &[]) };
+}
+
+/// Enables or disables the mitigation of Spectre variant 4 for the calling PE
+/// (SMCCC_ARCH_WORKAROUND_2).
+///
+/// Should only be called if [`arch_features`] reports it implemented and required.
+#[inline]
+pub fn workaround_2(enable: bool) {
+    unsafe { call(SMCCC_ARCH_WORKAROUND_2, &[enable as u64]) };
+}
+
+/// Invalidates the branch history of the calling PE (SMCCC_ARCH_WORKAROUND_3), which also
+/// mitigates what [`workaround_1`] does.
+///
+/// Should only be called if [`arch_features`] reports it implemented and required.
+#[inline]
+pub fn workaround_3() {
+    unsafe { call(SMCCC_ARCH_WORKAROUND_3, &[]) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_function_ids() {
+        assert_eq!(
+            fast_call(Service::Arm, false, 0x8000),
+            SMCCC_ARCH_WORKAROUND_1
+        );
+        assert_eq!(fast_call(Service::StandardSecure, true, 3), psci::CPU_ON);
+        // TRNG_RND64
+        assert_eq!(fast_call(Service::StandardSecure, true, 0x53), 0xC400_0053);
+
+        assert_eq!(SmcccError::check(0x1_0002), Ok(0x1_0002));
+        assert_eq!(
+            SmcccError::check(-2i64 as u64),
+            Err(SmcccError::NotRequired)
+        );
+        assert_eq!(
+            SmcccError::check(UNKNOWN_FUNCTION),
+            Err(SmcccError::NotSupported)
+        );
+    }
+}
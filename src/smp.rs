@@ -0,0 +1,75 @@
+//! Bring-up of the secondary cores.
+//!
+//! The device tree describes how each core is started by the `enable-method` property of its
+//! `cpu` node: `"psci"`, through the PSCI `CPU_ON` call of the firmware, or `"spin-table"`, where
+//! the core spins in firmware code until an entry point is written to its `cpu-release-addr`.
+//! [`start_cpu`] handles both.
+
+use crate::{
+    barrier,
+    cache::{Cache, Clean, DCache, PoC},
+    cpu::Affinity,
+    psci::{self, AffinityState, PsciError},
+    PhysAddr,
+};
+
+/// The method used to start a secondary core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableMethod {
+    /// The PSCI `CPU_ON` call, through the conduit selected with
+    /// [`psci::set_conduit`].
+    Psci,
+    /// Spin-table release, with a mapping of the `cpu-release-addr` of the core.
+    SpinTable(*mut u64),
+}
+
+/// Starts the core with the given affinity at the physical address `entry`.
+///
+/// With PSCI the core starts with `context_id` in `x0`. With a spin table `context_id` is not
+/// passed, and the core starts in the Exception level of the firmware's spin loop, usually EL2.
+/// In both cases the MMU is off.
+///
+/// # Safety
+///
+/// `entry` must point to valid startup code, see [`psci::cpu_on`]. With a spin table, the
+/// release address must be the one of the target core.
+#[inline]
+pub unsafe fn start_cpu(
+    target: Affinity,
+    method: EnableMethod,
+    entry: PhysAddr,
+    context_id: u64,
+) -> Result<(), PsciError> {
+    match method {
+        EnableMethod::Psci => psci::cpu_on(target.value(), entry, context_id),
+        EnableMethod::SpinTable(release_addr) => {
+            spin_table_release(release_addr, entry);
+            Ok(())
+        }
+    }
+}
+
+/// Releases a core waiting in a spin table: writes `entry` to its release address, cleans it to
+/// the point of coherency, as the waiting core runs with its caches off, and wakes it up with
+/// `sev`.
+///
+/// # Safety
+///
+/// `release_addr` must be a valid mapping of the `cpu-release-addr` of the core, and `entry`
+/// must point to valid startup code.
+#[inline]
+pub unsafe fn spin_table_release(release_addr: *mut u64, entry: PhysAddr) {
+    release_addr.write_volatile(entry.as_u64());
+    let addr = release_addr as usize;
+    DCache::<Clean, PoC>::flush_range(addr, addr + 8, barrier::SY);
+    crate::asm::sev();
+}
+
+/// Returns whether the core with the given affinity is on, or being powered up, according to
+/// the PSCI `AFFINITY_INFO` call.
+pub fn is_cpu_on(target: Affinity) -> Result<bool, PsciError> {
+    match psci::affinity_info(target.value(), 0)? {
+        AffinityState::Off => Ok(false),
+        AffinityState::On | AffinityState::OnPending => Ok(true),
+    }
+}
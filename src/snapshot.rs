@@ -0,0 +1,318 @@
+//! Snapshots of the EL1 system register context.
+//!
+//! A [`CpuSnapshot`] captures the EL1 system registers at one point in time. Two snapshots can be
+//! compared with [`CpuSnapshot::diff`], which reports every register that changed together with
+//! the fields that differ, e.g. around suspend/resume, EL transitions or world switches.
+
+use crate::registers::*;
+use core::fmt;
+
+/// A named bit field of a system register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegField {
+    /// The field name, as in the ARM ARM.
+    pub name: &'static str,
+    /// The position of the least significant bit of the field.
+    pub shift: usize,
+    /// The mask of the field, not shifted.
+    pub mask: u64,
+}
+
+impl RegField {
+    /// Extracts the value of this field from the raw register value.
+    #[inline]
+    pub fn read(&self, value: u64) -> u64 {
+        (value >> self.shift) & self.mask
+    }
+}
+
+macro_rules! reg_fields {
+    ($name:ident = $reg:ident [$($field:ident),*]) => {
+        const $name: &[RegField] = &[$(RegField {
+            name: stringify!($field),
+            shift: $reg::$field.shift,
+            mask: $reg::$field.mask,
+        }),*];
+    };
+}
+
+reg_fields!(SCTLR_EL1_FIELDS = SCTLR_EL1 [
+    UCI, EE, E0E, WXN, NTWE, NTWI, UCT, DZE, I, UMA, NAA, SA0, SA, C, A, M
+]);
+reg_fields!(TCR_EL1_FIELDS = TCR_EL1 [
+    TBID1, TBID0, TBI1, TBI0, AS, IPS, TG1, SH1, ORGN1, IRGN1, EPD1, A1, T1SZ, TG0, SH0, ORGN0,
+    IRGN0, EPD0, T0SZ
+]);
+reg_fields!(TTBR0_EL1_FIELDS = TTBR0_EL1 [ASID, BADDR, CnP]);
+reg_fields!(TTBR1_EL1_FIELDS = TTBR1_EL1 [ASID, BADDR, CnP]);
+reg_fields!(SPSR_EL1_FIELDS = SPSR_EL1 [N, Z, C, V, SS, IL, D, A, I, F, M]);
+reg_fields!(ESR_EL1_FIELDS = ESR_EL1 [EC, IL, ISS]);
+reg_fields!(DAIF_FIELDS = DAIF [D, A, I, F]);
+
+const MAIR_EL1_FIELDS: &[RegField] = &[
+    mair_attr("Attr7", 7),
+    mair_attr("Attr6", 6),
+    mair_attr("Attr5", 5),
+    mair_attr("Attr4", 4),
+    mair_attr("Attr3", 3),
+    mair_attr("Attr2", 2),
+    mair_attr("Attr1", 1),
+    mair_attr("Attr0", 0),
+];
+
+const fn mair_attr(name: &'static str, index: usize) -> RegField {
+    RegField {
+        name,
+        shift: index * 8,
+        mask: 0xff,
+    }
+}
+
+macro_rules! sys_regs {
+    ($($reg:ident => $fields:expr),* $(,)?) => {
+        /// System registers captured by a [`CpuSnapshot`].
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum SysReg {
+            $($reg),*
+        }
+
+        impl SysReg {
+            /// All captured registers, in capture order.
+            pub const ALL: &'static [SysReg] = &[$(SysReg::$reg),*];
+
+            /// Returns the architectural name of the register.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(SysReg::$reg => stringify!($reg)),*
+                }
+            }
+
+            /// Returns the decoded fields of the register, or an empty slice if the register is
+            /// just a 64-bit value (e.g. an address).
+            pub fn fields(self) -> &'static [RegField] {
+                match self {
+                    $(SysReg::$reg => $fields),*
+                }
+            }
+
+            /// Reads the current value of the register.
+            #[inline]
+            pub fn read(self) -> u64 {
+                match self {
+                    $(SysReg::$reg => $reg.get()),*
+                }
+            }
+        }
+    };
+}
+
+sys_regs! {
+    SCTLR_EL1 => SCTLR_EL1_FIELDS,
+    TCR_EL1 => TCR_EL1_FIELDS,
+    MAIR_EL1 => MAIR_EL1_FIELDS,
+    TTBR0_EL1 => TTBR0_EL1_FIELDS,
+    TTBR1_EL1 => TTBR1_EL1_FIELDS,
+    VBAR_EL1 => &[],
+    ELR_EL1 => &[],
+    SPSR_EL1 => SPSR_EL1_FIELDS,
+    ESR_EL1 => ESR_EL1_FIELDS,
+    FAR_EL1 => &[],
+    SP_EL0 => &[],
+    TPIDR_EL0 => &[],
+    TPIDRRO_EL0 => &[],
+    TPIDR_EL1 => &[],
+    DAIF => DAIF_FIELDS,
+}
+
+/// The number of registers captured by a [`CpuSnapshot`].
+const SYS_REG_COUNT: usize = SysReg::ALL.len();
+
+impl fmt::Display for SysReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The values of the EL1 system registers at one point in time.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CpuSnapshot {
+    regs: [u64; SYS_REG_COUNT],
+}
+
+impl CpuSnapshot {
+    /// Creates a snapshot with all registers set to zero.
+    pub const fn zero() -> Self {
+        Self {
+            regs: [0; SYS_REG_COUNT],
+        }
+    }
+
+    /// Captures the current values of all registers in [`SysReg::ALL`].
+    pub fn capture() -> Self {
+        let mut snapshot = Self::zero();
+        for &reg in SysReg::ALL {
+            snapshot.set(reg, reg.read());
+        }
+        snapshot
+    }
+
+    /// Returns the captured value of the given register.
+    #[inline]
+    pub fn get(&self, reg: SysReg) -> u64 {
+        self.regs[reg as usize]
+    }
+
+    /// Overrides the captured value of the given register.
+    #[inline]
+    pub fn set(&mut self, reg: SysReg, value: u64) {
+        self.regs[reg as usize] = value;
+    }
+
+    /// Returns an iterator over the registers whose value differs between `self` (old) and
+    /// `other` (new).
+    pub fn diff<'a>(&'a self, other: &'a CpuSnapshot) -> SnapshotDiff<'a> {
+        SnapshotDiff {
+            old: self,
+            new: other,
+            next: 0,
+        }
+    }
+}
+
+impl Default for CpuSnapshot {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl fmt::Debug for CpuSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("CpuSnapshot");
+        for &reg in SysReg::ALL {
+            f.field(reg.name(), &format_args!("{:#x}", self.get(reg)));
+        }
+        f.finish()
+    }
+}
+
+/// An iterator over the changed registers of two snapshots, returned by [`CpuSnapshot::diff`].
+#[derive(Debug, Clone)]
+pub struct SnapshotDiff<'a> {
+    old: &'a CpuSnapshot,
+    new: &'a CpuSnapshot,
+    next: usize,
+}
+
+impl<'a> Iterator for SnapshotDiff<'a> {
+    type Item = RegisterDiff;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(&reg) = SysReg::ALL.get(self.next) {
+            self.next += 1;
+            let (old, new) = (self.old.get(reg), self.new.get(reg));
+            if old != new {
+                return Some(RegisterDiff { reg, old, new });
+            }
+        }
+        None
+    }
+}
+
+/// A register whose value differs between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterDiff {
+    /// The changed register.
+    pub reg: SysReg,
+    /// The value in the old snapshot.
+    pub old: u64,
+    /// The value in the new snapshot.
+    pub new: u64,
+}
+
+impl RegisterDiff {
+    /// Returns the mask of the bits that changed.
+    #[inline]
+    pub fn changed_bits(&self) -> u64 {
+        self.old ^ self.new
+    }
+
+    /// Returns an iterator over the decoded fields that changed.
+    ///
+    /// The iterator is empty for registers without field definitions.
+    pub fn changed_fields(&self) -> impl Iterator<Item = FieldDiff> {
+        let (old, new) = (self.old, self.new);
+        self.reg.fields().iter().filter_map(move |field| {
+            let (old, new) = (field.read(old), field.read(new));
+            if old != new {
+                Some(FieldDiff { field, old, new })
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl fmt::Display for RegisterDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:#x} -> {:#x}", self.reg, self.old, self.new)?;
+        let mut fields = self.changed_fields().peekable();
+        if fields.peek().is_some() {
+            f.write_str(" (")?;
+            for (i, field) in fields.enumerate() {
+                if i != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", field)?;
+            }
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+/// A register field whose value differs between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldDiff {
+    /// The changed field.
+    pub field: &'static RegField,
+    /// The field value in the old snapshot.
+    pub old: u64,
+    /// The field value in the new snapshot.
+    pub new: u64,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:#x} -> {:#x}", self.field.name, self.old, self.new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_snapshot_diff() {
+        let old = CpuSnapshot::zero();
+        let mut new = CpuSnapshot::zero();
+        new.set(SysReg::SCTLR_EL1, 0b101);
+        new.set(SysReg::VBAR_EL1, 0xffff_0000_0000_0800);
+
+        let mut diff = old.diff(&new);
+        let sctlr = diff.next().unwrap();
+        assert_eq!(sctlr.reg, SysReg::SCTLR_EL1);
+        assert_eq!(sctlr.changed_bits(), 0b101);
+        let mut fields = sctlr.changed_fields();
+        assert_eq!(fields.next().map(|d| d.field.name), Some("C"));
+        assert_eq!(fields.next().map(|d| d.field.name), Some("M"));
+        assert!(fields.next().is_none());
+
+        let vbar = diff.next().unwrap();
+        assert_eq!(vbar.reg, SysReg::VBAR_EL1);
+        assert_eq!(vbar.changed_fields().count(), 0);
+        assert!(diff.next().is_none());
+
+        assert_eq!(new.diff(&new).count(), 0);
+    }
+}
@@ -0,0 +1,125 @@
+//! Low-power spin-waiting with WFE and the exclusive monitor.
+//!
+//! A load-exclusive arms the PE's exclusive monitor on the loaded location. A write to the
+//! location by another PE clears the monitor, which generates a wake-up event: a PE waiting in
+//! WFE after the load sleeps until the value may have changed, instead of polling it. If the
+//! write lands between the load and the WFE, the event is already pending and WFE returns
+//! immediately, so no wake-up is lost.
+//!
+//! [`wait_until`] waits for a condition on an atomic with this scheme. Writers don't need to do
+//! anything beyond storing to the location; [`notify_all`] wakes the PEs waiting in WFE for
+//! other reasons.
+
+use crate::barrier::{dsb, ISHST};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/// An atomic that can be loaded with a load-acquire exclusive (LDAXR).
+pub trait ExclusiveLoad {
+    /// The type of the value.
+    type Value: Copy;
+
+    /// Loads the value with the given ordering.
+    fn load(&self, order: Ordering) -> Self::Value;
+
+    /// Loads the value with acquire semantics and arms the exclusive monitor on it.
+    fn load_acquire_exclusive(&self) -> Self::Value;
+}
+
+macro_rules! exclusive_load {
+    ($($atomic:ty, $value:ty, $insn:literal, $reg:literal;)*) => {$(
+        impl ExclusiveLoad for $atomic {
+            type Value = $value;
+
+            #[inline]
+            fn load(&self, order: Ordering) -> $value {
+                <$atomic>::load(self, order)
+            }
+
+            #[inline]
+            fn load_acquire_exclusive(&self) -> $value {
+                match () {
+                    #[cfg(target_arch = "aarch64")]
+                    () => {
+                        let value: u64;
+                        unsafe {
+                            core::arch::asm!(
+                                concat!($insn, " {value:", $reg, "}, [{ptr}]"),
+                                value = out(reg) value,
+                                ptr = in(reg) self.as_ptr(),
+                                options(readonly, nostack, preserves_flags)
+                            )
+                        };
+                        value as $value
+                    }
+
+                    #[cfg(not(target_arch = "aarch64"))]
+                    () => unimplemented!(),
+                }
+            }
+        }
+    )*};
+}
+
+exclusive_load! {
+    AtomicU8, u8, "ldaxrb", "w";
+    AtomicU32, u32, "ldaxr", "w";
+    AtomicU64, u64, "ldaxr", "x";
+    AtomicUsize, usize, "ldaxr", "x";
+}
+
+/// Waits, in WFE between the loads, until `cond` holds for the value of `atomic`, and returns
+/// that value.
+///
+/// The value is loaded with acquire semantics, so the writes preceding the store that satisfied
+/// the condition are visible afterwards.
+#[inline]
+pub fn wait_until<A: ExclusiveLoad>(
+    atomic: &A,
+    mut cond: impl FnMut(A::Value) -> bool,
+) -> A::Value {
+    let value = atomic.load(Ordering::Acquire);
+    if cond(value) {
+        return value;
+    }
+    loop {
+        let value = atomic.load_acquire_exclusive();
+        if cond(value) {
+            return value;
+        }
+        crate::asm::wfe();
+    }
+}
+
+/// Waits until the value of `atomic` differs from `current`, and returns the new value.
+#[inline]
+pub fn wait_while_eq<A>(atomic: &A, current: A::Value) -> A::Value
+where
+    A: ExclusiveLoad,
+    A::Value: PartialEq,
+{
+    wait_until(atomic, |value| value != current)
+}
+
+/// Wakes all the PEs waiting in WFE (SEV), after making the preceding stores visible to them.
+///
+/// Not needed to wake the waiters of [`wait_until`], which wake up on the store to their
+/// location.
+#[inline]
+pub fn notify_all() {
+    unsafe { dsb(ISHST) };
+    crate::asm::sev();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_wait_until() {
+        let atomic = AtomicU32::new(3);
+        assert_eq!(wait_until(&atomic, |value| value > 2), 3);
+        assert_eq!(wait_while_eq(&atomic, 2), 3);
+        let atomic = AtomicUsize::new(1);
+        assert_eq!(wait_while_eq(&atomic, 0), 1);
+    }
+}
@@ -0,0 +1,171 @@
+//! Selection of the stack pointer with SPSel, and access to SP_EL0.
+//!
+//! At EL1, `sp` is SP_EL1 when SPSel is 1 (EL1h) and SP_EL0 when SPSel is 0 (EL1t). Exceptions
+//! taken to EL1 always start on SP_EL1, so a kernel running its threads in EL1t gets a separate
+//! handler stack for free, and a handler can move to the thread stack by switching to EL1t.
+//!
+//! Changing SPSel in the middle of a Rust function would move its stack frame under the
+//! compiler's feet, so this module switches only around a call: [`with_sp_el0`] and
+//! [`with_sp_elx`] run a closure on the other stack and switch back when it returns.
+
+use crate::{registers::*, VirtAddr};
+
+/// The stack pointer selected by SPSel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackSelect {
+    /// SP_EL0, at all exception levels (EL1t).
+    El0,
+    /// The stack pointer of the current exception level (EL1h).
+    ElX,
+}
+
+/// Returns the stack pointer currently in use.
+#[inline]
+pub fn current() -> StackSelect {
+    match SPSel.read_as_enum(SPSel::SP) {
+        Some(SPSel::SP::Value::EL0) => StackSelect::El0,
+        _ => StackSelect::ElX,
+    }
+}
+
+/// Returns the value of SP_EL0.
+///
+/// If SP_EL0 is the current stack pointer, where accessing it through the system register is
+/// UNDEFINED, this is the current stack pointer.
+#[inline]
+pub fn sp_el0() -> VirtAddr {
+    match current() {
+        StackSelect::El0 => VirtAddr::new_unchecked(crate::asm::sp() as u64),
+        StackSelect::ElX => VirtAddr::new_unchecked(SP_EL0.get()),
+    }
+}
+
+/// Sets SP_EL0.
+///
+/// # Safety
+///
+/// SP_EL0 is the stack pointer of EL0 after the next exception return to it, and the stack of
+/// [`with_sp_el0`]: it must be valid for them.
+///
+/// # Panics
+///
+/// Panics if SP_EL0 is the current stack pointer.
+#[inline]
+pub unsafe fn set_sp_el0(sp: VirtAddr) {
+    assert_eq!(current(), StackSelect::ElX, "SP_EL0 is in use");
+    SP_EL0.set(sp.as_u64());
+}
+
+/// Sets SP_EL0 while it is alive, and restores the previous value when dropped.
+///
+/// Guards must be dropped in the reverse order of their creation.
+#[derive(Debug)]
+#[must_use = "SP_EL0 is restored when the guard is dropped"]
+pub struct SpEl0Guard {
+    saved: VirtAddr,
+}
+
+impl SpEl0Guard {
+    /// Saves SP_EL0 and sets it to `sp`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`set_sp_el0`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if SP_EL0 is the current stack pointer.
+    #[inline]
+    pub unsafe fn new(sp: VirtAddr) -> Self {
+        let saved = sp_el0();
+        set_sp_el0(sp);
+        Self { saved }
+    }
+
+    /// Returns the value of SP_EL0 when the guard was created.
+    pub fn saved(&self) -> VirtAddr {
+        self.saved
+    }
+}
+
+impl Drop for SpEl0Guard {
+    #[inline]
+    fn drop(&mut self) {
+        SP_EL0.set(self.saved.as_u64());
+    }
+}
+
+/// Calls the closure stored in `data`, and stores its result there.
+///
+/// `data` points to the pair built by `call_with_spsel`.
+#[cfg(target_arch = "aarch64")]
+unsafe extern "C" fn trampoline<F: FnOnce() -> R, R>(data: *mut (Option<F>, Option<R>)) {
+    let data = &mut *data;
+    if let Some(f) = data.0.take() {
+        data.1 = Some(f());
+    }
+}
+
+/// Switches to the stack selected by `$enter`, calls `$f` and switches back with `$leave`.
+macro_rules! call_with_spsel {
+    ($f:expr, $enter:literal, $leave:literal) => {
+        match () {
+            #[cfg(target_arch = "aarch64")]
+            () => {
+                let mut data = (Some($f), None);
+                core::arch::asm!(
+                    $enter,
+                    "bl {trampoline}",
+                    $leave,
+                    trampoline = sym trampoline::<F, R>,
+                    inout("x0") &mut data as *mut _ => _,
+                    clobber_abi("C")
+                );
+                data.1.unwrap()
+            }
+
+            #[cfg(not(target_arch = "aarch64"))]
+            () => unimplemented!(),
+        }
+    };
+}
+
+/// Runs `f` on the stack SP_EL0 points to, with SPSel set to 0, and returns to the current stack
+/// afterwards.
+///
+/// Exceptions taken during `f` start on SP_EL1 below the current frame, so a handler can run the
+/// rest of its work on the stack of the interrupted thread, and still take nested exceptions on
+/// its own stack.
+///
+/// # Safety
+///
+/// SPSel must be 1. SP_EL0 must be the 16-byte aligned top of a stack large enough for `f`, not
+/// used by anything else, e.g. not the stack of an interrupted EL0 context.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub unsafe fn with_sp_el0<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    debug_assert_eq!(current(), StackSelect::ElX);
+    call_with_spsel!(f, "msr spsel, #0", "msr spsel, #1")
+}
+
+/// Runs `f` on the stack SP_EL1 points to, with SPSel set to 1, and returns to the current stack
+/// afterwards.
+///
+/// For code running in EL1t, e.g. a thread that needs the stack of the exception handlers.
+///
+/// # Safety
+///
+/// SPSel must be 0. SP_EL1 must be the 16-byte aligned top of a stack large enough for `f`, and
+/// exceptions taken to EL1 during `f` grow the same stack.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub unsafe fn with_sp_elx<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    debug_assert_eq!(current(), StackSelect::El0);
+    call_with_spsel!(f, "msr spsel, #1", "msr spsel, #0")
+}
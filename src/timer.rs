@@ -0,0 +1,385 @@
+//! Generic timer helpers.
+//!
+//! Conversions between counter ticks and time use 128-bit integer intermediates, so they neither
+//! overflow nor lose precision for any 64-bit tick count.
+//!
+//! The EL1 physical and virtual timers are driven through the [`Timer`] trait, implemented by
+//! [`PhysicalTimer`] (CNTP) and [`VirtualTimer`] (CNTV). Each fires its interrupt (PPI 30 and 27
+//! with a GIC) once its counter reaches the programmed deadline.
+
+use crate::{barrier::isb, registers::*};
+use core::{
+    ops::{Add, AddAssign, Sub},
+    time::Duration,
+};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Converts between generic timer ticks and nanoseconds for a fixed counter frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickConverter {
+    freq: u64,
+}
+
+impl TickConverter {
+    /// Creates a converter for a counter running at `freq` Hz.
+    ///
+    /// Panics if `freq` is zero.
+    pub const fn new(freq: u64) -> Self {
+        assert!(freq != 0, "counter frequency must not be zero");
+        Self { freq }
+    }
+
+    /// Creates a converter for the frequency programmed in CNTFRQ_EL0.
+    pub fn from_cntfrq() -> Self {
+        Self::new(CNTFRQ_EL0.get())
+    }
+
+    /// Returns the counter frequency in Hz.
+    pub const fn frequency(&self) -> u64 {
+        self.freq
+    }
+
+    /// Converts a number of ticks to nanoseconds, rounding down.
+    ///
+    /// Saturates at `u64::MAX` nanoseconds (more than 584 years).
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        saturate(u128::from(ticks) * NANOS_PER_SEC / u128::from(self.freq))
+    }
+
+    /// Converts nanoseconds to a number of ticks, rounding up so that waiting for the returned
+    /// number of ticks never takes less than `nanos`.
+    pub fn nanos_to_ticks(&self, nanos: u64) -> u64 {
+        self.nanos_u128_to_ticks(u128::from(nanos))
+    }
+
+    /// Converts a number of ticks to a [`Duration`], rounding down to the nanosecond.
+    pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        let secs = ticks / self.freq;
+        let rem = u128::from(ticks % self.freq);
+        let nanos = rem * NANOS_PER_SEC / u128::from(self.freq);
+        Duration::new(secs, nanos as u32)
+    }
+
+    /// Converts a [`Duration`] to a number of ticks, rounding up.
+    ///
+    /// Saturates at `u64::MAX` ticks.
+    pub fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        self.nanos_u128_to_ticks(duration.as_nanos())
+    }
+
+    /// Returns the time elapsed from `earlier` to `later`, or zero if `later` is earlier.
+    pub fn duration_between(&self, earlier: Instant, later: Instant) -> Duration {
+        self.ticks_to_duration(later.saturating_ticks_since(earlier))
+    }
+
+    /// Returns the instant `duration` after `instant`, saturating at the end of the counter
+    /// range.
+    pub fn instant_after(&self, instant: Instant, duration: Duration) -> Instant {
+        Instant(instant.0.saturating_add(self.duration_to_ticks(duration)))
+    }
+
+    fn nanos_u128_to_ticks(&self, nanos: u128) -> u64 {
+        let scaled = nanos.saturating_mul(u128::from(self.freq));
+        let ticks = scaled / NANOS_PER_SEC;
+        if scaled % NANOS_PER_SEC != 0 {
+            saturate(ticks + 1)
+        } else {
+            saturate(ticks)
+        }
+    }
+}
+
+fn saturate(value: u128) -> u64 {
+    if value > u128::from(u64::MAX) {
+        u64::MAX
+    } else {
+        value as u64
+    }
+}
+
+/// A reading of the monotonic physical counter (CNTPCT_EL0), or of the virtual counter
+/// (CNTVCT_EL0) with [`VirtualTimer`].
+///
+/// Use a [`TickConverter`] to convert differences of instants to a [`Duration`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Reads the physical counter.
+    ///
+    /// An ISB is issued first, so that the counter is not read speculatively ahead of the
+    /// preceding instructions.
+    #[inline]
+    pub fn now() -> Self {
+        unsafe { isb() };
+        Instant(CNTPCT_EL0.get())
+    }
+
+    /// Creates an instant from a raw counter value.
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Instant(ticks)
+    }
+
+    /// Returns the raw counter value.
+    pub const fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the number of ticks from `earlier` to `self`, or `None` if `earlier` is later.
+    pub fn checked_ticks_since(&self, earlier: Instant) -> Option<u64> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// Returns the number of ticks from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn saturating_ticks_since(&self, earlier: Instant) -> u64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl Add<u64> for Instant {
+    type Output = Self;
+    fn add(self, rhs: u64) -> Self::Output {
+        Instant(self.0 + rhs)
+    }
+}
+
+impl AddAssign<u64> for Instant {
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = u64;
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.0.checked_sub(rhs.0).unwrap()
+    }
+}
+
+/// CNTx_CTL_EL0.ENABLE
+const CTL_ENABLE: u64 = 1 << 0;
+/// CNTx_CTL_EL0.IMASK
+const CTL_IMASK: u64 = 1 << 1;
+/// CNTx_CTL_EL0.ISTATUS
+const CTL_ISTATUS: u64 = 1 << 2;
+
+/// One of the EL1 generic timers, which compares its counter with a deadline.
+///
+/// The required methods access the registers of the timer. The timer is stopped by default, and
+/// runs once a deadline is set.
+pub trait Timer {
+    /// Reads the counter of the timer, not speculatively ahead of the preceding instructions.
+    fn read_counter(&self) -> u64;
+    /// Reads the control register (CNTx_CTL_EL0).
+    fn read_ctl(&self) -> u64;
+    /// Writes the control register (CNTx_CTL_EL0).
+    fn write_ctl(&self, ctl: u64);
+    /// Reads the compare value register (CNTx_CVAL_EL0).
+    fn read_cval(&self) -> u64;
+    /// Writes the compare value register (CNTx_CVAL_EL0).
+    fn write_cval(&self, cval: u64);
+
+    /// Returns the current value of the counter of the timer.
+    fn now(&self) -> Instant {
+        Instant(self.read_counter())
+    }
+
+    /// Starts the timer with the given deadline. The timer condition is met, and the interrupt
+    /// asserted if enabled, once the counter reaches `deadline`.
+    fn set_deadline_at(&self, deadline: Instant) {
+        self.write_cval(deadline.0);
+        self.write_ctl(self.read_ctl() & CTL_IMASK | CTL_ENABLE);
+    }
+
+    /// Starts the timer with a deadline `duration` from now, converted with `conv`.
+    fn set_deadline_with(&self, conv: &TickConverter, duration: Duration) {
+        self.set_deadline_at(conv.instant_after(self.now(), duration));
+    }
+
+    /// Starts the timer with a deadline `duration` from now, converted with the frequency in
+    /// CNTFRQ_EL0.
+    fn set_deadline(&self, duration: Duration) {
+        self.set_deadline_with(&TickConverter::from_cntfrq(), duration);
+    }
+
+    /// Returns the deadline of the timer, if it is running.
+    fn deadline(&self) -> Option<Instant> {
+        if self.read_ctl() & CTL_ENABLE != 0 {
+            Some(Instant(self.read_cval()))
+        } else {
+            None
+        }
+    }
+
+    /// Stops the timer, which deasserts its interrupt.
+    fn stop(&self) {
+        self.write_ctl(self.read_ctl() & CTL_IMASK);
+    }
+
+    /// Unmasks the interrupt of the timer (CNTx_CTL_EL0.IMASK).
+    fn enable_irq(&self) {
+        self.write_ctl(self.read_ctl() & CTL_ENABLE);
+    }
+
+    /// Masks the interrupt of the timer (CNTx_CTL_EL0.IMASK).
+    fn disable_irq(&self) {
+        self.write_ctl(self.read_ctl() & CTL_ENABLE | CTL_IMASK);
+    }
+
+    /// Returns whether the timer is running and its deadline has been reached, regardless of
+    /// the interrupt mask.
+    fn is_expired(&self) -> bool {
+        self.read_ctl() & (CTL_ENABLE | CTL_ISTATUS) == CTL_ENABLE | CTL_ISTATUS
+    }
+}
+
+/// The EL1 physical timer (CNTP_CTL_EL0, CNTP_CVAL_EL0), which counts CNTPCT_EL0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhysicalTimer;
+
+impl Timer for PhysicalTimer {
+    #[inline]
+    fn read_counter(&self) -> u64 {
+        Instant::now().0
+    }
+
+    #[inline]
+    fn read_ctl(&self) -> u64 {
+        CNTP_CTL_EL0.get()
+    }
+
+    #[inline]
+    fn write_ctl(&self, ctl: u64) {
+        CNTP_CTL_EL0.set(ctl);
+    }
+
+    #[inline]
+    fn read_cval(&self) -> u64 {
+        CNTP_CVAL_EL0.get()
+    }
+
+    #[inline]
+    fn write_cval(&self, cval: u64) {
+        CNTP_CVAL_EL0.set(cval);
+    }
+}
+
+/// The EL1 virtual timer (CNTV_CTL_EL0, CNTV_CVAL_EL0), which counts CNTVCT_EL0, the physical
+/// count minus the offset programmed by a hypervisor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VirtualTimer;
+
+impl Timer for VirtualTimer {
+    #[inline]
+    fn read_counter(&self) -> u64 {
+        unsafe { isb() };
+        CNTVCT_EL0.get()
+    }
+
+    #[inline]
+    fn read_ctl(&self) -> u64 {
+        CNTV_CTL_EL0.get()
+    }
+
+    #[inline]
+    fn write_ctl(&self, ctl: u64) {
+        CNTV_CTL_EL0.set(ctl);
+    }
+
+    #[inline]
+    fn read_cval(&self) -> u64 {
+        CNTV_CVAL_EL0.get()
+    }
+
+    #[inline]
+    fn write_cval(&self, cval: u64) {
+        CNTV_CVAL_EL0.set(cval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_tick_conversion() {
+        // QEMU virt
+        let conv = TickConverter::new(62_500_000);
+        assert_eq!(conv.ticks_to_nanos(62_500_000), 1_000_000_000);
+        assert_eq!(conv.ticks_to_nanos(1), 16);
+        assert_eq!(conv.nanos_to_ticks(16), 1);
+        assert_eq!(conv.nanos_to_ticks(17), 2);
+        assert_eq!(
+            conv.ticks_to_duration(93_750_000),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            conv.duration_to_ticks(Duration::from_millis(1500)),
+            93_750_000
+        );
+
+        // Raspberry Pi 3
+        let conv = TickConverter::new(19_200_000);
+        assert_eq!(conv.nanos_to_ticks(1), 1);
+        assert_eq!(conv.ticks_to_nanos(3), 156);
+        assert_eq!(conv.ticks_to_nanos(u64::MAX), u64::MAX);
+        assert_eq!(conv.duration_to_ticks(Duration::MAX), u64::MAX);
+        assert_eq!(
+            conv.ticks_to_duration(u64::MAX),
+            Duration::new(u64::MAX / 19_200_000, 705_813_281)
+        );
+
+        let start = Instant::from_ticks(1000);
+        let end = conv.instant_after(start, Duration::from_secs(2));
+        assert_eq!(end - start, 38_400_000);
+        assert_eq!(conv.duration_between(start, end), Duration::from_secs(2));
+        assert_eq!(conv.duration_between(end, start), Duration::ZERO);
+    }
+
+    #[test]
+    pub fn test_timer() {
+        use core::cell::Cell;
+
+        #[derive(Default)]
+        struct FakeTimer {
+            counter: Cell<u64>,
+            ctl: Cell<u64>,
+            cval: Cell<u64>,
+        }
+
+        impl Timer for FakeTimer {
+            fn read_counter(&self) -> u64 {
+                self.counter.get()
+            }
+            fn read_ctl(&self) -> u64 {
+                let expired = self.counter.get() >= self.cval.get();
+                self.ctl.get() | if expired { CTL_ISTATUS } else { 0 }
+            }
+            fn write_ctl(&self, ctl: u64) {
+                self.ctl.set(ctl & (CTL_ENABLE | CTL_IMASK));
+            }
+            fn read_cval(&self) -> u64 {
+                self.cval.get()
+            }
+            fn write_cval(&self, cval: u64) {
+                self.cval.set(cval);
+            }
+        }
+
+        let timer = FakeTimer::default();
+        timer.counter.set(1000);
+        timer.disable_irq();
+        assert_eq!(timer.deadline(), None);
+        timer.set_deadline_with(&TickConverter::new(1_000_000), Duration::from_micros(500));
+        assert_eq!(timer.deadline(), Some(Instant::from_ticks(1500)));
+        assert_eq!(timer.ctl.get(), CTL_ENABLE | CTL_IMASK);
+        assert!(!timer.is_expired());
+        timer.counter.set(1500);
+        assert!(timer.is_expired());
+        timer.enable_irq();
+        assert_eq!(timer.ctl.get(), CTL_ENABLE);
+        timer.stop();
+        assert!(!timer.is_expired());
+    }
+}
@@ -1,31 +1,290 @@
 use crate::{
     addr::{PhysAddr, VirtAddr},
-    paging::PhysFrame,
+    barrier,
+    el::{debug_require_el, require_el, ExceptionLevel, InsufficientEl},
+    paging::{
+        bbm::notify_tlb_invalidated, memory_attribute::MairConfig, page::PageRange, PageSize,
+        PhysFrame, TranslationGranule,
+    },
     registers::*,
 };
+use core::fmt;
+use tock_registers::LocalRegisterCopy;
 
 /// Address Translate (Stage 1 EL1 Read).
 ///
 /// For Raspi 3, it always return the result of a translation table walk,
 /// regardless of the TLB caching.
+///
+/// Returns the physical address `vaddr` translates to, see [`at`] for the other
+/// address translation instructions, and [`AtTranslation::par`] for the memory
+/// attributes and shareability.
 #[inline]
-pub fn address_translate(vaddr: usize) -> usize {
-    let paddr: usize;
-    unsafe {
-        core::arch::asm!(
-            "at S1E1R, {vaddr}",
-            "mrs {paddr}, par_el1",
-            vaddr = in(reg) vaddr,
-            paddr = out(reg) paddr,
-            options(pure, readonly)
-        )
+pub fn address_translate(vaddr: usize) -> Result<PhysAddr, AddressTranslateError> {
+    at(VirtAddr::new_unchecked(vaddr as u64), AtOp::S1E1R).map(|translation| translation.addr)
+}
+
+/// Performs the given stage 1 address translation instruction for `vaddr`, and
+/// decodes the result from PAR_EL1.
+#[inline]
+pub fn at(vaddr: VirtAddr, op: AtOp) -> Result<AtTranslation, AddressTranslateError> {
+    macro_rules! at {
+        ($op:literal) => {{
+            let par: u64;
+            unsafe {
+                core::arch::asm!(
+                    concat!("at ", $op, ", {vaddr}"),
+                    "isb",
+                    "mrs {par}, par_el1",
+                    vaddr = in(reg) vaddr.as_u64(),
+                    par = out(reg) par,
+                    options(nostack)
+                )
+            }
+            par
+        }};
+    }
+
+    let par = match op {
+        AtOp::S1E1R => at!("s1e1r"),
+        AtOp::S1E1W => at!("s1e1w"),
+        AtOp::S1E0R => at!("s1e0r"),
+        AtOp::S1E0W => at!("s1e0w"),
+    };
+    decode_par(par, vaddr)
+}
+
+/// A stage 1 address translation instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtOp {
+    /// Translate as a read at EL1.
+    S1E1R,
+    /// Translate as a write at EL1.
+    S1E1W,
+    /// Translate as a read at EL0.
+    S1E0R,
+    /// Translate as a write at EL0.
+    S1E0W,
+}
+
+/// The shareability of a translated address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shareability {
+    /// Non-shareable.
+    NonShareable,
+    /// Outer Shareable.
+    OuterShareable,
+    /// Inner Shareable.
+    InnerShareable,
+}
+
+/// The result of a successful address translation instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtTranslation {
+    /// The physical address, including the page offset of the virtual address.
+    pub addr: PhysAddr,
+    /// Whether the address is in the Non-secure physical address space.
+    pub non_secure: bool,
+    /// The shareability of the memory.
+    pub shareability: Shareability,
+    /// The memory attributes, in the MAIR_EL1 encoding.
+    pub attr: u8,
+    /// The value of PAR_EL1 the translation was decoded from.
+    pub par: ParEl1,
+}
+
+/// The kind of fault of an aborted address translation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtFaultKind {
+    /// Address size fault.
+    AddressSize,
+    /// Translation fault: no valid descriptor.
+    Translation,
+    /// Access flag fault.
+    AccessFlag,
+    /// Permission fault.
+    Permission,
+    /// Synchronous external abort, on the table walk or on the translated address.
+    ExternalAbort,
+    /// Any other fault, e.g. a TLB conflict abort.
+    Other,
+}
+
+/// An aborted address translation, decoded from PAR_EL1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressTranslateError {
+    /// The fault status code (FST).
+    pub fst: u8,
+    /// The stage of translation that faulted (1 or 2).
+    pub stage: u8,
+    /// Whether the fault happened on a stage 2 walk for a stage 1 table walk.
+    pub ptw: bool,
+}
+
+impl AddressTranslateError {
+    /// Returns the kind of the fault.
+    pub fn kind(&self) -> AtFaultKind {
+        match self.fst >> 2 {
+            0b0000 => AtFaultKind::AddressSize,
+            0b0001 => AtFaultKind::Translation,
+            0b0010 => AtFaultKind::AccessFlag,
+            0b0011 => AtFaultKind::Permission,
+            0b0100 | 0b0101 => AtFaultKind::ExternalAbort,
+            _ => AtFaultKind::Other,
+        }
+    }
+
+    /// Returns the lookup level at which the fault happened, for address size,
+    /// translation, access flag and permission faults.
+    pub fn level(&self) -> Option<u8> {
+        match self.kind() {
+            AtFaultKind::AddressSize
+            | AtFaultKind::Translation
+            | AtFaultKind::AccessFlag
+            | AtFaultKind::Permission => Some(self.fst & 0b11),
+            _ => None,
+        }
+    }
+}
+
+/// PAR_EL1 fields.
+const PAR_F: u64 = 1 << 0;
+const PAR_FST_SHIFT: u64 = 1;
+const PAR_PTW: u64 = 1 << 8;
+const PAR_S: u64 = 1 << 9;
+const PAR_SH_SHIFT: u64 = 7;
+const PAR_NS: u64 = 1 << 9;
+const PAR_PA_MASK: u64 = 0x000f_ffff_ffff_f000;
+const PAR_ATTR_SHIFT: u64 = 56;
+
+/// A value of PAR_EL1, the result of an address translation instruction.
+///
+/// The getters of a successful translation return `None` for an aborted one, and the other way
+/// around.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ParEl1(u64);
+
+impl ParEl1 {
+    /// Creates a decoder for the given value of PAR_EL1.
+    pub const fn new(value: u64) -> Self {
+        ParEl1(value)
+    }
+
+    /// Reads PAR_EL1.
+    #[inline]
+    pub fn read() -> Self {
+        ParEl1(PAR_EL1.get())
+    }
+
+    /// Returns the raw value.
+    pub const fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns whether the translation aborted (F).
+    pub const fn is_fault(&self) -> bool {
+        self.0 & PAR_F != 0
+    }
+
+    /// Returns the address of the translated page, without the page offset.
+    pub fn page_addr(&self) -> Option<PhysAddr> {
+        if self.is_fault() {
+            None
+        } else {
+            Some(PhysAddr::new(self.0 & PAR_PA_MASK))
+        }
+    }
+
+    /// Returns whether the address is in the Non-secure physical address space (NS).
+    pub fn non_secure(&self) -> Option<bool> {
+        if self.is_fault() {
+            None
+        } else {
+            Some(self.0 & PAR_NS != 0)
+        }
+    }
+
+    /// Returns the shareability of the memory (SH).
+    ///
+    /// Device and Normal Non-cacheable memory are reported as Outer Shareable, whatever the
+    /// shareability of the descriptor.
+    pub fn shareability(&self) -> Option<Shareability> {
+        if self.is_fault() {
+            return None;
+        }
+        Some(match (self.0 >> PAR_SH_SHIFT) & 0b11 {
+            0b10 => Shareability::OuterShareable,
+            0b11 => Shareability::InnerShareable,
+            _ => Shareability::NonShareable,
+        })
+    }
+
+    /// Returns the memory attributes, in the MAIR_EL1 encoding (ATTR), to compare with e.g.
+    /// [`MairConfig::encoding`].
+    pub fn attr(&self) -> Option<u8> {
+        if self.is_fault() {
+            None
+        } else {
+            Some((self.0 >> PAR_ATTR_SHIFT) as u8)
+        }
+    }
+
+    /// Returns whether the memory is Device memory, i.e. the high nibble of the attributes is 0.
+    pub fn is_device(&self) -> Option<bool> {
+        self.attr().map(|attr| attr & 0xf0 == 0)
+    }
+
+    /// Returns the fault of an aborted translation.
+    pub fn fault(&self) -> Option<AddressTranslateError> {
+        if !self.is_fault() {
+            return None;
+        }
+        Some(AddressTranslateError {
+            fst: ((self.0 >> PAR_FST_SHIFT) & 0x3f) as u8,
+            stage: if self.0 & PAR_S != 0 { 2 } else { 1 },
+            ptw: self.0 & PAR_PTW != 0,
+        })
+    }
+
+    /// Returns the result of the translation of `vaddr`.
+    pub fn translation(&self, vaddr: VirtAddr) -> Result<AtTranslation, AddressTranslateError> {
+        match (self.page_addr(), self.fault()) {
+            (Some(page), _) => Ok(AtTranslation {
+                addr: page + (vaddr.as_u64() & 0xfff),
+                non_secure: self.0 & PAR_NS != 0,
+                shareability: self.shareability().unwrap(),
+                attr: (self.0 >> PAR_ATTR_SHIFT) as u8,
+                par: *self,
+            }),
+            (None, fault) => Err(fault.unwrap()),
+        }
+    }
+}
+
+impl fmt::Debug for ParEl1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.fault() {
+            Some(fault) => f.debug_tuple("ParEl1").field(&fault).finish(),
+            None => f
+                .debug_struct("ParEl1")
+                .field("page_addr", &self.page_addr().unwrap())
+                .field("non_secure", &self.non_secure().unwrap())
+                .field("shareability", &self.shareability().unwrap())
+                .field("attr", &format_args!("{:#04x}", self.attr().unwrap()))
+                .finish(),
+        }
     }
-    paddr
+}
+
+/// Decodes the value of PAR_EL1 after an address translation of `vaddr`.
+fn decode_par(par: u64, vaddr: VirtAddr) -> Result<AtTranslation, AddressTranslateError> {
+    ParEl1::new(par).translation(vaddr)
 }
 
 /// Read TTBRx_EL1 as PhysFrame
 #[inline]
 pub fn ttbr_el1_read(which: u8) -> PhysFrame {
+    debug_require_el(ExceptionLevel::EL1);
     let baddr = match which {
         0 => TTBR0_EL1.get_baddr(),
         1 => TTBR1_EL1.get_baddr(),
@@ -37,6 +296,7 @@ pub fn ttbr_el1_read(which: u8) -> PhysFrame {
 /// Write TTBRx_EL1 from PhysFrame
 #[inline]
 pub fn ttbr_el1_write(which: u8, frame: PhysFrame) {
+    debug_require_el(ExceptionLevel::EL1);
     let baddr = frame.start_address().as_u64();
     match which {
         0 => TTBR0_EL1.set_baddr(baddr),
@@ -48,6 +308,7 @@ pub fn ttbr_el1_write(which: u8, frame: PhysFrame) {
 /// Read TTBRx_EL1 as PhysFrame and ASID
 #[inline]
 pub fn ttbr_el1_read_asid(which: u8) -> (u16, PhysFrame) {
+    debug_require_el(ExceptionLevel::EL1);
     let (asid, baddr) = match which {
         0 => (
             TTBR0_EL1.read(TTBR0_EL1::ASID) as u16,
@@ -65,6 +326,7 @@ pub fn ttbr_el1_read_asid(which: u8) -> (u16, PhysFrame) {
 /// write TTBRx_EL1 from PhysFrame and ASID
 #[inline]
 pub fn ttbr_el1_write_asid(which: u8, asid: u16, frame: PhysFrame) {
+    debug_require_el(ExceptionLevel::EL1);
     let baddr = frame.start_address().as_u64();
     match which {
         0 => TTBR0_EL1.write(TTBR0_EL1::ASID.val(asid as u64) + TTBR0_EL1::BADDR.val(baddr >> 1)),
@@ -73,11 +335,568 @@ pub fn ttbr_el1_write_asid(which: u8, asid: u16, frame: PhysFrame) {
     };
 }
 
+/// Read VTTBR_EL2 as the VMID and PhysFrame of the stage 2 translation table
+#[inline]
+pub fn vttbr_el2_read_vmid() -> (u16, PhysFrame) {
+    debug_require_el(ExceptionLevel::EL2);
+    let vttbr = VTTBR_EL2.extract();
+    let baddr = vttbr.read(VTTBR_EL2::BADDR) << 1;
+    (
+        vttbr.read(VTTBR_EL2::VMID) as u16,
+        PhysFrame::containing_address(PhysAddr::new(baddr)),
+    )
+}
+
+/// write VTTBR_EL2 from PhysFrame and VMID
+#[inline]
+pub fn vttbr_el2_write_vmid(vmid: u16, frame: PhysFrame) {
+    debug_require_el(ExceptionLevel::EL2);
+    let baddr = frame.start_address().as_u64();
+    VTTBR_EL2.write(VTTBR_EL2::VMID.val(vmid as u64) + VTTBR_EL2::BADDR.val(baddr >> 1));
+}
+
+/// Returns whether stage 2 translation of the EL1&0 regime is enabled (HCR_EL2.VM).
+#[inline]
+pub fn is_stage2_enabled() -> bool {
+    debug_require_el(ExceptionLevel::EL2);
+    HCR_EL2.is_set(HCR_EL2::VM)
+}
+
+/// Enables or disables stage 2 translation of the EL1&0 regime (HCR_EL2.VM).
+///
+/// Must be executed at EL2.
+///
+/// # Safety
+///
+/// When enabling, VTCR_EL2 and VTTBR_EL2 must hold a valid stage 2 configuration for the
+/// guest that runs next.
+#[inline]
+pub unsafe fn set_stage2_enabled(enabled: bool) {
+    debug_require_el(ExceptionLevel::EL2);
+    HCR_EL2.modify(if enabled {
+        HCR_EL2::VM::Enable
+    } else {
+        HCR_EL2::VM::Disable
+    });
+    barrier::isb();
+}
+
+/// The size of ASIDs, selected by TCR_EL1.AS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AsidSize {
+    /// 8-bit ASIDs, the upper 8 bits of the ASID field are ignored.
+    Bits8,
+    /// 16-bit ASIDs.
+    Bits16,
+}
+
+impl AsidSize {
+    /// Returns the largest ASID size supported by the PE.
+    pub fn supported() -> Self {
+        match ID_AA64MMFR0_EL1.read_as_enum(ID_AA64MMFR0_EL1::ASIDBits) {
+            Some(ID_AA64MMFR0_EL1::ASIDBits::Value::Bits_16) => AsidSize::Bits16,
+            _ => AsidSize::Bits8,
+        }
+    }
+
+    /// Returns the largest ASID of this size.
+    pub fn max_asid(self) -> u16 {
+        match self {
+            AsidSize::Bits8 => 0xff,
+            AsidSize::Bits16 => 0xffff,
+        }
+    }
+}
+
+/// An error indicating that a TTBRx_EL1 value is not valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtbrError {
+    /// The ASID doesn't fit in the ASID size.
+    AsidTooLarge,
+    /// The PE doesn't support the ASID size.
+    AsidSizeNotSupported,
+}
+
+/// A builder for the value of TTBR0_EL1 or TTBR1_EL1, e.g.
+/// `TtbrBuilder::new(root).asid(asid).cnp(true).apply(0)`.
+#[derive(Debug, Clone, Copy)]
+pub struct TtbrBuilder {
+    baddr: u64,
+    asid: u16,
+    asid_size: AsidSize,
+    cnp: bool,
+}
+
+impl TtbrBuilder {
+    /// Creates a builder for the translation table at `frame`, with ASID 0, 8-bit ASIDs and
+    /// CnP cleared.
+    pub fn new(frame: PhysFrame) -> Self {
+        Self {
+            baddr: frame.start_address().as_u64(),
+            asid: 0,
+            asid_size: AsidSize::Bits8,
+            cnp: false,
+        }
+    }
+
+    /// Sets the ASID.
+    pub fn asid(mut self, asid: u16) -> Self {
+        self.asid = asid;
+        self
+    }
+
+    /// Sets the ASID size in use (TCR_EL1.AS), against which the ASID is validated.
+    pub fn asid_size(mut self, asid_size: AsidSize) -> Self {
+        self.asid_size = asid_size;
+        self
+    }
+
+    /// Sets the Common not Private bit, which allows the PEs of the Inner Shareable domain to
+    /// share TLB entries for the translation table. All PEs using the table with CnP must use
+    /// the same ASID and table.
+    pub fn cnp(mut self, cnp: bool) -> Self {
+        self.cnp = cnp;
+        self
+    }
+
+    /// Returns the register value, checking the ASID against the ASID sizes `supported` by the
+    /// PE.
+    pub fn value(&self, supported: AsidSize) -> Result<u64, TtbrError> {
+        if self.asid_size > supported {
+            return Err(TtbrError::AsidSizeNotSupported);
+        }
+        if self.asid > self.asid_size.max_asid() {
+            return Err(TtbrError::AsidTooLarge);
+        }
+        Ok((TTBR0_EL1::ASID.val(self.asid as u64)
+            + TTBR0_EL1::BADDR.val(self.baddr >> 1)
+            + TTBR0_EL1::CnP.val(self.cnp as u64))
+        .value)
+    }
+
+    /// Writes TTBRx_EL1, `which` being 0 or 1.
+    ///
+    /// Completes preceding writes to the translation tables before the write, and synchronizes
+    /// the context after it.
+    ///
+    /// # Safety
+    ///
+    /// See [`switch_ttbr0`].
+    pub unsafe fn apply(self, which: u8) -> Result<(), TtbrError> {
+        debug_require_el(ExceptionLevel::EL1);
+        let value = self.value(AsidSize::supported())?;
+        barrier::dsb(barrier::ISHST);
+        match which {
+            0 => TTBR0_EL1.set(value),
+            1 => TTBR1_EL1.set(value),
+            _ => {}
+        }
+        barrier::isb();
+        Ok(())
+    }
+}
+
+/// The cacheability of translation table walks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cacheability {
+    /// Normal memory, Non-cacheable.
+    NonCacheable = 0b00,
+    /// Normal memory, Write-Back Read-Allocate Write-Allocate Cacheable.
+    WriteBackWriteAllocate = 0b01,
+    /// Normal memory, Write-Through Read-Allocate No Write-Allocate Cacheable.
+    WriteThrough = 0b10,
+    /// Normal memory, Write-Back Read-Allocate No Write-Allocate Cacheable.
+    WriteBackNoWriteAllocate = 0b11,
+}
+
+/// An error indicating that a TCR_EL1 configuration is not valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcrError {
+    /// The VA size is not between 25 and 48 bits, or 52 bits with the 64KiB granule and the
+    /// `lva` feature on a PE supporting it.
+    VaBitsOutOfRange,
+    /// The PE doesn't support the translation granule.
+    GranuleNotSupported,
+    /// The PE doesn't support the ASID size.
+    AsidSizeNotSupported,
+}
+
+/// The configuration of the translation of one VA range.
+#[derive(Debug, Clone, Copy)]
+struct TcrRange {
+    va_bits: u8,
+    granule_size: u64,
+    tg0: u64,
+    tg1: u64,
+}
+
+/// A builder for TCR_EL1, the translation control register of the EL1&0 regime.
+///
+/// VA ranges that are not configured with [`ttbr0`](Self::ttbr0) or [`ttbr1`](Self::ttbr1) have
+/// translation table walks disabled.
+#[derive(Debug, Clone, Copy)]
+pub struct TcrBuilder {
+    ttbr0: Option<TcrRange>,
+    ttbr1: Option<TcrRange>,
+    cacheability: Cacheability,
+    shareability: Shareability,
+    asid_size: AsidSize,
+    asid_in_ttbr1: bool,
+    tbi: [bool; 2],
+    tcma: [bool; 2],
+}
+
+impl Default for TcrBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TcrBuilder {
+    /// Creates a builder with both VA ranges disabled, Write-Back Write-Allocate Inner
+    /// Shareable table walks and 8-bit ASIDs taken from TTBR0_EL1.
+    pub fn new() -> Self {
+        Self {
+            ttbr0: None,
+            ttbr1: None,
+            cacheability: Cacheability::WriteBackWriteAllocate,
+            shareability: Shareability::InnerShareable,
+            asid_size: AsidSize::Bits8,
+            asid_in_ttbr1: false,
+            tbi: [false; 2],
+            tcma: [false; 2],
+        }
+    }
+
+    /// Enables the lower VA range (TTBR0_EL1), of `va_bits` bits, with the translation
+    /// granule `G`.
+    pub fn ttbr0<G: TranslationGranule>(mut self, va_bits: u8) -> Self {
+        self.ttbr0 = Some(TcrRange::new::<G>(va_bits));
+        self
+    }
+
+    /// Enables the upper VA range (TTBR1_EL1), of `va_bits` bits, with the translation
+    /// granule `G`.
+    pub fn ttbr1<G: TranslationGranule>(mut self, va_bits: u8) -> Self {
+        self.ttbr1 = Some(TcrRange::new::<G>(va_bits));
+        self
+    }
+
+    /// Sets the inner and outer cacheability of translation table walks.
+    pub fn cacheability(mut self, cacheability: Cacheability) -> Self {
+        self.cacheability = cacheability;
+        self
+    }
+
+    /// Sets the shareability of translation table walks.
+    pub fn shareability(mut self, shareability: Shareability) -> Self {
+        self.shareability = shareability;
+        self
+    }
+
+    /// Sets the ASID size.
+    pub fn asid_size(mut self, asid_size: AsidSize) -> Self {
+        self.asid_size = asid_size;
+        self
+    }
+
+    /// Selects whether the ASID is taken from TTBR1_EL1 instead of TTBR0_EL1 (A1).
+    pub fn asid_in_ttbr1(mut self, asid_in_ttbr1: bool) -> Self {
+        self.asid_in_ttbr1 = asid_in_ttbr1;
+        self
+    }
+
+    /// Selects whether the top byte of the addresses of the lower and upper VA ranges is ignored
+    /// by the translation (TBI0 and TBI1), e.g. to hold memory tags.
+    pub fn top_byte_ignore(mut self, ttbr0: bool, ttbr1: bool) -> Self {
+        self.tbi = [ttbr0, ttbr1];
+        self
+    }
+
+    /// Selects whether the accesses with the logical tag 0b0000 (lower VA range) and 0b1111
+    /// (upper VA range) are unchecked by MTE (TCMA0 and TCMA1).
+    pub fn tag_check_match_all(mut self, ttbr0: bool, ttbr1: bool) -> Self {
+        self.tcma = [ttbr0, ttbr1];
+        self
+    }
+
+    /// Returns the register value, validated against the features of the PE described by the
+    /// value of ID_AA64MMFR0_EL1. The intermediate physical address size is the physical
+    /// address size of the PE.
+    pub fn value(&self, mmfr0: u64) -> Result<u64, TcrError> {
+        use ID_AA64MMFR0_EL1::*;
+
+        let mmfr0 = LocalRegisterCopy::<u64, ID_AA64MMFR0_EL1::Register>::new(mmfr0);
+        let supported = |range: &TcrRange| match range.granule_size {
+            0x1000 => mmfr0.matches_all(TGran4::Supported),
+            0x4000 => mmfr0.matches_all(TGran16::Supported),
+            _ => mmfr0.matches_all(TGran64::Supported),
+        };
+        for range in self.ttbr0.iter().chain(self.ttbr1.iter()) {
+            if !(25..=range.max_va_bits()).contains(&range.va_bits) {
+                return Err(TcrError::VaBitsOutOfRange);
+            }
+            if !supported(range) {
+                return Err(TcrError::GranuleNotSupported);
+            }
+        }
+        if self.asid_size == AsidSize::Bits16 && !mmfr0.matches_all(ASIDBits::Bits_16) {
+            return Err(TcrError::AsidSizeNotSupported);
+        }
+
+        let cacheability = self.cacheability as u64;
+        let shareability = match self.shareability {
+            Shareability::NonShareable => 0b00,
+            Shareability::OuterShareable => 0b10,
+            Shareability::InnerShareable => 0b11,
+        };
+        let mut tcr = TCR_EL1::IPS.val(mmfr0.read(PARange))
+            + TCR_EL1::AS.val((self.asid_size == AsidSize::Bits16) as u64)
+            + TCR_EL1::A1.val(self.asid_in_ttbr1 as u64)
+            + TCR_EL1::TBI0.val(self.tbi[0] as u64)
+            + TCR_EL1::TBI1.val(self.tbi[1] as u64);
+        tcr += match self.ttbr0 {
+            Some(range) => {
+                TCR_EL1::T0SZ.val(64 - range.va_bits as u64)
+                    + TCR_EL1::TG0.val(range.tg0)
+                    + TCR_EL1::IRGN0.val(cacheability)
+                    + TCR_EL1::ORGN0.val(cacheability)
+                    + TCR_EL1::SH0.val(shareability)
+            }
+            None => TCR_EL1::EPD0::DisableTTBR0Walks + TCR_EL1::TG0::KiB_4,
+        };
+        tcr += match self.ttbr1 {
+            Some(range) => {
+                TCR_EL1::T1SZ.val(64 - range.va_bits as u64)
+                    + TCR_EL1::TG1.val(range.tg1)
+                    + TCR_EL1::IRGN1.val(cacheability)
+                    + TCR_EL1::ORGN1.val(cacheability)
+                    + TCR_EL1::SH1.val(shareability)
+            }
+            None => TCR_EL1::EPD1::DisableTTBR1Walks + TCR_EL1::TG1::KiB_4,
+        };
+        // TCMA0 and TCMA1 are not described by `TCR_EL1`
+        Ok(tcr.value | (self.tcma[0] as u64) << 57 | (self.tcma[1] as u64) << 58)
+    }
+
+    /// Validates the configuration against the features of the PE and writes TCR_EL1.
+    ///
+    /// The new configuration is used by the instructions after this function returns.
+    ///
+    /// # Safety
+    ///
+    /// The translation tables in use must match the new configuration, or the MMU must be
+    /// disabled. TLB entries created with another configuration must be invalidated.
+    #[inline]
+    pub unsafe fn apply(self) -> Result<(), TcrError> {
+        let value = self.value(ID_AA64MMFR0_EL1.get())?;
+        self.check_va_range(ID_AA64MMFR2_EL1.get())?;
+        TCR_EL1.set(value);
+        barrier::isb();
+        Ok(())
+    }
+
+    /// Checks that VA sizes over 48 bits are supported by the PE described by the value of
+    /// ID_AA64MMFR2_EL1.
+    fn check_va_range(&self, mmfr2: u64) -> Result<(), TcrError> {
+        let mmfr2 = LocalRegisterCopy::<u64, ID_AA64MMFR2_EL1::Register>::new(mmfr2);
+        let large = self
+            .ttbr0
+            .iter()
+            .chain(self.ttbr1.iter())
+            .any(|range| range.va_bits > 48);
+        if large && !mmfr2.matches_all(ID_AA64MMFR2_EL1::VARange::Bits_52) {
+            return Err(TcrError::VaBitsOutOfRange);
+        }
+        Ok(())
+    }
+}
+
+impl TcrRange {
+    /// Returns the largest VA size of the granule.
+    fn max_va_bits(&self) -> u8 {
+        if cfg!(feature = "lva") && self.granule_size == 0x10000 {
+            52
+        } else {
+            48
+        }
+    }
+
+    fn new<G: TranslationGranule>(va_bits: u8) -> Self {
+        Self {
+            va_bits,
+            granule_size: G::Page::SIZE,
+            tg0: G::TCR_TG0.value >> TCR_EL1::TG0.shift,
+            tg1: G::TCR_TG1.value >> TCR_EL1::TG1.shift,
+        }
+    }
+}
+
+/// An error indicating that an MMU configuration is not valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmuError {
+    /// The TCR_EL1 configuration is not valid.
+    Tcr(TcrError),
+    /// A TTBRx_EL1 value is not valid.
+    Ttbr(TtbrError),
+    /// The PE doesn't run at EL1 or higher.
+    ExceptionLevel(InsufficientEl),
+}
+
+impl From<TcrError> for MmuError {
+    fn from(err: TcrError) -> Self {
+        MmuError::Tcr(err)
+    }
+}
+
+impl From<TtbrError> for MmuError {
+    fn from(err: TtbrError) -> Self {
+        MmuError::Ttbr(err)
+    }
+}
+
+impl From<InsufficientEl> for MmuError {
+    fn from(err: InsufficientEl) -> Self {
+        MmuError::ExceptionLevel(err)
+    }
+}
+
+/// The configuration of the EL1&0 translation regime installed by [`enable_mmu`].
+///
+/// The ASIDs of the translation tables are validated against the ASID size of the
+/// [`TcrBuilder`].
+#[derive(Debug, Clone, Copy)]
+pub struct MmuConfig {
+    mair: MairConfig,
+    tcr: TcrBuilder,
+    ttbr0: Option<TtbrBuilder>,
+    ttbr1: Option<TtbrBuilder>,
+    caches: bool,
+}
+
+impl MmuConfig {
+    /// Creates a configuration with the given memory attributes and translation control, no
+    /// translation tables and the data and instruction caches enabled.
+    pub fn new(mair: MairConfig, tcr: TcrBuilder) -> Self {
+        Self {
+            mair,
+            tcr,
+            ttbr0: None,
+            ttbr1: None,
+            caches: true,
+        }
+    }
+
+    /// Sets the translation table of the lower VA range.
+    pub fn ttbr0(mut self, ttbr0: TtbrBuilder) -> Self {
+        self.ttbr0 = Some(ttbr0);
+        self
+    }
+
+    /// Sets the translation table of the upper VA range.
+    pub fn ttbr1(mut self, ttbr1: TtbrBuilder) -> Self {
+        self.ttbr1 = Some(ttbr1);
+        self
+    }
+
+    /// Selects whether the data and instruction caches are enabled with the MMU (SCTLR_EL1.C
+    /// and SCTLR_EL1.I).
+    pub fn caches(mut self, caches: bool) -> Self {
+        self.caches = caches;
+        self
+    }
+
+    /// Returns the values of TCR_EL1, TTBR0_EL1 and TTBR1_EL1, validated against the features
+    /// of the PE described by the value of ID_AA64MMFR0_EL1. Unset translation tables are 0.
+    pub(crate) fn values(&self, mmfr0: u64) -> Result<(u64, u64, u64), MmuError> {
+        let tcr = self.tcr.value(mmfr0)?;
+        // the TCR value is valid, so its ASID size is supported
+        let asid_size = self.tcr.asid_size;
+        let ttbr = |ttbr: Option<TtbrBuilder>| match ttbr {
+            Some(ttbr) => ttbr.asid_size(asid_size).value(asid_size),
+            None => Ok(0),
+        };
+        Ok((tcr, ttbr(self.ttbr0)?, ttbr(self.ttbr1)?))
+    }
+}
+
+/// Turns on the MMU of the current PE with the given configuration.
+///
+/// The configuration is validated against the features of the PE, and the current Exception
+/// level checked, before any register is written. Then the TLB of the PE is invalidated, MAIR_EL1,
+/// TCR_EL1 and the TTBRs are written, and their writes synchronized before SCTLR_EL1.M (and C and I
+/// if enabled) is set. The instructions after this function returns are translated.
+///
+/// # Safety
+///
+/// The translation tables must be valid, and map the code and stack in use at the same
+/// addresses, e.g. with an identity mapping, and with the same memory types, as before the MMU
+/// is enabled.
+#[inline]
+pub unsafe fn enable_mmu(config: &MmuConfig) -> Result<(), MmuError> {
+    require_el(ExceptionLevel::EL1)?;
+    let (tcr, ttbr0, ttbr1) = config.values(ID_AA64MMFR0_EL1.get())?;
+    local_invalidate_tlb_all();
+    MAIR_EL1.set(config.mair.value());
+    TCR_EL1.set(tcr);
+    TTBR0_EL1.set(ttbr0);
+    TTBR1_EL1.set(ttbr1);
+    // the tables must be visible to the table walks, which start with the MMU
+    barrier::dsb(barrier::ISH);
+    barrier::isb();
+    if config.caches {
+        SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+    } else {
+        SCTLR_EL1.modify(SCTLR_EL1::M::Enable);
+    }
+    barrier::isb();
+    Ok(())
+}
+
+/// Turns off the MMU and the data cache of the current PE (SCTLR_EL1.M and SCTLR_EL1.C).
+///
+/// The instruction cache is kept enabled. The instructions after this function returns use
+/// flat addresses, and data accesses are Device-nGnRnE.
+///
+/// # Safety
+///
+/// The code and stack in use must be identity mapped. Dirty lines of the data cache must be
+/// cleaned before, e.g. with
+/// [`flush_all_sets_ways`](crate::cache::DCache::flush_all_sets_ways), as data accesses no
+/// longer look up the cache.
+#[inline]
+pub unsafe fn disable_mmu() {
+    barrier::dsb(barrier::SY);
+    SCTLR_EL1.modify(SCTLR_EL1::M::Disable + SCTLR_EL1::C::NonCacheable);
+    barrier::isb();
+    local_invalidate_tlb_all();
+}
+
+/// Install the translation table at `root` for the lower VA range (TTBR0_EL1),
+/// with the given ASID.
+///
+/// Completes preceding writes to the translation tables before the switch, and
+/// synchronizes the context, so that subsequent instructions use the new tables.
+///
+/// # Safety
+///
+/// `root` must be the root of a valid page table hierarchy, and the code and data
+/// in use must stay mapped. The TLB must not hold entries of another address
+/// space tagged with `asid`: invalidate them with [`invalidate_tlb_asid`] before
+/// reusing an ASID.
+#[inline]
+pub unsafe fn switch_ttbr0(root: PhysAddr, asid: u16) {
+    core::arch::asm!("dsb ishst", options(nostack));
+    ttbr_el1_write_asid(0, asid, PhysFrame::containing_address(root));
+    core::arch::asm!("isb", options(nostack));
+}
+
 /// Invalidate all TLB entries in all PEs.
 #[inline]
 pub fn invalidate_tlb_all() {
     // All stage 1 translations used at EL1, in the Inner Shareable shareability
     // domain.
+    #[cfg(target_arch = "aarch64")]
     unsafe {
         core::arch::asm!(
             "dsb ishst",
@@ -87,12 +906,16 @@ pub fn invalidate_tlb_all() {
             options(nostack)
         )
     }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::All);
+    notify_tlb_invalidated();
 }
 
 /// Invalidate all TLB entries in the current PE.
 #[inline]
 pub fn local_invalidate_tlb_all() {
     // All stage 1 translations used at EL1
+    #[cfg(target_arch = "aarch64")]
     unsafe {
         core::arch::asm!(
             "dsb nshst",
@@ -102,6 +925,9 @@ pub fn local_invalidate_tlb_all() {
             options(nostack)
         )
     }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::local_tlbi(crate::sim::TlbiTarget::All);
+    notify_tlb_invalidated();
 }
 
 /// Invalidate TLB entries in all PEs by the virtual address.
@@ -109,14 +935,839 @@ pub fn local_invalidate_tlb_all() {
 pub fn invalidate_tlb_vaddr(vaddr: VirtAddr) {
     // Translations used at EL1 for the specified address, for all ASID values,
     // in the Inner Shareable shareability domain.
+    #[cfg(target_arch = "aarch64")]
     unsafe {
         core::arch::asm!(
             "dsb ishst",
             "tlbi vaae1is, {vaddr}",
             "dsb ish",
             "isb",
-            vaddr = in(reg) vaddr.as_u64() >> 12,
+            vaddr = in(reg) tlbi_vaddr(vaddr),
             options(nostack)
         )
     }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Vaddr {
+        vaddr,
+        asid: None,
+        last_level: false,
+    });
+    notify_tlb_invalidated();
+}
+
+/// Invalidate TLB entries in all PEs by the ASID.
+#[inline]
+pub fn invalidate_tlb_asid(asid: u16) {
+    // Non-global translations used at EL1 with the specified ASID, in the Inner
+    // Shareable shareability domain.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi aside1is, {arg}",
+            "dsb ish",
+            "isb",
+            arg = in(reg) tlbi_asid(asid),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Asid(asid));
+    notify_tlb_invalidated();
+}
+
+/// Invalidate TLB entries in the current PE by the ASID.
+#[inline]
+pub fn local_invalidate_tlb_asid(asid: u16) {
+    // Non-global translations used at EL1 with the specified ASID
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb nshst",
+            "tlbi aside1, {arg}",
+            "dsb nsh",
+            "isb",
+            arg = in(reg) tlbi_asid(asid),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::local_tlbi(crate::sim::TlbiTarget::Asid(asid));
+    notify_tlb_invalidated();
+}
+
+/// Invalidate TLB entries in all PEs by the virtual address and ASID.
+#[inline]
+pub fn invalidate_tlb_vaddr_asid(vaddr: VirtAddr, asid: u16) {
+    // Translations used at EL1 for the specified address and ASID, and global
+    // translations for the address, in the Inner Shareable shareability domain.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vae1is, {arg}",
+            "dsb ish",
+            "isb",
+            arg = in(reg) tlbi_vaddr_asid(vaddr, asid),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Vaddr {
+        vaddr,
+        asid: Some(asid),
+        last_level: false,
+    });
+    notify_tlb_invalidated();
+}
+
+/// Invalidate TLB entries in the current PE by the virtual address and ASID.
+#[inline]
+pub fn local_invalidate_tlb_vaddr_asid(vaddr: VirtAddr, asid: u16) {
+    // Translations used at EL1 for the specified address and ASID, and global
+    // translations for the address.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb nshst",
+            "tlbi vae1, {arg}",
+            "dsb nsh",
+            "isb",
+            arg = in(reg) tlbi_vaddr_asid(vaddr, asid),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::local_tlbi(crate::sim::TlbiTarget::Vaddr {
+        vaddr,
+        asid: Some(asid),
+        last_level: false,
+    });
+    notify_tlb_invalidated();
+}
+
+/// Invalidate last level TLB entries in all PEs by the virtual address and ASID.
+///
+/// Cached intermediate table entries are kept, so this is only enough when a
+/// page or block descriptor changed, not a table descriptor.
+#[inline]
+pub fn invalidate_tlb_vaddr_asid_last_level(vaddr: VirtAddr, asid: u16) {
+    // Last level translations used at EL1 for the specified address and ASID, in
+    // the Inner Shareable shareability domain.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vale1is, {arg}",
+            "dsb ish",
+            "isb",
+            arg = in(reg) tlbi_vaddr_asid(vaddr, asid),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Vaddr {
+        vaddr,
+        asid: Some(asid),
+        last_level: true,
+    });
+    notify_tlb_invalidated();
+}
+
+/// Invalidate last level TLB entries in the current PE by the virtual address
+/// and ASID.
+///
+/// See [`invalidate_tlb_vaddr_asid_last_level`].
+#[inline]
+pub fn local_invalidate_tlb_vaddr_asid_last_level(vaddr: VirtAddr, asid: u16) {
+    // Last level translations used at EL1 for the specified address and ASID
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb nshst",
+            "tlbi vale1, {arg}",
+            "dsb nsh",
+            "isb",
+            arg = in(reg) tlbi_vaddr_asid(vaddr, asid),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::local_tlbi(crate::sim::TlbiTarget::Vaddr {
+        vaddr,
+        asid: Some(asid),
+        last_level: true,
+    });
+    notify_tlb_invalidated();
+}
+
+/// Invalidate last level TLB entries in all PEs by the virtual address, for all
+/// ASIDs.
+///
+/// Cached intermediate table entries are kept, so this is only enough when a
+/// page or block descriptor changed, not a table descriptor.
+#[inline]
+pub fn invalidate_tlb_vaddr_leaf_only(vaddr: VirtAddr) {
+    // Last level translations used at EL1 for the specified address, for all
+    // ASID values, in the Inner Shareable shareability domain.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vaale1is, {vaddr}",
+            "dsb ish",
+            "isb",
+            vaddr = in(reg) tlbi_vaddr(vaddr),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Vaddr {
+        vaddr,
+        asid: None,
+        last_level: true,
+    });
+    notify_tlb_invalidated();
+}
+
+/// The TLBI operand selecting all entries of `asid`.
+#[inline]
+fn tlbi_asid(asid: u16) -> u64 {
+    (asid as u64) << 48
+}
+
+/// The TLBI operand selecting the page containing `vaddr`: VA[55:12] in bits
+/// [43:0], with the TTL hint in bits [47:44] left zero.
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+#[inline]
+fn tlbi_vaddr(vaddr: VirtAddr) -> u64 {
+    (vaddr.as_u64() >> 12) & ((1 << 44) - 1)
+}
+
+/// The TLBI operand selecting the entries of `asid` for the page containing
+/// `vaddr`: the ASID in bits [63:48], and VA[55:12] in bits [43:0].
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+#[inline]
+fn tlbi_vaddr_asid(vaddr: VirtAddr, asid: u16) -> u64 {
+    tlbi_asid(asid) | tlbi_vaddr(vaddr)
+}
+
+/// Invalidate all EL2 TLB entries in all PEs.
+///
+/// Must be executed at EL2.
+#[inline]
+pub fn invalidate_tlb_all_el2() {
+    debug_require_el(ExceptionLevel::EL2);
+    // All stage 1 translations used at EL2, in the Inner Shareable shareability
+    // domain.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi alle2is",
+            "dsb ish",
+            "isb",
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::AllEl2);
+    notify_tlb_invalidated();
+}
+
+/// Invalidate all EL2 TLB entries in the current PE.
+///
+/// Must be executed at EL2.
+#[inline]
+pub fn local_invalidate_tlb_all_el2() {
+    debug_require_el(ExceptionLevel::EL2);
+    // All stage 1 translations used at EL2
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb nshst",
+            "tlbi alle2",
+            "dsb nsh",
+            "isb",
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::local_tlbi(crate::sim::TlbiTarget::AllEl2);
+    notify_tlb_invalidated();
+}
+
+/// Invalidate the TLB entries of the current guest (VTTBR_EL2.VMID) in all
+/// PEs, of both stages of translation.
+///
+/// Must be executed at EL2.
+#[inline]
+pub fn invalidate_tlb_guest_all() {
+    debug_require_el(ExceptionLevel::EL2);
+    // All stage 1 and stage 2 translations used at EL1 with the current VMID,
+    // in the Inner Shareable shareability domain.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vmalls12e1is",
+            "dsb ish",
+            "isb",
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Guest(None));
+    notify_tlb_invalidated();
+}
+
+/// Invalidate the TLB entries of the current guest (VTTBR_EL2.VMID) in the
+/// current PE, of both stages of translation.
+///
+/// Must be executed at EL2.
+#[inline]
+pub fn local_invalidate_tlb_guest_all() {
+    debug_require_el(ExceptionLevel::EL2);
+    // All stage 1 and stage 2 translations used at EL1 with the current VMID
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb nshst",
+            "tlbi vmalls12e1",
+            "dsb nsh",
+            "isb",
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::local_tlbi(crate::sim::TlbiTarget::Guest(None));
+    notify_tlb_invalidated();
+}
+
+/// Invalidate the TLB entries of the guest `vmid` in all PEs, of both stages
+/// of translation.
+///
+/// The VMID is selected by switching VTTBR_EL2.VMID for the duration of the
+/// invalidation, so this must be executed at EL2 while no guest runs on the
+/// current PE.
+#[inline]
+pub fn invalidate_tlb_vmid(vmid: u16) {
+    debug_require_el(ExceptionLevel::EL2);
+    #[cfg(target_arch = "aarch64")]
+    {
+        let saved = VTTBR_EL2.get();
+        VTTBR_EL2.set(saved & !tlbi_asid(u16::MAX) | tlbi_asid(vmid));
+        unsafe { barrier::isb() };
+        invalidate_tlb_guest_all();
+        VTTBR_EL2.set(saved);
+        unsafe { barrier::isb() };
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        crate::sim::tlbi(crate::sim::TlbiTarget::Guest(Some(vmid)));
+        notify_tlb_invalidated();
+    }
+}
+
+/// Invalidate the stage 2 TLB entries of the current guest (VTTBR_EL2.VMID)
+/// for the intermediate physical address `ipa` in all PEs.
+///
+/// The stage 1 entries of the guest are invalidated too, as they may hold
+/// translations combined with the old stage 2 one. Must be executed at EL2.
+#[inline]
+pub fn invalidate_tlb_ipa(ipa: PhysAddr) {
+    debug_require_el(ExceptionLevel::EL2);
+    // Stage 2 translations used at EL1 for the specified IPA with the current
+    // VMID, then all stage 1 translations of the VMID, in the Inner Shareable
+    // shareability domain.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi ipas2e1is, {arg}",
+            "dsb ish",
+            "tlbi vmalle1is",
+            "dsb ish",
+            "isb",
+            arg = in(reg) tlbi_ipa(ipa),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Ipa(ipa));
+    notify_tlb_invalidated();
+}
+
+/// Invalidate the stage 2 TLB entries of the current guest (VTTBR_EL2.VMID)
+/// for the intermediate physical address `ipa` in the current PE.
+///
+/// See [`invalidate_tlb_ipa`].
+#[inline]
+pub fn local_invalidate_tlb_ipa(ipa: PhysAddr) {
+    debug_require_el(ExceptionLevel::EL2);
+    // Stage 2 translations used at EL1 for the specified IPA with the current
+    // VMID, then all stage 1 translations of the VMID
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "dsb nshst",
+            "tlbi ipas2e1, {arg}",
+            "dsb nsh",
+            "tlbi vmalle1",
+            "dsb nsh",
+            "isb",
+            arg = in(reg) tlbi_ipa(ipa),
+            options(nostack)
+        )
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::local_tlbi(crate::sim::TlbiTarget::Ipa(ipa));
+    notify_tlb_invalidated();
+}
+
+/// The TLBI operand selecting the page containing `ipa`: IPA[51:12] in bits
+/// [39:0].
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+#[inline]
+fn tlbi_ipa(ipa: PhysAddr) -> u64 {
+    (ipa.as_u64() >> 12) & ((1 << 40) - 1)
+}
+
+/// Returns whether the TLBI range instructions are implemented
+/// (FEAT_TLBIRANGE, ID_AA64ISAR0_EL1.TLB).
+#[inline]
+pub fn is_tlb_range_supported() -> bool {
+    ID_AA64ISAR0_EL1.read(ID_AA64ISAR0_EL1::TLB) >= 2
+}
+
+/// Calls `op` with the operands of the TLBIs invalidating `pages` pages of
+/// `granule` bytes, starting with the page number `first`: `(true, operand)`
+/// for a range TLBI, `(false, operand)` for a single page TLBI.
+///
+/// A range TLBI covers `(NUM + 1) << (5 * SCALE + 1)` pages, so the pages are
+/// taken with the largest scale first, and an odd last page with a single page
+/// TLBI. `level` is the TTL hint of the range TLBIs: the lookup level of the
+/// entries to invalidate, or 0 if unknown.
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+fn tlbi_range_ops(first: u64, pages: u64, granule: u64, level: u8, mut op: impl FnMut(bool, u64)) {
+    let shift = granule.trailing_zeros();
+    // TG: 0b01 for 4KiB, 0b10 for 16KiB, 0b11 for 64KiB
+    let tg = match granule {
+        0x1000 => 0b01,
+        0x4000 => 0b10,
+        _ => 0b11,
+    };
+    let mut page = first;
+    let mut remaining = pages;
+    for scale in (0..4).rev() {
+        let unit = 1 << (5 * scale + 1);
+        while remaining >= unit {
+            let num = (remaining / unit).min(32);
+            // TG in bits [47:46], SCALE in [45:44], NUM in [43:39], TTL in
+            // [38:37] and the base page number in [36:0].
+            op(
+                true,
+                tg << 46
+                    | scale << 44
+                    | (num - 1) << 39
+                    | (level as u64 & 0b11) << 37
+                    | page & ((1 << 37) - 1),
+            );
+            page += num * unit;
+            remaining -= num * unit;
+        }
+    }
+    if remaining == 1 {
+        op(false, ((page << shift) >> 12) & ((1 << 44) - 1));
+    }
+}
+
+/// Issues the TLBIs of [`tlbi_range_ops`] for all ASIDs, without barriers.
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn tlbi_vaae1is_range(first: u64, pages: u64, granule: u64, level: u8) {
+    tlbi_range_ops(first, pages, granule, level, |range, arg| unsafe {
+        if range {
+            // TLBI RVAAE1IS, by its encoding so that no architecture extension
+            // is needed to assemble it
+            core::arch::asm!("sys #0, c8, c2, #3, {arg}", arg = in(reg) arg, options(nostack))
+        } else {
+            core::arch::asm!("tlbi vaae1is, {arg}", arg = in(reg) arg, options(nostack))
+        }
+    });
+}
+
+/// Issues the TLBIs of [`tlbi_range_ops`] for `asid` and the global entries,
+/// without barriers.
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn tlbi_vae1is_range(first: u64, pages: u64, granule: u64, level: u8, asid: u16) {
+    tlbi_range_ops(first, pages, granule, level, |range, arg| unsafe {
+        let arg = tlbi_asid(asid) | arg;
+        if range {
+            // TLBI RVAE1IS
+            core::arch::asm!("sys #0, c8, c2, #1, {arg}", arg = in(reg) arg, options(nostack))
+        } else {
+            core::arch::asm!("tlbi vae1is, {arg}", arg = in(reg) arg, options(nostack))
+        }
+    });
+}
+
+/// Invalidate TLB entries in all PEs for every 4KiB page in the virtual
+/// address interval [start, end).
+///
+/// Uses the TLBI range instructions if implemented, so that a large range
+/// takes a few TLBIs instead of one per page, and falls back to
+/// [`invalidate_tlb_range_chunked`] otherwise.
+#[inline]
+pub fn invalidate_tlb_range(start: VirtAddr, end: VirtAddr) {
+    if start >= end {
+        return;
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if !is_tlb_range_supported() {
+            return invalidate_tlb_range_chunked(start, end, usize::MAX, || {});
+        }
+        let first = start.as_u64() >> 12;
+        let pages = ((end.as_u64() - 1) >> 12) - first + 1;
+        unsafe { core::arch::asm!("dsb ishst", options(nostack)) };
+        tlbi_vaae1is_range(first, pages, 0x1000, 0);
+        unsafe { core::arch::asm!("dsb ish", "isb", options(nostack)) };
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Range {
+        start,
+        end,
+        asid: None,
+    });
+    notify_tlb_invalidated();
+}
+
+/// Invalidate TLB entries in all PEs for every 4KiB page in the virtual
+/// address interval [start, end), at most `pages_per_chunk` pages at a time,
+/// calling `yield_now` between chunks.
+///
+/// Each chunk is completed with `dsb ish` before `yield_now` is called, so the
+/// caller can take pending interrupts or reschedule there to keep the latency
+/// of invalidating large ranges bounded.
+#[inline]
+pub fn invalidate_tlb_range_chunked<F: FnMut()>(
+    start: VirtAddr,
+    end: VirtAddr,
+    pages_per_chunk: usize,
+    mut yield_now: F,
+) {
+    assert!(pages_per_chunk != 0, "chunk size must not be zero");
+    if start >= end {
+        return;
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        // one event per chunk
+        let chunk = (pages_per_chunk as u64).saturating_mul(0x1000);
+        let mut chunk_start = start.align_down(0x1000);
+        loop {
+            let chunk_end = VirtAddr::new_truncate(
+                chunk_start.as_u64().saturating_add(chunk).min(end.as_u64()),
+            );
+            crate::sim::tlbi(crate::sim::TlbiTarget::Range {
+                start: chunk_start,
+                end: chunk_end,
+                asid: None,
+            });
+            if chunk_end >= end {
+                break;
+            }
+            yield_now();
+            chunk_start = chunk_end;
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        hardware_invalidate_tlb_range_chunked(start, end, pages_per_chunk, &mut yield_now);
+    }
+    notify_tlb_invalidated();
+}
+
+/// The TLBIs of [`invalidate_tlb_range_chunked`].
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn hardware_invalidate_tlb_range_chunked<F: FnMut()>(
+    start: VirtAddr,
+    end: VirtAddr,
+    pages_per_chunk: usize,
+    yield_now: &mut F,
+) {
+    // The TLBI operand holds VA[55:12] in bits [43:0].
+    let mut page = (start.as_u64() >> 12) & ((1 << 44) - 1);
+    let last = ((end.as_u64() - 1) >> 12) & ((1 << 44) - 1);
+    unsafe { core::arch::asm!("dsb ishst", options(nostack)) };
+    loop {
+        let chunk_last = page.saturating_add(pages_per_chunk as u64 - 1).min(last);
+        while page <= chunk_last {
+            unsafe {
+                core::arch::asm!(
+                    "tlbi vaae1is, {page}",
+                    page = in(reg) page,
+                    options(nostack)
+                )
+            };
+            page += 1;
+        }
+        unsafe { core::arch::asm!("dsb ish", options(nostack)) };
+        if page > last {
+            break;
+        }
+        yield_now();
+    }
+    unsafe { core::arch::asm!("isb", options(nostack)) };
+}
+
+/// Invalidate TLB entries in all PEs for every page in `pages`.
+///
+/// Unlike [`invalidate_tlb_range`], only one TLBI is issued per page of size
+/// `S`, and the invalidations are completed by a single `dsb ish`. With the
+/// TLBI range instructions, the whole range takes a few TLBIs, with the lookup
+/// level of `S` as the TTL hint: the pages must be mapped by entries of size
+/// `S`, as done by the mappers.
+#[inline]
+pub fn invalidate_tlb_pages<S: PageSize>(pages: PageRange<S>) {
+    if pages.is_empty() {
+        return;
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Range {
+        start: pages.start.start_address(),
+        end: pages.end.start_address(),
+        asid: None,
+    });
+    #[cfg(target_arch = "aarch64")]
+    hardware_invalidate_tlb_pages(pages, None);
+    notify_tlb_invalidated();
+}
+
+/// Invalidate TLB entries in all PEs for every page in `pages`, only for the
+/// given ASID (and global mappings).
+///
+/// Like [`invalidate_tlb_pages`], but the entries of the other ASIDs are kept,
+/// e.g. when unmapping pages of a single address space.
+#[inline]
+pub fn invalidate_tlb_pages_asid<S: PageSize>(pages: PageRange<S>, asid: u16) {
+    if pages.is_empty() {
+        return;
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    crate::sim::tlbi(crate::sim::TlbiTarget::Range {
+        start: pages.start.start_address(),
+        end: pages.end.start_address(),
+        asid: Some(asid),
+    });
+    #[cfg(target_arch = "aarch64")]
+    hardware_invalidate_tlb_pages(pages, Some(asid));
+    notify_tlb_invalidated();
+}
+
+/// The TLBIs of [`invalidate_tlb_pages`] and [`invalidate_tlb_pages_asid`].
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn hardware_invalidate_tlb_pages<S: PageSize>(pages: PageRange<S>, asid: Option<u16>) {
+    unsafe { core::arch::asm!("dsb ishst", options(nostack)) };
+    if is_tlb_range_supported() {
+        let granule = <S::Granule as TranslationGranule>::Page::SIZE;
+        let first = pages.start.start_address().as_u64() >> granule.trailing_zeros();
+        let count = pages.len() * (S::SIZE / granule);
+        match asid {
+            Some(asid) => tlbi_vae1is_range(first, count, granule, S::LEVEL as u8, asid),
+            None => tlbi_vaae1is_range(first, count, granule, S::LEVEL as u8),
+        }
+    } else {
+        for page in pages {
+            match asid {
+                Some(asid) => unsafe {
+                    core::arch::asm!(
+                        "tlbi vae1is, {arg}",
+                        arg = in(reg) tlbi_vaddr_asid(page.start_address(), asid),
+                        options(nostack)
+                    )
+                },
+                None => unsafe {
+                    core::arch::asm!(
+                        "tlbi vaae1is, {page}",
+                        page = in(reg) (page.start_address().as_u64() >> 12) & ((1 << 44) - 1),
+                        options(nostack)
+                    )
+                },
+            }
+        }
+    }
+    unsafe { core::arch::asm!("dsb ish", "isb", options(nostack)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_decode_par() {
+        let vaddr = VirtAddr::new(0xffff_0000_1234_5678);
+        let translation = decode_par(0xff00_0000_8765_4180, vaddr).unwrap();
+        assert_eq!(translation.addr, PhysAddr::new(0x8765_4678));
+        assert_eq!(translation.shareability, Shareability::InnerShareable);
+        assert_eq!(translation.attr, 0xff);
+        assert!(!translation.non_secure);
+        assert_eq!(translation.par.attr(), Some(0xff));
+        assert_eq!(translation.par.is_device(), Some(false));
+        assert_eq!(
+            translation.par.page_addr(),
+            Some(PhysAddr::new(0x8765_4000))
+        );
+        assert_eq!(ParEl1::new(0x0400_0000_0000_0a00).is_device(), Some(true));
+        assert_eq!(ParEl1::new(0x0400_0000_0000_0a00).non_secure(), Some(true));
+
+        // permission fault at level 3
+        let err = decode_par((0b00_1111 << 1) | 1, vaddr).unwrap_err();
+        assert_eq!(err.kind(), AtFaultKind::Permission);
+        assert_eq!(err.level(), Some(3));
+        assert_eq!(err.stage, 1);
+        assert_eq!(ParEl1::new((0b00_1111 << 1) | 1).attr(), None);
+    }
+
+    #[test]
+    pub fn test_ttbr_builder() {
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8_0000));
+        let ttbr = TtbrBuilder::new(frame).asid(0x1234).cnp(true);
+        assert_eq!(ttbr.value(AsidSize::Bits16), Err(TtbrError::AsidTooLarge));
+        let ttbr = ttbr.asid_size(AsidSize::Bits16);
+        assert_eq!(
+            ttbr.value(AsidSize::Bits8),
+            Err(TtbrError::AsidSizeNotSupported)
+        );
+        assert_eq!(ttbr.value(AsidSize::Bits16), Ok(0x1234_0000_0008_0001));
+    }
+
+    #[test]
+    pub fn test_tcr_builder() {
+        use crate::paging::{Granule16KiB, Granule4KiB};
+
+        // 4KiB granule and 48-bit PAs only
+        let mmfr0 = (0b1111 << 24) | 0b0101;
+        let tcr = TcrBuilder::new().ttbr0::<Granule4KiB>(48);
+        assert_eq!(
+            tcr.value(mmfr0),
+            Ok(0x10 | 0x100 | 0x400 | 0x3000 | 0x80_0000 | (0b10 << 30) | (0b101 << 32))
+        );
+        assert_eq!(
+            tcr.ttbr1::<Granule16KiB>(48).value(mmfr0),
+            Err(TcrError::GranuleNotSupported)
+        );
+        assert_eq!(
+            TcrBuilder::new().ttbr0::<Granule4KiB>(52).value(mmfr0),
+            Err(TcrError::VaBitsOutOfRange)
+        );
+        assert_eq!(
+            tcr.asid_size(AsidSize::Bits16).value(mmfr0),
+            Err(TcrError::AsidSizeNotSupported)
+        );
+        assert_eq!(
+            tcr.top_byte_ignore(true, true)
+                .tag_check_match_all(false, true)
+                .value(mmfr0)
+                .unwrap()
+                ^ tcr.value(mmfr0).unwrap(),
+            1 << 37 | 1 << 38 | 1 << 58
+        );
+    }
+
+    #[test]
+    pub fn test_mmu_config() {
+        use crate::paging::Granule4KiB;
+
+        let mmfr0 = (0b1111 << 24) | 0b0101;
+        let tcr = TcrBuilder::new().ttbr0::<Granule4KiB>(48);
+        let root = TtbrBuilder::new(PhysFrame::containing_address(PhysAddr::new(0x8_0000)));
+        let config = MmuConfig::new(MairConfig::new(), tcr).ttbr0(root.asid(0x12));
+        assert_eq!(
+            config.values(mmfr0),
+            Ok((tcr.value(mmfr0).unwrap(), 0x0012_0000_0008_0000, 0))
+        );
+        // validated against the ASID size of TCR_EL1
+        assert_eq!(
+            config.ttbr1(root.asid(0x123)).values(mmfr0),
+            Err(MmuError::Ttbr(TtbrError::AsidTooLarge))
+        );
+        assert_eq!(
+            MmuConfig::new(MairConfig::new(), tcr.asid_size(AsidSize::Bits16)).values(mmfr0),
+            Err(MmuError::Tcr(TcrError::AsidSizeNotSupported))
+        );
+    }
+
+    #[test]
+    pub fn test_tlbi_operand() {
+        assert_eq!(tlbi_asid(0x1234), 0x1234_0000_0000_0000);
+        // a higher half address must not set the TTL hint
+        assert_eq!(
+            tlbi_vaddr(VirtAddr::new(0xffff_8000_1234_5678)),
+            0x0ff8_0001_2345
+        );
+        assert_eq!(tlbi_vaddr(VirtAddr::new(0x40_1000)), 0x401);
+        assert_eq!(
+            tlbi_vaddr_asid(VirtAddr::new(0xffff_8000_1234_5678), 0xab),
+            0x00ab_0ff8_0001_2345
+        );
+        assert_eq!(
+            tlbi_ipa(PhysAddr::new(0x000f_1234_5678_9abc)),
+            0xf1_2345_6789
+        );
+    }
+
+    #[test]
+    pub fn test_tlbi_range_ops() {
+        fn ops(first: u64, pages: u64, granule: u64, level: u8) -> ([(bool, u64); 8], usize) {
+            let mut ops = [(false, 0); 8];
+            let mut count = 0;
+            tlbi_range_ops(first, pages, granule, level, |range, arg| {
+                ops[count] = (range, arg);
+                count += 1;
+            });
+            (ops, count)
+        }
+
+        // a single page
+        let (list, count) = ops(0x12345, 1, 0x1000, 0);
+        assert_eq!(&list[..count], &[(false, 0x12345)]);
+
+        // 2 pages: NUM = 0, SCALE = 0
+        let (list, count) = ops(0x12345, 2, 0x1000, 3);
+        assert_eq!(&list[..count], &[(true, 1 << 46 | 3 << 37 | 0x12345)]);
+
+        // 67 pages: 64 pages, a pair, then one page
+        let (list, count) = ops(0x100, 67, 0x1000, 0);
+        assert_eq!(
+            &list[..count],
+            &[
+                (true, 1 << 46 | 1 << 44 | 0x100),
+                (true, 1 << 46 | 0x140),
+                (false, 0x142)
+            ]
+        );
+
+        // 64KiB granule: page numbers in 64KiB units, single page operand in
+        // 4KiB units
+        let (list, count) = ops(0x10, 3, 0x10000, 3);
+        assert_eq!(
+            &list[..count],
+            &[(true, 3 << 46 | 3 << 37 | 0x10), (false, 0x120)]
+        );
+
+        // more than 32 << 16 pages: several TLBIs at the largest scale
+        let (list, count) = ops(0, (32 << 16) + (1 << 16), 0x1000, 0);
+        assert_eq!(
+            &list[..count],
+            &[
+                (true, 1 << 46 | 3 << 44 | 31 << 39),
+                (true, 1 << 46 | 3 << 44 | 0x20_0000)
+            ]
+        );
+    }
 }
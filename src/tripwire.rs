@@ -0,0 +1,246 @@
+//! Watchpoint-based tripwires for hunting memory corruption.
+//!
+//! [`watch_writes`] arms a hardware watchpoint over a critical structure (e.g. a page table
+//! root). Any write to it raises a watchpoint debug exception, which the kernel forwards to
+//! [`handle_debug_exception`] to find out which tripwire was hit and from which PC. Hits are also
+//! reported to the [fault sink](crate::fault).
+
+use crate::{
+    barrier::isb,
+    debug::{self, write_dbgwcr, write_dbgwvr},
+    fault::{self, FaultRecord, FaultSource, Severity},
+    registers::*,
+    VirtAddr,
+};
+use core::{
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tock_registers::LocalRegisterCopy;
+
+/// The maximum number of watchpoints defined by the architecture.
+const MAX_WATCHPOINTS: usize = debug::MAX_DEBUG_REGISTERS;
+
+/// Start (inclusive) and end (exclusive) of the watched range of each slot. An end of zero marks
+/// a free slot.
+static WATCH_START: [AtomicU64; MAX_WATCHPOINTS] = [ZERO; MAX_WATCHPOINTS];
+static WATCH_END: [AtomicU64; MAX_WATCHPOINTS] = [ZERO; MAX_WATCHPOINTS];
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// DBGWCR_EL1 fields.
+const WCR_E: u64 = 1 << 0;
+const WCR_PAC_EL0_EL1: u64 = 0b11 << 1;
+const WCR_LSC_STORE: u64 = 0b10 << 3;
+const WCR_BAS_SHIFT: u64 = 5;
+const WCR_MASK_SHIFT: u64 = 24;
+
+/// The largest region a single watchpoint can cover (2GiB).
+const MAX_MASK_BITS: u32 = 31;
+
+/// An error indicating that a tripwire could not be armed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TripwireError {
+    /// The given range is empty.
+    EmptyRange,
+    /// The given range can't be covered by a single watchpoint.
+    RangeTooLarge,
+    /// All watchpoints of this core are already in use.
+    NoFreeWatchpoint,
+}
+
+/// An armed tripwire, returned by [`watch_writes`].
+#[derive(Debug)]
+#[must_use = "an armed tripwire stays armed until `disarm` is called"]
+pub struct Tripwire {
+    slot: usize,
+}
+
+impl Tripwire {
+    /// Returns the watchpoint number used by this tripwire.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Disarms the tripwire and releases its watchpoint.
+    pub fn disarm(self) {
+        disarm_slot(self.slot);
+    }
+}
+
+/// A tripwire hit, returned by [`handle_debug_exception`].
+#[derive(Debug, Clone)]
+pub struct TripwireHit {
+    /// The watchpoint number of the tripwire.
+    pub slot: usize,
+    /// The address of the instruction that performed the write.
+    pub pc: u64,
+    /// The address reported by the watchpoint (FAR_EL1).
+    pub addr: VirtAddr,
+    /// The range watched by the tripwire.
+    pub range: Range<VirtAddr>,
+}
+
+impl fmt::Display for TripwireHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tripwire {} ({:#x}..{:#x}) hit by write to {:#x} at pc {:#x}",
+            self.slot,
+            self.range.start.as_u64(),
+            self.range.end.as_u64(),
+            self.addr.as_u64(),
+            self.pc
+        )
+    }
+}
+
+impl TripwireHit {
+    /// Returns whether the reported address lies in the watched range.
+    ///
+    /// A watchpoint covers a naturally aligned power-of-two region, so writes next to an
+    /// unaligned watched range can trigger it as well.
+    pub fn in_range(&self) -> bool {
+        self.range.contains(&self.addr)
+    }
+}
+
+/// Arms a watchpoint that traps every write (at EL0 or EL1) to `range`.
+///
+/// The watchpoint covers the smallest naturally aligned region containing `range`, of at most
+/// 2GiB. This also enables debug exceptions in MDSCR_EL1 and unmasks them in PSTATE.
+///
+/// Watchpoints are per core: the tripwire only catches writes performed by the calling core.
+pub fn watch_writes(range: Range<VirtAddr>) -> Result<Tripwire, TripwireError> {
+    let (start, end) = (range.start.as_u64(), range.end.as_u64());
+    if start >= end {
+        return Err(TripwireError::EmptyRange);
+    }
+    let (base, ctrl) = watch_region(start, end).ok_or(TripwireError::RangeTooLarge)?;
+
+    let count = debug::num_watchpoints();
+    let slot = (0..count.min(MAX_WATCHPOINTS))
+        .find(|&slot| {
+            WATCH_END[slot]
+                .compare_exchange(0, end, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })
+        .ok_or(TripwireError::NoFreeWatchpoint)?;
+    WATCH_START[slot].store(start, Ordering::Release);
+
+    unsafe {
+        write_dbgwvr(slot, base);
+        write_dbgwcr(slot, ctrl);
+        debug::enable_debug();
+    }
+    Ok(Tripwire { slot })
+}
+
+/// Identifies the tripwire that caused a debug exception.
+///
+/// Call this from the synchronous exception handler with the values of ESR_EL1, FAR_EL1 and
+/// ELR_EL1. Returns `None` if the exception was not caused by a tripwire. Otherwise the tripwire
+/// is disarmed, so that the faulting write completes when the handler returns.
+pub fn handle_debug_exception(esr: u64, far: u64, elr: u64) -> Option<TripwireHit> {
+    use ESR_EL1::EC::Value::{WatchpointCurrentEL, WatchpointLowerEL};
+
+    let esr = LocalRegisterCopy::<u64, ESR_EL1::Register>::new(esr);
+    match esr.read_as_enum(ESR_EL1::EC) {
+        Some(WatchpointCurrentEL) | Some(WatchpointLowerEL) => {}
+        _ => return None,
+    }
+
+    let slot = (0..MAX_WATCHPOINTS).find(|&slot| {
+        let end = WATCH_END[slot].load(Ordering::Acquire);
+        end != 0
+            && matches!(
+                watch_region(WATCH_START[slot].load(Ordering::Acquire), end),
+                Some((base, ctrl)) if region_contains(base, ctrl, far)
+            )
+    })?;
+    let range = VirtAddr::new(WATCH_START[slot].load(Ordering::Acquire))
+        ..VirtAddr::new(WATCH_END[slot].load(Ordering::Acquire));
+    disarm_slot(slot);
+
+    let hit = TripwireHit {
+        slot,
+        pc: elr,
+        addr: VirtAddr::new_unchecked(far),
+        range,
+    };
+    fault::report(&FaultRecord {
+        source: FaultSource::Tripwire,
+        severity: Severity::Error,
+        message: format_args!("{}", hit),
+    });
+    Some(hit)
+}
+
+fn disarm_slot(slot: usize) {
+    unsafe {
+        write_dbgwcr(slot, 0);
+        isb();
+    }
+    WATCH_END[slot].store(0, Ordering::Release);
+}
+
+/// Computes the watchpoint value (base address) and control register fields for the region
+/// `[start, end)`.
+fn watch_region(start: u64, end: u64) -> Option<(u64, u64)> {
+    let last = end - 1;
+    let ctrl = WCR_E | WCR_PAC_EL0_EL1 | WCR_LSC_STORE;
+    if start >> 3 == last >> 3 {
+        // Within one doubleword: select the bytes with BAS.
+        let bas = ((1u64 << (last - start + 1)) - 1) << (start & 7);
+        return Some((start & !7, ctrl | bas << WCR_BAS_SHIFT));
+    }
+    let bits = 64 - (start ^ last).leading_zeros();
+    if bits > MAX_MASK_BITS {
+        return None;
+    }
+    let base = start & !((1u64 << bits) - 1);
+    Some((
+        base,
+        ctrl | 0xff << WCR_BAS_SHIFT | u64::from(bits) << WCR_MASK_SHIFT,
+    ))
+}
+
+fn region_contains(base: u64, ctrl: u64, addr: u64) -> bool {
+    let mask_bits = (ctrl >> WCR_MASK_SHIFT) & 0x1f;
+    if mask_bits != 0 {
+        addr >> mask_bits == base >> mask_bits
+    } else {
+        addr & !7 == base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_watch_region() {
+        // 4 bytes inside a doubleword
+        let (base, ctrl) = watch_region(0x1002, 0x1006).unwrap();
+        assert_eq!(base, 0x1000);
+        assert_eq!((ctrl >> WCR_BAS_SHIFT) & 0xff, 0b0011_1100);
+        assert_eq!(ctrl >> WCR_MASK_SHIFT, 0);
+        assert!(region_contains(base, ctrl, 0x1005));
+        assert!(!region_contains(base, ctrl, 0x1008));
+
+        // a page table
+        let (base, ctrl) = watch_region(0x8_0000, 0x8_1000).unwrap();
+        assert_eq!(base, 0x8_0000);
+        assert_eq!(ctrl >> WCR_MASK_SHIFT, 12);
+        assert!(region_contains(base, ctrl, 0x8_0ff8));
+        assert!(!region_contains(base, ctrl, 0x8_1000));
+
+        // unaligned range straddling a boundary
+        let (base, ctrl) = watch_region(0xff8, 0x1008).unwrap();
+        assert_eq!(base, 0);
+        assert_eq!(ctrl >> WCR_MASK_SHIFT, 13);
+
+        assert_eq!(watch_region(0, 0x1_0000_0000), None);
+    }
+}
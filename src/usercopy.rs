@@ -0,0 +1,183 @@
+//! Copies between kernel and user memory that survive faults.
+//!
+//! [`copy_from_user`] and [`copy_to_user`] access user memory with the unprivileged load and
+//! store instructions (LDTR and STTR), so the access permissions of EL0 are checked: a user
+//! pointer to kernel memory faults instead of leaking or corrupting it.
+//!
+//! A fault in a copy is a normal data abort taken to EL1. The abort handler passes ELR_EL1 to
+//! [`fixup`], and if the fault belongs to a copy routine, sets ELR_EL1 to the returned address
+//! and returns from the exception instead of treating the fault as fatal. The copy then returns
+//! [`UserCopyError::Fault`] with the number of bytes that were not copied.
+//!
+//! The handler must restore the general-purpose registers of the interrupted copy when it
+//! returns.
+
+/// An error returned by [`copy_from_user`] and [`copy_to_user`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserCopyError {
+    /// The user range is not in the lower VA range, or wraps around.
+    InvalidAddress,
+    /// The copy faulted, with at most `not_copied` bytes left to copy.
+    Fault {
+        /// The number of bytes that were not copied.
+        not_copied: usize,
+    },
+}
+
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    ".pushsection .text.usercopy, \"ax\"",
+    ".balign 4",
+    ".global __aarch64_usercopy_start",
+    "__aarch64_usercopy_start:",
+    // x0: destination, x1: source, x2: length. Returns the number of bytes not copied.
+    ".global __aarch64_copy_from_user",
+    "__aarch64_copy_from_user:",
+    "   cmp x2, #8",
+    "   b.lo 2f",
+    "1: ldtr x3, [x1]",
+    "   str x3, [x0], #8",
+    "   add x1, x1, #8",
+    "   sub x2, x2, #8",
+    "   cmp x2, #8",
+    "   b.hs 1b",
+    "2: cbz x2, 3f",
+    "   ldtrb w3, [x1]",
+    "   strb w3, [x0], #1",
+    "   add x1, x1, #1",
+    "   sub x2, x2, #1",
+    "   b 2b",
+    "3: mov x0, x2",
+    "   ret",
+    ".global __aarch64_copy_to_user",
+    "__aarch64_copy_to_user:",
+    "   cmp x2, #8",
+    "   b.lo 2f",
+    "1: ldr x3, [x1], #8",
+    "   sttr x3, [x0]",
+    "   add x0, x0, #8",
+    "   sub x2, x2, #8",
+    "   cmp x2, #8",
+    "   b.hs 1b",
+    "2: cbz x2, 3f",
+    "   ldrb w3, [x1], #1",
+    "   sttrb w3, [x0]",
+    "   add x0, x0, #1",
+    "   sub x2, x2, #1",
+    "   b 2b",
+    "3: mov x0, x2",
+    "   ret",
+    ".global __aarch64_usercopy_end",
+    "__aarch64_usercopy_end:",
+    // resumed at on a fault, with x2 holding the number of bytes not copied
+    ".global __aarch64_usercopy_fixup",
+    "__aarch64_usercopy_fixup:",
+    "   mov x0, x2",
+    "   ret",
+    ".popsection",
+);
+
+#[cfg(target_arch = "aarch64")]
+extern "C" {
+    fn __aarch64_copy_from_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn __aarch64_copy_to_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    static __aarch64_usercopy_start: u8;
+    static __aarch64_usercopy_end: u8;
+    static __aarch64_usercopy_fixup: u8;
+}
+
+/// Returns whether `[addr, addr + len)` lies in the lower VA range.
+fn is_user_range(addr: usize, len: usize) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => (end as u64) <= 1 << crate::addr::VA_BITS,
+        None => false,
+    }
+}
+
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+fn copy_result(not_copied: usize) -> Result<(), UserCopyError> {
+    match not_copied {
+        0 => Ok(()),
+        not_copied => Err(UserCopyError::Fault { not_copied }),
+    }
+}
+
+/// Copies `len` bytes from the user address `src` to `dst`.
+///
+/// # Safety
+///
+/// `dst` must be valid for writes of `len` bytes. The data abort handler must call [`fixup`].
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub unsafe fn copy_from_user(dst: *mut u8, src: usize, len: usize) -> Result<(), UserCopyError> {
+    if !is_user_range(src, len) {
+        return Err(UserCopyError::InvalidAddress);
+    }
+    match () {
+        // LDTR is checked against PAN when PSTATE.UAO is set
+        #[cfg(target_arch = "aarch64")]
+        () => copy_result(crate::pan::with_user_access(|| {
+            __aarch64_copy_from_user(dst, src as *const u8, len)
+        })),
+
+        #[cfg(not(target_arch = "aarch64"))]
+        () => unimplemented!(),
+    }
+}
+
+/// Copies `len` bytes from `src` to the user address `dst`.
+///
+/// # Safety
+///
+/// `src` must be valid for reads of `len` bytes. The data abort handler must call [`fixup`].
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused_variables))]
+#[inline]
+pub unsafe fn copy_to_user(dst: usize, src: *const u8, len: usize) -> Result<(), UserCopyError> {
+    if !is_user_range(dst, len) {
+        return Err(UserCopyError::InvalidAddress);
+    }
+    match () {
+        #[cfg(target_arch = "aarch64")]
+        () => copy_result(crate::pan::with_user_access(|| {
+            __aarch64_copy_to_user(dst as *mut u8, src, len)
+        })),
+
+        #[cfg(not(target_arch = "aarch64"))]
+        () => unimplemented!(),
+    }
+}
+
+/// Returns the address to resume at if `pc`, the ELR_EL1 value of a data abort, is an access
+/// of a user copy routine.
+///
+/// Returns `None` for any other fault, which the handler deals with as usual.
+#[inline]
+pub fn fixup(pc: u64) -> Option<u64> {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let start = &__aarch64_usercopy_start as *const u8 as u64;
+        let end = &__aarch64_usercopy_end as *const u8 as u64;
+        if (start..end).contains(&pc) {
+            return Some(&__aarch64_usercopy_fixup as *const u8 as u64);
+        }
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = pc;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_user_range() {
+        assert!(is_user_range(0x40_0000, 0x1000));
+        assert!(is_user_range(0xffff_ffff_f000, 0x1000));
+        assert!(!is_user_range(0xffff_ffff_f000, 0x1001));
+        assert!(!is_user_range(0xffff_0000_0000_0000, 8));
+        assert!(!is_user_range(usize::MAX, 2));
+        assert_eq!(copy_result(0), Ok(()));
+        assert_eq!(copy_result(3), Err(UserCopyError::Fault { not_copied: 3 }));
+    }
+}
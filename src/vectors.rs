@@ -0,0 +1,140 @@
+//! Exception vector tables.
+//!
+//! A vector table has 16 entries of 128 bytes (32 instructions), one for each exception type
+//! ([`VectorKind`]) and source ([`VectorSource`]), and must be aligned to 2KiB. The
+//! [`vector_table!`](crate::vector_table) macro lays out a table whose entries branch to the given
+//! handlers, and [`install`] makes it the table of EL1.
+
+use crate::{barrier, registers::*};
+
+/// The alignment of a vector table.
+pub const VECTOR_TABLE_ALIGN: usize = 2048;
+
+/// The size of an entry of a vector table.
+pub const VECTOR_ENTRY_SIZE: usize = 0x80;
+
+/// The type of an exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorKind {
+    /// Synchronous exception, e.g. an abort, an SVC or an undefined instruction.
+    Synchronous = 0,
+    /// IRQ interrupt.
+    Irq = 1,
+    /// FIQ interrupt.
+    Fiq = 2,
+    /// SError interrupt.
+    SError = 3,
+}
+
+/// The state in which an exception was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorSource {
+    /// From the current Exception level, using SP_EL0.
+    CurrentElSp0 = 0,
+    /// From the current Exception level, using SP_ELx.
+    CurrentElSpx = 1,
+    /// From a lower Exception level in AArch64.
+    LowerElAArch64 = 2,
+    /// From a lower Exception level in AArch32.
+    LowerElAArch32 = 3,
+}
+
+/// Returns the offset of the entry of the given exception in a vector table.
+pub const fn vector_offset(source: VectorSource, kind: VectorKind) -> usize {
+    (source as usize * 4 + kind as usize) * VECTOR_ENTRY_SIZE
+}
+
+/// The layout of an exception vector table: 16 entries of 32 instructions, aligned to 2KiB.
+///
+/// Tables are defined with [`vector_table!`](crate::vector_table), which places them in an
+/// executable section.
+#[repr(C, align(2048))]
+pub struct VectorTable {
+    entries: [[u32; VECTOR_ENTRY_SIZE / 4]; 16],
+}
+
+impl VectorTable {
+    /// Returns the address of the table.
+    pub fn addr(&self) -> usize {
+        self as *const _ as usize
+    }
+
+    /// Returns the instructions of the entry of the given exception.
+    pub fn entry(&self, source: VectorSource, kind: VectorKind) -> &[u32; VECTOR_ENTRY_SIZE / 4] {
+        &self.entries[source as usize * 4 + kind as usize]
+    }
+
+    /// Returns the address of the entry of the given exception.
+    pub fn entry_addr(&self, source: VectorSource, kind: VectorKind) -> usize {
+        self.addr() + vector_offset(source, kind)
+    }
+}
+
+/// Defines a vector table named `$name` whose entries branch to the given handlers.
+///
+/// The 16 handlers are given in the order of the entries: synchronous, IRQ, FIQ and SError, from
+/// the current EL with SP_EL0, the current EL with SP_ELx, a lower EL in AArch64 and a lower EL
+/// in AArch32. They must be global symbols, e.g. `#[no_mangle]` functions or assembly routines,
+/// within ±128MiB of the table. They are entered with the registers of the interrupted context,
+/// so they usually save them before calling into Rust code.
+///
+/// The table is placed in the `.text.vectors` section and declared as an extern `static $name:
+/// VectorTable`, to be passed to [`install`].
+#[macro_export]
+macro_rules! vector_table {
+    ($name:ident, $($handler:ident),+ $(,)?) => {
+        const _: () = assert!(
+            [$(stringify!($handler)),+].len() == 16,
+            "a vector table has 16 entries"
+        );
+
+        core::arch::global_asm!(concat!(
+            ".pushsection .text.vectors, \"ax\"\n",
+            ".balign 2048\n",
+            ".global ", stringify!($name), "\n",
+            stringify!($name), ":\n",
+            $(".balign 0x80\n", "b ", stringify!($handler), "\n",)+
+            ".balign 0x80\n",
+            ".popsection\n",
+        ));
+
+        extern "C" {
+            static $name: $crate::vectors::VectorTable;
+        }
+    };
+}
+
+/// Installs `table` as the vector table of EL1 (VBAR_EL1).
+///
+/// # Safety
+///
+/// The entries of `table` must handle all exceptions that can be taken to EL1, and the table
+/// must stay mapped as executable.
+#[inline]
+pub unsafe fn install(table: &'static VectorTable) {
+    VBAR_EL1.set(table.addr() as u64);
+    barrier::isb();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_vector_offset() {
+        assert_eq!(
+            vector_offset(VectorSource::CurrentElSp0, VectorKind::Synchronous),
+            0
+        );
+        assert_eq!(
+            vector_offset(VectorSource::CurrentElSpx, VectorKind::Irq),
+            0x280
+        );
+        assert_eq!(
+            vector_offset(VectorSource::LowerElAArch32, VectorKind::SError),
+            0x780
+        );
+        assert_eq!(core::mem::size_of::<VectorTable>(), VECTOR_TABLE_ALIGN);
+        assert_eq!(core::mem::align_of::<VectorTable>(), VECTOR_TABLE_ALIGN);
+    }
+}